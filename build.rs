@@ -3,11 +3,61 @@
 
 use std::env::var;
 use std::env::var_os;
+use std::fs::read_to_string;
 use std::path::Path;
+use std::path::PathBuf;
+
+
+/// Determine the version of libreadline, as `(major, minor)`, from the
+/// `RL_READLINE_VERSION` definition in its `readline.h` header, along
+/// with the path to the header.
+fn readline_version() -> Option<(PathBuf, (u32, u32))> {
+  let mut dirs = Vec::new();
+  if let Some(dir) = var_os("READLINE_INCLUDE_DIR") {
+    dirs.push(PathBuf::from(dir));
+  }
+  dirs.push(PathBuf::from("/usr/include"));
+  dirs.push(PathBuf::from("/usr/local/include"));
+
+  dirs.iter().find_map(|dir| {
+    let path = dir.join("readline").join("readline.h");
+    let header = read_to_string(&path).ok()?;
+    let version = header.lines().find_map(|line| {
+      let mut words = line.split_whitespace();
+      if words.next()? != "#define" || words.next()? != "RL_READLINE_VERSION" {
+        return None
+      }
+      let version = words.next()?.trim_start_matches("0x");
+      let version = u32::from_str_radix(version, 16).ok()?;
+      Some((version >> 8, version & 0xff))
+    })?;
+    Some((path, version))
+  })
+}
 
 
 fn main() {
   println!("cargo:rerun-if-env-changed=CARGO_CFG_TARGET_OS");
+  println!("cargo:rerun-if-env-changed=READLINE_INCLUDE_DIR");
+  println!("cargo:rustc-check-cfg=cfg(readline_7)");
+  println!("cargo:rustc-check-cfg=cfg(readline_8_1)");
+
+  // Functionality only available in more recent versions of
+  // libreadline is made use of conditionally. If we can't tell the
+  // version, we assume a recent one.
+  let version = match readline_version() {
+    Some((path, version)) => {
+      println!("cargo:rerun-if-changed={}", path.display());
+      version
+    },
+    None => (u32::MAX, 0),
+  };
+  if version >= (7, 0) {
+    println!("cargo:rustc-cfg=readline_7");
+  }
+  if version >= (8, 1) {
+    println!("cargo:rustc-cfg=readline_8_1");
+  }
 
   let link_static = var_os("READLINE_STATIC").is_some() || cfg!(feature = "static");
 
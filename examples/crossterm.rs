@@ -0,0 +1,20 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! An example illustrating how to use the ready-made `crossterm` based
+//! frontend for reading lines.
+
+use std::io::Result;
+
+use rline::frontend::crossterm::read_line;
+use rline::Readline;
+
+
+fn main() -> Result<()> {
+  let rl = Readline::new();
+
+  while let Some(line) = read_line(&rl, "> ")? {
+    println!("{}", line.to_string_lossy());
+  }
+  Ok(())
+}
@@ -0,0 +1,48 @@
+/*
+ * Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+/*
+ * The C API of rline, available when building the crate with the
+ * `capi` feature enabled. Refer to the documentation of the `capi`
+ * module for details.
+ */
+
+#ifndef RLINE_H
+#define RLINE_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The key got processed and editing of the line continues. */
+#define RLINE_PENDING 0
+/* A line got completed. */
+#define RLINE_LINE 1
+/* The interrupt character got fed, discarding the line. */
+#define RLINE_INTERRUPTED 2
+/* The end-of-file character got fed on an empty line. */
+#define RLINE_EOF 3
+/* The operation failed, e.g., because of invalid arguments. */
+#define RLINE_ERROR -1
+
+/* An opaque `Readline` instance. */
+typedef struct rline rline;
+
+rline *rline_new(void);
+void rline_free(rline *rl);
+int rline_feed(rline *rl, const uint8_t *key, size_t len, char **line);
+size_t rline_peek(const rline *rl, char *buf, size_t size, size_t *cursor);
+int rline_reset(rline *rl, const char *line, size_t cursor, bool clear_undo);
+void rline_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
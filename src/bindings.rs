@@ -0,0 +1,610 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Enumeration and summarization of key bindings.
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::fmt::Display;
+use std::fmt::Error;
+use std::fmt::Formatter;
+use std::fmt::Write as _;
+use std::io;
+
+use libc::c_char;
+use libc::c_int;
+use libc::c_void;
+use libc::free;
+
+use crate::key::describe;
+use crate::key::escape;
+use crate::keymap;
+use crate::kill::rl_command_func_t;
+
+
+// Declarations as provided by libreadline.
+extern "C" {
+  fn rl_initialize_funmap();
+  fn rl_funmap_names() -> *mut *const c_char;
+  fn rl_named_function(name: *const c_char) -> Option<rl_command_func_t>;
+  fn rl_invoking_keyseqs_in_map(
+    function: rl_command_func_t,
+    map: *mut c_void,
+  ) -> *mut *mut c_char;
+  fn rl_get_keymap() -> *mut c_void;
+  fn rl_set_keymap(map: *mut c_void);
+  fn rl_get_keymap_name(map: *mut c_void) -> *const c_char;
+  fn rl_parse_and_bind(line: *mut c_char) -> c_int;
+  fn rl_translate_keyseq(seq: *const c_char, array: *mut c_char, len: *mut c_int) -> c_int;
+}
+
+
+/// A named command along with the key sequences invoking it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Binding {
+  /// The name of the command.
+  command: CString,
+  /// The key sequences the command is bound to.
+  keys: Vec<CString>,
+  /// The human readable descriptions of `keys`.
+  names: Vec<String>,
+  /// The function implementing the command.
+  func: usize,
+}
+
+impl Binding {
+  /// Retrieve the name of the command, e.g., `beginning-of-line`.
+  pub fn command(&self) -> &CStr {
+    &self.command
+  }
+
+  /// Retrieve the key sequences invoking the command, in the notation
+  /// used by `inputrc` files (e.g., `\C-a` or `\M-f`).
+  pub fn keys(&self) -> &[CString] {
+    &self.keys
+  }
+
+  /// Retrieve the key sequences invoking the command in human readable
+  /// form (e.g., `C-a` or `M-f`), in the same order as
+  /// [`keys`][Binding::keys]. See [`key::describe`][crate::key::describe].
+  pub fn key_names(&self) -> &[String] {
+    &self.names
+  }
+
+  /// Retrieve the group the command belongs to.
+  pub fn group(&self) -> BindingGroup {
+    BindingGroup::of(self.command.to_bytes())
+  }
+}
+
+
+/// Enumerate the named commands bound to at least one key sequence in
+/// the current keymap, ordered by their names.
+///
+/// # Safety
+/// Callers must ensure that the instance is active.
+pub(crate) unsafe fn current() -> Vec<Binding> {
+  let mut bindings = Vec::new();
+  unsafe {
+    rl_initialize_funmap();
+    let names = rl_funmap_names();
+    if names.is_null() {
+      return bindings
+    }
+
+    let keymap = rl_get_keymap();
+    let mut name = names;
+    while !(*name).is_null() {
+      if let Some(func) = rl_named_function(*name) {
+        let seqs = rl_invoking_keyseqs_in_map(func, keymap);
+        if !seqs.is_null() {
+          let mut keys = Vec::new();
+          let mut seq = seqs;
+          while !(*seq).is_null() {
+            keys.push(CStr::from_ptr(*seq).to_owned());
+            free((*seq).cast());
+            seq = seq.add(1);
+          }
+          free(seqs.cast());
+
+          if !keys.is_empty() {
+            let names = keys.iter().map(|key| name_of(key)).collect();
+            bindings.push(Binding {
+              command: CStr::from_ptr(*name).to_owned(),
+              keys,
+              names,
+              func: func as usize,
+            })
+          }
+        }
+      }
+      name = name.add(1);
+    }
+    // The names themselves are owned by libreadline.
+    free(names.cast());
+  }
+  bindings
+}
+
+
+/// Translate a key sequence in the notation used by `inputrc` files
+/// into the form it would be fed.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held.
+unsafe fn translate(seq: &CStr) -> Option<Vec<u8>> {
+  // A translated sequence is never longer than its textual
+  // representation.
+  let mut array = vec![0u8; seq.to_bytes().len() + 1];
+  let mut len = 0;
+  let result = unsafe { rl_translate_keyseq(seq.as_ptr(), array.as_mut_ptr().cast(), &mut len) };
+  if result == 0 && len > 0 {
+    let () = array.truncate(len as usize);
+    Some(array)
+  } else {
+    None
+  }
+}
+
+
+/// Describe a key sequence in the notation used by `inputrc` files in
+/// human readable form, falling back to the sequence itself.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held.
+unsafe fn name_of(seq: &CStr) -> String {
+  match unsafe { translate(seq) } {
+    Some(keys) => describe(&keys),
+    None => seq.to_string_lossy().into_owned(),
+  }
+}
+
+
+/// Find the key sequence invoking the named command in the current
+/// keymap, in the form it would be fed.
+///
+/// If the command is bound to multiple key sequences, the first one
+/// reported by libreadline is used.
+///
+/// # Safety
+/// Callers must ensure that the instance is active.
+pub(crate) unsafe fn invoking_keys(command: &CStr) -> Option<Vec<u8>> {
+  unsafe {
+    let func = rl_named_function(command.as_ptr())?;
+    let seqs = rl_invoking_keyseqs_in_map(func, rl_get_keymap());
+    if seqs.is_null() {
+      return None
+    }
+
+    let mut keys = None;
+    let mut seq = seqs;
+    while !(*seq).is_null() {
+      if keys.is_none() {
+        keys = translate(CStr::from_ptr(*seq));
+      }
+      free((*seq).cast());
+      seq = seq.add(1);
+    }
+    free(seqs.cast());
+    keys
+  }
+}
+
+
+/// Export the bindings of the current keymap, to commands as well as
+/// to macros, in the format used by `inputrc` files.
+///
+/// # Safety
+/// Callers must ensure that the instance is active.
+pub(crate) unsafe fn export() -> String {
+  let mut text = String::new();
+  let map = unsafe { rl_get_keymap() };
+  let name = unsafe { rl_get_keymap_name(map) };
+  if !name.is_null() {
+    let name = unsafe { CStr::from_ptr(name) };
+    let _result = writeln!(text, "set keymap {}", name.to_string_lossy());
+  }
+
+  for binding in unsafe { current() } {
+    for key in &binding.keys {
+      let _result = writeln!(
+        text,
+        "\"{}\": {}",
+        key.to_string_lossy(),
+        binding.command.to_string_lossy()
+      );
+    }
+  }
+
+  for (key, value) in unsafe { keymap::macros(map) } {
+    let () = text.push('"');
+    let () = escape(&key, &mut text);
+    let () = text.push_str("\": \"");
+    let () = escape(&value, &mut text);
+    let () = text.push_str("\"\n");
+  }
+  text
+}
+
+
+/// Import bindings and variable settings in the format used by
+/// `inputrc` files, line by line.
+///
+/// # Safety
+/// Callers must ensure that the instance is active.
+pub(crate) unsafe fn import(text: &[u8]) -> io::Result<()> {
+  // A `set keymap` directive only applies to the remainder of the
+  // text.
+  let map = unsafe { rl_get_keymap() };
+  let mut result = Ok(());
+
+  for (idx, line) in text.split(|b| *b == b'\n').enumerate() {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    if line.iter().all(u8::is_ascii_whitespace) {
+      continue
+    }
+
+    let mut line = match CString::new(line) {
+      Ok(line) => line.into_bytes_with_nul(),
+      Err(err) => {
+        result = Err(io::Error::new(io::ErrorKind::InvalidInput, err));
+        break
+      },
+    };
+    // libreadline modifies the line while parsing it.
+    let rc = unsafe { rl_parse_and_bind(line.as_mut_ptr().cast()) };
+    if rc != 0 && result.is_ok() {
+      result = Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("failed to parse line {}", idx + 1),
+      ));
+    }
+  }
+
+  let () = unsafe { rl_set_keymap(map) };
+  result
+}
+
+
+/// A group of related commands, as used for structuring a
+/// [`CheatSheet`].
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum BindingGroup {
+  /// Commands moving the cursor.
+  Movement,
+  /// Commands inserting, deleting, or otherwise modifying text.
+  Editing,
+  /// Commands killing text and yanking it back.
+  Killing,
+  /// Commands navigating and searching the history.
+  History,
+  /// Commands completing text.
+  Completion,
+  /// Other commands provided by libreadline, e.g., for keyboard macros
+  /// or switching the editing mode.
+  Miscellaneous,
+  /// Commands not provided by libreadline, i.e., ones added by the
+  /// application.
+  Custom,
+}
+
+impl BindingGroup {
+  /// Determine the group of the command with the given name.
+  fn of(command: &[u8]) -> Self {
+    match command {
+      b"backward-byte"
+      | b"backward-char"
+      | b"backward-word"
+      | b"beginning-of-line"
+      | b"character-search"
+      | b"character-search-backward"
+      | b"end-of-line"
+      | b"exchange-point-and-mark"
+      | b"forward-byte"
+      | b"forward-char"
+      | b"forward-word"
+      | b"next-screen-line"
+      | b"previous-screen-line"
+      | b"set-mark"
+      | b"vi-bWord"
+      | b"vi-back-to-indent"
+      | b"vi-backward-bigword"
+      | b"vi-backward-word"
+      | b"vi-bword"
+      | b"vi-char-search"
+      | b"vi-column"
+      | b"vi-eWord"
+      | b"vi-end-bigword"
+      | b"vi-end-word"
+      | b"vi-eword"
+      | b"vi-fWord"
+      | b"vi-first-print"
+      | b"vi-forward-bigword"
+      | b"vi-forward-word"
+      | b"vi-fword"
+      | b"vi-goto-mark"
+      | b"vi-match"
+      | b"vi-next-word"
+      | b"vi-prev-word"
+      | b"vi-set-mark" => Self::Movement,
+      b"accept-line"
+      | b"backward-delete-char"
+      | b"bracketed-paste-begin"
+      | b"capitalize-word"
+      | b"delete-char"
+      | b"delete-horizontal-space"
+      | b"downcase-word"
+      | b"forward-backward-delete-char"
+      | b"insert-comment"
+      | b"overwrite-mode"
+      | b"quoted-insert"
+      | b"revert-line"
+      | b"self-insert"
+      | b"tab-insert"
+      | b"transpose-chars"
+      | b"transpose-words"
+      | b"undo"
+      | b"upcase-word"
+      | b"vi-append-eol"
+      | b"vi-append-mode"
+      | b"vi-change-case"
+      | b"vi-change-char"
+      | b"vi-delete"
+      | b"vi-eof-maybe"
+      | b"vi-insert-beg"
+      | b"vi-overstrike"
+      | b"vi-overstrike-delete"
+      | b"vi-redo"
+      | b"vi-replace"
+      | b"vi-rubout"
+      | b"vi-subst"
+      | b"vi-undo" => Self::Editing,
+      b"backward-kill-line"
+      | b"backward-kill-word"
+      | b"copy-backward-word"
+      | b"copy-forward-word"
+      | b"copy-region-as-kill"
+      | b"kill-line"
+      | b"kill-region"
+      | b"kill-whole-line"
+      | b"kill-word"
+      | b"unix-filename-rubout"
+      | b"unix-line-discard"
+      | b"unix-word-rubout"
+      | b"vi-change-to"
+      | b"vi-delete-to"
+      | b"vi-put"
+      | b"vi-unix-word-rubout"
+      | b"vi-yank-pop"
+      | b"vi-yank-to"
+      | b"yank"
+      | b"yank-pop" => Self::Killing,
+      b"beginning-of-history"
+      | b"end-of-history"
+      | b"fetch-history"
+      | b"forward-search-history"
+      | b"history-search-backward"
+      | b"history-search-forward"
+      | b"history-substring-search-backward"
+      | b"history-substring-search-forward"
+      | b"next-history"
+      | b"non-incremental-forward-search-history"
+      | b"non-incremental-forward-search-history-again"
+      | b"non-incremental-reverse-search-history"
+      | b"non-incremental-reverse-search-history-again"
+      | b"operate-and-get-next"
+      | b"previous-history"
+      | b"reverse-search-history"
+      | b"vi-fetch-history"
+      | b"vi-search"
+      | b"vi-search-again"
+      | b"vi-yank-arg"
+      | b"yank-last-arg"
+      | b"yank-nth-arg" => Self::History,
+      b"complete"
+      | b"delete-char-or-list"
+      | b"export-completions"
+      | b"insert-completions"
+      | b"menu-complete"
+      | b"menu-complete-backward"
+      | b"old-menu-complete"
+      | b"possible-completions"
+      | b"tilde-expand"
+      | b"vi-complete"
+      | b"vi-tilde-expand" => Self::Completion,
+      b"abort"
+      | b"arrow-key-prefix"
+      | b"call-last-kbd-macro"
+      | b"clear-display"
+      | b"clear-screen"
+      | b"digit-argument"
+      | b"do-lowercase-version"
+      | b"dump-functions"
+      | b"dump-macros"
+      | b"dump-variables"
+      | b"emacs-editing-mode"
+      | b"end-kbd-macro"
+      | b"execute-named-command"
+      | b"print-last-kbd-macro"
+      | b"re-read-init-file"
+      | b"redraw-current-line"
+      | b"skip-csi-sequence"
+      | b"start-kbd-macro"
+      | b"tty-status"
+      | b"universal-argument"
+      | b"vi-arg-digit"
+      | b"vi-editing-mode"
+      | b"vi-insertion-mode"
+      | b"vi-movement-mode" => Self::Miscellaneous,
+      _ => Self::Custom,
+    }
+  }
+
+  /// Retrieve a human-readable title of the group.
+  pub fn title(&self) -> &'static str {
+    match self {
+      Self::Movement => "Movement",
+      Self::Editing => "Editing",
+      Self::Killing => "Killing and yanking",
+      Self::History => "History",
+      Self::Completion => "Completion",
+      Self::Miscellaneous => "Miscellaneous",
+      Self::Custom => "Custom commands",
+    }
+  }
+}
+
+
+/// A grouped, human-readable summary of the key bindings in effect.
+///
+/// The summary reflects the actual bindings, including those made by
+/// the user's `inputrc`, rather than libreadline's defaults. Commands
+/// known under multiple names (e.g., `vi-bWord` and
+/// `vi-backward-bigword`) are listed only once, using their longest
+/// name. `self-insert` and `do-lowercase-version`, which are bound to
+/// most keys, are left out.
+///
+/// The `Display` implementation renders the summary as plain text,
+/// suitable for a help screen:
+/// ```text
+/// Movement
+///   backward-char      C-b, Left
+///   beginning-of-line  C-a, Home
+/// ...
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CheatSheet {
+  /// The bindings, grouped and ordered by command name.
+  groups: BTreeMap<BindingGroup, Vec<Binding>>,
+}
+
+impl CheatSheet {
+  /// Create a `CheatSheet` from the given bindings.
+  pub(crate) fn new(bindings: Vec<Binding>) -> Self {
+    let mut bindings = bindings
+      .into_iter()
+      .filter(|binding| {
+        !matches!(binding.command.to_bytes(), b"self-insert" | b"do-lowercase-version")
+      })
+      .collect::<Vec<_>>();
+
+    // Of aliases, keep the longest (and thus presumably most
+    // descriptive) name.
+    let () = bindings.sort_by_key(|binding| {
+      (binding.func, Reverse(binding.command.to_bytes().len()))
+    });
+    let () = bindings.dedup_by_key(|binding| binding.func);
+    let () = bindings.sort_by(|x, y| x.command.cmp(&y.command));
+
+    let mut groups = BTreeMap::<_, Vec<_>>::new();
+    for binding in bindings {
+      let () = groups.entry(binding.group()).or_default().push(binding);
+    }
+    Self { groups }
+  }
+
+  /// Retrieve the groups of bindings, each with its bindings ordered by
+  /// command name. Empty groups are omitted.
+  pub fn groups(&self) -> impl Iterator<Item = (BindingGroup, &[Binding])> {
+    self
+      .groups
+      .iter()
+      .map(|(group, bindings)| (*group, bindings.as_slice()))
+  }
+}
+
+impl Display for CheatSheet {
+  fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+    let width = self
+      .groups
+      .values()
+      .flatten()
+      .map(|binding| binding.command.to_bytes().len())
+      .max()
+      .unwrap_or(0);
+
+    for (idx, (group, bindings)) in self.groups().enumerate() {
+      if idx > 0 {
+        let () = writeln!(f)?;
+      }
+      let () = writeln!(f, "{}", group.title())?;
+
+      for binding in bindings {
+        let keys = binding.names.join(", ");
+        let command = binding.command.to_string_lossy();
+        let () = writeln!(f, "  {:width$}  {}", command, keys, width = width)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::Readline;
+
+
+  /// Create a `Binding` object.
+  fn binding(command: &str, keys: &[&str], func: usize) -> Binding {
+    let keys = keys
+      .iter()
+      .map(|key| CString::new(*key).unwrap())
+      .collect::<Vec<_>>();
+    let names = {
+      let _guard = Readline::mutex().lock().unwrap();
+      keys.iter().map(|key| unsafe { name_of(key) }).collect()
+    };
+
+    Binding {
+      command: CString::new(command).unwrap(),
+      keys,
+      names,
+      func,
+    }
+  }
+
+  /// Check that a cheat sheet is grouped and formatted as expected.
+  #[test]
+  fn cheat_sheet() {
+    let bindings = vec![
+      binding("beginning-of-line", &["\\C-a"], 1),
+      binding("backward-char", &["\\C-b", "\\eOD"], 2),
+      binding("self-insert", &["a", "b"], 3),
+      binding("vi-bWord", &["B"], 4),
+      binding("vi-backward-bigword", &["B"], 4),
+      binding("yank", &["\\C-y"], 5),
+      binding("my-command", &["\\C-xm"], 6),
+    ];
+    let sheet = CheatSheet::new(bindings);
+    let groups = sheet
+      .groups()
+      .map(|(group, bindings)| (group, bindings.len()))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      groups,
+      vec![
+        (BindingGroup::Movement, 3),
+        (BindingGroup::Killing, 1),
+        (BindingGroup::Custom, 1),
+      ]
+    );
+
+    let expected = r"Movement
+  backward-char        C-b, Left
+  beginning-of-line    C-a
+  vi-backward-bigword  B
+
+Killing and yanking
+  yank                 C-y
+
+Custom commands
+  my-command           C-x m
+";
+    assert_eq!(sheet.to_string(), expected);
+  }
+}
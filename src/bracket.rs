@@ -0,0 +1,87 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Matching of brackets and quotes in a line.
+
+
+/// Retrieve the closing counterpart of the given opening bracket.
+fn closer(opener: u8) -> Option<u8> {
+  match opener {
+    b'(' => Some(b')'),
+    b'[' => Some(b']'),
+    b'{' => Some(b'}'),
+    _ => None,
+  }
+}
+
+
+/// Find the position of the bracket or quote matching the one at
+/// `position` in `line`.
+///
+/// Similar to libreadline's handling of `blink-matching-paren`, text
+/// enclosed in any of the given quote characters is skipped when
+/// looking for brackets, and backslashes carry no special meaning.
+pub(crate) fn find(line: &[u8], position: usize, quotes: &[u8]) -> Option<usize> {
+  let mut brackets = Vec::new();
+  let mut quote = None;
+
+  for (i, &c) in line.iter().enumerate() {
+    if let Some((start, delimiter)) = quote {
+      if c == delimiter {
+        if start == position {
+          return Some(i)
+        } else if i == position {
+          return Some(start)
+        }
+        quote = None;
+      }
+    } else if quotes.contains(&c) {
+      quote = Some((i, c));
+    } else if closer(c).is_some() {
+      brackets.push((i, c));
+    } else if matches!(c, b')' | b']' | b'}') {
+      match brackets.pop() {
+        Some((start, opener)) if closer(opener) == Some(c) => {
+          if start == position {
+            return Some(i)
+          } else if i == position {
+            return Some(start)
+          }
+        },
+        // Mismatched brackets invalidate all enclosing ones.
+        _ => brackets.clear(),
+      }
+    }
+  }
+  None
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+
+  /// Check that we find matching brackets and quotes.
+  #[test]
+  fn matching() {
+    let quotes = b"\"'";
+    let line = b"f(a[1], {b}) \"(x\" 'y'";
+    assert_eq!(find(line, 1, quotes), Some(11));
+    assert_eq!(find(line, 11, quotes), Some(1));
+    assert_eq!(find(line, 3, quotes), Some(5));
+    assert_eq!(find(line, 8, quotes), Some(10));
+    assert_eq!(find(line, 13, quotes), Some(16));
+    assert_eq!(find(line, 16, quotes), Some(13));
+    assert_eq!(find(line, 18, quotes), Some(20));
+    // Brackets inside quotes are ignored.
+    assert_eq!(find(line, 14, quotes), None);
+    assert_eq!(find(line, 0, quotes), None);
+    assert_eq!(find(line, 100, quotes), None);
+
+    assert_eq!(find(b"(]", 0, quotes), None);
+    assert_eq!(find(b"(\"", 0, quotes), None);
+    assert_eq!(find(b"(\")\")", 0, quotes), Some(4));
+    assert_eq!(find(b"(\")\")", 0, b""), Some(2));
+  }
+}
@@ -0,0 +1,420 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Construction-time configuration of `Readline` instances.
+
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Result;
+use std::os::unix::ffi::OsStrExt as _;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::EditMode;
+use crate::Limit;
+#[cfg(feature = "zeroize")]
+use crate::Mask;
+use crate::Readline;
+use crate::INPUT_CAPACITY;
+
+
+/// Convert a path into a C string.
+fn path_to_cstring(path: &Path) -> Result<CString> {
+  CString::new(path.as_os_str().as_bytes()).map_err(|err| Error::new(ErrorKind::InvalidInput, err))
+}
+
+
+/// A builder for [`Readline`] instances, configuring them before they
+/// are first used.
+///
+/// Settings not explicitly configured are left at the defaults of a
+/// [`Readline::new`] instance.
+#[derive(Clone, Debug, Default)]
+pub struct ReadlineBuilder {
+  /// The editing mode to start out in.
+  mode: Option<EditMode>,
+  /// The application name to use for conditionals in the init file.
+  name: Option<CString>,
+  /// The init file to read.
+  init_file: Option<PathBuf>,
+  /// The name of the terminal the line is displayed on.
+  terminal: Option<CString>,
+  /// Whether to isolate the instance from all others.
+  isolated: bool,
+  /// The initial line and cursor position.
+  line: Option<(CString, usize)>,
+  /// The limit to apply to the history.
+  history_limit: Limit,
+  /// The maximum number of undo records to keep, if limited.
+  undo_limit: Option<usize>,
+  /// The file to read the history from.
+  history_file: Option<PathBuf>,
+  /// Whether to synchronize the history with the history file.
+  history_sync: bool,
+  /// The prompt to use.
+  prompt: Option<CString>,
+  /// Whether to force 8-bit clean input handling.
+  eight_bit: bool,
+  /// Whether the instance handles secret input.
+  #[cfg(feature = "zeroize")]
+  secret: bool,
+  /// The label identifying the instance.
+  label: Option<String>,
+  /// The input to process as part of the first feed.
+  input: Vec<u8>,
+}
+
+impl ReadlineBuilder {
+  /// Create a new `ReadlineBuilder` with default settings.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Set the editing mode to start out in.
+  pub fn edit_mode(mut self, mode: EditMode) -> Self {
+    self.mode = Some(mode);
+    self
+  }
+
+  /// Set the application name to use for `$if` conditionals while
+  /// reading the init file configured via
+  /// [`init_file`][ReadlineBuilder::init_file].
+  pub fn name<S>(mut self, name: S) -> Self
+  where
+    S: AsRef<CStr>,
+  {
+    self.name = Some(name.as_ref().to_owned());
+    self
+  }
+
+  /// Set an init file (in `inputrc` format) to read in addition to the
+  /// one libreadline read when it got initialized.
+  ///
+  /// Key bindings made by the file affect all instances that are not
+  /// isolated, unless [`isolated`][ReadlineBuilder::isolated] is set.
+  pub fn init_file<P>(mut self, path: P) -> Self
+  where
+    P: Into<PathBuf>,
+  {
+    self.init_file = Some(path.into());
+    self
+  }
+
+  /// Set the name of the terminal the line is displayed on, as used for
+  /// `$if term=...` conditionals in the init file. See
+  /// [`Readline::set_terminal_name`].
+  pub fn terminal_name<S>(mut self, name: S) -> Self
+  where
+    S: AsRef<CStr>,
+  {
+    self.terminal = Some(name.as_ref().to_owned());
+    self
+  }
+
+  /// Set whether to isolate the instance from all others. See
+  /// [`Readline::set_isolated`].
+  pub fn isolated(mut self, isolated: bool) -> Self {
+    self.isolated = isolated;
+    self
+  }
+
+  /// Set the initial line and cursor position.
+  ///
+  /// The cursor position is checked when the instance is built.
+  pub fn line<S>(mut self, line: S, cursor: usize) -> Self
+  where
+    S: AsRef<CStr>,
+  {
+    self.line = Some((line.as_ref().to_owned(), cursor));
+    self
+  }
+
+  /// Set the limit to apply to the history.
+  pub fn history_limit(mut self, limit: Limit) -> Self {
+    self.history_limit = limit;
+    self
+  }
+
+  /// Limit the number of undo records kept for the line (see
+  /// [`Readline::set_undo_limit`]).
+  pub fn undo_limit(mut self, limit: usize) -> Self {
+    self.undo_limit = Some(limit);
+    self
+  }
+
+  /// Set a file to read the initial history from. A file that does not
+  /// exist is treated as an empty history.
+  pub fn history_file<P>(mut self, path: P) -> Self
+  where
+    P: Into<PathBuf>,
+  {
+    self.history_file = Some(path.into());
+    self
+  }
+
+  /// Set whether to keep the history synchronized with the file set via
+  /// [`history_file`][ReadlineBuilder::history_file]. See
+  /// [`Readline::set_history_sync`].
+  pub fn history_sync(mut self, sync: bool) -> Self {
+    self.history_sync = sync;
+    self
+  }
+
+  /// Set the prompt to use.
+  pub fn prompt<S>(mut self, prompt: S) -> Self
+  where
+    S: AsRef<CStr>,
+  {
+    self.prompt = Some(prompt.as_ref().to_owned());
+    self
+  }
+
+  /// Set whether the instance is used for secret input, such as
+  /// passwords.
+  ///
+  /// Secret input is hidden entirely (see [`Mask::Hidden`]) and wiped
+  /// from memory (see [`Readline::set_sensitive`]).
+  #[cfg(feature = "zeroize")]
+  pub fn secret(mut self, secret: bool) -> Self {
+    self.secret = secret;
+    self
+  }
+
+  /// Set whether to force 8-bit clean input handling. See
+  /// [`Readline::force_eight_bit`].
+  ///
+  /// Note that the setting affects all instances.
+  pub fn eight_bit(mut self, eight_bit: bool) -> Self {
+    self.eight_bit = eight_bit;
+    self
+  }
+
+  /// Set a label identifying the instance in its `Debug` output. See
+  /// [`Readline::set_label`].
+  pub fn label<S>(mut self, label: S) -> Self
+  where
+    S: Into<String>,
+  {
+    self.label = Some(label.into());
+    self
+  }
+
+  /// Set input to process before the first key fed, e.g., for
+  /// pre-typing a command for the user to edit.
+  ///
+  /// The input is processed just like fed keys are, meaning that it
+  /// may comprise arbitrary key sequences, including escape sequences
+  /// for moving the cursor. It is left pending (see
+  /// [`Readline::pending_input`]) until the first
+  /// [`feed`][Readline::feed], which processes it ahead of the fed key
+  /// and reports a line completed by either.
+  pub fn input<B>(mut self, input: B) -> Self
+  where
+    B: Into<Vec<u8>>,
+  {
+    self.input = input.into();
+    self
+  }
+
+  /// Create the configured `Readline` instance.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the init file or the history file could not be read or
+  /// if the input set via [`input`][ReadlineBuilder::input] exceeds the
+  /// capacity of libreadline's input buffer (see
+  /// [`Readline::input_capacity`]).
+  ///
+  /// # Panics
+  ///
+  /// Panics if the initial cursor position is not within the initial
+  /// line.
+  pub fn build(self) -> Result<Readline> {
+    if self.input.len() > INPUT_CAPACITY {
+      return Err(Error::new(
+        ErrorKind::InvalidInput,
+        "initial input exceeds input buffer capacity",
+      ))
+    }
+
+    if self.eight_bit {
+      let () = Readline::force_eight_bit();
+    }
+
+    let rl = match &self.line {
+      Some((line, cursor)) => Readline::with_line(line, *cursor),
+      None => Readline::new(),
+    };
+
+    if self.isolated {
+      rl.set_isolated(true);
+    }
+
+    if let Some(label) = self.label {
+      rl.set_label(Some(label));
+    }
+
+    if let Some(terminal) = &self.terminal {
+      rl.set_terminal_name(Some(terminal));
+    }
+
+    if let Some(path) = &self.init_file {
+      let path = path_to_cstring(path)?;
+      let () = rl.read_init_file(self.name.as_deref(), &path)?;
+    }
+
+    if let Some(mode) = self.mode {
+      rl.set_edit_mode(mode);
+    }
+
+    rl.set_history_limit(self.history_limit);
+    if let Some(limit) = self.undo_limit {
+      rl.set_undo_limit(Some(limit));
+    }
+
+    if let Some(path) = &self.history_file {
+      if self.history_sync {
+        let () = rl.set_history_sync(Some(path))?;
+      } else {
+        let path = path_to_cstring(path)?;
+        match rl.read_history(&path) {
+          Ok(()) => (),
+          Err(err) if err.kind() == ErrorKind::NotFound => (),
+          Err(err) => return Err(err),
+        }
+      }
+    }
+
+    if let Some(prompt) = &self.prompt {
+      rl.set_prompt(prompt);
+    }
+
+    #[cfg(feature = "zeroize")]
+    {
+      if self.secret {
+        rl.set_mask(Some(Mask::Hidden));
+        rl.set_sensitive(true);
+      }
+    }
+
+    // Pending input is handed to libreadline on activation and
+    // processed as part of the next feed.
+    rl.state.borrow_mut().pending = self.input;
+    Ok(rl)
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::env::temp_dir;
+  use std::fs::remove_file;
+  use std::fs::write;
+  use std::process;
+
+  use crate::Usage;
+
+
+  /// Check that a `Readline` instance is configured as requested.
+  #[test]
+  fn build() {
+    let history = temp_dir().join(format!("rline-history-{}", process::id()));
+    let () = write(&history, "first\nsecond\nthird\n").unwrap();
+
+    let rl = ReadlineBuilder::new()
+      .edit_mode(EditMode::ViInsert)
+      .line(CString::new("abc").unwrap(), 1)
+      .history_limit(Limit {
+        entries: Some(2),
+        bytes: None,
+      })
+      .undo_limit(16)
+      .history_file(&history)
+      .prompt(CString::new("> ").unwrap())
+      .label("search-bar")
+      .build()
+      .unwrap();
+    let () = remove_file(&history).unwrap();
+
+    assert_eq!(rl.edit_mode(), EditMode::ViInsert);
+    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("abc").unwrap(), 1));
+    assert_eq!(rl.history_usage(), Usage { entries: 2, bytes: 11 });
+    assert_eq!(rl.undo_limit(), Some(16));
+    assert_eq!(rl.prompt(), CString::new("> ").unwrap());
+    assert_eq!(rl.label().as_deref(), Some("search-bar"));
+  }
+
+  /// Check that init files are honored.
+  #[test]
+  fn init_file() {
+    let inputrc = temp_dir().join(format!("rline-inputrc-{}", process::id()));
+    let text = concat!(
+      "$if rline-test\nset editing-mode vi\n$endif\n",
+      "$if term=rline-term\n\"Q\": beginning-of-line\n$endif\n",
+    );
+    let () = write(&inputrc, text).unwrap();
+
+    let build = |terminal: &str| {
+      ReadlineBuilder::new()
+        .name(CString::new("rline-test").unwrap())
+        .terminal_name(CString::new(terminal).unwrap())
+        .init_file(&inputrc)
+        .isolated(true)
+        .build()
+        .unwrap()
+    };
+    let rl1 = build("rline-term");
+    let rl2 = build("other-term");
+    let () = remove_file(&inputrc).unwrap();
+
+    assert_eq!(rl1.edit_mode(), EditMode::ViInsert);
+    assert_eq!(Readline::new().edit_mode(), EditMode::Emacs);
+    assert_eq!(rl1.terminal_name(), Some(CString::new("rline-term").unwrap()));
+
+    for rl in [&rl1, &rl2].iter() {
+      assert_eq!(rl.feed(b"abQ"), None);
+    }
+    assert_eq!(rl1.peek(|s, p| (s.to_owned(), p)), (CString::new("ab").unwrap(), 0));
+    assert_eq!(rl2.peek(|s, p| (s.to_owned(), p)), (CString::new("abQ").unwrap(), 3));
+  }
+
+  /// Check that initial input is processed as part of the first feed.
+  #[test]
+  fn input() {
+    let rl = ReadlineBuilder::new()
+      .input(&b"git commit -m ''\x1b[D"[..])
+      .build()
+      .unwrap();
+    assert_eq!(rl.pending_input(), 19);
+    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::default(), 0));
+
+    assert_eq!(rl.feed(b"x"), None);
+    let line = CString::new("git commit -m 'x'").unwrap();
+    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (line.clone(), 16));
+    assert_eq!(rl.feed(b"\r"), Some(line));
+
+    let rl = ReadlineBuilder::new().input(&b"abc\r"[..]).build().unwrap();
+    assert_eq!(rl.feed(b"d"), Some(CString::new("abc").unwrap()));
+    assert_eq!(rl.feed(b"\r"), Some(CString::new("d").unwrap()));
+
+    let err = ReadlineBuilder::new()
+      .input(vec![b'a'; INPUT_CAPACITY + 1])
+      .build()
+      .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+  }
+
+  /// Check that a missing history file is not treated as an error.
+  #[test]
+  fn missing_history_file() {
+    let rl = ReadlineBuilder::new()
+      .history_file("/this/path/does/not/exist")
+      .build()
+      .unwrap();
+    assert_eq!(rl.history_usage(), Usage::default());
+  }
+}
@@ -0,0 +1,253 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! A C API for using [`Readline`] instances from other languages.
+//!
+//! The shared library can be built via
+//! ```sh
+//! cargo rustc --release --lib --features capi --crate-type cdylib
+//! ```
+//! (which requires Cargo 1.64 or newer) and the corresponding
+//! declarations are provided by `include/rline.h`.
+//!
+//! Instances are opaque and owned by the caller, who has to release
+//! them via [`rline_free`]. An instance must not be used from multiple
+//! threads concurrently, but may be moved between threads. Panics are
+//! not propagated across the API boundary, but reported as failures.
+
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::os::raw::c_int;
+use std::panic::catch_unwind;
+use std::panic::AssertUnwindSafe;
+use std::ptr::copy_nonoverlapping;
+use std::ptr::null_mut;
+use std::slice;
+
+use crate::Feed;
+use crate::Readline;
+
+
+/// The key got processed and editing of the line continues.
+pub const RLINE_PENDING: c_int = 0;
+/// A line got completed.
+pub const RLINE_LINE: c_int = 1;
+/// The interrupt character got fed, discarding the line.
+pub const RLINE_INTERRUPTED: c_int = 2;
+/// The end-of-file character got fed on an empty line.
+pub const RLINE_EOF: c_int = 3;
+/// The operation failed, e.g., because of invalid arguments.
+pub const RLINE_ERROR: c_int = -1;
+
+
+/// Run the given function, reporting `error` should it panic.
+fn guarded<F, R>(error: R, f: F) -> R
+where
+  F: FnOnce() -> R,
+{
+  catch_unwind(AssertUnwindSafe(f)).unwrap_or(error)
+}
+
+
+/// Create a new `Readline` instance.
+///
+/// `NULL` is returned on failure.
+#[no_mangle]
+pub extern "C" fn rline_new() -> *mut Readline {
+  guarded(null_mut(), || Box::into_raw(Box::new(Readline::new())))
+}
+
+
+/// Destroy a `Readline` instance created by [`rline_new`].
+///
+/// # Safety
+/// `rl` has to be `NULL` or a pointer returned by [`rline_new`] that has
+/// not been destroyed yet.
+#[no_mangle]
+pub unsafe extern "C" fn rline_free(rl: *mut Readline) {
+  if !rl.is_null() {
+    let () = guarded((), || drop(unsafe { Box::from_raw(rl) }));
+  }
+}
+
+
+/// Feed a key, i.e., `len` bytes starting at `key`, to a `Readline`
+/// instance.
+///
+/// One of the `RLINE_*` constants is returned. In the case of
+/// [`RLINE_LINE`], the completed line is stored in `line`, unless it is
+/// `NULL`, and has to be released via [`rline_string_free`].
+///
+/// # Safety
+/// `rl` has to be a valid instance and `key` has to point to `len`
+/// readable bytes. `line` has to be `NULL` or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn rline_feed(
+  rl: *mut Readline,
+  key: *const u8,
+  len: usize,
+  line: *mut *mut c_char,
+) -> c_int {
+  if rl.is_null() || (key.is_null() && len != 0) {
+    return RLINE_ERROR
+  }
+
+  let rl = unsafe { &*rl };
+  let key = if len == 0 {
+    &[][..]
+  } else {
+    unsafe { slice::from_raw_parts(key, len) }
+  };
+
+  guarded(RLINE_ERROR, || match rl.feed_key(key) {
+    Feed::Pending => RLINE_PENDING,
+    Feed::Line(completed) => {
+      if !line.is_null() {
+        unsafe { *line = completed.into_raw() };
+      }
+      RLINE_LINE
+    },
+    Feed::Interrupted => RLINE_INTERRUPTED,
+    Feed::Eof => RLINE_EOF,
+  })
+}
+
+
+/// Retrieve the line currently being edited along with the cursor
+/// position.
+///
+/// As much of the line as fits is copied into the `size` bytes starting
+/// at `buf`, always NUL terminated unless `size` is zero. The cursor
+/// position, a byte offset into the line, is stored in `cursor`, unless
+/// it is `NULL`. Returned is the length of the line, excluding the NUL
+/// terminator, which can be used for detecting truncation (just like
+/// with `snprintf`).
+///
+/// # Safety
+/// `rl` has to be a valid instance and `buf` has to point to `size`
+/// writable bytes. `cursor` has to be `NULL` or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn rline_peek(
+  rl: *const Readline,
+  buf: *mut c_char,
+  size: usize,
+  cursor: *mut usize,
+) -> usize {
+  if rl.is_null() || (buf.is_null() && size != 0) {
+    return 0
+  }
+
+  let rl = unsafe { &*rl };
+  guarded(0, || {
+    rl.peek(|line, position| {
+      let line = line.to_bytes();
+      if size != 0 {
+        let count = line.len().min(size - 1);
+        unsafe {
+          copy_nonoverlapping(line.as_ptr().cast(), buf, count);
+          *buf.add(count) = 0;
+        }
+      }
+      if !cursor.is_null() {
+        unsafe { *cursor = position };
+      }
+      line.len()
+    })
+  })
+}
+
+
+/// Reset the line being edited to the NUL terminated `line`, with the
+/// cursor at byte offset `cursor`, optionally clearing the undo list.
+///
+/// Returns zero on success and [`RLINE_ERROR`] if the cursor position
+/// is out of bounds.
+///
+/// # Safety
+/// `rl` has to be a valid instance and `line` has to point to a NUL
+/// terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rline_reset(
+  rl: *mut Readline,
+  line: *const c_char,
+  cursor: usize,
+  clear_undo: bool,
+) -> c_int {
+  if rl.is_null() || line.is_null() {
+    return RLINE_ERROR
+  }
+
+  let rl = unsafe { &*rl };
+  let line = unsafe { CStr::from_ptr(line) };
+  if cursor > line.to_bytes().len() {
+    return RLINE_ERROR
+  }
+
+  guarded(RLINE_ERROR, || {
+    let () = rl.reset(line, cursor, clear_undo);
+    0
+  })
+}
+
+
+/// Release a string returned by one of the functions of this API.
+///
+/// # Safety
+/// `s` has to be `NULL` or a string returned by this API that has not
+/// been released yet.
+#[no_mangle]
+pub unsafe extern "C" fn rline_string_free(s: *mut c_char) {
+  if !s.is_null() {
+    drop(unsafe { CString::from_raw(s) })
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::ptr::null;
+
+
+  /// Check that the API can be used for editing lines.
+  #[test]
+  fn edit_line() {
+    let rl = rline_new();
+    assert!(!rl.is_null());
+
+    let mut line = null_mut();
+    unsafe {
+      assert_eq!(rline_feed(rl, b"abc".as_ptr(), 3, &mut line), RLINE_PENDING);
+      assert!(line.is_null());
+
+      let mut buf: [c_char; 8] = [1; 8];
+      let mut cursor = 0;
+      assert_eq!(rline_peek(rl, buf.as_mut_ptr(), buf.len(), &mut cursor), 3);
+      assert_eq!(CStr::from_ptr(buf.as_ptr()).to_bytes(), b"abc");
+      assert_eq!(cursor, 3);
+
+      // The line gets truncated to fit the buffer.
+      assert_eq!(rline_peek(rl, buf.as_mut_ptr(), 3, null_mut()), 3);
+      assert_eq!(CStr::from_ptr(buf.as_ptr()).to_bytes(), b"ab");
+      assert_eq!(rline_peek(rl, null_mut(), 0, null_mut()), 3);
+
+      let text = CString::new("xyz").unwrap();
+      assert_eq!(rline_reset(rl, text.as_ptr(), 4, false), RLINE_ERROR);
+      assert_eq!(rline_reset(rl, text.as_ptr(), 1, true), 0);
+      assert_eq!(rline_feed(rl, b"-".as_ptr(), 1, null_mut()), RLINE_PENDING);
+
+      assert_eq!(rline_feed(rl, b"\n".as_ptr(), 1, &mut line), RLINE_LINE);
+      assert_eq!(CStr::from_ptr(line).to_bytes(), b"x-yz");
+      let () = rline_string_free(line);
+
+      assert_eq!(rline_feed(rl, b"\x04".as_ptr(), 1, null_mut()), RLINE_EOF);
+      assert_eq!(rline_feed(rl, null(), 0, null_mut()), RLINE_PENDING);
+      assert_eq!(rline_feed(null_mut(), null(), 0, null_mut()), RLINE_ERROR);
+
+      let () = rline_free(rl);
+      let () = rline_free(null_mut());
+    }
+  }
+}
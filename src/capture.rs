@@ -0,0 +1,128 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Capturing of output libreadline writes to its output stream.
+
+use std::ptr::null_mut;
+use std::slice;
+
+use libc::c_char;
+use libc::fclose;
+use libc::fflush;
+use libc::free;
+use libc::open_memstream;
+use libc::size_t;
+use libc::FILE;
+
+
+// Declarations as provided by libreadline.
+extern "C" {
+  static mut rl_outstream: *mut FILE;
+
+  // Not part of the public header, but exported nonetheless.
+  static mut _rl_out_stream: *mut FILE;
+}
+
+
+/// A capture of the output libreadline writes to its output stream.
+///
+/// While a capture is in progress, libreadline's output stream refers
+/// to an in-memory buffer instead of the terminal.
+pub(crate) struct Capture {
+  /// The in-memory stream output is redirected to.
+  stream: *mut FILE,
+  /// The buffer backing `stream`, as managed by the C library.
+  buffer: Box<*mut c_char>,
+  /// The size of the data in `buffer`.
+  size: Box<size_t>,
+  /// The value of `rl_outstream` before the capture started.
+  outstream: *mut FILE,
+  /// The value of `_rl_out_stream` before the capture started.
+  out_stream: *mut FILE,
+}
+
+impl Capture {
+  /// Start capturing libreadline's output.
+  ///
+  /// `None` is returned if no in-memory stream could be created, in
+  /// which case output is not redirected.
+  ///
+  /// # Safety
+  /// Callers must ensure that the global mutex is held.
+  pub(crate) unsafe fn start() -> Option<Self> {
+    // The C library keeps pointers to both the buffer pointer and the
+    // size, so they need stable addresses.
+    let mut buffer = Box::new(null_mut());
+    let mut size = Box::new(0);
+    let stream = unsafe { open_memstream(&mut *buffer, &mut *size) };
+    if stream.is_null() {
+      return None
+    }
+
+    let capture = unsafe {
+      let capture = Self {
+        stream,
+        buffer,
+        size,
+        outstream: rl_outstream,
+        out_stream: _rl_out_stream,
+      };
+      rl_outstream = stream;
+      _rl_out_stream = stream;
+      capture
+    };
+    Some(capture)
+  }
+
+  /// Stop capturing, restoring libreadline's output stream, and
+  /// retrieve the captured output.
+  ///
+  /// # Safety
+  /// Callers must ensure that the global mutex is held.
+  pub(crate) unsafe fn finish(self) -> Vec<u8> {
+    unsafe {
+      rl_outstream = self.outstream;
+      _rl_out_stream = self.out_stream;
+
+      let _result = fflush(self.stream);
+      let output = if self.buffer.is_null() {
+        Vec::new()
+      } else {
+        slice::from_raw_parts((*self.buffer).cast::<u8>(), *self.size).to_vec()
+      };
+
+      // Closing the stream updates the buffer pointer one last time,
+      // so only free it afterwards.
+      let _result = fclose(self.stream);
+      free((*self.buffer).cast());
+      output
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use libc::fputs;
+
+  use crate::Readline;
+
+
+  /// Check that output written to libreadline's output stream is
+  /// captured.
+  #[test]
+  fn capture() {
+    let rl = Readline::new();
+    let _guard = rl.activate();
+
+    let capture = unsafe { Capture::start() }.unwrap();
+    let _result = unsafe { fputs(b"foo\0".as_ptr().cast(), rl_outstream) };
+    let _result = unsafe { fputs(b"bar\0".as_ptr().cast(), _rl_out_stream) };
+    assert_eq!(unsafe { capture.finish() }, b"foobar");
+
+    let capture = unsafe { Capture::start() }.unwrap();
+    assert_eq!(unsafe { capture.finish() }, b"");
+  }
+}
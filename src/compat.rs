@@ -0,0 +1,1021 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! A facade mimicking the commonly used parts of `rustyline`'s API, to
+//! ease migration of existing code to this crate.
+//!
+//! ```rust,no_run
+//! use rline::compat::DefaultEditor;
+//! use rline::compat::ReadlineError;
+//!
+//! let mut editor = DefaultEditor::new().unwrap();
+//! loop {
+//!   match editor.readline(">> ") {
+//!     Ok(line) => {
+//!       let _added = editor.add_history_entry(line.as_str()).unwrap();
+//!       println!("Line: {}", line);
+//!     },
+//!     Err(ReadlineError::Interrupted) => continue,
+//!     Err(ReadlineError::Eof) => break,
+//!     Err(err) => panic!("{}", err),
+//!   }
+//! }
+//! ```
+
+mod filename;
+
+use std::cell::Cell;
+use std::error::Error as StdError;
+use std::ffi::CString;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fmt::Result as FmtResult;
+use std::io;
+use std::io::stdin;
+use std::io::stdout;
+use std::io::BufRead as _;
+use std::io::ErrorKind;
+use std::io::Write;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use termion::raw::IntoRawMode as _;
+
+use crate::frontend::redraw;
+use crate::frontend::termion::columns;
+use crate::render::Renderer;
+use crate::Feed;
+use crate::Readline;
+
+pub use filename::Entry;
+pub use filename::EntryProvider;
+pub use filename::FilenameCompleter;
+pub use filename::LocalFs;
+
+
+/// ASCII end-of-text indicator, as sent by Ctrl-C.
+const ETX: u8 = 0x03;
+/// ASCII horizontal tab, as sent by the tab key.
+const TAB: u8 = b'\t';
+
+
+/// The error type used by this module.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ReadlineError {
+  /// An I/O error occurred.
+  Io(io::Error),
+  /// The user signaled the end of input by pressing Ctrl-D on an empty
+  /// line or the input reached end-of-file.
+  Eof,
+  /// The user pressed Ctrl-C.
+  Interrupted,
+}
+
+impl Display for ReadlineError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+    match self {
+      Self::Io(err) => Display::fmt(err, f),
+      Self::Eof => f.write_str("EOF"),
+      Self::Interrupted => f.write_str("Interrupted"),
+    }
+  }
+}
+
+impl StdError for ReadlineError {
+  fn source(&self) -> Option<&(dyn StdError + 'static)> {
+    match self {
+      Self::Io(err) => Some(err),
+      Self::Eof | Self::Interrupted => None,
+    }
+  }
+}
+
+impl From<io::Error> for ReadlineError {
+  fn from(err: io::Error) -> Self {
+    Self::Io(err)
+  }
+}
+
+
+/// The result type used by this module.
+pub type Result<T> = std::result::Result<T, ReadlineError>;
+
+
+/// The type of entity a completion candidate refers to.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum CandidateKind {
+  /// A file (or, generally, a file system entry other than a
+  /// directory).
+  File,
+  /// A directory.
+  Directory,
+  /// A command line option, e.g., `--verbose`.
+  Option,
+  /// An entry of the history.
+  History,
+  /// Anything else.
+  Other,
+}
+
+
+/// A completion candidate.
+///
+/// Besides the texts to display and insert, a candidate may carry
+/// metadata for frontends rendering rich completion menus.
+pub trait Candidate {
+  /// The text to display when listing the candidate.
+  fn display(&self) -> &str;
+  /// The text to insert when completing the candidate.
+  fn replacement(&self) -> &str;
+
+  /// The type of entity the candidate refers to, if known.
+  fn kind(&self) -> Option<CandidateKind> {
+    None
+  }
+
+  /// An application defined identifier of the style to display the
+  /// candidate in (e.g., a color), if any.
+  fn style(&self) -> Option<u32> {
+    None
+  }
+}
+
+impl Candidate for String {
+  fn display(&self) -> &str {
+    self
+  }
+
+  fn replacement(&self) -> &str {
+    self
+  }
+}
+
+
+/// A completion candidate with differing display and replacement
+/// texts.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Pair {
+  /// The text to display when listing the candidate.
+  pub display: String,
+  /// The text to insert when completing the candidate.
+  pub replacement: String,
+}
+
+impl Candidate for Pair {
+  fn display(&self) -> &str {
+    &self.display
+  }
+
+  fn replacement(&self) -> &str {
+    &self.replacement
+  }
+}
+
+
+/// A completion candidate annotated with metadata.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Annotated {
+  /// The text to display when listing the candidate.
+  pub display: String,
+  /// The text to insert when completing the candidate.
+  pub replacement: String,
+  /// The type of entity the candidate refers to, if known.
+  pub kind: Option<CandidateKind>,
+  /// An application defined identifier of the style to display the
+  /// candidate in, if any.
+  pub style: Option<u32>,
+}
+
+impl Annotated {
+  /// Create a new `Annotated` object displaying and inserting the given
+  /// text, without any metadata.
+  pub fn new<S>(text: S) -> Self
+  where
+    S: Into<String>,
+  {
+    let text = text.into();
+    Self {
+      display: text.clone(),
+      replacement: text,
+      kind: None,
+      style: None,
+    }
+  }
+}
+
+impl Candidate for Annotated {
+  fn display(&self) -> &str {
+    &self.display
+  }
+
+  fn replacement(&self) -> &str {
+    &self.replacement
+  }
+
+  fn kind(&self) -> Option<CandidateKind> {
+    self.kind
+  }
+
+  fn style(&self) -> Option<u32> {
+    self.style
+  }
+}
+
+
+/// The context in which a completion is requested.
+#[derive(Debug)]
+pub struct Context<'rl> {
+  /// The instance the completion is requested for.
+  rl: &'rl Readline,
+}
+
+impl<'rl> Context<'rl> {
+  /// Retrieve the `Readline` instance the completion is requested for.
+  pub fn readline(&self) -> &'rl Readline {
+    self.rl
+  }
+}
+
+
+/// A token for cancelling the gathering of completion candidates,
+/// possibly from a different thread.
+///
+/// Clones of a token share their state.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+  /// Create a new `CancelToken` that is not cancelled.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Request cancellation.
+  ///
+  /// The token stays cancelled until it is [`reset`][CancelToken::reset].
+  pub fn cancel(&self) {
+    self.0.store(true, Ordering::Relaxed)
+  }
+
+  /// Reset the token to not being cancelled.
+  pub fn reset(&self) {
+    self.0.store(false, Ordering::Relaxed)
+  }
+
+  /// Check whether cancellation got requested.
+  pub fn is_cancelled(&self) -> bool {
+    self.0.load(Ordering::Relaxed)
+  }
+}
+
+
+/// A sink collecting completion candidates as they are produced by a
+/// [`Completer`].
+///
+/// Providers of large candidate sets should stop producing candidates
+/// once the sink reports that it is done, which happens as soon as a
+/// candidate got refused because enough were gathered for the
+/// completion at hand, the deadline for the completion passed, or it
+/// got cancelled.
+#[derive(Debug)]
+pub struct CandidateSink<C> {
+  /// The candidates gathered so far.
+  candidates: Vec<C>,
+  /// The maximum number of candidates to gather, if any.
+  limit: Option<usize>,
+  /// The point in time after which no more candidates are gathered, if
+  /// any.
+  deadline: Option<Instant>,
+  /// The token to check for cancellation, if any.
+  cancel: Option<CancelToken>,
+  /// Whether a candidate got refused.
+  refused: bool,
+}
+
+impl<C> CandidateSink<C> {
+  /// Create a new `CandidateSink` gathering at most `limit`
+  /// candidates.
+  fn new(limit: Option<usize>) -> Self {
+    Self {
+      candidates: Vec::new(),
+      limit,
+      deadline: None,
+      cancel: None,
+      refused: false,
+    }
+  }
+
+  /// Retrieve the point in time after which no more candidates are
+  /// gathered, if any.
+  ///
+  /// Providers performing blocking operations may use it to bound
+  /// their waiting time.
+  pub fn deadline(&self) -> Option<Instant> {
+    self.deadline
+  }
+
+  /// Add a candidate to the sink.
+  ///
+  /// `false` is returned once no more candidates are wanted, in which
+  /// case the provider should stop producing them. Candidates pushed
+  /// after that point are discarded. Note that the sink only knows
+  /// that more candidates exist than it gathers once one got refused,
+  /// so with a limit in place one candidate beyond it gets requested.
+  pub fn push(&mut self, candidate: C) -> bool {
+    let full = self
+      .limit
+      .map(|limit| self.candidates.len() >= limit)
+      .unwrap_or(false);
+    if full || self.is_done() {
+      self.refused = true;
+      return false
+    }
+
+    self.candidates.push(candidate);
+    !self.is_done()
+  }
+
+  /// Add candidates from the given iterator, pulling from it only as
+  /// long as more candidates are wanted.
+  ///
+  /// The return value has the same meaning as for
+  /// [`push`][CandidateSink::push].
+  pub fn extend<I>(&mut self, candidates: I) -> bool
+  where
+    I: IntoIterator<Item = C>,
+  {
+    for candidate in candidates {
+      if !self.push(candidate) {
+        return false
+      }
+    }
+    !self.is_done()
+  }
+
+  /// Check whether a candidate got refused because enough were
+  /// gathered or gathering got aborted, because the deadline passed or
+  /// cancellation got requested.
+  pub fn is_done(&self) -> bool {
+    let expired = self
+      .deadline
+      .map(|deadline| Instant::now() >= deadline)
+      .unwrap_or(false);
+    let cancelled = self
+      .cancel
+      .as_ref()
+      .map(CancelToken::is_cancelled)
+      .unwrap_or(false);
+    self.refused || expired || cancelled
+  }
+
+  /// Retrieve the number of candidates gathered so far.
+  pub fn len(&self) -> usize {
+    self.candidates.len()
+  }
+
+  /// Check whether no candidates were gathered so far.
+  pub fn is_empty(&self) -> bool {
+    self.candidates.is_empty()
+  }
+
+  /// Destructure the sink into the gathered candidates and a flag
+  /// indicating whether gathering stopped early, i.e., whether more
+  /// candidates may exist.
+  fn into_parts(self) -> (Vec<C>, bool) {
+    let done = self.is_done();
+    (self.candidates, done)
+  }
+}
+
+
+/// A trait for providing completion candidates.
+pub trait Completer {
+  /// The type of candidates produced.
+  type Candidate: Candidate;
+
+  /// Determine the candidates for completing the given line, with
+  /// `pos` being the cursor position.
+  ///
+  /// On success, the start position of the text to replace (which ends
+  /// at `pos`) is reported along with the candidates. By default, no
+  /// candidates are reported.
+  fn complete(
+    &self,
+    line: &str,
+    pos: usize,
+    ctx: &Context<'_>,
+  ) -> Result<(usize, Vec<Self::Candidate>)> {
+    let _ = (line, ctx);
+    Ok((pos, Vec::new()))
+  }
+
+  /// Determine the candidates for completing the given line, with
+  /// `pos` being the cursor position, handing them to `sink` one by
+  /// one.
+  ///
+  /// Implementations serving large candidate sets should produce
+  /// candidates lazily and stop as soon as the sink signals that it is
+  /// done. On success, the start position of the text to replace is
+  /// reported. By default, candidates are retrieved via
+  /// [`complete`][Completer::complete].
+  fn complete_into(
+    &self,
+    line: &str,
+    pos: usize,
+    ctx: &Context<'_>,
+    sink: &mut CandidateSink<Self::Candidate>,
+  ) -> Result<usize> {
+    let (start, candidates) = self.complete(line, pos, ctx)?;
+    let _more = sink.extend(candidates);
+    Ok(start)
+  }
+}
+
+impl Completer for () {
+  type Candidate = String;
+}
+
+
+/// A trait for helpers assisting with editing a line.
+///
+/// Contrary to `rustyline`, only completion is supported. Highlighting,
+/// hinting, and validation are available on the underlying
+/// [`Readline`] instance instead.
+pub trait Helper: Completer {}
+
+impl Helper for () {}
+
+
+/// Determine the line and cursor position resulting from replacing the
+/// text between `start` and `pos` with the longest common prefix of the
+/// given candidates' replacements.
+///
+/// `None` is returned if the line would stay unchanged.
+fn apply<C>(line: &str, pos: usize, start: usize, candidates: &[C]) -> Option<(String, usize)>
+where
+  C: Candidate,
+{
+  if start > pos {
+    return None
+  }
+
+  let first = candidates.first()?.replacement();
+  let common = candidates.iter().skip(1).fold(first, |common, candidate| {
+    let len = common
+      .char_indices()
+      .zip(candidate.replacement().chars())
+      .find(|((_, c1), c2)| c1 != c2)
+      .map(|((idx, _), _)| idx)
+      .unwrap_or_else(|| common.len().min(candidate.replacement().len()));
+    &common[..len]
+  });
+
+  let prefix = line.get(..start)?;
+  let suffix = line.get(pos..)?;
+
+  let mut new = String::with_capacity(prefix.len() + common.len() + suffix.len());
+  new.push_str(prefix);
+  new.push_str(common);
+  let cursor = new.len();
+  new.push_str(suffix);
+
+  if new == line {
+    None
+  } else {
+    Some((new, cursor))
+  }
+}
+
+
+/// A line editor with an interface resembling that of `rustyline`'s
+/// `Editor`.
+///
+/// The editor reads from stdin and renders to stdout, transitioning
+/// the terminal into raw mode while reading a line.
+#[derive(Debug)]
+pub struct Editor<H> {
+  /// The underlying `Readline` instance.
+  rl: Readline,
+  /// The helper to use, if any.
+  helper: Option<H>,
+  /// The maximum number of candidates to gather per completion, if
+  /// any.
+  max_candidates: Option<usize>,
+  /// The time budget for gathering candidates, if any.
+  completion_timeout: Option<Duration>,
+  /// The token for cancelling completions, if any.
+  cancel: Option<CancelToken>,
+  /// Whether the most recent completion was based on a partial set of
+  /// candidates.
+  partial: Cell<bool>,
+}
+
+/// An editor without a helper.
+pub type DefaultEditor = Editor<()>;
+
+impl<H> Editor<H>
+where
+  H: Helper,
+{
+  /// Create a new editor.
+  ///
+  /// This function does not currently fail. It returns a `Result` for
+  /// the sake of compatibility.
+  pub fn new() -> Result<Self> {
+    Ok(Self {
+      rl: Readline::new(),
+      helper: None,
+      max_candidates: None,
+      completion_timeout: None,
+      cancel: None,
+      partial: Cell::new(false),
+    })
+  }
+
+  /// Read a line from the terminal, using the given prompt.
+  ///
+  /// Pressing the tab key completes the text before the cursor if a
+  /// helper is set. Completed lines are not added to the history
+  /// automatically; use [`add_history_entry`][Editor::add_history_entry]
+  /// for that.
+  ///
+  /// # Errors
+  ///
+  /// [`ReadlineError::Interrupted`] is returned if the user pressed
+  /// Ctrl-C, which also discards the line being edited, and
+  /// [`ReadlineError::Eof`] if the end of input got signaled. Lines
+  /// that are not valid UTF-8 are reported as an I/O error of kind
+  /// [`InvalidData`][ErrorKind::InvalidData].
+  pub fn readline(&mut self, prompt: &str) -> Result<String> {
+    let prompt_c =
+      CString::new(prompt).map_err(|err| io::Error::new(ErrorKind::InvalidInput, err))?;
+    self.rl.set_prompt(&prompt_c);
+
+    let stdin = stdin();
+    let mut stdin = stdin.lock();
+    let mut stdout = stdout().into_raw_mode()?;
+
+    let mut renderer = Renderer::new(columns());
+    let () = redraw(&self.rl, &mut renderer, prompt, &mut stdout)?;
+
+    loop {
+      let buffer = stdin.fill_buf()?;
+      let mut consumed = 0;
+      let mut result = if buffer.is_empty() {
+        Some(Err(ReadlineError::Eof))
+      } else {
+        None
+      };
+
+      for &byte in buffer {
+        consumed += 1;
+
+        match byte {
+          ETX => result = Some(Err(ReadlineError::Interrupted)),
+          TAB if self.helper.is_some() => {
+            let () = self.complete(&mut renderer, &mut stdout)?;
+          },
+          // We feed byte by byte so that we never process input past
+          // a completed line.
+          _ => {
+            result = match self.rl.feed_key([byte]) {
+              Feed::Line(line) => Some(Ok(line)),
+              Feed::Eof => Some(Err(ReadlineError::Eof)),
+              Feed::Interrupted => Some(Err(ReadlineError::Interrupted)),
+              Feed::Pending => None,
+            }
+          },
+        }
+
+        if result.is_some() {
+          break
+        }
+      }
+      stdin.consume(consumed);
+
+      // The terminal may have been resized in the meantime.
+      renderer.set_columns(columns());
+
+      match result {
+        Some(result) => {
+          let text = match &result {
+            Ok(line) => line.to_string_lossy().into_owned(),
+            Err(..) => self.rl.peek(|text, _| text.to_string_lossy().into_owned()),
+          };
+          let output = renderer.render(prompt, &text, text.len());
+          stdout.write_all(output.as_bytes())?;
+          stdout.write_all(renderer.finish().as_bytes())?;
+          stdout.flush()?;
+
+          if result.is_err() {
+            self.rl.reset(CString::default(), 0, true);
+          }
+
+          break result.and_then(|line| {
+            line
+              .into_string()
+              .map_err(|err| io::Error::new(ErrorKind::InvalidData, err.utf8_error()).into())
+          })
+        },
+        None => redraw(&self.rl, &mut renderer, prompt, &mut stdout)?,
+      }
+    }
+  }
+
+  /// Complete the text before the cursor using the configured helper.
+  ///
+  /// If the candidates do not allow for extending the text, they are
+  /// listed below the line. If gathering stopped early, because of the
+  /// maximum configured via
+  /// [`set_max_candidates`][Editor::set_max_candidates], the time
+  /// budget, or the cancellation token, the text stays unchanged and
+  /// the gathered candidates are listed, as the complete set may not
+  /// share their common prefix.
+  fn complete<W>(&self, renderer: &mut Renderer, mut w: W) -> Result<()>
+  where
+    W: Write,
+  {
+    let helper = match &self.helper {
+      Some(helper) => helper,
+      None => return Ok(()),
+    };
+
+    let (line, pos) = self.rl.peek_str(|line, pos| (line.map(str::to_string), pos));
+    let line = match line {
+      Ok(line) => line,
+      // We cannot complete lines that are not valid UTF-8.
+      Err(..) => return Ok(()),
+    };
+
+    let ctx = Context { rl: &self.rl };
+    let mut sink = CandidateSink::new(self.max_candidates);
+    sink.deadline = self
+      .completion_timeout
+      .and_then(|timeout| Instant::now().checked_add(timeout));
+    sink.cancel = self.cancel.clone();
+
+    let start = helper.complete_into(&line, pos, &ctx, &mut sink)?;
+    let (candidates, truncated) = sink.into_parts();
+    let () = self.partial.set(truncated);
+    let applied = if truncated {
+      None
+    } else {
+      apply(&line, pos, start, &candidates)
+    };
+
+    if let Some((new, cursor)) = applied {
+      let () = self
+        .rl
+        .reset_str(new, cursor, false)
+        .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+    } else if candidates.len() > 1 || truncated {
+      let mut list = candidates
+        .iter()
+        .map(Candidate::display)
+        .collect::<Vec<_>>()
+        .join("  ");
+      if truncated {
+        list.push_str("  ...");
+      }
+      w.write_all(renderer.finish().as_bytes())?;
+      w.write_all(list.as_bytes())?;
+      w.write_all(b"\r\n")?;
+    }
+    Ok(())
+  }
+
+  /// Add a line to the history.
+  ///
+  /// Empty lines and lines equal to the most recent entry are ignored,
+  /// in which case `false` is returned.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error of kind [`InvalidInput`][ErrorKind::InvalidInput]
+  /// if the line contains a NUL byte.
+  pub fn add_history_entry<S>(&mut self, line: S) -> Result<bool>
+  where
+    S: AsRef<str>,
+  {
+    let line = line.as_ref();
+    if line.is_empty() {
+      return Ok(false)
+    }
+
+    let line = CString::new(line).map_err(|err| io::Error::new(ErrorKind::InvalidInput, err))?;
+    let duplicate = self
+      .rl
+      .activate()
+      .state
+      .history
+      .lines()
+      .next_back()
+      .map(|last| last == line.as_c_str())
+      .unwrap_or(false);
+    if duplicate {
+      return Ok(false)
+    }
+
+    self.rl.add_history(line);
+    Ok(true)
+  }
+
+  /// Set the helper to use.
+  pub fn set_helper(&mut self, helper: Option<H>) {
+    self.helper = helper
+  }
+
+  /// Set the maximum number of candidates to gather per completion.
+  ///
+  /// Once a helper produces more candidates than that, it is asked to
+  /// stop producing them (see [`CandidateSink`]), keeping the latency of
+  /// completion bounded for huge candidate sets. `None`, the default,
+  /// means that all candidates are gathered.
+  pub fn set_max_candidates(&mut self, max: Option<usize>) {
+    self.max_candidates = max
+  }
+
+  /// Retrieve the maximum number of candidates gathered per
+  /// completion.
+  pub fn max_candidates(&self) -> Option<usize> {
+    self.max_candidates
+  }
+
+  /// Set the time budget for gathering candidates per completion.
+  ///
+  /// Once it is exceeded, helpers are asked to stop producing
+  /// candidates, keeping the editor responsive. Helpers that do not
+  /// produce candidates lazily (see [`Completer::complete_into`]) can
+  /// only be interrupted once they returned, in which case none of
+  /// their candidates are used.
+  pub fn set_completion_timeout(&mut self, timeout: Option<Duration>) {
+    self.completion_timeout = timeout
+  }
+
+  /// Retrieve the time budget for gathering candidates per completion.
+  pub fn completion_timeout(&self) -> Option<Duration> {
+    self.completion_timeout
+  }
+
+  /// Set the token for cancelling completions.
+  ///
+  /// While the token is cancelled, helpers are asked to stop producing
+  /// candidates, the same way as when the time budget is exceeded.
+  pub fn set_cancel_token(&mut self, token: Option<CancelToken>) {
+    self.cancel = token
+  }
+
+  /// Retrieve the token for cancelling completions, if any.
+  pub fn cancel_token(&self) -> Option<&CancelToken> {
+    self.cancel.as_ref()
+  }
+
+  /// Check whether the most recent completion stopped gathering
+  /// candidates early, i.e., whether it was based on a partial result.
+  pub fn is_completion_partial(&self) -> bool {
+    self.partial.get()
+  }
+
+  /// Retrieve the helper in use, if any.
+  pub fn helper(&self) -> Option<&H> {
+    self.helper.as_ref()
+  }
+
+  /// Retrieve the helper in use, if any.
+  pub fn helper_mut(&mut self) -> Option<&mut H> {
+    self.helper.as_mut()
+  }
+
+  /// Retrieve the underlying [`Readline`] instance.
+  pub fn readline_instance(&self) -> &Readline {
+    &self.rl
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+
+  /// A completer for a fixed set of words.
+  struct Words(&'static [&'static str]);
+
+  impl Completer for Words {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Result<(usize, Vec<String>)> {
+      let start = line[..pos].rfind(' ').map(|idx| idx + 1).unwrap_or(0);
+      let word = &line[start..pos];
+      let candidates = self
+        .0
+        .iter()
+        .filter(|candidate| candidate.starts_with(word))
+        .map(|candidate| candidate.to_string())
+        .collect();
+      Ok((start, candidates))
+    }
+  }
+
+  impl Helper for Words {}
+
+
+  /// Check that we apply completion candidates correctly.
+  #[test]
+  fn completion() {
+    let words = Words(&["foo", "foobar", "foobaz", "qux"]);
+    let rl = Readline::new();
+    let ctx = Context { rl: &rl };
+
+    let (start, candidates) = words.complete("x q", 3, &ctx).unwrap();
+    assert_eq!(apply("x q", 3, start, &candidates), Some(("x qux".to_string(), 5)));
+
+    let (start, candidates) = words.complete("fooba!", 5, &ctx).unwrap();
+    assert_eq!(apply("fooba!", 5, start, &candidates), None);
+
+    let (start, candidates) = words.complete("f", 1, &ctx).unwrap();
+    assert_eq!(apply("f", 1, start, &candidates), Some(("foo".to_string(), 3)));
+
+    let (start, candidates) = words.complete("z", 1, &ctx).unwrap();
+    assert_eq!(apply("z", 1, start, &candidates), None);
+
+    let candidates = ["\u{e4}b".to_string(), "\u{e4}c".to_string()];
+    assert_eq!(apply("", 0, 0, &candidates), Some(("\u{e4}".to_string(), 2)));
+  }
+
+  /// Check that metadata of candidates is reported.
+  #[test]
+  fn candidate_metadata() {
+    let candidate = "foo".to_string();
+    assert_eq!(candidate.kind(), None);
+    assert_eq!(candidate.style(), None);
+
+    let candidate = Annotated {
+      display: "src".to_string(),
+      kind: Some(CandidateKind::Directory),
+      style: Some(4),
+      ..Annotated::new("src/")
+    };
+    assert_eq!(candidate.display(), "src");
+    assert_eq!(candidate.replacement(), "src/");
+    assert_eq!(candidate.kind(), Some(CandidateKind::Directory));
+    assert_eq!(candidate.style(), Some(4));
+
+    let candidates = [candidate, Annotated::new("srv")];
+    assert_eq!(apply("s", 1, 0, &candidates), Some(("sr".to_string(), 2)));
+  }
+
+  /// A completer producing an endless stream of candidates.
+  struct Endless;
+
+  impl Completer for Endless {
+    type Candidate = String;
+
+    fn complete_into(
+      &self,
+      _line: &str,
+      pos: usize,
+      _ctx: &Context<'_>,
+      sink: &mut CandidateSink<String>,
+    ) -> Result<usize> {
+      let _more = sink.extend((0..).map(|idx| format!("x{}", idx)));
+      Ok(pos)
+    }
+  }
+
+  impl Helper for Endless {}
+
+
+  /// Check that candidate gathering stops once the limit got reached.
+  #[test]
+  fn candidate_sink() {
+    let mut sink = CandidateSink::new(Some(2));
+    assert!(sink.is_empty());
+    assert!(sink.push("a".to_string()));
+    assert!(sink.push("b".to_string()));
+    assert!(!sink.is_done());
+    assert!(!sink.push("c".to_string()));
+    assert!(sink.is_done());
+    assert!(!sink.push("c".to_string()));
+    assert_eq!(sink.len(), 2);
+    assert_eq!(sink.into_parts(), (vec!["a".to_string(), "b".to_string()], true));
+
+    let mut sink = CandidateSink::new(Some(3));
+    assert!(sink.extend(vec!["a".to_string(), "b".to_string()]));
+    assert!(!sink.into_parts().1);
+
+    // Exactly as many candidates as the limit allows are not
+    // considered truncated.
+    let mut sink = CandidateSink::new(Some(2));
+    assert!(sink.extend(vec!["a".to_string(), "b".to_string()]));
+    assert_eq!(sink.into_parts(), (vec!["a".to_string(), "b".to_string()], false));
+
+    let mut sink = CandidateSink::new(Some(0));
+    assert!(!sink.push("a".to_string()));
+    assert_eq!(sink.into_parts(), (Vec::new(), true));
+
+    let mut sink = CandidateSink::new(None);
+    assert!(sink.extend((0..1000).map(|idx| idx.to_string())));
+    assert!(!sink.is_done());
+    assert_eq!(sink.into_parts().0.len(), 1000);
+
+    let words = Words(&["foo", "foobar", "qux"]);
+    let rl = Readline::new();
+    let ctx = Context { rl: &rl };
+    let mut sink = CandidateSink::new(Some(1));
+    let start = words.complete_into("x f", 3, &ctx, &mut sink).unwrap();
+    assert_eq!(start, 2);
+    assert_eq!(sink.into_parts(), (vec!["foo".to_string()], true));
+
+    let words = Words(&["foo", "qux"]);
+    let mut sink = CandidateSink::new(Some(1));
+    let start = words.complete_into("x f", 3, &ctx, &mut sink).unwrap();
+    assert_eq!(start, 2);
+    assert_eq!(sink.into_parts(), (vec!["foo".to_string()], false));
+  }
+
+  /// Check that a unique match completes with a candidate limit of
+  /// one.
+  #[test]
+  fn unique_completion_with_limit() {
+    let mut editor = Editor::<Words>::new().unwrap();
+    editor.set_helper(Some(Words(&["foo", "qux"])));
+    editor.set_max_candidates(Some(1));
+    let _feed = editor.rl.feed_key([b'f']);
+
+    let mut renderer = Renderer::new(80);
+    let () = editor.complete(&mut renderer, &mut Vec::new()).unwrap();
+    assert!(!editor.is_completion_partial());
+    assert_eq!(editor.rl.peek(|s, pos| (s.to_owned(), pos)), (CString::new("foo").unwrap(), 3));
+  }
+
+  /// Check that completion with an endless stream of candidates lists
+  /// the gathered ones without altering the line.
+  #[test]
+  fn endless_completion() {
+    let mut editor = Editor::<Endless>::new().unwrap();
+    editor.set_helper(Some(Endless));
+    editor.set_max_candidates(Some(3));
+    assert_eq!(editor.max_candidates(), Some(3));
+    let _feed = editor.rl.feed_key([b'x']);
+
+    let mut renderer = Renderer::new(80);
+    let mut output = Vec::new();
+    let () = editor.complete(&mut renderer, &mut output).unwrap();
+    assert!(output.ends_with(b"x0  x1  x2  ...\r\n"));
+    assert_eq!(editor.rl.peek(|s, pos| (s.to_owned(), pos)), (CString::new("x").unwrap(), 1));
+  }
+
+  /// Check that candidate gathering honors the time budget and
+  /// cancellation token.
+  #[test]
+  fn completion_budget() {
+    let mut editor = Editor::<Endless>::new().unwrap();
+    editor.set_helper(Some(Endless));
+    editor.set_completion_timeout(Some(Duration::from_millis(10)));
+    assert_eq!(editor.completion_timeout(), Some(Duration::from_millis(10)));
+    let _feed = editor.rl.feed_key([b'x']);
+
+    let mut renderer = Renderer::new(80);
+    let mut output = Vec::new();
+    let () = editor.complete(&mut renderer, &mut output).unwrap();
+    assert!(editor.is_completion_partial());
+    assert!(output.ends_with(b"  ...\r\n"));
+    assert_eq!(editor.rl.peek(|s, pos| (s.to_owned(), pos)), (CString::new("x").unwrap(), 1));
+
+    let token = CancelToken::new();
+    let () = token.cancel();
+    editor.set_completion_timeout(None);
+    editor.set_cancel_token(Some(token.clone()));
+    assert!(editor.cancel_token().unwrap().is_cancelled());
+
+    let () = editor.complete(&mut renderer, &mut Vec::new()).unwrap();
+    assert!(editor.is_completion_partial());
+
+    let () = token.reset();
+    let mut editor = Editor::<Words>::new().unwrap();
+    editor.set_helper(Some(Words(&["foo", "foobar"])));
+    editor.set_cancel_token(Some(token));
+    let _feed = editor.rl.feed_key([b'f']);
+    let () = editor.complete(&mut renderer, &mut Vec::new()).unwrap();
+    assert!(!editor.is_completion_partial());
+    assert_eq!(editor.rl.peek(|s, pos| (s.to_owned(), pos)), (CString::new("foo").unwrap(), 3));
+  }
+
+  /// Check that history entries are added as expected.
+  #[test]
+  fn history_entries() {
+    let mut editor = DefaultEditor::new().unwrap();
+    assert!(!editor.add_history_entry("").unwrap());
+    assert!(editor.add_history_entry("a").unwrap());
+    assert!(!editor.add_history_entry("a").unwrap());
+    assert!(editor.add_history_entry("b").unwrap());
+    assert!(editor.add_history_entry("a").unwrap());
+    assert!(editor.add_history_entry("a\0").is_err());
+  }
+}
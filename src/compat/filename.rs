@@ -0,0 +1,352 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Completion of file names served by arbitrary providers.
+
+use std::fs::read_dir;
+use std::io;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use super::Annotated;
+use super::CandidateKind;
+use super::Completer;
+use super::Context;
+use super::Helper;
+use super::Result;
+
+
+/// Characters that have to be escaped with a backslash in unquoted
+/// words.
+const SPECIAL: &str = " \t\n\\\"'`$&;|<>()[]{}*?!#";
+
+
+/// An entry of a directory, as listed by an [`EntryProvider`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Entry {
+  /// The name of the entry, without any path components.
+  pub name: String,
+  /// Whether the entry is a directory.
+  pub is_dir: bool,
+}
+
+
+/// A trait for providers of directory listings, serving file name
+/// completion from local or remote file systems, archives, in-memory
+/// trees, or similar.
+pub trait EntryProvider {
+  /// List the entries of the directory at the given path.
+  ///
+  /// The path is the directory part of the word being completed, as
+  /// typed by the user but with quoting removed. It is empty for the
+  /// current directory and ends with a `/` otherwise. A directory that
+  /// does not exist should be reported as an error of kind
+  /// [`NotFound`][ErrorKind::NotFound], which results in no candidates.
+  fn list(&self, dir: &str) -> io::Result<Vec<Entry>>;
+}
+
+impl<P> EntryProvider for &P
+where
+  P: EntryProvider + ?Sized,
+{
+  fn list(&self, dir: &str) -> io::Result<Vec<Entry>> {
+    (**self).list(dir)
+  }
+}
+
+
+/// An [`EntryProvider`] listing directories of the local file system.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LocalFs;
+
+impl EntryProvider for LocalFs {
+  fn list(&self, dir: &str) -> io::Result<Vec<Entry>> {
+    let dir = if dir.is_empty() { "." } else { dir };
+    let mut entries = Vec::new();
+    for entry in read_dir(Path::new(dir))? {
+      let entry = entry?;
+      // File names that are not valid UTF-8 cannot be completed.
+      if let Ok(name) = entry.file_name().into_string() {
+        // Symbolic links to directories count as directories.
+        let is_dir = entry.path().is_dir();
+        entries.push(Entry { name, is_dir })
+      }
+    }
+    Ok(entries)
+  }
+}
+
+
+/// The word to complete, as found before the cursor.
+#[derive(Debug, Eq, PartialEq)]
+struct Word {
+  /// The byte index at which the word starts.
+  start: usize,
+  /// The word with quoting removed.
+  text: String,
+  /// The quote character the word started with, if any.
+  quote: Option<char>,
+}
+
+/// Find the word ending at `pos`, honoring quotes and backslash
+/// escapes.
+fn find_word(line: &str, pos: usize) -> Option<Word> {
+  let mut start = 0;
+  let mut text = String::new();
+  let mut first = None;
+  let mut quote = None;
+  let mut escaped = false;
+
+  for (idx, c) in line.get(..pos)?.char_indices() {
+    if escaped {
+      escaped = false;
+      text.push(c);
+      continue
+    }
+
+    match (quote, c) {
+      (None, c) if c.is_whitespace() => {
+        start = idx + c.len_utf8();
+        text.clear();
+        first = None;
+        continue
+      },
+      (None, '\\') | (Some('"'), '\\') => escaped = true,
+      (None, '\'') | (None, '"') => quote = Some(c),
+      (Some(q), c) if q == c => quote = None,
+      (_, c) => text.push(c),
+    }
+
+    if first.is_none() {
+      first = Some(c);
+    }
+  }
+
+  let quote = first.filter(|c| *c == '\'' || *c == '"');
+  Some(Word { start, text, quote })
+}
+
+
+/// Quote the given text for insertion into a word that started with
+/// the given quote character, if any.
+fn quote(text: &str, quote: Option<char>) -> String {
+  let mut quoted = String::with_capacity(text.len() + 2);
+  match quote {
+    Some('\'') => {
+      quoted.push('\'');
+      for c in text.chars() {
+        if c == '\'' {
+          // Single quoted strings cannot contain single quotes, so
+          // close the quote, add an escaped one, and reopen it.
+          quoted.push_str("'\\''");
+        } else {
+          quoted.push(c)
+        }
+      }
+    },
+    Some(q) => {
+      quoted.push(q);
+      for c in text.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+          quoted.push('\\');
+        }
+        quoted.push(c)
+      }
+    },
+    None => {
+      for c in text.chars() {
+        if SPECIAL.contains(c) {
+          quoted.push('\\');
+        }
+        quoted.push(c)
+      }
+    },
+  }
+  quoted
+}
+
+
+/// A [`Completer`] completing file names, with the directory listings
+/// served by an [`EntryProvider`].
+///
+/// The completer takes care of finding the word to complete (which may
+/// be quoted or contain backslash escapes), splitting it into the
+/// directory to list and the prefix to match, and quoting the
+/// resulting candidates appropriately. Directories are completed with
+/// a trailing `/`. Hidden entries, i.e., ones starting with a `.`, are
+/// only reported if the prefix starts with a `.` as well. Candidates
+/// are ordered by name.
+#[derive(Clone, Debug, Default)]
+pub struct FilenameCompleter<P> {
+  /// The provider of directory listings.
+  provider: P,
+}
+
+impl<P> FilenameCompleter<P>
+where
+  P: EntryProvider,
+{
+  /// Create a new `FilenameCompleter` using the given provider.
+  pub fn new(provider: P) -> Self {
+    Self { provider }
+  }
+
+  /// Retrieve the provider of directory listings.
+  pub fn provider(&self) -> &P {
+    &self.provider
+  }
+}
+
+impl<P> Completer for FilenameCompleter<P>
+where
+  P: EntryProvider,
+{
+  type Candidate = Annotated;
+
+  fn complete(
+    &self,
+    line: &str,
+    pos: usize,
+    _ctx: &Context<'_>,
+  ) -> Result<(usize, Vec<Annotated>)> {
+    let word = match find_word(line, pos) {
+      Some(word) => word,
+      None => return Ok((pos, Vec::new())),
+    };
+
+    let split = word.text.rfind('/').map(|idx| idx + 1).unwrap_or(0);
+    let (dir, prefix) = word.text.split_at(split);
+    let mut entries = match self.provider.list(dir) {
+      Ok(entries) => entries,
+      Err(err) if err.kind() == ErrorKind::NotFound => Vec::new(),
+      Err(err) => return Err(err.into()),
+    };
+    let () = entries.sort_by(|x, y| x.name.cmp(&y.name));
+
+    let candidates = entries
+      .into_iter()
+      .filter(|entry| entry.name.starts_with(prefix))
+      .filter(|entry| !entry.name.starts_with('.') || prefix.starts_with('.'))
+      .map(|entry| {
+        let (display, kind) = if entry.is_dir {
+          (format!("{}/", entry.name), CandidateKind::Directory)
+        } else {
+          (entry.name, CandidateKind::File)
+        };
+
+        let path = format!("{}{}", dir, display);
+        let mut replacement = quote(&path, word.quote);
+        if !entry.is_dir {
+          // A file name is complete, so close the quote, if any.
+          if let Some(quote) = word.quote {
+            replacement.push(quote);
+          }
+        }
+
+        Annotated {
+          kind: Some(kind),
+          replacement,
+          ..Annotated::new(display)
+        }
+      })
+      .collect();
+
+    Ok((word.start, candidates))
+  }
+}
+
+impl<P> Helper for FilenameCompleter<P> where P: EntryProvider {}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::collections::BTreeMap;
+
+  use crate::Readline;
+
+  use super::super::apply;
+  use super::super::Candidate as _;
+
+
+  /// An in-memory tree of directories.
+  struct Tree(BTreeMap<&'static str, Vec<(&'static str, bool)>>);
+
+  impl EntryProvider for Tree {
+    fn list(&self, dir: &str) -> io::Result<Vec<Entry>> {
+      let entries = self.0.get(dir).ok_or_else(|| io::Error::from(ErrorKind::NotFound))?;
+      let entries = entries
+        .iter()
+        .map(|(name, is_dir)| Entry {
+          name: name.to_string(),
+          is_dir: *is_dir,
+        })
+        .collect();
+      Ok(entries)
+    }
+  }
+
+
+  /// Check that words to complete are found as expected.
+  #[test]
+  fn word_finding() {
+    let word = |text: &str, start, quote| Word {
+      start,
+      text: text.to_string(),
+      quote,
+    };
+
+    assert_eq!(find_word("ls fo", 5), Some(word("fo", 3, None)));
+    assert_eq!(find_word("ls a\\ b", 7), Some(word("a b", 3, None)));
+    assert_eq!(find_word("ls 'a b", 7), Some(word("a b", 3, Some('\''))));
+    assert_eq!(find_word("ls \"a\\\"b\" c", 11), Some(word("c", 10, None)));
+    assert_eq!(find_word("ls ", 3), Some(word("", 3, None)));
+    assert_eq!(find_word("ls", 3), None);
+  }
+
+  /// Check that text is quoted as expected.
+  #[test]
+  fn quoting() {
+    assert_eq!(quote("a b$", None), "a\\ b\\$");
+    assert_eq!(quote("a b$", Some('"')), "\"a b\\$");
+    assert_eq!(quote("it's", Some('\'')), "'it'\\''s");
+  }
+
+  /// Check that file names are completed using a provider.
+  #[test]
+  fn completion() {
+    let mut tree = BTreeMap::new();
+    let _prev = tree.insert("", vec![("src", true), ("my file", false), (".hidden", false)]);
+    let _prev = tree.insert("src/", vec![("main.rs", false), ("lib.rs", false), ("bin", true)]);
+    let completer = FilenameCompleter::new(Tree(tree));
+    let rl = Readline::new();
+    let ctx = Context { rl: &rl };
+
+    let (start, candidates) = completer.complete("cat s", 5, &ctx).unwrap();
+    assert_eq!(start, 4);
+    assert_eq!(candidates.len(), 1);
+    assert_eq!(candidates[0].display(), "src/");
+    assert_eq!(candidates[0].replacement(), "src/");
+    assert_eq!(candidates[0].kind(), Some(CandidateKind::Directory));
+
+    let (start, candidates) = completer.complete("cat src/", 8, &ctx).unwrap();
+    let names = candidates.iter().map(|c| c.display()).collect::<Vec<_>>();
+    assert_eq!(names, vec!["bin/", "lib.rs", "main.rs"]);
+    assert_eq!(apply("cat src/", 8, start, &candidates), None);
+
+    let (start, candidates) = completer.complete("cat m", 5, &ctx).unwrap();
+    assert_eq!(apply("cat m", 5, start, &candidates), Some(("cat my\\ file".to_string(), 12)));
+
+    let (start, candidates) = completer.complete("cat 'm", 6, &ctx).unwrap();
+    assert_eq!(apply("cat 'm", 6, start, &candidates), Some(("cat 'my file'".to_string(), 13)));
+
+    let (_start, candidates) = completer.complete("cat ", 4, &ctx).unwrap();
+    assert_eq!(candidates.len(), 2);
+    let (_start, candidates) = completer.complete("cat .", 5, &ctx).unwrap();
+    assert_eq!(candidates.len(), 1);
+
+    let (_start, candidates) = completer.complete("cat x/", 6, &ctx).unwrap();
+    assert!(candidates.is_empty());
+  }
+}
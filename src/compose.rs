@@ -0,0 +1,304 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Entering of characters not available on the keyboard through
+//! digraphs.
+//!
+//! A [`Composer`] sits in front of a [`Readline`] instance. After the
+//! trigger key got fed, the following two characters are combined into
+//! a single one as per the composer's table, e.g., `a` and `:` into
+//! `ä`. That allows for entering such characters on terminals without
+//! compose support provided by the operating system.
+
+use std::collections::HashMap;
+use std::str;
+
+use crate::Feed;
+use crate::Readline;
+
+
+/// The default trigger key, Ctrl-K, as used by Vim for digraphs.
+pub const DEFAULT_TRIGGER: u8 = 0x0b;
+
+/// A subset of the digraphs defined by RFC 1345, covering the most
+/// commonly used Latin characters and symbols.
+const RFC1345: [(&str, char); 85] = [
+  ("A:", 'Ä'),
+  ("E:", 'Ë'),
+  ("I:", 'Ï'),
+  ("O:", 'Ö'),
+  ("U:", 'Ü'),
+  ("a:", 'ä'),
+  ("e:", 'ë'),
+  ("i:", 'ï'),
+  ("o:", 'ö'),
+  ("u:", 'ü'),
+  ("y:", 'ÿ'),
+  ("A'", 'Á'),
+  ("E'", 'É'),
+  ("I'", 'Í'),
+  ("O'", 'Ó'),
+  ("U'", 'Ú'),
+  ("Y'", 'Ý'),
+  ("a'", 'á'),
+  ("e'", 'é'),
+  ("i'", 'í'),
+  ("o'", 'ó'),
+  ("u'", 'ú'),
+  ("y'", 'ý'),
+  ("A!", 'À'),
+  ("E!", 'È'),
+  ("I!", 'Ì'),
+  ("O!", 'Ò'),
+  ("U!", 'Ù'),
+  ("a!", 'à'),
+  ("e!", 'è'),
+  ("i!", 'ì'),
+  ("o!", 'ò'),
+  ("u!", 'ù'),
+  ("A>", 'Â'),
+  ("E>", 'Ê'),
+  ("I>", 'Î'),
+  ("O>", 'Ô'),
+  ("U>", 'Û'),
+  ("a>", 'â'),
+  ("e>", 'ê'),
+  ("i>", 'î'),
+  ("o>", 'ô'),
+  ("u>", 'û'),
+  ("A?", 'Ã'),
+  ("N?", 'Ñ'),
+  ("O?", 'Õ'),
+  ("a?", 'ã'),
+  ("n?", 'ñ'),
+  ("o?", 'õ'),
+  ("AA", 'Å'),
+  ("aa", 'å'),
+  ("AE", 'Æ'),
+  ("ae", 'æ'),
+  ("O/", 'Ø'),
+  ("o/", 'ø'),
+  ("C,", 'Ç'),
+  ("c,", 'ç'),
+  ("ss", 'ß'),
+  ("D-", 'Đ'),
+  ("d-", 'đ'),
+  ("L/", 'Ł'),
+  ("l/", 'ł'),
+  ("!I", '¡'),
+  ("?I", '¿'),
+  ("Ct", '¢'),
+  ("Pd", '£'),
+  ("Ye", '¥'),
+  ("Eu", '€'),
+  ("SE", '§'),
+  ("Co", '©'),
+  ("Rg", '®'),
+  ("DG", '°'),
+  ("+-", '±'),
+  ("2S", '²'),
+  ("3S", '³'),
+  ("My", 'µ'),
+  ("PI", '¶'),
+  (".M", '·'),
+  ("<<", '«'),
+  (">>", '»'),
+  ("14", '¼'),
+  ("12", '½'),
+  ("34", '¾'),
+  ("*X", '×'),
+  ("-:", '÷'),
+];
+
+
+/// The state of a composition.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum State {
+  /// No composition is in progress.
+  Idle,
+  /// The trigger key got fed.
+  Triggered,
+  /// The trigger key and the first character got fed.
+  First(char),
+}
+
+
+/// An engine combining digraphs into single characters before they get
+/// fed to libreadline.
+#[derive(Clone, Debug)]
+pub struct Composer {
+  /// The key starting a composition.
+  trigger: u8,
+  /// The table mapping digraphs to characters.
+  table: HashMap<(char, char), char>,
+  /// The state of the composition in progress.
+  state: State,
+}
+
+impl Composer {
+  /// Create a new `Composer` with an empty table, starting compositions
+  /// upon the given trigger key.
+  pub fn new(trigger: u8) -> Self {
+    Self {
+      trigger,
+      table: HashMap::new(),
+      state: State::Idle,
+    }
+  }
+
+  /// Create a new `Composer` using a table of commonly used digraphs as
+  /// defined by RFC 1345, e.g., `a:` for `ä` or `Eu` for `€`.
+  pub fn rfc1345(trigger: u8) -> Self {
+    let mut composer = Self::new(trigger);
+    for (digraph, c) in RFC1345.iter() {
+      let mut chars = digraph.chars();
+      // All digraphs in the table consist of exactly two characters.
+      let first = chars.next().unwrap();
+      let second = chars.next().unwrap();
+      composer.insert(first, second, *c);
+    }
+    composer
+  }
+
+  /// Add a digraph to the table, replacing any existing mapping for it.
+  pub fn insert(&mut self, first: char, second: char, c: char) {
+    let _prev = self.table.insert((first, second), c);
+  }
+
+  /// Check whether a composition is in progress.
+  pub fn is_composing(&self) -> bool {
+    self.state != State::Idle
+  }
+
+  /// Abort the composition in progress, if any.
+  pub fn cancel(&mut self) {
+    self.state = State::Idle
+  }
+
+  /// Process a key, producing the bytes to feed to libreadline, if any.
+  ///
+  /// Keys not part of a composition are passed through as is. Once the
+  /// second character of a digraph got fed, the resulting character is
+  /// produced, with the reverse order of the two characters being
+  /// accepted as well. If no such digraph is known, the second
+  /// character is produced instead. Feeding the trigger key twice
+  /// produces the trigger key itself, while other keys that are not a
+  /// single character abort the composition and are passed through.
+  pub fn compose(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+    let c = str::from_utf8(key).ok().and_then(|s| {
+      let mut chars = s.chars();
+      match (chars.next(), chars.next()) {
+        (Some(c), None) if !c.is_control() => Some(c),
+        _ => None,
+      }
+    });
+
+    match (self.state, c) {
+      (State::Idle, _) if key == [self.trigger] => {
+        self.state = State::Triggered;
+        None
+      },
+      (State::Idle, _) => Some(key.to_vec()),
+      (State::Triggered, Some(c)) => {
+        self.state = State::First(c);
+        None
+      },
+      (State::First(first), Some(second)) => {
+        self.state = State::Idle;
+        let c = self
+          .table
+          .get(&(first, second))
+          .or_else(|| self.table.get(&(second, first)))
+          .copied()
+          .unwrap_or(second);
+        Some(c.to_string().into_bytes())
+      },
+      (State::Triggered, None) | (State::First(..), None) => {
+        self.state = State::Idle;
+        Some(key.to_vec())
+      },
+    }
+  }
+
+  /// Process a key as [`compose`][Composer::compose] does and feed the
+  /// resulting bytes, if any, to the given `Readline` instance.
+  ///
+  /// [`Feed::Pending`] is reported while a composition is in progress.
+  pub fn feed(&mut self, rl: &Readline, key: &[u8]) -> Feed {
+    match self.compose(key) {
+      Some(key) => rl.feed_key(key),
+      None => Feed::Pending,
+    }
+  }
+}
+
+impl Default for Composer {
+  /// Create a `Composer` using the RFC 1345 table and the default
+  /// trigger key, Ctrl-K.
+  fn default() -> Self {
+    Self::rfc1345(DEFAULT_TRIGGER)
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::ffi::CString;
+
+
+  /// Check that we can compose characters from digraphs.
+  #[test]
+  fn composition() {
+    let mut composer = Composer::default();
+    assert_eq!(composer.compose(b"a"), Some(b"a".to_vec()));
+    assert_eq!(composer.compose(b"\x0b"), None);
+    assert!(composer.is_composing());
+    assert_eq!(composer.compose(b"a"), None);
+    assert_eq!(composer.compose(b":"), Some("ä".as_bytes().to_vec()));
+    assert!(!composer.is_composing());
+
+    // The reverse order works as well.
+    assert_eq!(composer.compose(b"\x0b"), None);
+    assert_eq!(composer.compose(b"u"), None);
+    assert_eq!(composer.compose(b"E"), Some("€".as_bytes().to_vec()));
+
+    // Unknown digraphs produce the second character.
+    assert_eq!(composer.compose(b"\x0b"), None);
+    assert_eq!(composer.compose(b"x"), None);
+    assert_eq!(composer.compose(b"q"), Some(b"q".to_vec()));
+
+    // Other keys abort the composition.
+    assert_eq!(composer.compose(b"\x0b"), None);
+    assert_eq!(composer.compose(b"\x1b[D"), Some(b"\x1b[D".to_vec()));
+    assert_eq!(composer.compose(b"\x0b"), None);
+    assert_eq!(composer.compose(b"\x0b"), Some(b"\x0b".to_vec()));
+
+    let mut composer = Composer::new(b'\\');
+    composer.insert('-', '>', '\u{2192}');
+    assert_eq!(composer.compose(b"\\"), None);
+    assert_eq!(composer.compose(b"-"), None);
+    assert_eq!(composer.compose(b">"), Some("\u{2192}".as_bytes().to_vec()));
+    assert_eq!(composer.compose(b"\\"), None);
+    assert_eq!(composer.compose(b"a"), None);
+    assert_eq!(composer.compose(b":"), Some(b":".to_vec()));
+  }
+
+  /// Check that we can feed composed characters to a `Readline`
+  /// instance.
+  #[test]
+  fn feeding() {
+    Readline::force_eight_bit();
+
+    let rl = Readline::new();
+    let mut composer = Composer::default();
+    for key in [&b"\x0b"[..], b"o", b":", b"l"].iter() {
+      assert_eq!(composer.feed(&rl, key), Feed::Pending);
+    }
+    assert_eq!(
+      composer.feed(&rl, b"\r"),
+      Feed::Line(CString::new("öl").unwrap())
+    );
+  }
+}
@@ -0,0 +1,118 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Determination of changes to the line between two points in time.
+
+
+/// A change to the line caused by feeding input.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Change {
+  /// Neither the line nor the cursor changed.
+  Unchanged,
+  /// Only the cursor moved.
+  Cursor {
+    /// The previous cursor position.
+    from: usize,
+    /// The new cursor position.
+    to: usize,
+  },
+  /// A contiguous range of text got replaced. Pure insertions and
+  /// deletions are represented as replacements of or with zero bytes,
+  /// respectively.
+  Text {
+    /// The byte offset at which the change starts, in both the
+    /// previous and the new line.
+    start: usize,
+    /// The number of bytes removed from the previous line.
+    removed: usize,
+    /// The number of bytes inserted into the new line.
+    inserted: usize,
+    /// The new cursor position.
+    cursor: usize,
+  },
+}
+
+impl Change {
+  /// Determine the change that transformed `before` (with the cursor at
+  /// `from`) into `after` (with the cursor at `to`).
+  ///
+  /// Multiple distinct modifications are reported as a single change
+  /// covering all of them.
+  pub(crate) fn new(before: &[u8], from: usize, after: &[u8], to: usize) -> Self {
+    if before == after {
+      if from == to {
+        Self::Unchanged
+      } else {
+        Self::Cursor { from, to }
+      }
+    } else {
+      let (prefix, suffix) = common(before, after);
+      Self::Text {
+        start: prefix,
+        removed: before.len() - prefix - suffix,
+        inserted: after.len() - prefix - suffix,
+        cursor: to,
+      }
+    }
+  }
+}
+
+
+/// Determine the length of the common prefix and suffix of the two
+/// given byte slices. Prefix and suffix never overlap.
+pub(crate) fn common(before: &[u8], after: &[u8]) -> (usize, usize) {
+  let prefix = before
+    .iter()
+    .zip(after)
+    .take_while(|(x, y)| x == y)
+    .count();
+  let suffix = before[prefix..]
+    .iter()
+    .rev()
+    .zip(after[prefix..].iter().rev())
+    .take_while(|(x, y)| x == y)
+    .count();
+
+  (prefix, suffix)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+
+  /// Check that we determine changes correctly.
+  #[test]
+  fn changes() {
+    assert_eq!(Change::new(b"abc", 1, b"abc", 1), Change::Unchanged);
+    assert_eq!(Change::new(b"abc", 1, b"abc", 3), Change::Cursor { from: 1, to: 3 });
+    assert_eq!(
+      Change::new(b"abc", 1, b"axbc", 2),
+      Change::Text {
+        start: 1,
+        removed: 0,
+        inserted: 1,
+        cursor: 2,
+      }
+    );
+    assert_eq!(
+      Change::new(b"aaaa", 4, b"aa", 2),
+      Change::Text {
+        start: 2,
+        removed: 2,
+        inserted: 0,
+        cursor: 2,
+      }
+    );
+    assert_eq!(
+      Change::new(b"abcd", 0, b"aXYd", 0),
+      Change::Text {
+        start: 1,
+        removed: 2,
+        inserted: 2,
+        cursor: 0,
+      }
+    );
+  }
+}
@@ -0,0 +1,66 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Typed representation of libreadline's state flags.
+
+use bitflags::bitflags;
+
+
+bitflags! {
+  /// Flags describing what libreadline is currently doing, as kept in
+  /// its `rl_readline_state` variable.
+  pub struct StateFlags: u32 {
+    /// libreadline is initializing.
+    const INITIALIZING = 0x0000001;
+    /// libreadline's initialization is done.
+    const INITIALIZED = 0x0000002;
+    /// The terminal is prepared.
+    const TERMPREPPED = 0x0000004;
+    /// A command key is being read.
+    const READCMD = 0x0000008;
+    /// Input following an escape character is being read.
+    const METANEXT = 0x0000010;
+    /// A command is being dispatched.
+    const DISPATCHING = 0x0000020;
+    /// A command is reading more input.
+    const MOREINPUT = 0x0000040;
+    /// An incremental history search is in progress.
+    const ISEARCH = 0x0000080;
+    /// A non-incremental history search is in progress.
+    const NSEARCH = 0x0000100;
+    /// A history search is in progress.
+    const SEARCH = 0x0000200;
+    /// A numeric argument is being read.
+    const NUMERICARG = 0x0000400;
+    /// Input is being read from a keyboard macro.
+    const MACROINPUT = 0x0000800;
+    /// A keyboard macro is being defined.
+    const MACRODEF = 0x0001000;
+    /// Overwrite mode is active.
+    const OVERWRITE = 0x0002000;
+    /// Completion is in progress.
+    const COMPLETING = 0x0004000;
+    /// libreadline's signal handler is running.
+    const SIGHANDLER = 0x0008000;
+    /// An undo is in progress.
+    const UNDOING = 0x0010000;
+    /// Input was pushed back for reading next.
+    const INPUTPENDING = 0x0020000;
+    /// Terminal special characters got saved.
+    const TTYCSAVED = 0x0040000;
+    /// The callback interface is in use.
+    const CALLBACK = 0x0080000;
+    /// A vi motion argument is being read.
+    const VIMOTION = 0x0100000;
+    /// A multi-key command is being read.
+    const MULTIKEY = 0x0200000;
+    /// vi command mode was entered at least once.
+    const VICMDONCE = 0x0400000;
+    /// A vi character search is in progress.
+    const CHARSEARCH = 0x0800000;
+    /// The display is being updated.
+    const REDISPLAYING = 0x1000000;
+    /// A line got accepted.
+    const DONE = 0x2000000;
+  }
+}
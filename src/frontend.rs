@@ -0,0 +1,86 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Ready-made frontends driving a [`Readline`][crate::Readline]
+//! instance from a terminal or a GUI.
+//!
+//! Each frontend is gated behind a feature of the same name as the
+//! library it is based on.
+
+#[cfg(feature = "crossterm")]
+pub mod crossterm;
+#[cfg(feature = "iced")]
+pub mod iced;
+#[cfg(feature = "termion")]
+pub mod termion;
+
+use std::ffi::CString;
+use std::io::Result;
+use std::io::Write;
+
+use crate::render::Renderer;
+use crate::Readline;
+
+
+/// The terminal width to assume if the actual one is unknown.
+pub(crate) const DEFAULT_COLUMNS: usize = 80;
+
+
+/// Render the current state of the given `Readline` instance.
+pub(crate) fn redraw<W>(
+  rl: &Readline,
+  renderer: &mut Renderer,
+  prompt: &str,
+  mut w: W,
+) -> Result<()>
+where
+  W: Write,
+{
+  // libreadline may display a different prompt in special states,
+  // e.g., while searching the history incrementally.
+  let display_prompt = rl.display_prompt();
+  let mut prompt = if display_prompt != rl.prompt() {
+    display_prompt.to_string_lossy().into_owned()
+  } else {
+    prompt.to_string()
+  };
+  if let Some(mode) = rl.mode_string() {
+    prompt.insert_str(0, &mode.to_string_lossy());
+  }
+
+  rl.peek(|text, cursor| {
+    let output = renderer.render(&prompt, &text.to_string_lossy(), cursor);
+    w.write_all(output.as_bytes())
+  })?;
+  w.flush()
+}
+
+
+/// Render a completed line (or end of input) and record it in the
+/// instance's history.
+pub(crate) fn complete<W>(
+  rl: &Readline,
+  renderer: &mut Renderer,
+  prompt: &str,
+  line: &Option<CString>,
+  mut w: W,
+) -> Result<()>
+where
+  W: Write,
+{
+  let text = line
+    .as_ref()
+    .map(|line| line.to_string_lossy().into_owned())
+    .unwrap_or_default();
+  let output = renderer.render(prompt, &text, text.len());
+  w.write_all(output.as_bytes())?;
+  w.write_all(renderer.finish().as_bytes())?;
+  w.flush()?;
+
+  if let Some(line) = line {
+    if !line.as_bytes().is_empty() {
+      rl.add_history(line);
+    }
+  }
+  Ok(())
+}
@@ -0,0 +1,193 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! A frontend based on the `crossterm` crate.
+
+use std::ffi::CString;
+use std::io::stdout;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Result;
+
+use crossterm::event::read;
+use crossterm::event::Event;
+use crossterm::event::KeyCode;
+use crossterm::event::KeyEvent;
+use crossterm::event::KeyEventKind;
+use crossterm::event::KeyModifiers;
+use crossterm::terminal::disable_raw_mode;
+use crossterm::terminal::enable_raw_mode;
+use crossterm::terminal::size;
+
+use crate::key::Key;
+use crate::key::Modifiers;
+use crate::render::Renderer;
+use crate::Feed;
+use crate::Readline;
+
+use super::complete;
+use super::redraw;
+use super::DEFAULT_COLUMNS;
+
+
+/// Retrieve the width of the terminal, in columns.
+fn columns() -> usize {
+  match size() {
+    Ok((columns, _)) if columns > 0 => columns.into(),
+    _ => DEFAULT_COLUMNS,
+  }
+}
+
+
+/// A guard keeping the terminal in raw mode while alive.
+struct RawMode;
+
+impl RawMode {
+  fn enable() -> Result<Self> {
+    let () = enable_raw_mode()?;
+    Ok(Self)
+  }
+}
+
+impl Drop for RawMode {
+  fn drop(&mut self) {
+    let _result = disable_raw_mode();
+  }
+}
+
+
+/// Convert a `crossterm` key event into the byte sequence to feed to
+/// libreadline.
+///
+/// `None` is returned for key releases and for keys that have no
+/// representation libreadline could understand.
+pub fn convert(event: &KeyEvent) -> Option<Vec<u8>> {
+  if event.kind == KeyEventKind::Release {
+    return None
+  }
+
+  let key = match event.code {
+    KeyCode::Char(c) => Key::Char(c),
+    KeyCode::Backspace => Key::Backspace,
+    KeyCode::Enter => Key::Enter,
+    KeyCode::Tab => Key::Tab,
+    KeyCode::BackTab => Key::BackTab,
+    KeyCode::Esc => Key::Escape,
+    KeyCode::Left => Key::Left,
+    KeyCode::Right => Key::Right,
+    KeyCode::Up => Key::Up,
+    KeyCode::Down => Key::Down,
+    KeyCode::Home => Key::Home,
+    KeyCode::End => Key::End,
+    KeyCode::PageUp => Key::PageUp,
+    KeyCode::PageDown => Key::PageDown,
+    KeyCode::Insert => Key::Insert,
+    KeyCode::Delete => Key::Delete,
+    KeyCode::F(n) => Key::F(n),
+    KeyCode::Null => Key::Char('@'),
+    _ => return None,
+  };
+
+  let modifiers = Modifiers {
+    // Shift is already reflected in the character itself.
+    shift: event.modifiers.contains(KeyModifiers::SHIFT) && !matches!(key, Key::Char(..)),
+    ctrl: event.modifiers.contains(KeyModifiers::CONTROL) || event.code == KeyCode::Null,
+    alt: event.modifiers.contains(KeyModifiers::ALT),
+  };
+  key.encode(modifiers)
+}
+
+
+/// Feed a `crossterm` event to the given `Readline` instance.
+///
+/// This function is meant for applications running their own event
+/// loop. Key presses and pasted text are fed to libreadline, all other
+/// events are ignored. If pasted text contains multiple lines, only the
+/// first one is completed and the remainder is discarded.
+pub fn feed_event(rl: &Readline, event: &Event) -> Option<CString> {
+  match event {
+    Event::Key(key) => convert(key).and_then(|bytes| rl.feed(bytes)),
+    Event::Paste(text) => text.bytes().find_map(|byte| rl.feed([byte])),
+    _ => None,
+  }
+}
+
+
+/// Read a line from the terminal, using the provided `Readline`
+/// instance for editing it.
+///
+/// The terminal is transitioned into raw mode for the duration of the
+/// call and the prompt as well as the line being edited are rendered
+/// to stdout. Completed lines that are not empty are added to the
+/// instance's history.
+///
+/// `None` is returned if the user signaled the end of input by feeding
+/// the end-of-file character (see [`Readline::set_eof_char`]) on an
+/// empty line.
+pub fn read_line(rl: &Readline, prompt: &str) -> Result<Option<CString>> {
+  let prompt_c =
+    CString::new(prompt).map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+  rl.set_prompt(&prompt_c);
+
+  let _raw = RawMode::enable()?;
+  let mut stdout = stdout();
+  let mut renderer = Renderer::new(columns());
+  let () = redraw(rl, &mut renderer, prompt, &mut stdout)?;
+
+  loop {
+    let event = read()?;
+    let result = match &event {
+      Event::Key(key) => match convert(key) {
+        Some(bytes) => match rl.feed_key(bytes) {
+          Feed::Line(line) => Some(Some(line)),
+          Feed::Eof => Some(None),
+          Feed::Pending | Feed::Interrupted => None,
+        },
+        None => None,
+      },
+      Event::Resize(columns, _) => {
+        renderer.set_columns(usize::from(*columns).max(1));
+        None
+      },
+      event => feed_event(rl, event).map(Some),
+    };
+
+    match result {
+      Some(line) => {
+        let () = complete(rl, &mut renderer, prompt, &line, &mut stdout)?;
+        return Ok(line)
+      },
+      None => redraw(rl, &mut renderer, prompt, &mut stdout)?,
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+
+  /// Check that we convert `crossterm` key events properly.
+  #[test]
+  fn key_conversion() {
+    let event = KeyEvent::new(KeyCode::Char('A'), KeyModifiers::SHIFT);
+    assert_eq!(convert(&event).unwrap(), b"A");
+
+    let event = KeyEvent::new(KeyCode::Char('a'), KeyModifiers::CONTROL);
+    assert_eq!(convert(&event).unwrap(), b"\x01");
+
+    let event = KeyEvent::new(KeyCode::Left, KeyModifiers::SHIFT);
+    assert_eq!(convert(&event).unwrap(), b"\x1b[1;2D");
+
+    let rl = Readline::new();
+    let events = [
+      Event::Key(KeyEvent::new(KeyCode::Char('b'), KeyModifiers::NONE)),
+      Event::Key(KeyEvent::new(KeyCode::Left, KeyModifiers::NONE)),
+      Event::Paste("a".to_string()),
+      Event::Key(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE)),
+    ];
+    let line = events.iter().find_map(|event| feed_event(&rl, event));
+    assert_eq!(line, Some(CString::new("ab").unwrap()));
+  }
+}
@@ -0,0 +1,212 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Building blocks for prompt widgets based on the `iced` GUI library.
+
+use std::ffi::CString;
+
+use iced_core::keyboard::key::Named;
+use iced_core::keyboard::Event;
+use iced_core::keyboard::Key as IcedKey;
+
+use crate::key::Key;
+use crate::key::Modifiers;
+use crate::unicode::column;
+use crate::Readline;
+
+
+/// Convert a named `iced` key into a [`Key`].
+fn named(named: Named) -> Option<Key> {
+  let key = match named {
+    Named::Enter => Key::Enter,
+    Named::Tab => Key::Tab,
+    Named::Space => Key::Char(' '),
+    Named::Backspace => Key::Backspace,
+    Named::Escape => Key::Escape,
+    Named::ArrowLeft => Key::Left,
+    Named::ArrowRight => Key::Right,
+    Named::ArrowUp => Key::Up,
+    Named::ArrowDown => Key::Down,
+    Named::Home => Key::Home,
+    Named::End => Key::End,
+    Named::PageUp => Key::PageUp,
+    Named::PageDown => Key::PageDown,
+    Named::Insert => Key::Insert,
+    Named::Delete => Key::Delete,
+    Named::F1 => Key::F(1),
+    Named::F2 => Key::F(2),
+    Named::F3 => Key::F(3),
+    Named::F4 => Key::F(4),
+    Named::F5 => Key::F(5),
+    Named::F6 => Key::F(6),
+    Named::F7 => Key::F(7),
+    Named::F8 => Key::F(8),
+    Named::F9 => Key::F(9),
+    Named::F10 => Key::F(10),
+    Named::F11 => Key::F(11),
+    Named::F12 => Key::F(12),
+    _ => return None,
+  };
+  Some(key)
+}
+
+
+/// Convert an `iced` keyboard event into the byte sequence to feed to
+/// libreadline.
+///
+/// `None` is returned for events other than key presses and for keys
+/// that have no representation libreadline could understand.
+pub fn convert(event: &Event) -> Option<Vec<u8>> {
+  let (key, modifiers, text) = match event {
+    Event::KeyPressed {
+      key,
+      modifiers,
+      text,
+      ..
+    } => (key, modifiers, text),
+    _ => return None,
+  };
+
+  let ctrl = modifiers.control();
+  let alt = modifiers.alt();
+
+  // Unless modifiers are involved, the produced text is what should be
+  // inserted. That takes care of dead keys and the like.
+  if let Some(text) = text {
+    if !ctrl && !alt && !text.is_empty() && !text.chars().any(char::is_control) {
+      return Some(text.as_bytes().to_vec())
+    }
+  }
+
+  let key = match key {
+    IcedKey::Named(named) => self::named(*named)?,
+    IcedKey::Character(c) => Key::Char(c.chars().next()?),
+    IcedKey::Unidentified => return None,
+  };
+
+  let modifiers = Modifiers {
+    // Shift is already reflected in the character itself.
+    shift: modifiers.shift() && !matches!(key, Key::Char(..)),
+    ctrl,
+    alt,
+  };
+  key.encode(modifiers)
+}
+
+
+/// Feed an `iced` keyboard event to the given `Readline` instance.
+///
+/// Events that are not key presses are ignored.
+pub fn feed_event(rl: &Readline, event: &Event) -> Option<CString> {
+  convert(event).and_then(|bytes| rl.feed(bytes))
+}
+
+
+/// A snapshot of the state of a `Readline` instance, ready for display
+/// in a prompt widget.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct View {
+  /// The line being edited.
+  pub line: String,
+  /// The column the cursor is located at, relative to the start of the
+  /// line.
+  pub cursor_column: usize,
+  /// The text suggested for appending to the line, if any.
+  pub suggestion: Option<String>,
+}
+
+impl View {
+  /// Capture the current state of the given `Readline` instance.
+  ///
+  /// Invalid UTF-8 sequences in the line are replaced with
+  /// `U+FFFD REPLACEMENT CHARACTER`.
+  pub fn new(rl: &Readline) -> Self {
+    let (line, cursor_column) = rl.peek(|line, cursor| {
+      let bytes = line.to_bytes();
+      let before = String::from_utf8_lossy(&bytes[..cursor]);
+      let line = line.to_string_lossy().into_owned();
+      let cursor_column = column(&before, before.len());
+      (line, cursor_column)
+    });
+    let suggestion = rl
+      .suggestion()
+      .map(|suggestion| suggestion.to_string_lossy().into_owned());
+
+    Self {
+      line,
+      cursor_column,
+      suggestion,
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use iced_core::keyboard::key::Code;
+  use iced_core::keyboard::key::Physical;
+  use iced_core::keyboard::Location;
+  use iced_core::keyboard::Modifiers as IcedModifiers;
+  use iced_core::SmolStr;
+
+
+  /// Create a key press event.
+  fn press(key: IcedKey, modifiers: IcedModifiers, text: Option<&str>) -> Event {
+    Event::KeyPressed {
+      key: key.clone(),
+      modified_key: key,
+      physical_key: Physical::Code(Code::KeyA),
+      location: Location::Standard,
+      modifiers,
+      text: text.map(SmolStr::new),
+    }
+  }
+
+
+  /// Check that we convert `iced` key events properly.
+  #[test]
+  fn key_conversion() {
+    let a = IcedKey::Character(SmolStr::new("a"));
+    let event = press(a.clone(), IcedModifiers::empty(), Some("a"));
+    assert_eq!(convert(&event).unwrap(), b"a");
+
+    let event = press(a.clone(), IcedModifiers::CTRL, None);
+    assert_eq!(convert(&event).unwrap(), b"\x01");
+
+    let event = press(a, IcedModifiers::ALT, Some("a"));
+    assert_eq!(convert(&event).unwrap(), b"\x1ba");
+
+    let left = IcedKey::Named(Named::ArrowLeft);
+    let event = press(left, IcedModifiers::SHIFT, None);
+    assert_eq!(convert(&event).unwrap(), b"\x1b[1;2D");
+
+    let event = press(IcedKey::Named(Named::Enter), IcedModifiers::empty(), Some("\r"));
+    assert_eq!(convert(&event).unwrap(), b"\r");
+
+    let event = Event::ModifiersChanged(IcedModifiers::SHIFT);
+    assert_eq!(convert(&event), None);
+  }
+
+  /// Check that a `View` reflects the state of a `Readline` instance.
+  #[test]
+  fn view() {
+    let rl = Readline::new();
+    rl.set_autosuggestions(true);
+    rl.add_history(CString::new("\u{4f60}\u{597d} world").unwrap());
+
+    rl.reset(CString::new("\u{4f60}").unwrap(), 3, true);
+    let view = View::new(&rl);
+    assert_eq!(view.line, "\u{4f60}");
+    assert_eq!(view.cursor_column, 2);
+    assert_eq!(view.suggestion.as_deref(), Some("\u{597d} world"));
+
+    let event = press(IcedKey::Character(SmolStr::new("y")), IcedModifiers::SHIFT, Some("Y"));
+    assert_eq!(feed_event(&rl, &event), None);
+    let view = View::new(&rl);
+    assert_eq!(view.line, "\u{4f60}Y");
+    assert_eq!(view.cursor_column, 3);
+    assert_eq!(view.suggestion, None);
+  }
+}
@@ -0,0 +1,96 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! A frontend based on the `termion` crate.
+
+use std::ffi::CString;
+use std::io::stdin;
+use std::io::stdout;
+use std::io::BufRead;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Result;
+use std::io::Write;
+
+use termion::raw::IntoRawMode as _;
+use termion::terminal_size;
+
+use crate::render::Renderer;
+use crate::Feed;
+use crate::Readline;
+
+use super::complete;
+use super::redraw;
+use super::DEFAULT_COLUMNS;
+
+
+/// Retrieve the width of the terminal, in columns.
+pub(crate) fn columns() -> usize {
+  match terminal_size() {
+    Ok((columns, _)) if columns > 0 => columns.into(),
+    _ => DEFAULT_COLUMNS,
+  }
+}
+
+
+/// Read a line from the terminal, using the provided `Readline`
+/// instance for editing it.
+///
+/// The terminal is transitioned into raw mode for the duration of the
+/// call and the prompt as well as the line being edited are rendered
+/// to stdout. Completed lines that are not empty are added to the
+/// instance's history.
+///
+/// `None` is returned if the user signaled the end of input by feeding
+/// the end-of-file character (see [`Readline::set_eof_char`]) on an
+/// empty line or if stdin reached end-of-file.
+pub fn read_line(rl: &Readline, prompt: &str) -> Result<Option<CString>> {
+  let prompt_c =
+    CString::new(prompt).map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+  rl.set_prompt(&prompt_c);
+
+  let stdin = stdin();
+  let mut stdin = stdin.lock();
+  let mut stdout = stdout().into_raw_mode()?;
+
+  let mut renderer = Renderer::new(columns());
+  let () = redraw(rl, &mut renderer, prompt, &mut stdout)?;
+
+  loop {
+    let buffer = stdin.fill_buf()?;
+    if buffer.is_empty() {
+      stdout.write_all(renderer.finish().as_bytes())?;
+      stdout.flush()?;
+      return Ok(None)
+    }
+
+    let mut consumed = 0;
+    let mut result = None;
+
+    for &byte in buffer {
+      consumed += 1;
+
+      // We feed byte by byte so that we never process input past a
+      // completed line. Incomplete escape sequences are buffered by
+      // libreadline until the remaining bytes arrive.
+      match rl.feed_key([byte]) {
+        Feed::Line(line) => result = Some(Some(line)),
+        Feed::Eof => result = Some(None),
+        Feed::Pending | Feed::Interrupted => continue,
+      }
+      break
+    }
+    stdin.consume(consumed);
+
+    // The terminal may have been resized in the meantime.
+    renderer.set_columns(columns());
+
+    match result {
+      Some(line) => {
+        let () = complete(rl, &mut renderer, prompt, &line, &mut stdout)?;
+        return Ok(line)
+      },
+      None => redraw(rl, &mut renderer, prompt, &mut stdout)?,
+    }
+  }
+}
@@ -0,0 +1,254 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! A runtime agnostic asynchronous interface to [`Readline`], based on
+//! the `Stream` and `Sink` traits.
+
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::ffi::CString;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Context;
+use std::task::Poll;
+use std::task::Waker;
+
+use futures_core::Stream;
+use futures_sink::Sink;
+
+use crate::worker::Worker;
+use crate::Feed;
+use crate::Readline;
+
+
+/// The state shared between an [`Input`] and the corresponding
+/// [`Lines`] object.
+#[derive(Debug, Default)]
+struct Shared {
+  /// Completed lines not yet retrieved.
+  lines: VecDeque<CString>,
+  /// Whether all input has been processed or the end of input got
+  /// signaled.
+  closed: bool,
+  /// The waker to notify once a line got completed or input closed.
+  waker: Option<Waker>,
+}
+
+impl Shared {
+  /// Wake the task waiting for lines, if any.
+  fn wake(&mut self) {
+    if let Some(waker) = self.waker.take() {
+      waker.wake()
+    }
+  }
+}
+
+
+/// A `Sink` accepting input bytes for a [`Readline`] instance.
+///
+/// Items can be arbitrary chunks of input and are fed byte by byte.
+/// Input is processed on a worker thread, in the order in which it was
+/// sent. Closing or dropping the sink terminates the corresponding
+/// [`Lines`] stream once all input got processed. So does feeding the
+/// end-of-file character on an empty line (see
+/// [`Readline::set_eof_char`]), with any input following it being
+/// ignored.
+#[derive(Debug)]
+pub struct Input {
+  /// The worker owning the `Readline` instance.
+  worker: Worker,
+  /// The state shared with the `Lines` stream.
+  shared: Arc<Mutex<Shared>>,
+  /// Whether the sink has been closed already.
+  closed: bool,
+}
+
+impl Input {
+  /// Close the sink, causing the corresponding stream to end once all
+  /// previously sent input got processed.
+  fn close(&mut self) {
+    if !self.closed {
+      self.closed = true;
+
+      let shared = Arc::clone(&self.shared);
+      let () = self.worker.submit(move |_rl| {
+        let mut shared = shared.lock().unwrap();
+        shared.closed = true;
+        shared.wake();
+      });
+    }
+  }
+}
+
+impl<K> Sink<K> for Input
+where
+  K: AsRef<[u8]>,
+{
+  type Error = Infallible;
+
+  fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    Poll::Ready(Ok(()))
+  }
+
+  fn start_send(self: Pin<&mut Self>, item: K) -> Result<(), Self::Error> {
+    let input = item.as_ref().to_vec();
+    let shared = Arc::clone(&self.shared);
+    let () = self.worker.submit(move |rl| {
+      // Input following the end of input is ignored.
+      if shared.lock().unwrap().closed {
+        return
+      }
+
+      for byte in input {
+        match rl.feed_key([byte]) {
+          Feed::Line(line) => {
+            let mut shared = shared.lock().unwrap();
+            shared.lines.push_back(line);
+            shared.wake();
+          },
+          Feed::Eof => {
+            let mut shared = shared.lock().unwrap();
+            shared.closed = true;
+            shared.wake();
+            break
+          },
+          Feed::Pending | Feed::Interrupted => (),
+        }
+      }
+    });
+    Ok(())
+  }
+
+  /// Flush the sink.
+  ///
+  /// Input is handed to the worker thread right away, so flushing
+  /// completes immediately. It does not wait for the input to be
+  /// processed.
+  fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    Poll::Ready(Ok(()))
+  }
+
+  fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    let () = self.get_mut().close();
+    Poll::Ready(Ok(()))
+  }
+}
+
+impl Drop for Input {
+  fn drop(&mut self) {
+    self.close()
+  }
+}
+
+
+/// A `Stream` of lines completed by a [`Readline`] instance.
+#[derive(Debug)]
+pub struct Lines {
+  /// The state shared with the `Input` sink.
+  shared: Arc<Mutex<Shared>>,
+}
+
+impl Stream for Lines {
+  type Item = CString;
+
+  fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    let mut shared = self.shared.lock().unwrap();
+    if let Some(line) = shared.lines.pop_front() {
+      Poll::Ready(Some(line))
+    } else if shared.closed {
+      Poll::Ready(None)
+    } else {
+      shared.waker = Some(cx.waker().clone());
+      Poll::Pending
+    }
+  }
+}
+
+
+/// Create an [`Input`] sink and a [`Lines`] stream for driving the
+/// given [`Readline`] instance asynchronously.
+///
+/// The instance is moved onto a dedicated worker thread, which is shut
+/// down once the sink got dropped and all input processed.
+pub fn channel(rl: Readline) -> (Input, Lines) {
+  let shared = Arc::new(Mutex::new(Shared::default()));
+  let input = Input {
+    worker: Worker::new(rl),
+    shared: Arc::clone(&shared),
+    closed: false,
+  };
+  let lines = Lines { shared };
+  (input, lines)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::ptr::null;
+  use std::task::RawWaker;
+  use std::task::RawWakerVTable;
+  use std::thread::yield_now;
+
+
+  /// Create a `Waker` that does nothing.
+  fn noop_waker() -> Waker {
+    fn clone(_: *const ()) -> RawWaker {
+      RawWaker::new(null(), &VTABLE)
+    }
+    fn noop(_: *const ()) {}
+
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+    unsafe { Waker::from_raw(clone(null())) }
+  }
+
+  /// Poll the provided stream until it yields an item.
+  fn next(lines: &mut Lines) -> Option<CString> {
+    let waker = noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    loop {
+      match Pin::new(&mut *lines).poll_next(&mut cx) {
+        Poll::Ready(item) => break item,
+        Poll::Pending => yield_now(),
+      }
+    }
+  }
+
+
+  /// Check that we can drive a `Readline` instance through a `Sink`
+  /// and `Stream` pair.
+  #[test]
+  fn sink_and_stream() {
+    let waker = noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    let (mut input, mut lines) = channel(Readline::new());
+
+    let mut input = Pin::new(&mut input);
+    let () = input.as_mut().start_send(b"ab\rc").unwrap();
+    let () = input.as_mut().start_send("d\r").unwrap();
+    assert_eq!(next(&mut lines), Some(CString::new("ab").unwrap()));
+    assert_eq!(next(&mut lines), Some(CString::new("cd").unwrap()));
+
+    let () = input.as_mut().start_send(b"e\r").unwrap();
+    match Sink::<&[u8]>::poll_close(input, &mut cx) {
+      Poll::Ready(Ok(())) => (),
+      _ => panic!("failed to close sink"),
+    }
+    assert_eq!(next(&mut lines), Some(CString::new("e").unwrap()));
+    assert_eq!(next(&mut lines), None);
+  }
+
+  /// Check that the end-of-file character terminates the stream.
+  #[test]
+  fn eof() {
+    let (mut input, mut lines) = channel(Readline::new());
+
+    let mut input = Pin::new(&mut input);
+    let () = input.as_mut().start_send(b"a\x04\r\x04b\r").unwrap();
+    let () = input.as_mut().start_send(b"c\r").unwrap();
+    assert_eq!(next(&mut lines), Some(CString::new("a").unwrap()));
+    assert_eq!(next(&mut lines), None);
+  }
+}
@@ -0,0 +1,291 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Golden files describing input sessions along with their expected
+//! outcome.
+
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::fmt::Display;
+use std::fmt::Error;
+use std::fmt::Formatter;
+use std::fs::read_to_string;
+use std::io;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::str;
+use std::str::FromStr;
+
+use crate::record::unescape;
+use crate::record::Escaped;
+use crate::Feed;
+use crate::ReadlineTester;
+
+
+/// A single step of a golden session.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Step {
+  /// Type the given keys.
+  Keys(Vec<u8>),
+  /// Expect the given line to be edited.
+  Line(Vec<u8>),
+  /// Expect the cursor to be at the given byte offset.
+  Cursor(usize),
+  /// Expect the given outcome of a feed.
+  Outcome(Feed),
+}
+
+
+/// A mismatch between the expected and the actual outcome of a golden
+/// session.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GoldenMismatch {
+  /// The number of the line in the golden file containing the failed
+  /// expectation. Mismatches detected after the last step, such as
+  /// unexpected outcomes, refer to the line following the last one.
+  pub line: usize,
+  /// The expectation, as stated in the golden file.
+  pub expected: String,
+  /// The actual state, in the same format.
+  pub actual: String,
+}
+
+impl Display for GoldenMismatch {
+  fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+    write!(
+      f,
+      "line {}: expected `{}` but found `{}`",
+      self.line, self.expected, self.actual
+    )
+  }
+}
+
+impl std::error::Error for GoldenMismatch {}
+
+
+/// Format the given step as it would appear in a golden file.
+fn format_step(step: &Step) -> String {
+  match step {
+    Step::Keys(keys) => format!("keys {}", Escaped(keys)),
+    Step::Line(text) => format!("line {}", Escaped(text)),
+    Step::Cursor(cursor) => format!("cursor {}", cursor),
+    Step::Outcome(outcome) => format_outcome(Some(outcome)),
+  }
+}
+
+
+/// Format the given outcome as it would appear in a golden file.
+fn format_outcome(outcome: Option<&Feed>) -> String {
+  match outcome {
+    Some(Feed::Line(line)) => format!("accepted {}", Escaped(line.to_bytes())),
+    Some(Feed::Interrupted) => "interrupted".to_string(),
+    Some(Feed::Eof) => "eof".to_string(),
+    Some(Feed::Pending) | None => "nothing".to_string(),
+  }
+}
+
+
+/// A description of an input session along with its expected outcome,
+/// for use in golden file based tests.
+///
+/// A golden file is a text file containing one step per line. Empty
+/// lines and lines starting with `#` are ignored. Each step is one of
+/// the following, with arguments being escaped as in the textual form
+/// of a [`Recording`][crate::Recording], i.e., with spaces and bytes
+/// outside of the printable ASCII range hex encoded (e.g., `\x20`) or
+/// written as `\e`, `\n`, `\r`, or `\t`, and with backslashes
+/// doubled:
+/// - `keys <keys>`: type the given keys, character by character
+/// - `line <text>`: expect the line being edited to be `text`
+/// - `cursor <offset>`: expect the cursor to be at byte `offset`
+/// - `accepted <text>`: expect the line `text` to have been accepted
+/// - `eof`: expect the end of input to have been signaled
+/// - `interrupted`: expect the line to have been interrupted
+///
+/// For example:
+/// ```text
+/// # Move the cursor left and insert text.
+/// keys ab\e[D\x20c
+/// line a\x20cb
+/// cursor 3
+/// keys \r
+/// accepted a\x20cb
+/// ```
+///
+/// Outcomes reported by feeds (`accepted`, `eof`, `interrupted`) have
+/// to be expected in the order they occurred. An outcome not expected
+/// by the end of the session constitutes a mismatch.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Golden {
+  /// The steps of the session, along with their line number.
+  steps: Vec<(usize, Step)>,
+}
+
+impl Golden {
+  /// Read a golden session from the file at the given path.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the file could not be read or if its content
+  /// is not a valid golden session.
+  pub fn read<P>(path: P) -> io::Result<Self>
+  where
+    P: AsRef<Path>,
+  {
+    read_to_string(path)?.parse()
+  }
+
+  /// Run the session on the given tester, checking all expectations.
+  ///
+  /// # Errors
+  ///
+  /// Returns the first expectation that was not met.
+  pub fn run(&self, rl: &ReadlineTester) -> Result<(), GoldenMismatch> {
+    let mut outcomes = VecDeque::from(rl.take_outcomes());
+    let mismatch = |line, expected, actual| GoldenMismatch {
+      line,
+      expected: format_step(expected),
+      actual,
+    };
+
+    for (line, step) in &self.steps {
+      match step {
+        Step::Keys(keys) => {
+          let _rl = match str::from_utf8(keys) {
+            Ok(keys) => rl.type_str(keys),
+            Err(..) => rl.type_bytes(keys),
+          };
+          outcomes.extend(rl.take_outcomes());
+        },
+        Step::Line(text) => {
+          let actual = rl.peek(|line, _| line.to_bytes().to_vec());
+          if &actual != text {
+            return Err(mismatch(*line, step, format!("line {}", Escaped(&actual))))
+          }
+        },
+        Step::Cursor(cursor) => {
+          let actual = rl.peek(|_, cursor| cursor);
+          if actual != *cursor {
+            return Err(mismatch(*line, step, format!("cursor {}", actual)))
+          }
+        },
+        Step::Outcome(outcome) => {
+          let actual = outcomes.pop_front();
+          if actual.as_ref() != Some(outcome) {
+            return Err(mismatch(*line, step, format_outcome(actual.as_ref())))
+          }
+        },
+      }
+    }
+
+    if let Some(outcome) = outcomes.pop_front() {
+      let line = self.steps.last().map(|(line, _)| line + 1).unwrap_or(1);
+      return Err(GoldenMismatch {
+        line,
+        expected: format_outcome(None),
+        actual: format_outcome(Some(&outcome)),
+      })
+    }
+    Ok(())
+  }
+}
+
+impl FromStr for Golden {
+  type Err = io::Error;
+
+  /// Parse a golden session from its textual representation.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error of kind [`InvalidData`][ErrorKind::InvalidData]
+  /// if `s` is not a valid golden session.
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    fn parse_step(line: &str) -> Option<Step> {
+      let mut fields = line.splitn(2, ' ');
+      let step = match (fields.next()?, fields.next()) {
+        ("keys", Some(keys)) => Step::Keys(unescape(keys)?),
+        ("line", text) => Step::Line(unescape(text.unwrap_or(""))?),
+        ("cursor", Some(cursor)) => Step::Cursor(cursor.parse().ok()?),
+        ("accepted", text) => {
+          let text = CString::new(unescape(text.unwrap_or(""))?).ok()?;
+          Step::Outcome(Feed::Line(text))
+        },
+        ("eof", None) => Step::Outcome(Feed::Eof),
+        ("interrupted", None) => Step::Outcome(Feed::Interrupted),
+        _ => return None,
+      };
+      Some(step)
+    }
+
+    let steps = s
+      .lines()
+      .enumerate()
+      .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+      .map(|(idx, line)| {
+        let step = parse_step(line).ok_or_else(|| {
+          io::Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid golden session step in line {}: {}", idx + 1, line),
+          )
+        })?;
+        Ok((idx + 1, step))
+      })
+      .collect::<io::Result<_>>()?;
+
+    Ok(Self { steps })
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+
+  /// Check that golden sessions are parsed and run as expected.
+  #[test]
+  fn running() {
+    let golden = r"
+# Editing a line.
+keys ab\x1b[D\x20c
+line a\x20cb
+cursor 3
+keys \r
+accepted a\x20cb
+line
+keys \x04
+eof
+";
+    let golden = golden.parse::<Golden>().unwrap();
+    let rl = ReadlineTester::new();
+    let () = golden.run(&rl).unwrap();
+
+    let golden = "keys x\nline y\n".parse::<Golden>().unwrap();
+    let err = ReadlineTester::new();
+    let err = golden.run(&err).unwrap_err();
+    assert_eq!(
+      err,
+      GoldenMismatch {
+        line: 2,
+        expected: "line y".to_string(),
+        actual: "line x".to_string(),
+      }
+    );
+
+    let golden = "keys x\\r\n".parse::<Golden>().unwrap();
+    let err = golden.run(&ReadlineTester::new()).unwrap_err();
+    assert_eq!(err.line, 2);
+    assert_eq!(err.expected, "nothing");
+    assert_eq!(err.actual, "accepted x");
+  }
+
+  /// Check that invalid golden sessions are rejected.
+  #[test]
+  fn invalid() {
+    let invalid = ["keys", "cursor x", "eof x", "type a", "keys \\q", "accepted \\x00"];
+    for text in invalid.iter() {
+      let err = text.parse::<Golden>().unwrap_err();
+      assert_eq!(err.kind(), ErrorKind::InvalidData, "{}", text);
+    }
+  }
+}
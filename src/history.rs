@@ -0,0 +1,337 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Per-instance management of libhistory's global history list.
+
+use std::any::Any;
+use std::collections::VecDeque;
+use std::ffi::CStr;
+use std::fmt::Debug;
+use std::fmt::Error;
+use std::fmt::Formatter;
+use std::io;
+use std::mem::size_of;
+use std::mem::size_of_val;
+use std::ptr::null_mut;
+use std::slice;
+
+use libc::c_char;
+use libc::c_int;
+use libc::c_void;
+use libc::free;
+
+use crate::Limit;
+use crate::Usage;
+
+
+/// libhistory's representation of a single history entry.
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub(crate) struct HIST_ENTRY {
+  pub(crate) line: *mut c_char,
+  pub(crate) timestamp: *mut c_char,
+  pub(crate) data: *mut c_void,
+}
+
+/// libhistory's `HISTORY_STATE`, capturing the global history list.
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug)]
+struct HISTORY_STATE {
+  entries: *mut *mut HIST_ENTRY,
+  offset: c_int,
+  length: c_int,
+  size: c_int,
+  flags: c_int,
+}
+
+
+// Declarations as provided by libhistory (which is part of
+// libreadline).
+extern "C" {
+  static history_length: c_int;
+
+  fn history_get_history_state() -> *mut HISTORY_STATE;
+  fn history_set_history_state(state: *mut HISTORY_STATE);
+
+  fn using_history();
+  fn history_list() -> *mut *mut HIST_ENTRY;
+  fn history_set_pos(pos: c_int) -> c_int;
+  fn add_history(line: *const c_char);
+  fn read_history(filename: *const c_char) -> c_int;
+  fn remove_history(which: c_int) -> *mut HIST_ENTRY;
+  fn free_history_entry(entry: *mut HIST_ENTRY) -> *mut c_void;
+  fn clear_history();
+  fn history_total_bytes() -> c_int;
+}
+
+
+/// Application data associated with a history entry.
+pub(crate) struct Data(pub(crate) Box<dyn Any + Send>);
+
+impl Debug for Data {
+  fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+    f.debug_tuple("Data").finish()
+  }
+}
+
+
+/// An entry of a `Readline` instance's history.
+#[derive(Debug)]
+pub struct HistoryEntry<'history> {
+  /// The line making up the entry.
+  line: &'history CStr,
+  /// The data associated with the entry, if any.
+  data: Option<&'history (dyn Any + Send)>,
+}
+
+impl<'history> HistoryEntry<'history> {
+  /// Retrieve the line making up the entry.
+  pub fn line(&self) -> &'history CStr {
+    self.line
+  }
+
+  /// Retrieve the data associated with the entry when it got added
+  /// (see [`Readline::add_history_with_data`][crate::Readline::add_history_with_data]),
+  /// if any and if it is of type `T`.
+  pub fn data<T>(&self) -> Option<&'history T>
+  where
+    T: Any,
+  {
+    self.data?.downcast_ref()
+  }
+}
+
+
+/// The history list of a single `Readline` instance.
+///
+/// libhistory keeps exactly one history list in global variables. In
+/// much the same way as we do for `readline_state`, we save and
+/// restore that list on every context switch, making the history
+/// effectively per-instance.
+///
+/// libreadline uses the application data pointer of history entries
+/// for its own purposes, so we keep data associated with entries
+/// separately, one (optional) item per entry.
+#[derive(Debug)]
+pub(crate) struct History {
+  state: HISTORY_STATE,
+  limit: Limit,
+  data: VecDeque<Option<Data>>,
+}
+
+// SAFETY: The history entries are heap allocated and only ever
+//         accessed while holding the global mutex, regardless of the
+//         thread we are on.
+unsafe impl Send for History {}
+
+impl History {
+  /// Create a new, empty history.
+  pub(crate) fn new() -> Self {
+    Self {
+      state: HISTORY_STATE {
+        entries: null_mut(),
+        offset: 0,
+        length: 0,
+        size: 0,
+        flags: 0,
+      },
+      limit: Limit::default(),
+      data: VecDeque::new(),
+    }
+  }
+
+  /// Load the history from libhistory's globals.
+  pub(crate) fn load(&mut self) {
+    unsafe {
+      let state = history_get_history_state();
+      assert!(!state.is_null(), "failed to retrieve history state");
+      self.state = *state;
+      free(state.cast());
+    }
+  }
+
+  /// Save the history into libhistory's globals.
+  pub(crate) fn save(&self) {
+    let mut state = self.state;
+    unsafe { history_set_history_state(&mut state) }
+  }
+
+  /// Retrieve the number of entries, as of the most recent load.
+  pub(crate) fn len(&self) -> usize {
+    self.state.length as usize
+  }
+
+  /// Retrieve the history entries currently in libhistory's globals.
+  ///
+  /// The history has to be active for the result to be meaningful.
+  pub(crate) fn entries(&self) -> &[*mut HIST_ENTRY] {
+    unsafe {
+      let list = history_list();
+      if list.is_null() {
+        &[]
+      } else {
+        slice::from_raw_parts(list, history_length as _)
+      }
+    }
+  }
+
+  /// Retrieve the lines of the active history, from oldest to newest.
+  pub(crate) fn lines(&self) -> impl DoubleEndedIterator<Item = &'_ CStr> + '_ {
+    self.entries().iter().filter_map(|entry| {
+      let line = unsafe { (**entry).line };
+      if line.is_null() {
+        None
+      } else {
+        Some(unsafe { CStr::from_ptr(line) })
+      }
+    })
+  }
+
+  /// Retrieve the entries of the active history along with their
+  /// data, from oldest to newest.
+  pub(crate) fn with_data(&self) -> Vec<HistoryEntry<'_>> {
+    self
+      .entries()
+      .iter()
+      .zip(self.data.iter())
+      .map(|(entry, data)| {
+        let line = unsafe { (**entry).line };
+        HistoryEntry {
+          line: if line.is_null() {
+            <&CStr>::default()
+          } else {
+            unsafe { CStr::from_ptr(line) }
+          },
+          data: data.as_ref().map(|Data(data)| &**data),
+        }
+      })
+      .collect()
+  }
+
+  /// Append a line to the active history, evicting the oldest entries
+  /// if the configured limit is exceeded.
+  pub(crate) fn add(&mut self, line: &CStr, data: Option<Data>) {
+    unsafe { add_history(line.as_ptr()) };
+    self.data.push_back(data);
+    self.enforce();
+    // Position the history cursor past the newly added entry, so that
+    // navigating backwards starts with it.
+    unsafe { using_history() }
+  }
+
+  /// Position the history cursor at the entry with the given index of
+  /// the active history.
+  pub(crate) fn set_position(&mut self, index: usize) {
+    let _result = unsafe { history_set_pos(index as _) };
+  }
+
+  /// Append the lines stored in the given file to the active history,
+  /// evicting the oldest entries if the configured limit is exceeded.
+  pub(crate) fn read(&mut self, path: &CStr) -> io::Result<()> {
+    let result = unsafe { read_history(path.as_ptr()) };
+    if result != 0 {
+      return Err(io::Error::from_raw_os_error(result))
+    }
+
+    let entries = self.entries().len();
+    self.data.resize_with(entries, || None);
+    self.enforce();
+    unsafe { using_history() }
+    Ok(())
+  }
+
+  /// Set the limit to apply to the history, evicting entries as
+  /// necessary.
+  pub(crate) fn set_limit(&mut self, limit: Limit) {
+    self.limit = limit;
+    self.enforce();
+    unsafe { using_history() }
+  }
+
+  /// Remove the oldest entries of the active history until it fits
+  /// within the configured limit.
+  fn enforce(&mut self) {
+    let mut usage = self.usage();
+    while !self.limit.admits(usage) {
+      let entry = unsafe { remove_history(0) };
+      if entry.is_null() {
+        break
+      }
+
+      let bytes = unsafe { entry_bytes(entry) };
+      let _data = unsafe { free_history_entry(entry) };
+      let _metadata = self.data.pop_front();
+      usage.entries -= 1;
+      usage.bytes -= bytes;
+    }
+  }
+
+  /// Report the memory used by the active history.
+  pub(crate) fn usage(&self) -> Usage {
+    Usage {
+      entries: self.entries().len(),
+      bytes: unsafe { history_total_bytes() } as _,
+    }
+  }
+
+  /// Approximate the heap memory used by the active history, in bytes,
+  /// including libhistory's bookkeeping and associated data.
+  pub(crate) fn memory(&self) -> usize {
+    let entries = self.entries();
+    let list = (self.state.size.max(0) as usize).max(entries.len()) * size_of::<*mut HIST_ENTRY>();
+    let bytes = entries
+      .iter()
+      .map(|entry| {
+        let entry = unsafe { &**entry };
+        let mut bytes = size_of::<HIST_ENTRY>();
+        if !entry.line.is_null() {
+          bytes += unsafe { CStr::from_ptr(entry.line) }.to_bytes_with_nul().len();
+        }
+        if !entry.timestamp.is_null() {
+          bytes += unsafe { CStr::from_ptr(entry.timestamp) }.to_bytes_with_nul().len();
+        }
+        bytes
+      })
+      .sum::<usize>();
+    let data = self.data.capacity() * size_of::<Option<Data>>()
+      + self
+        .data
+        .iter()
+        .flatten()
+        .map(|Data(data)| size_of_val(&**data))
+        .sum::<usize>();
+    list + bytes + data
+  }
+
+  /// Release all memory associated with the active history.
+  pub(crate) fn clear(&mut self) {
+    unsafe {
+      clear_history();
+      let state = history_get_history_state();
+      free((*state).entries.cast());
+      free(state.cast());
+    }
+
+    self.state = Self::new().state;
+    self.data.clear();
+    // Make sure that libhistory does not keep a dangling reference to
+    // the array we just freed.
+    self.save();
+  }
+}
+
+
+/// Calculate the number of bytes occupied by the given history entry,
+/// in the same way libhistory's `history_total_bytes` does.
+unsafe fn entry_bytes(entry: *const HIST_ENTRY) -> usize {
+  let entry = unsafe { &*entry };
+  let mut bytes = 0;
+  if !entry.line.is_null() {
+    bytes += unsafe { CStr::from_ptr(entry.line) }.to_bytes().len();
+  }
+  if !entry.timestamp.is_null() {
+    bytes += unsafe { CStr::from_ptr(entry.timestamp) }.to_bytes().len();
+  }
+  bytes
+}
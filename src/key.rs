@@ -0,0 +1,592 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Functionality for converting key presses into the byte sequences
+//! libreadline understands.
+//!
+//! libreadline works on bytes as they would be emitted by a terminal.
+//! Applications that receive input in the form of decoded key events
+//! (e.g., from a GUI toolkit or a terminal library) can use the types
+//! in this module to convert them into a feedable form.
+
+pub mod gdk;
+#[cfg(feature = "proptest")]
+pub mod strategy;
+
+use std::fmt::Display;
+use std::fmt::Error;
+use std::fmt::Formatter;
+use std::fmt::Write as _;
+use std::str;
+
+
+/// A key on a keyboard.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum Key {
+  /// A key producing a character.
+  Char(char),
+  /// The backspace key.
+  Backspace,
+  /// The enter key.
+  Enter,
+  /// The tab key.
+  Tab,
+  /// The tab key pressed together with shift.
+  BackTab,
+  /// The escape key.
+  Escape,
+  /// The left arrow key.
+  Left,
+  /// The right arrow key.
+  Right,
+  /// The up arrow key.
+  Up,
+  /// The down arrow key.
+  Down,
+  /// The home key.
+  Home,
+  /// The end key.
+  End,
+  /// The page up key.
+  PageUp,
+  /// The page down key.
+  PageDown,
+  /// The insert key.
+  Insert,
+  /// The delete key.
+  Delete,
+  /// A function key, e.g., `F(1)` for F1.
+  F(u8),
+}
+
+
+impl Display for Key {
+  fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+    match self {
+      Self::Char(' ') => f.write_str("Space"),
+      Self::Char(c) => write!(f, "{}", c),
+      Self::Backspace => f.write_str("Backspace"),
+      Self::Enter => f.write_str("Enter"),
+      Self::Tab => f.write_str("Tab"),
+      Self::BackTab => f.write_str("BackTab"),
+      Self::Escape => f.write_str("Esc"),
+      Self::Left => f.write_str("Left"),
+      Self::Right => f.write_str("Right"),
+      Self::Up => f.write_str("Up"),
+      Self::Down => f.write_str("Down"),
+      Self::Home => f.write_str("Home"),
+      Self::End => f.write_str("End"),
+      Self::PageUp => f.write_str("PageUp"),
+      Self::PageDown => f.write_str("PageDown"),
+      Self::Insert => f.write_str("Insert"),
+      Self::Delete => f.write_str("Delete"),
+      Self::F(n) => write!(f, "F{}", n),
+    }
+  }
+}
+
+
+/// The modifiers active while a key is pressed.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct Modifiers {
+  /// Whether the shift key is held.
+  pub shift: bool,
+  /// Whether the control key is held.
+  pub ctrl: bool,
+  /// Whether the alt (or meta) key is held.
+  pub alt: bool,
+}
+
+impl Modifiers {
+  /// The xterm style modifier parameter for CSI sequences, if any
+  /// modifier is active.
+  fn parameter(self) -> Option<u8> {
+    let value = 1 + u8::from(self.shift) + 2 * u8::from(self.alt) + 4 * u8::from(self.ctrl);
+    if value > 1 {
+      Some(value)
+    } else {
+      None
+    }
+  }
+}
+
+
+/// Create a CSI sequence of the form `ESC [ <code> <final>` or
+/// `ESC [ 1 ; <modifiers> <final>`.
+fn csi_letter(letter: char, modifiers: Modifiers) -> Vec<u8> {
+  match modifiers.parameter() {
+    Some(param) => format!("\x1b[1;{}{}", param, letter).into_bytes(),
+    None => format!("\x1b[{}", letter).into_bytes(),
+  }
+}
+
+/// Create a CSI sequence of the form `ESC [ <number> ~` or
+/// `ESC [ <number> ; <modifiers> ~`.
+fn csi_tilde(number: u8, modifiers: Modifiers) -> Vec<u8> {
+  match modifiers.parameter() {
+    Some(param) => format!("\x1b[{};{}~", number, param).into_bytes(),
+    None => format!("\x1b[{}~", number).into_bytes(),
+  }
+}
+
+/// Create an SS3 sequence (`ESC O <final>`), as used for F1 to F4.
+fn ss3(letter: char, modifiers: Modifiers) -> Vec<u8> {
+  match modifiers.parameter() {
+    Some(param) => format!("\x1b[1;{}{}", param, letter).into_bytes(),
+    None => format!("\x1bO{}", letter).into_bytes(),
+  }
+}
+
+
+impl Key {
+  /// Encode the key, pressed with the given modifiers, into the byte
+  /// sequence an xterm compatible terminal would emit for it.
+  ///
+  /// The result can be passed to [`Readline::feed`][crate::Readline::feed].
+  /// `None` is returned for keys that have no such representation,
+  /// e.g., function keys beyond F12.
+  pub fn encode(self, modifiers: Modifiers) -> Option<Vec<u8>> {
+    let bytes = match self {
+      Self::Char(c) => {
+        let mut bytes = if modifiers.ctrl {
+          match c {
+            '@' | ' ' => vec![0x00],
+            'a'..='z' | 'A'..='Z' | '[' | '\\' | ']' | '^' | '_' => {
+              vec![(c.to_ascii_uppercase() as u8) & 0x1f]
+            },
+            '?' => vec![0x7f],
+            _ => c.to_string().into_bytes(),
+          }
+        } else {
+          c.to_string().into_bytes()
+        };
+
+        if modifiers.alt {
+          bytes.insert(0, 0x1b);
+        }
+        bytes
+      },
+      Self::Backspace => {
+        if modifiers.alt {
+          vec![0x1b, 0x7f]
+        } else if modifiers.ctrl {
+          vec![0x08]
+        } else {
+          vec![0x7f]
+        }
+      },
+      Self::Enter => {
+        if modifiers.alt {
+          vec![0x1b, b'\r']
+        } else {
+          vec![b'\r']
+        }
+      },
+      Self::Tab if modifiers.shift => b"\x1b[Z".to_vec(),
+      Self::Tab => {
+        if modifiers.alt {
+          vec![0x1b, b'\t']
+        } else {
+          vec![b'\t']
+        }
+      },
+      Self::BackTab => b"\x1b[Z".to_vec(),
+      Self::Escape => vec![0x1b],
+      Self::Up => csi_letter('A', modifiers),
+      Self::Down => csi_letter('B', modifiers),
+      Self::Right => csi_letter('C', modifiers),
+      Self::Left => csi_letter('D', modifiers),
+      Self::Home => csi_letter('H', modifiers),
+      Self::End => csi_letter('F', modifiers),
+      Self::Insert => csi_tilde(2, modifiers),
+      Self::Delete => csi_tilde(3, modifiers),
+      Self::PageUp => csi_tilde(5, modifiers),
+      Self::PageDown => csi_tilde(6, modifiers),
+      Self::F(n) => match n {
+        1 => ss3('P', modifiers),
+        2 => ss3('Q', modifiers),
+        3 => ss3('R', modifiers),
+        4 => ss3('S', modifiers),
+        5 => csi_tilde(15, modifiers),
+        6 => csi_tilde(17, modifiers),
+        7 => csi_tilde(18, modifiers),
+        8 => csi_tilde(19, modifiers),
+        9 => csi_tilde(20, modifiers),
+        10 => csi_tilde(21, modifiers),
+        11 => csi_tilde(23, modifiers),
+        12 => csi_tilde(24, modifiers),
+        _ => return None,
+      },
+    };
+    Some(bytes)
+  }
+}
+
+
+/// The result of decoding the start of a byte sequence into a key
+/// press.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Decode {
+  /// A key press, along with the number of bytes it spans.
+  Key(Key, Modifiers, usize),
+  /// A sequence of the given length that does not correspond to a
+  /// known key, e.g., an unsupported escape sequence.
+  Unknown(usize),
+  /// The bytes are the start of a key press, but incomplete.
+  Incomplete,
+}
+
+
+/// Convert an xterm style modifier parameter into `Modifiers`.
+fn modifiers(param: Option<u16>) -> Modifiers {
+  let bits = param.unwrap_or(1).saturating_sub(1);
+  Modifiers {
+    shift: bits & 1 != 0,
+    alt: bits & 2 != 0,
+    ctrl: bits & 4 != 0,
+  }
+}
+
+
+/// Decode a CSI sequence, i.e., one of the form
+/// `ESC [ <params> <final>`, with `bytes` starting after the `[`.
+fn decode_csi(bytes: &[u8]) -> Decode {
+  let end = match bytes.iter().position(|b| (0x40..=0x7e).contains(b)) {
+    Some(end) => end,
+    None if bytes.iter().all(|b| (0x20..=0x3f).contains(b)) => return Decode::Incomplete,
+    None => return Decode::Unknown(2),
+  };
+  let len = 2 + end + 1;
+
+  let params = bytes[..end]
+    .split(|b| *b == b';')
+    .map(|param| {
+      str::from_utf8(param)
+        .ok()
+        .and_then(|param| param.parse::<u16>().ok())
+    })
+    .collect::<Vec<_>>();
+  let number = params.first().copied().flatten();
+  let modifiers = modifiers(params.get(1).copied().flatten());
+
+  let key = match (bytes[end], number) {
+    (b'A', _) => Key::Up,
+    (b'B', _) => Key::Down,
+    (b'C', _) => Key::Right,
+    (b'D', _) => Key::Left,
+    (b'H', _) => Key::Home,
+    (b'F', _) => Key::End,
+    (b'P', _) => Key::F(1),
+    (b'Q', _) => Key::F(2),
+    (b'R', _) => Key::F(3),
+    (b'S', _) => Key::F(4),
+    (b'Z', _) => Key::BackTab,
+    (b'~', Some(1)) | (b'~', Some(7)) => Key::Home,
+    (b'~', Some(2)) => Key::Insert,
+    (b'~', Some(3)) => Key::Delete,
+    (b'~', Some(4)) | (b'~', Some(8)) => Key::End,
+    (b'~', Some(5)) => Key::PageUp,
+    (b'~', Some(6)) => Key::PageDown,
+    (b'~', Some(15)) => Key::F(5),
+    (b'~', Some(17)) => Key::F(6),
+    (b'~', Some(18)) => Key::F(7),
+    (b'~', Some(19)) => Key::F(8),
+    (b'~', Some(20)) => Key::F(9),
+    (b'~', Some(21)) => Key::F(10),
+    (b'~', Some(23)) => Key::F(11),
+    (b'~', Some(24)) => Key::F(12),
+    _ => return Decode::Unknown(len),
+  };
+  Decode::Key(key, modifiers, len)
+}
+
+
+/// Decode a key press not involving an escape sequence.
+fn decode_plain(bytes: &[u8]) -> Decode {
+  let ctrl = Modifiers {
+    ctrl: true,
+    ..Default::default()
+  };
+  let none = Modifiers::default();
+
+  let (key, modifiers) = match bytes[0] {
+    b'\r' | b'\n' => (Key::Enter, none),
+    b'\t' => (Key::Tab, none),
+    0x7f => (Key::Backspace, none),
+    0x08 => (Key::Backspace, ctrl),
+    0x00 => (Key::Char(' '), ctrl),
+    b @ 0x01..=0x1a => (Key::Char(char::from(b'a' + b - 1)), ctrl),
+    b @ 0x1b..=0x1f => (Key::Char(char::from(b + 0x40)), ctrl),
+    b if b.is_ascii() => (Key::Char(char::from(b)), none),
+    b => {
+      let len = match b {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return Decode::Unknown(1),
+      };
+      let available = bytes.len().min(len);
+      if let Some(invalid) = bytes[1..available].iter().position(|b| b & 0xc0 != 0x80) {
+        return Decode::Unknown(1 + invalid)
+      }
+      if available < len {
+        return Decode::Incomplete
+      }
+      match str::from_utf8(&bytes[..len]) {
+        Ok(s) => return Decode::Key(Key::Char(s.chars().next().unwrap()), none, len),
+        Err(_) => return Decode::Unknown(len),
+      }
+    },
+  };
+  Decode::Key(key, modifiers, 1)
+}
+
+
+/// Decode the first key press in the given bytes, as emitted by an
+/// xterm compatible terminal.
+///
+/// This is the inverse of [`Key::encode`], with the exception of
+/// control characters that have dedicated keys (e.g., Ctrl-M is
+/// reported as [`Key::Enter`]). A lone Escape byte is decoded as
+/// [`Key::Escape`], so callers have to make sure to pass in all
+/// bytes available.
+pub(crate) fn decode(bytes: &[u8]) -> Decode {
+  match bytes {
+    [] => Decode::Incomplete,
+    [0x1b] => Decode::Key(Key::Escape, Modifiers::default(), 1),
+    [0x1b, b'[', rest @ ..] => decode_csi(rest),
+    [0x1b, b'O'] => Decode::Incomplete,
+    [0x1b, b'O', b, ..] => {
+      let key = match b {
+        b'A' => Key::Up,
+        b'B' => Key::Down,
+        b'C' => Key::Right,
+        b'D' => Key::Left,
+        b'H' => Key::Home,
+        b'F' => Key::End,
+        b'P' => Key::F(1),
+        b'Q' => Key::F(2),
+        b'R' => Key::F(3),
+        b'S' => Key::F(4),
+        _ => return Decode::Unknown(3),
+      };
+      Decode::Key(key, Modifiers::default(), 3)
+    },
+    [0x1b, 0x1b, ..] => Decode::Key(Key::Escape, Modifiers::default(), 1),
+    [0x1b, rest @ ..] => match decode_plain(rest) {
+      Decode::Key(key, modifiers, len) => {
+        let modifiers = Modifiers {
+          alt: true,
+          ..modifiers
+        };
+        Decode::Key(key, modifiers, 1 + len)
+      },
+      Decode::Unknown(len) => Decode::Unknown(1 + len),
+      Decode::Incomplete => Decode::Incomplete,
+    },
+    _ => decode_plain(bytes),
+  }
+}
+
+
+/// Append the given bytes in the notation used by `inputrc` files
+/// (e.g., `\e[99~`) to `output`.
+pub(crate) fn escape(bytes: &[u8], output: &mut String) {
+  for &b in bytes {
+    let _result = match b {
+      0x1b => write!(output, "\\e"),
+      0x7f => write!(output, "\\C-?"),
+      b'\\' | b'"' | b'\'' => write!(output, "\\{}", char::from(b)),
+      0x00..=0x1f => write!(output, "\\C-{}", char::from(b + 0x40).to_ascii_lowercase()),
+      0x20..=0x7e => write!(output, "{}", char::from(b)),
+      _ => write!(output, "\\{:03o}", b),
+    };
+  }
+}
+
+
+/// Describe a sequence of raw key bytes in human readable form, e.g.,
+/// for displaying shortcuts to users.
+///
+/// Individual keys are separated by spaces and modifiers are denoted
+/// the way Emacs does it, with `C-` for control, `M-` for meta (alt),
+/// and `S-` for shift. For example, `\x18\x1b[A` is described as
+/// `C-x Up`. Bytes that do not form a known key are rendered in the
+/// notation used by `inputrc` files.
+pub fn describe(bytes: &[u8]) -> String {
+  let mut output = String::new();
+  let mut bytes = bytes;
+
+  while !bytes.is_empty() {
+    if !output.is_empty() {
+      output.push(' ');
+    }
+
+    let len = match decode(bytes) {
+      Decode::Key(key, modifiers, len) => {
+        if modifiers.ctrl {
+          output.push_str("C-");
+        }
+        if modifiers.alt {
+          output.push_str("M-");
+        }
+        if modifiers.shift {
+          output.push_str("S-");
+        }
+        let _result = write!(output, "{}", key);
+        len
+      },
+      Decode::Unknown(len) => {
+        let () = escape(&bytes[..len], &mut output);
+        len
+      },
+      Decode::Incomplete => {
+        let () = escape(bytes, &mut output);
+        bytes.len()
+      },
+    };
+    bytes = &bytes[len..];
+  }
+  output
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+
+  /// Check that we encode keys as expected.
+  #[test]
+  fn encoding() {
+    let none = Modifiers::default();
+    let ctrl = Modifiers {
+      ctrl: true,
+      ..Default::default()
+    };
+    let alt = Modifiers {
+      alt: true,
+      ..Default::default()
+    };
+
+    assert_eq!(Key::Char('a').encode(none).unwrap(), b"a");
+    assert_eq!(Key::Char('\u{e4}').encode(none).unwrap(), "\u{e4}".as_bytes());
+    assert_eq!(Key::Char('a').encode(ctrl).unwrap(), b"\x01");
+    assert_eq!(Key::Char('B').encode(ctrl).unwrap(), b"\x02");
+    assert_eq!(Key::Char('b').encode(alt).unwrap(), b"\x1bb");
+    assert_eq!(Key::Left.encode(none).unwrap(), b"\x1b[D");
+    assert_eq!(Key::Left.encode(ctrl).unwrap(), b"\x1b[1;5D");
+    assert_eq!(Key::Delete.encode(none).unwrap(), b"\x1b[3~");
+    assert_eq!(Key::F(1).encode(none).unwrap(), b"\x1bOP");
+    assert_eq!(Key::F(12).encode(ctrl).unwrap(), b"\x1b[24;5~");
+    assert_eq!(Key::F(13).encode(none), None);
+  }
+
+  /// Check that we decode byte sequences into keys as expected.
+  #[test]
+  fn decoding() {
+    let none = Modifiers::default();
+    let ctrl = Modifiers {
+      ctrl: true,
+      ..Default::default()
+    };
+    let alt = Modifiers {
+      alt: true,
+      ..Default::default()
+    };
+
+    assert_eq!(decode(b"ab"), Decode::Key(Key::Char('a'), none, 1));
+    assert_eq!(decode("\u{e4}".as_bytes()), Decode::Key(Key::Char('\u{e4}'), none, 2));
+    assert_eq!(decode(b"\xc3"), Decode::Incomplete);
+    assert_eq!(decode(b"\xc3a"), Decode::Unknown(1));
+    assert_eq!(decode(b"\xff"), Decode::Unknown(1));
+    assert_eq!(decode(b"\x01"), Decode::Key(Key::Char('a'), ctrl, 1));
+    assert_eq!(decode(b"\r"), Decode::Key(Key::Enter, none, 1));
+    assert_eq!(decode(b"\x1b"), Decode::Key(Key::Escape, none, 1));
+    assert_eq!(decode(b"\x1bb"), Decode::Key(Key::Char('b'), alt, 2));
+    assert_eq!(decode(b"\x1b["), Decode::Incomplete);
+    assert_eq!(decode(b"\x1b[1;5"), Decode::Incomplete);
+    assert_eq!(decode(b"\x1b[1;5Dx"), Decode::Key(Key::Left, ctrl, 6));
+    assert_eq!(decode(b"\x1b[24;5~"), Decode::Key(Key::F(12), ctrl, 7));
+    assert_eq!(decode(b"\x1b[99~"), Decode::Unknown(5));
+    assert_eq!(decode(b"\x1bOP"), Decode::Key(Key::F(1), none, 3));
+  }
+
+  /// Check that raw key bytes are described as expected.
+  #[test]
+  fn describing() {
+    assert_eq!(describe(b""), "");
+    assert_eq!(describe(b"\x18\x1b[A"), "C-x Up");
+    assert_eq!(describe(b"\x1bf"), "M-f");
+    assert_eq!(describe(b"\x1b[1;5D"), "C-Left");
+    assert_eq!(describe(b"\x1b\x1b"), "Esc Esc");
+    assert_eq!(describe(b"a \t\r"), "a Space Tab Enter");
+    assert_eq!(describe(b"\x00\x1f"), "C-Space C-_");
+    assert_eq!(describe(b"\x1b[99~x"), "\\e[99~ x");
+    assert_eq!(describe(b"\x1b["), "\\e[");
+    assert_eq!(describe(b"\xff"), "\\377");
+  }
+
+  /// Check that decoding is the inverse of encoding.
+  #[test]
+  fn encode_decode() {
+    let keys = [
+      Key::Char('a'),
+      Key::Char('\u{20ac}'),
+      Key::Backspace,
+      Key::Enter,
+      Key::Tab,
+      Key::BackTab,
+      Key::Escape,
+      Key::Left,
+      Key::Up,
+      Key::End,
+      Key::PageDown,
+      Key::Delete,
+      Key::F(1),
+      Key::F(12),
+    ];
+    let modifiers = [
+      Modifiers::default(),
+      Modifiers {
+        ctrl: true,
+        ..Default::default()
+      },
+      Modifiers {
+        alt: true,
+        ..Default::default()
+      },
+    ];
+
+    for key in keys.iter() {
+      for modifiers in modifiers.iter() {
+        let encoded = key.encode(*modifiers).unwrap();
+        match decode(&encoded) {
+          Decode::Key(decoded, decoded_modifiers, len) => {
+            assert_eq!(len, encoded.len(), "{:?}", encoded);
+            let reencoded = decoded.encode(decoded_modifiers);
+            assert_eq!(reencoded.as_ref(), Some(&encoded), "{:?}", (key, modifiers));
+          },
+          decoded => panic!("{:?} decoded as {:?}", encoded, decoded),
+        }
+      }
+    }
+  }
+
+  /// Check that keys and modifiers can be created from unstructured
+  /// data.
+  #[cfg(feature = "arbitrary")]
+  #[test]
+  fn arbitrary_keys() {
+    use arbitrary::Arbitrary as _;
+    use arbitrary::Unstructured;
+
+    let data = (0..=255).rev().collect::<Vec<u8>>();
+    let mut unstructured = Unstructured::new(&data);
+    let key = Key::arbitrary(&mut unstructured).unwrap();
+    let modifiers = Modifiers::arbitrary(&mut unstructured).unwrap();
+    let _encoded = key.encode(modifiers);
+  }
+}
@@ -0,0 +1,112 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Conversion of GDK key events into the byte sequences libreadline
+//! understands.
+//!
+//! The functions in this module work on the raw key value and
+//! modifier state of a GDK key event (as retrieved via, e.g.,
+//! `gdk::EventKey::keyval` and `gdk::EventKey::state`), so that no
+//! dependency on the GTK stack is required.
+
+use super::Key;
+use super::Modifiers;
+
+
+/// The GDK modifier mask bit for the shift key.
+pub const SHIFT_MASK: u32 = 1 << 0;
+/// The GDK modifier mask bit for the control key.
+pub const CONTROL_MASK: u32 = 1 << 2;
+/// The GDK modifier mask bit for the alt key (`GDK_MOD1_MASK` in GTK 3
+/// and `GDK_ALT_MASK` in GTK 4).
+pub const ALT_MASK: u32 = 1 << 3;
+
+/// The flag marking key values directly encoding a Unicode code point.
+const UNICODE_FLAG: u32 = 0x0100_0000;
+
+
+/// Convert a GDK key value into a [`Key`].
+///
+/// `None` is returned for key values that have no corresponding key,
+/// e.g., those of modifier keys.
+pub fn key(keyval: u32) -> Option<Key> {
+  let key = match keyval {
+    0x20..=0x7e | 0xa0..=0xff => Key::Char(char::from(keyval as u8)),
+    0xff08 => Key::Backspace,
+    0xff09 => Key::Tab,
+    0xfe20 => Key::BackTab,
+    0xff0d | 0xff8d => Key::Enter,
+    0xff1b => Key::Escape,
+    0xff50 | 0xff95 => Key::Home,
+    0xff51 | 0xff96 => Key::Left,
+    0xff52 | 0xff97 => Key::Up,
+    0xff53 | 0xff98 => Key::Right,
+    0xff54 | 0xff99 => Key::Down,
+    0xff55 | 0xff9a => Key::PageUp,
+    0xff56 | 0xff9b => Key::PageDown,
+    0xff57 | 0xff9c => Key::End,
+    0xff63 | 0xff9e => Key::Insert,
+    0xffff | 0xff9f => Key::Delete,
+    0xff80 => Key::Char(' '),
+    0xffaa => Key::Char('*'),
+    0xffab => Key::Char('+'),
+    0xffac => Key::Char(','),
+    0xffad => Key::Char('-'),
+    0xffae => Key::Char('.'),
+    0xffaf => Key::Char('/'),
+    0xffb0..=0xffb9 => Key::Char(char::from(b'0' + (keyval - 0xffb0) as u8)),
+    0xffbd => Key::Char('='),
+    0xffbe..=0xffc9 => Key::F((keyval - 0xffbe + 1) as u8),
+    _ if keyval & 0xff00_0000 == UNICODE_FLAG => Key::Char(char::from_u32(keyval & 0x00ff_ffff)?),
+    _ => return None,
+  };
+  Some(key)
+}
+
+
+/// Convert a GDK modifier state into [`Modifiers`].
+pub fn modifiers(state: u32) -> Modifiers {
+  Modifiers {
+    shift: state & SHIFT_MASK != 0,
+    ctrl: state & CONTROL_MASK != 0,
+    alt: state & ALT_MASK != 0,
+  }
+}
+
+
+/// Convert a GDK key event, represented by its key value and modifier
+/// state, into the byte sequence to feed to libreadline.
+///
+/// `None` is returned for keys that have no representation libreadline
+/// could understand.
+pub fn convert(keyval: u32, state: u32) -> Option<Vec<u8>> {
+  let key = key(keyval)?;
+  let mut modifiers = modifiers(state);
+  // Shift is already reflected in the key value of characters.
+  modifiers.shift &= !matches!(key, Key::Char(..));
+  key.encode(modifiers)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+
+  /// Check that we convert GDK key events properly.
+  #[test]
+  fn conversion() {
+    assert_eq!(convert(0x61, 0).unwrap(), b"a");
+    assert_eq!(convert(0x41, SHIFT_MASK).unwrap(), b"A");
+    assert_eq!(convert(0x61, CONTROL_MASK).unwrap(), b"\x01");
+    assert_eq!(convert(0x62, ALT_MASK).unwrap(), b"\x1bb");
+    assert_eq!(convert(0xe4, 0).unwrap(), "\u{e4}".as_bytes());
+    assert_eq!(convert(0x0100_20ac, 0).unwrap(), "\u{20ac}".as_bytes());
+    assert_eq!(convert(0xff0d, 0).unwrap(), b"\r");
+    assert_eq!(convert(0xff51, SHIFT_MASK).unwrap(), b"\x1b[1;2D");
+    assert_eq!(convert(0xffb7, 0).unwrap(), b"7");
+    assert_eq!(convert(0xffbe, 0).unwrap(), b"\x1bOP");
+    // Shift_L
+    assert_eq!(convert(0xffe1, SHIFT_MASK), None);
+  }
+}
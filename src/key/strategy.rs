@@ -0,0 +1,102 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! [`proptest`] strategies for generating keys and sequences of key
+//! presses, for property testing input handling.
+
+use proptest::collection::vec;
+use proptest::collection::SizeRange;
+use proptest::prelude::any;
+use proptest::prelude::Just;
+use proptest::prelude::Strategy;
+use proptest::prop_oneof;
+
+use super::Key;
+use super::Modifiers;
+
+
+/// Create a strategy generating arbitrary keys.
+///
+/// Function keys are limited to those having an encoding, i.e., F1 to
+/// F12.
+pub fn key() -> impl Strategy<Value = Key> {
+  prop_oneof![
+    4 => any::<char>().prop_map(Key::Char),
+    1 => prop_oneof![
+      Just(Key::Backspace),
+      Just(Key::Enter),
+      Just(Key::Tab),
+      Just(Key::BackTab),
+      Just(Key::Escape),
+      Just(Key::Left),
+      Just(Key::Right),
+      Just(Key::Up),
+      Just(Key::Down),
+      Just(Key::Home),
+      Just(Key::End),
+      Just(Key::PageUp),
+      Just(Key::PageDown),
+      Just(Key::Insert),
+      Just(Key::Delete),
+      (1..=12u8).prop_map(Key::F),
+    ],
+  ]
+}
+
+
+/// Create a strategy generating arbitrary modifier combinations.
+pub fn modifiers() -> impl Strategy<Value = Modifiers> {
+  any::<(bool, bool, bool)>().prop_map(|(shift, ctrl, alt)| Modifiers { shift, ctrl, alt })
+}
+
+
+/// Create a strategy generating presses of keys generated by `keys`
+/// with modifiers generated by `modifiers`, encoded as per
+/// [`Key::encode`].
+///
+/// Combinations without an encoding are rejected.
+pub fn press<K, M>(keys: K, modifiers: M) -> impl Strategy<Value = Vec<u8>>
+where
+  K: Strategy<Value = Key>,
+  M: Strategy<Value = Modifiers>,
+{
+  (keys, modifiers).prop_filter_map("key has no encoding", |(key, modifiers)| {
+    key.encode(modifiers)
+  })
+}
+
+
+/// Create a strategy generating sequences of arbitrary key presses with
+/// a length in the given range, encoded as per [`Key::encode`].
+///
+/// Each element of a sequence is meant to be fed individually, e.g.,
+/// via [`Readline::feed`][crate::Readline::feed].
+pub fn sequence(len: impl Into<SizeRange>) -> impl Strategy<Value = Vec<Vec<u8>>> {
+  vec(press(key(), modifiers()), len)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use proptest::proptest;
+
+
+  proptest! {
+    /// Check that characters pressed without modifiers are encoded as
+    /// UTF-8.
+    #[test]
+    fn char_encoding(c in any::<char>()) {
+      let bytes = Key::Char(c).encode(Modifiers::default()).unwrap();
+      assert_eq!(bytes, c.to_string().into_bytes());
+    }
+
+    /// Check that generated sequences consist of non-empty presses.
+    #[test]
+    fn sequences(sequence in sequence(0..8)) {
+      assert!(sequence.len() < 8);
+      assert!(sequence.iter().all(|press| !press.is_empty()));
+    }
+  }
+}
@@ -0,0 +1,395 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Per-instance management of libreadline's global keymaps.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::ffi::CStr;
+use std::fmt::Debug;
+use std::fmt::Error;
+use std::fmt::Formatter;
+use std::mem::size_of;
+use std::mem::transmute;
+use std::ptr::addr_of;
+use std::ptr::addr_of_mut;
+use std::ptr::copy_nonoverlapping;
+
+use libc::c_char;
+use libc::c_void;
+use libc::free;
+use libc::malloc;
+use libc::strdup;
+
+use crate::kill::rl_command_func_t;
+
+
+/// The number of entries in a keymap.
+const KEYMAP_SIZE: usize = 257;
+/// The index of the entry in a keymap holding the command shadowed by
+/// the key sequence leading to it.
+const ANYOTHERKEY: usize = KEYMAP_SIZE - 1;
+
+/// libreadline's `ISFUNC` key type.
+const ISFUNC: c_char = 0;
+/// libreadline's `ISKMAP` key type.
+const ISKMAP: c_char = 1;
+/// libreadline's `ISMACR` key type.
+const ISMACR: c_char = 2;
+
+
+/// libreadline's representation of a single keymap entry.
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy)]
+struct KEYMAP_ENTRY {
+  type_: c_char,
+  function: Option<rl_command_func_t>,
+}
+
+#[allow(non_camel_case_types)]
+type KEYMAP_ENTRY_ARRAY = [KEYMAP_ENTRY; KEYMAP_SIZE];
+
+/// The contents of libreadline's built-in keymaps.
+type Maps = [KEYMAP_ENTRY_ARRAY; 5];
+
+
+// Declarations as provided by libreadline.
+extern "C" {
+  static mut emacs_standard_keymap: KEYMAP_ENTRY_ARRAY;
+  static mut emacs_meta_keymap: KEYMAP_ENTRY_ARRAY;
+  static mut emacs_ctlx_keymap: KEYMAP_ENTRY_ARRAY;
+  static mut vi_insertion_keymap: KEYMAP_ENTRY_ARRAY;
+  static mut vi_movement_keymap: KEYMAP_ENTRY_ARRAY;
+
+  // Not part of the public header, but exported nonetheless.
+  static mut _rl_dispatching_keymap: *mut KEYMAP_ENTRY;
+}
+
+
+/// Retrieve the addresses of libreadline's built-in keymaps.
+///
+/// # Safety
+/// The function has no safety requirements, but older compilers deem
+/// taking the address of an external static unsafe.
+unsafe fn builtin() -> [*mut KEYMAP_ENTRY; 5] {
+  [
+    addr_of_mut!(emacs_standard_keymap).cast(),
+    addr_of_mut!(emacs_meta_keymap).cast(),
+    addr_of_mut!(emacs_ctlx_keymap).cast(),
+    addr_of_mut!(vi_insertion_keymap).cast(),
+    addr_of_mut!(vi_movement_keymap).cast(),
+  ]
+}
+
+/// Read the contents of libreadline's built-in keymaps.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held.
+unsafe fn read() -> Maps {
+  unsafe {
+    [
+      addr_of!(emacs_standard_keymap).read(),
+      addr_of!(emacs_meta_keymap).read(),
+      addr_of!(emacs_ctlx_keymap).read(),
+      addr_of!(vi_insertion_keymap).read(),
+      addr_of!(vi_movement_keymap).read(),
+    ]
+  }
+}
+
+/// Write the given contents into libreadline's built-in keymaps.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held.
+unsafe fn write(maps: &Maps) {
+  unsafe {
+    addr_of_mut!(emacs_standard_keymap).write(maps[0]);
+    addr_of_mut!(emacs_meta_keymap).write(maps[1]);
+    addr_of_mut!(emacs_ctlx_keymap).write(maps[2]);
+    addr_of_mut!(vi_insertion_keymap).write(maps[3]);
+    addr_of_mut!(vi_movement_keymap).write(maps[4]);
+  }
+}
+
+
+/// Replace the keymaps and macros referenced by `map` with copies of
+/// them, skipping built-in keymaps.
+///
+/// `copies` maps already copied keymaps to their copies, so that a
+/// keymap referenced more than once is copied only once.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held and that `map`
+/// points to a valid keymap.
+unsafe fn duplicate(
+  map: *mut KEYMAP_ENTRY,
+  builtin: &[*mut KEYMAP_ENTRY],
+  copies: &mut HashMap<*mut KEYMAP_ENTRY, *mut KEYMAP_ENTRY>,
+) {
+  for i in 0..KEYMAP_SIZE {
+    let entry = unsafe { &mut *map.add(i) };
+    let ptr = match entry.function {
+      Some(function) => function as usize as *mut KEYMAP_ENTRY,
+      None => continue,
+    };
+
+    let copy = match entry.type_ {
+      ISKMAP if builtin.contains(&ptr) => continue,
+      ISKMAP => match copies.get(&ptr) {
+        Some(copy) => *copy,
+        None => {
+          let copy = unsafe { malloc(size_of::<KEYMAP_ENTRY_ARRAY>()) }.cast::<KEYMAP_ENTRY>();
+          assert!(!copy.is_null(), "failed to allocate keymap");
+
+          let () = unsafe { copy_nonoverlapping(ptr, copy, KEYMAP_SIZE) };
+          let _prev = copies.insert(ptr, copy);
+          let () = unsafe { duplicate(copy, builtin, copies) };
+          copy
+        },
+      },
+      ISMACR => {
+        let copy = unsafe { strdup(ptr as *const c_char) };
+        assert!(!copy.is_null(), "failed to allocate macro");
+        copy.cast()
+      },
+      _ => continue,
+    };
+
+    entry.function = Some(unsafe { transmute::<*mut KEYMAP_ENTRY, rl_command_func_t>(copy) });
+  }
+}
+
+
+/// Release the keymaps and macros referenced by `map`, skipping
+/// built-in keymaps and ones that got released already, and unbind the
+/// keys referencing them.
+///
+/// # Safety
+/// Callers must ensure that `map` points to a valid keymap and that the
+/// keymaps and macros referenced by it are not in use elsewhere.
+unsafe fn discard_map(
+  map: *mut KEYMAP_ENTRY,
+  builtin: &[*mut KEYMAP_ENTRY],
+  freed: &mut HashSet<*mut KEYMAP_ENTRY>,
+) {
+  for i in 0..KEYMAP_SIZE {
+    let entry = unsafe { &mut *map.add(i) };
+    let ptr = match entry.function {
+      Some(function) => function as usize as *mut KEYMAP_ENTRY,
+      None => continue,
+    };
+
+    match entry.type_ {
+      ISKMAP if builtin.contains(&ptr) => continue,
+      ISKMAP => {
+        if freed.insert(ptr) {
+          unsafe {
+            discard_map(ptr, builtin, freed);
+            free(ptr.cast());
+          }
+        }
+      },
+      ISMACR => unsafe { free(ptr.cast()) },
+      _ => continue,
+    }
+
+    *entry = KEYMAP_ENTRY {
+      type_: ISFUNC,
+      function: None,
+    };
+  }
+}
+
+
+/// A copy of libreadline's built-in keymaps.
+///
+/// libreadline modifies its built-in keymaps in place when keys get
+/// bound. Isolated instances keep their own copy of them, which we
+/// swap in and out on every context switch, similar to what we do for
+/// `readline_state`. The keymaps libreadline allocated for multi-key
+/// sequences (e.g., `\e[`) as well as macros are referenced from the
+/// built-in ones. The copy owns copies of these, too, which it releases
+/// when dropped.
+pub(crate) struct Keymaps(Maps);
+
+impl Keymaps {
+  /// Capture the contents of libreadline's keymaps.
+  ///
+  /// # Safety
+  /// Callers must ensure that the global mutex is held.
+  pub(crate) unsafe fn capture() -> Box<Self> {
+    let mut keymaps = Box::new(Self(unsafe { read() }));
+    let builtin = unsafe { builtin() };
+    let mut copies = HashMap::new();
+
+    for map in keymaps.0.iter_mut() {
+      unsafe { duplicate(map.as_mut_ptr(), &builtin, &mut copies) }
+    }
+    keymaps
+  }
+
+  /// Update the copy with the contents of libreadline's keymaps.
+  ///
+  /// The keymaps and macros referenced by libreadline's keymaps are
+  /// expected to be the copies owned by this object, or ones allocated
+  /// by libreadline in their place, while they were in use.
+  ///
+  /// # Safety
+  /// Callers must ensure that the global mutex is held.
+  pub(crate) unsafe fn load(&mut self) {
+    self.0 = unsafe { read() }
+  }
+
+  /// Write the copy back into libreadline's keymaps.
+  ///
+  /// # Safety
+  /// Callers must ensure that the global mutex is held.
+  unsafe fn save(&self) {
+    unsafe { write(&self.0) }
+  }
+}
+
+impl Drop for Keymaps {
+  fn drop(&mut self) {
+    let builtin = unsafe { builtin() };
+    let mut freed = HashSet::new();
+
+    // SAFETY: The keymaps and macros referenced are owned by us. Our
+    //         users make sure to switch libreadline's keymaps away from
+    //         our copy before dropping it.
+    for map in self.0.iter_mut() {
+      unsafe { discard_map(map.as_mut_ptr(), &builtin, &mut freed) }
+    }
+  }
+}
+
+impl Debug for Keymaps {
+  fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+    f.debug_struct("Keymaps").finish()
+  }
+}
+
+
+/// Switch libreadline's keymaps to the given per-instance ones or, if
+/// `None`, to the ones shared by all instances that are not isolated.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held.
+pub(crate) unsafe fn switch(keymaps: Option<&Keymaps>) {
+  /// The keymaps shared by non-isolated instances, stashed away while
+  /// an isolated instance's keymaps are in place.
+  static mut SHARED: Option<Box<Maps>> = None;
+
+  // SAFETY: As per the function contract, callers need to hold the
+  //         global mutex.
+  let shared = unsafe { &mut *addr_of_mut!(SHARED) };
+  match keymaps {
+    Some(keymaps) => {
+      if shared.is_none() {
+        *shared = Some(Box::new(unsafe { read() }));
+      }
+      unsafe { keymaps.save() }
+    },
+    None => {
+      if let Some(maps) = shared.take() {
+        unsafe { write(&maps) }
+      }
+    },
+  }
+}
+
+
+/// Run `dispatch` with `key` temporarily bound to the command shadowed
+/// by the incomplete key sequence libreadline is currently dispatching,
+/// in the keymap it is dispatching in.
+///
+/// This is how libreadline itself resolves a key sequence once it
+/// timed out waiting for its remainder. `None` is returned and
+/// `dispatch` is not run if no command is shadowed.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held and that a key
+/// sequence is being dispatched.
+pub(crate) unsafe fn with_shadowed<F, R>(key: u8, dispatch: F) -> Option<R>
+where
+  F: FnOnce() -> R,
+{
+  let map = unsafe { _rl_dispatching_keymap };
+  if map.is_null() {
+    return None
+  }
+
+  let shadowed = unsafe { *map.add(ANYOTHERKEY) };
+  let _function = shadowed.function?;
+
+  let entry = unsafe { map.add(usize::from(key)) };
+  let original = unsafe { entry.replace(shadowed) };
+  let result = dispatch();
+  let () = unsafe { entry.write(original) };
+  Some(result)
+}
+
+
+/// Enumerate the key sequences bound to macros in the given keymap,
+/// along with the macros' text.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held and that `map`
+/// points to a valid keymap.
+pub(crate) unsafe fn macros(map: *mut c_void) -> Vec<(Vec<u8>, Vec<u8>)> {
+  /// Collect the macros bound in `map`, with `prefix` being the key
+  /// sequence leading to it.
+  unsafe fn collect(
+    map: *mut KEYMAP_ENTRY,
+    prefix: &mut Vec<u8>,
+    visited: &mut HashSet<*mut KEYMAP_ENTRY>,
+    macros: &mut Vec<(Vec<u8>, Vec<u8>)>,
+  ) {
+    if !visited.insert(map) {
+      return
+    }
+
+    // The last entry holds the command shadowed by the prefix, which
+    // is not reachable by a key of its own.
+    for i in 0..ANYOTHERKEY {
+      let entry = unsafe { *map.add(i) };
+      let ptr = match entry.function {
+        Some(function) => function as usize,
+        None => continue,
+      };
+
+      let () = prefix.push(i as u8);
+      match entry.type_ {
+        ISKMAP => unsafe { collect(ptr as *mut KEYMAP_ENTRY, prefix, visited, macros) },
+        ISMACR => {
+          let text = unsafe { CStr::from_ptr(ptr as *const c_char) };
+          let () = macros.push((prefix.clone(), text.to_bytes().to_vec()));
+        },
+        _ => (),
+      }
+      let _key = prefix.pop();
+    }
+  }
+
+  let mut macros = Vec::new();
+  let mut visited = HashSet::new();
+  unsafe { collect(map.cast(), &mut Vec::new(), &mut visited, &mut macros) };
+  macros
+}
+
+
+/// Release all keymaps and macros libreadline allocated for key
+/// sequences bound in its built-in keymaps, unbinding these sequences.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held and that the keymaps
+/// shared by non-isolated instances are in place.
+pub(crate) unsafe fn discard() {
+  let builtin = unsafe { builtin() };
+  let mut freed = HashSet::new();
+
+  for map in builtin.iter() {
+    unsafe { discard_map(*map, &builtin, &mut freed) }
+  }
+}
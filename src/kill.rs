@@ -0,0 +1,326 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Tracking of text killed by libreadline's kill commands.
+
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::mem::size_of;
+
+use libc::c_int;
+
+use crate::diff::common;
+use crate::Limit;
+use crate::Usage;
+
+
+/// The number of entries libreadline's kill ring holds. The number is
+/// fixed, as `rl_set_retained_kills` is not actually implemented.
+pub(crate) const MAX_KILLS: usize = 10;
+
+
+#[allow(non_camel_case_types)]
+pub(crate) type rl_command_func_t = unsafe extern "C" fn(c_int, c_int) -> c_int;
+
+
+// Declarations as provided by libreadline.
+extern "C" {
+  fn rl_kill_line(count: c_int, key: c_int) -> c_int;
+  fn rl_backward_kill_line(count: c_int, key: c_int) -> c_int;
+  fn rl_kill_full_line(count: c_int, key: c_int) -> c_int;
+  fn rl_unix_line_discard(count: c_int, key: c_int) -> c_int;
+  fn rl_kill_word(count: c_int, key: c_int) -> c_int;
+  fn rl_backward_kill_word(count: c_int, key: c_int) -> c_int;
+  fn rl_unix_word_rubout(count: c_int, key: c_int) -> c_int;
+  fn rl_unix_filename_rubout(count: c_int, key: c_int) -> c_int;
+  fn rl_kill_region(count: c_int, key: c_int) -> c_int;
+  fn rl_vi_delete_to(count: c_int, key: c_int) -> c_int;
+  fn rl_vi_change_to(count: c_int, key: c_int) -> c_int;
+  fn rl_vi_delete(count: c_int, key: c_int) -> c_int;
+  fn rl_vi_rubout(count: c_int, key: c_int) -> c_int;
+}
+
+
+/// Check whether the given libreadline command adds text to the kill
+/// ring by removing it from the line.
+pub(crate) fn is_kill(func: rl_command_func_t) -> bool {
+  let kills: [rl_command_func_t; 13] = [
+    rl_kill_line,
+    rl_backward_kill_line,
+    rl_kill_full_line,
+    rl_unix_line_discard,
+    rl_kill_word,
+    rl_backward_kill_word,
+    rl_unix_word_rubout,
+    rl_unix_filename_rubout,
+    rl_kill_region,
+    rl_vi_delete_to,
+    rl_vi_change_to,
+    rl_vi_delete,
+    rl_vi_rubout,
+  ];
+
+  kills.iter().any(|kill| *kill as usize == func as usize)
+}
+
+
+/// Check whether the given kill command kills text backward, in which
+/// case libreadline prepends the text to the entry it gets merged with,
+/// given the cursor positions before (`from`) and after (`to`) its
+/// execution.
+fn is_backward(func: rl_command_func_t, from: usize, to: usize) -> bool {
+  let backward: [rl_command_func_t; 5] = [
+    rl_backward_kill_line,
+    rl_unix_line_discard,
+    rl_backward_kill_word,
+    rl_unix_word_rubout,
+    rl_unix_filename_rubout,
+  ];
+
+  let region: rl_command_func_t = rl_kill_region;
+  if func as usize == region as usize {
+    // The region is killed backward if the cursor was located after
+    // the mark, in which case it ends up at the start of the region.
+    to < from
+  } else {
+    backward.iter().any(|kill| *kill as usize == func as usize)
+  }
+}
+
+
+/// Determine the bytes removed from `before` to arrive at `after`,
+/// provided that a single contiguous range was removed.
+///
+/// `at` is the likely start of the removed range, which is used to
+/// disambiguate removals from repetitive text (e.g., removing `ab `
+/// from `ab ab`).
+fn removed<'b>(before: &'b [u8], after: &[u8], at: usize) -> Option<&'b [u8]> {
+  if after.len() >= before.len() {
+    return None
+  }
+
+  let end = at + before.len() - after.len();
+  if at <= after.len() && before[..at] == after[..at] && before[end..] == after[at..] {
+    return Some(&before[at..end])
+  }
+
+  let (prefix, suffix) = common(before, after);
+  Some(&before[prefix..before.len() - suffix])
+}
+
+
+/// Determine the text killed by an executed command, if any, given the
+/// cursor position after its execution.
+pub(crate) fn killed<'b>(
+  func: Option<rl_command_func_t>,
+  before: &'b [u8],
+  after: &[u8],
+  cursor: usize,
+) -> Option<&'b [u8]> {
+  match func {
+    // Kill commands leave the cursor at the start of the killed text.
+    Some(func) if is_kill(func) => removed(before, after, cursor),
+    _ => None,
+  }
+}
+
+
+/// The way killed text gets combined with the most recent entry of the
+/// kill ring.
+#[derive(Clone, Copy, Debug)]
+enum Combine {
+  /// The text forms a new entry.
+  None,
+  /// The text replaces the most recent entry.
+  Replace,
+  /// The text gets merged into the most recent entry, prepended to it
+  /// if it was killed backward and appended otherwise.
+  Merge { backward: bool },
+}
+
+
+/// A mirror of libreadline's kill ring for a single `Readline`
+/// instance.
+///
+/// libreadline keeps its kill ring private, so we can neither inspect
+/// nor bound it directly. Instead, we record the text removed by every
+/// kill command as it is executed, retaining as many entries as
+/// libreadline does.
+#[derive(Debug, Default)]
+pub(crate) struct KillRing {
+  /// The killed text, oldest entry first.
+  entries: VecDeque<CString>,
+  /// The total number of bytes currently stored.
+  bytes: usize,
+}
+
+impl KillRing {
+  /// Record the effect of an executed command on the line, given the
+  /// cursor positions before (`from`) and after (`to`) its execution.
+  ///
+  /// `consecutive` indicates whether the command directly followed
+  /// another kill, in which case libreadline combines the killed text
+  /// with the most recent entry: it merges the two in the emacs editing
+  /// mode and replaces the entry in the vi editing mode.
+  #[allow(clippy::too_many_arguments)]
+  pub(crate) fn record(
+    &mut self,
+    func: Option<rl_command_func_t>,
+    before: &[u8],
+    from: usize,
+    after: &[u8],
+    to: usize,
+    consecutive: bool,
+    vi: bool,
+  ) {
+    if let Some(text) = killed(func, before, after, to) {
+      let combine = match (consecutive, vi) {
+        (false, _) => Combine::None,
+        (true, true) => Combine::Replace,
+        (true, false) => {
+          let backward = matches!(func, Some(func) if is_backward(func, from, to));
+          Combine::Merge { backward }
+        },
+      };
+      self.push(text, combine);
+    }
+  }
+
+  /// Record the effect of a vi operator (e.g., `vi-delete-to`) that
+  /// completed along with its motion.
+  ///
+  /// Contrary to other kills, libreadline does not merge the text
+  /// killed by such an operator with that of a subsequent kill.
+  pub(crate) fn record_operator(
+    &mut self,
+    func: Option<rl_command_func_t>,
+    before: &[u8],
+    after: &[u8],
+    cursor: usize,
+  ) {
+    if let Some(text) = killed(func, before, after, cursor) {
+      self.push(text, Combine::None);
+    }
+  }
+
+  /// Add text to the ring as a new entry, as if it got killed by a
+  /// command on its own.
+  pub(crate) fn add(&mut self, text: &[u8]) {
+    self.push(text, Combine::None);
+  }
+
+  /// Add killed text to the ring, combining it with the most recent
+  /// entry as requested.
+  fn push(&mut self, text: &[u8], combine: Combine) {
+    let merged = match (self.entries.pop_back(), combine) {
+      (Some(last), Combine::Merge { backward }) => {
+        self.bytes -= last.as_bytes().len();
+        let last = last.into_bytes();
+        let mut bytes = Vec::with_capacity(last.len() + text.len());
+        if backward {
+          bytes.extend_from_slice(text);
+          bytes.extend_from_slice(&last);
+        } else {
+          bytes.extend_from_slice(&last);
+          bytes.extend_from_slice(text);
+        }
+        bytes
+      },
+      (Some(last), Combine::Replace) => {
+        self.bytes -= last.as_bytes().len();
+        text.to_vec()
+      },
+      (Some(last), Combine::None) => {
+        self.entries.push_back(last);
+        text.to_vec()
+      },
+      (None, _) => text.to_vec(),
+    };
+
+    // The text originates from a NUL terminated line buffer and so
+    // cannot contain any NUL bytes itself.
+    let entry = CString::new(merged).unwrap();
+    self.bytes += entry.as_bytes().len();
+    self.entries.push_back(entry);
+    self.enforce()
+  }
+
+  /// Retrieve the most recent entry of the ring, if any.
+  pub(crate) fn last(&self) -> Option<&CString> {
+    self.entries.back()
+  }
+
+  /// Retrieve the entries of the ring, oldest first.
+  pub(crate) fn entries(&self) -> impl Iterator<Item = &CString> {
+    self.entries.iter()
+  }
+
+  /// Replace the entries of the ring, evicting entries as necessary.
+  #[cfg(feature = "serde")]
+  pub(crate) fn set_entries<I>(&mut self, entries: I)
+  where
+    I: IntoIterator<Item = CString>,
+  {
+    self.entries = entries.into_iter().collect();
+    self.bytes = self.entries.iter().map(|entry| entry.as_bytes().len()).sum();
+    self.enforce()
+  }
+
+  /// Remove the oldest entries that libreadline no longer retains.
+  fn enforce(&mut self) {
+    while self.entries.len() > MAX_KILLS {
+      match self.entries.pop_front() {
+        Some(entry) => self.bytes -= entry.as_bytes().len(),
+        None => break,
+      }
+    }
+  }
+
+  /// Remove the oldest entries until the ring fits within the given
+  /// limit, reporting whether any got removed.
+  pub(crate) fn enforce_limit(&mut self, limit: Limit) -> bool {
+    let mut evicted = false;
+    while !limit.admits(self.usage()) {
+      match self.entries.pop_front() {
+        Some(entry) => self.bytes -= entry.as_bytes().len(),
+        None => break,
+      }
+      evicted = true;
+    }
+    evicted
+  }
+
+  /// Report the memory used by the ring.
+  pub(crate) fn usage(&self) -> Usage {
+    Usage {
+      entries: self.entries.len(),
+      bytes: self.bytes,
+    }
+  }
+
+  /// Approximate the heap memory used by the ring, in bytes, including
+  /// bookkeeping.
+  pub(crate) fn memory(&self) -> usize {
+    // Each entry carries a NUL terminator in addition to its text.
+    self.entries.capacity() * size_of::<CString>() + self.bytes + self.entries.len()
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+
+  #[test]
+  fn removed_range() {
+    assert_eq!(removed(b"abcdef", b"abef", 2), Some(&b"cd"[..]));
+    assert_eq!(removed(b"abcdef", b"", 0), Some(&b"abcdef"[..]));
+    assert_eq!(removed(b"aaaa", b"aa", 1), Some(&b"aa"[..]));
+    assert_eq!(removed(b"abc", b"abc", 0), None);
+    assert_eq!(removed(b"bar baz", b"baz", 0), Some(&b"bar "[..]));
+    // An inconsistent position is ignored.
+    assert_eq!(removed(b"bar baz", b"baz", 3), Some(&b"r ba"[..]));
+    assert_eq!(removed(b"abcdef", b"abef", 9), Some(&b"cd"[..]));
+  }
+}
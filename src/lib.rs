@@ -30,21 +30,91 @@
 //! Note that libreadline does not have a clear separation between the
 //! core logic of handling input (based on characters) and displaying
 //! them. It is highly questionable whether this crate achieved a 100%
-//! isolation.
+//! isolation. [`Readline::set_stray_output_hook`] can help with finding
+//! out.
 
+mod bindings;
+mod bracket;
+mod builder;
+mod capture;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "compat")]
+pub mod compat;
+mod diff;
+mod flags;
+#[cfg(feature = "test-util")]
+mod golden;
+mod history;
+mod keymap;
+mod kill;
+mod mask;
+mod merge;
+mod metrics;
+mod observer;
+mod record;
+mod register;
+mod repl;
+#[cfg(feature = "serde")]
+mod session;
+mod stream;
+mod suggest;
+mod sync;
+#[cfg(feature = "test-util")]
+mod tester;
+mod transform;
+mod undo;
+mod word;
+#[cfg(any(feature = "futures", feature = "tokio"))]
+mod worker;
+pub mod compose;
+pub mod frontend;
+#[cfg(feature = "futures")]
+pub mod futures;
+pub mod key;
+pub mod render;
+#[cfg(feature = "tokio")]
+pub mod tokio;
+#[cfg(feature = "encoding_rs")]
+pub mod transcode;
+pub mod unicode;
+
+use std::any::Any;
+use std::borrow::Cow;
 use std::cell::RefCell;
 use std::cell::RefMut;
+use std::collections::HashMap;
 use std::ffi::CStr;
 use std::ffi::CString;
+use std::ffi::IntoStringError;
+use std::ffi::NulError;
+use std::ffi::OsString;
+use std::convert::TryFrom as _;
 use std::fmt::Debug;
 use std::fmt::Error;
 use std::fmt::Formatter;
+use std::io;
+use std::marker::PhantomData;
 use std::mem::replace;
+use std::mem::ManuallyDrop;
 use std::mem::MaybeUninit;
+use std::ops::Range;
+use std::os::unix::ffi::OsStringExt as _;
+use std::path::Path;
+use std::path::PathBuf;
+use std::ptr;
 use std::ptr::addr_of;
 use std::ptr::addr_of_mut;
 use std::ptr::null;
 use std::ptr::null_mut;
+use std::slice;
+use std::str;
+use std::str::Utf8Error;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicI32;
+use std::sync::atomic::AtomicPtr;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 use std::sync::Mutex;
 use std::sync::MutexGuard;
 use std::sync::Once;
@@ -52,18 +122,78 @@ use std::sync::TryLockError;
 
 use libc::c_char;
 use libc::c_int;
+use libc::c_ulong;
 use libc::c_void;
 use libc::calloc;
+#[cfg(readline_7)]
+use libc::fflush;
 use libc::free;
+#[cfg(readline_7)]
+use libc::FILE;
 
 use uid::Id as IdT;
 
+#[cfg(feature = "zeroize")]
+use zeroize::Zeroize as _;
+#[cfg(feature = "zeroize")]
+use zeroize::Zeroizing;
+
+pub use crate::bindings::Binding;
+pub use crate::bindings::BindingGroup;
+pub use crate::bindings::CheatSheet;
+pub use crate::builder::ReadlineBuilder;
+pub use crate::diff::Change;
+pub use crate::flags::StateFlags;
+pub use crate::history::HistoryEntry;
+#[cfg(feature = "test-util")]
+pub use crate::golden::Golden;
+#[cfg(feature = "test-util")]
+pub use crate::golden::GoldenMismatch;
+pub use crate::mask::Mask;
+pub use crate::merge::Dedup;
+pub use crate::merge::HistoryMerge;
+pub use crate::merge::HistorySource;
+pub use crate::merge::MergeOrder;
+pub use crate::metrics::Metrics;
+pub use crate::observer::LineObserver;
+pub use crate::record::RecordedEvent;
+pub use crate::record::Recording;
+pub use crate::repl::Repl;
+#[cfg(feature = "serde")]
+pub use crate::session::Session;
+pub use crate::stream::FeedLines;
+#[cfg(feature = "test-util")]
+pub use crate::tester::ReadlineTester;
+pub use crate::transform::Transform;
+pub use crate::transform::TransformFn;
+pub use crate::undo::UndoKind;
+pub use crate::undo::UndoRecord;
+pub use crate::undo::UndoRecords;
+
+use crate::capture::Capture;
+use crate::history::Data;
+use crate::history::History;
+use crate::keymap::Keymaps;
+use crate::kill::rl_command_func_t;
+use crate::kill::KillRing;
+use crate::metrics::Counters;
+use crate::observer::Edit;
+use crate::record::Recorder;
+use crate::register::Registers;
+use crate::sync::HistorySync;
+use crate::undo::UNDO_LIST;
+
 #[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
 struct T(());
 
 type Id = IdT<T>;
 
 
+/// An identifier uniquely identifying a `Readline` instance.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct InstanceId(Id);
+
+
 #[allow(non_camel_case_types)]
 type rl_voidfunc_t = extern "C" fn();
 #[allow(non_camel_case_types)]
@@ -79,13 +209,39 @@ extern "C" {
   static mut rl_line_buffer: *mut c_char;
   static mut rl_line_buffer_len: c_int;
   static mut rl_point: c_int;
+  static mut rl_num_chars_to_read: c_int;
+  static mut rl_already_prompted: c_int;
+  static mut rl_terminal_name: *const c_char;
   static mut rl_end: c_int;
-  static mut rl_undo_list: *mut c_void;
+  static rl_mark: c_int;
+  static mut rl_undo_list: *mut UNDO_LIST;
+  static mut rl_prompt: *mut c_char;
+  static mut rl_display_prompt: *mut c_char;
+
+  static mut rl_last_func: Option<rl_command_func_t>;
+
+  static rl_editing_mode: c_int;
+  static mut rl_readline_name: *const c_char;
+  static rl_readline_state: c_ulong;
+  static rl_numeric_arg: c_int;
+  // Not part of the public header, but exported nonetheless.
+  static rl_arg_sign: c_int;
+
+  static rl_basic_quote_characters: *const c_char;
+  static rl_basic_word_break_characters: *const c_char;
+  static rl_completer_word_break_characters: *mut c_char;
 
   static mut rl_executing_keyseq: *mut c_char;
   static mut rl_key_sequence_length: c_int;
 
   static mut rl_input_available_hook: *mut rl_hook_func_t;
+  static mut rl_startup_hook: *mut rl_hook_func_t;
+  static mut rl_pre_input_hook: *mut rl_hook_func_t;
+
+  // Not part of the public header, but exported nonetheless.
+  static mut _rl_callback_func: *mut c_void;
+  static mut _rl_last_command_was_kill: c_int;
+  static mut _rl_callback_data: *mut c_void;
 
   static mut rl_catch_signals: c_int;
   static mut rl_catch_sigwinch: c_int;
@@ -95,9 +251,51 @@ extern "C" {
   static mut rl_deprep_term_function: *mut rl_voidfunc_t;
 
   fn rl_callback_handler_install(prompt: *const c_char, handler: *mut rl_vcpfunc_t);
+  fn rl_callback_handler_remove();
   fn rl_stuff_char(c: c_int) -> c_int;
   fn rl_callback_read_char();
+  fn rl_read_init_file(filename: *const c_char) -> c_int;
+  fn rl_emacs_editing_mode(count: c_int, key: c_int) -> c_int;
+  fn rl_vi_editing_mode(count: c_int, key: c_int) -> c_int;
+  fn rl_vi_movement_mode(count: c_int, key: c_int) -> c_int;
+  fn rl_variable_value(variable: *const c_char) -> *mut c_char;
+  fn rl_variable_bind(variable: *const c_char, value: *const c_char) -> c_int;
+  fn rl_get_keymap() -> *mut c_void;
+  fn rl_insert(count: c_int, key: c_int) -> c_int;
+  fn rl_newline(count: c_int, key: c_int) -> c_int;
+  fn rl_abort(count: c_int, key: c_int) -> c_int;
+  fn rl_do_lowercase_version(count: c_int, key: c_int) -> c_int;
+  fn rl_yank_pop(count: c_int, key: c_int) -> c_int;
+  #[cfg(readline_7)]
+  fn rl_vi_yank_pop(count: c_int, key: c_int) -> c_int;
+  fn rl_rubout(count: c_int, key: c_int) -> c_int;
+  fn rl_forward_char(count: c_int, key: c_int) -> c_int;
+  fn rl_backward_char(count: c_int, key: c_int) -> c_int;
+  fn rl_quoted_insert(count: c_int, key: c_int) -> c_int;
+  #[cfg(readline_8_1)]
+  fn rl_operate_and_get_next(count: c_int, key: c_int) -> c_int;
+  fn rl_get_previous_history(count: c_int, key: c_int) -> c_int;
+  fn rl_vi_put(count: c_int, key: c_int) -> c_int;
+  fn rl_kill_text(from: c_int, to: c_int) -> c_int;
+  fn rl_delete_text(start: c_int, end: c_int) -> c_int;
+  fn rl_begin_undo_group() -> c_int;
+  fn rl_end_undo_group() -> c_int;
+  fn rl_function_of_keyseq_len(
+    keyseq: *const c_char,
+    len: usize,
+    map: *mut c_void,
+    type_: *mut c_int,
+  ) -> Option<rl_command_func_t>;
+  fn rl_get_keymap_by_name(name: *const c_char) -> *mut c_void;
+  fn rl_get_screen_size(rows: *mut c_int, columns: *mut c_int);
+  fn rl_set_screen_size(rows: c_int, columns: c_int);
+  fn rl_insert_text(text: *const c_char) -> c_int;
+  fn rl_extend_line_buffer(len: c_int);
   fn rl_replace_line(text: *const c_char, clear_undo: c_int);
+  fn rl_set_prompt(prompt: *const c_char) -> c_int;
+  fn rl_on_new_line() -> c_int;
+  fn rl_on_new_line_with_prompt() -> c_int;
+  fn rl_expand_prompt(prompt: *mut c_char) -> c_int;
 
   fn rl_save_state(state: *mut readline_state) -> c_int;
   // Note that the actual prototype accepts a mutable pointer to
@@ -105,9 +303,37 @@ extern "C" {
   fn rl_restore_state(state: *const readline_state) -> c_int;
 
   fn rl_free_undo_list();
+  fn rl_do_undo() -> c_int;
+  fn rl_free_line_state();
+  #[cfg(readline_7)]
+  fn rl_callback_sigcleanup();
+  #[cfg(readline_7)]
+  fn rl_clear_visible_line() -> c_int;
+  #[cfg(readline_8_1)]
+  fn rl_activate_mark();
+  #[cfg(readline_8_1)]
+  fn rl_deactivate_mark();
+  #[cfg(readline_8_1)]
+  fn rl_mark_active_p() -> c_int;
+  fn rl_read_key() -> c_int;
+  fn where_history() -> c_int;
+  // Not part of the public header, but exported nonetheless.
+  fn _rl_pushed_input_available() -> c_int;
+  static mut _rl_screenheight: c_int;
+  static mut _rl_screenwidth: c_int;
+  static mut _rl_screenchars: c_int;
+  #[cfg(readline_7)]
+  static mut _rl_out_stream: *mut FILE;
+  #[cfg(readline_8_1)]
+  static mut _rl_keep_mark_active: c_int;
 }
 
 
+/// The number of bytes libreadline's input buffer can hold. The buffer
+/// is 512 bytes in size, but one slot is always kept free.
+const INPUT_CAPACITY: usize = 511;
+
+
 /// A helper function for loading a `readline_state` object.
 fn load_state(state: *mut readline_state) {
   let result = unsafe { rl_save_state(state) };
@@ -143,6 +369,81 @@ impl Debug for readline_state {
 }
 
 
+/// The state of a command waiting for more input in callback mode
+/// (e.g., `character-search`), which libreadline does not include in
+/// `readline_state`.
+#[derive(Debug)]
+struct Callback {
+  func: *mut c_void,
+  data: *mut c_void,
+}
+
+// SAFETY: The callback data is heap allocated and only ever accessed
+//         while holding the global mutex, regardless of the thread we
+//         are on.
+unsafe impl Send for Callback {}
+
+impl Callback {
+  /// Load the state from libreadline's globals.
+  fn load(&mut self) {
+    unsafe {
+      self.func = _rl_callback_func;
+      self.data = _rl_callback_data;
+    }
+  }
+
+  /// Save the state into libreadline's globals.
+  fn save(&self) {
+    unsafe {
+      _rl_callback_func = self.func;
+      _rl_callback_data = self.data;
+    }
+  }
+}
+
+impl Default for Callback {
+  fn default() -> Self {
+    Self {
+      func: null_mut(),
+      data: null_mut(),
+    }
+  }
+}
+
+
+/// The state of the active region, which libreadline does not include
+/// in `readline_state` either.
+#[cfg(readline_8_1)]
+#[derive(Debug, Default)]
+struct ActiveMark {
+  active: bool,
+  keep: c_int,
+}
+
+#[cfg(readline_8_1)]
+impl ActiveMark {
+  /// Load the state from libreadline's globals.
+  fn load(&mut self) {
+    unsafe {
+      self.active = rl_mark_active_p() != 0;
+      self.keep = _rl_keep_mark_active;
+    }
+  }
+
+  /// Save the state into libreadline's globals.
+  fn save(&self) {
+    unsafe {
+      if self.active {
+        rl_activate_mark()
+      } else {
+        rl_deactivate_mark()
+      }
+      _rl_keep_mark_active = self.keep;
+    }
+  }
+}
+
+
 trait Locked {
   /// Check whether a lock is currently held.
   fn is_locked(&self) -> bool;
@@ -161,473 +462,7748 @@ impl<T> Locked for Mutex<T> {
 }
 
 
-/// A wrapper for `MutexGuard` ensuring that our libreadline state is read back before dropping.
-struct ReadlineGuard<'data> {
-  _guard: MutexGuard<'data, Id>,
-  state: RefMut<'data, Box<readline_state>>,
+/// The entirety of the state associated with a single `Readline`
+/// instance.
+#[derive(Debug)]
+struct State {
+  /// libreadline's own state.
+  rl: readline_state,
+  /// libreadline's callback state.
+  callback: Callback,
+  /// libreadline's active region state.
+  #[cfg(readline_8_1)]
+  mark: ActiveMark,
+  /// The instance's history list.
+  history: History,
+  /// The synchronization of the history with a shared file, if
+  /// enabled.
+  history_sync: Option<HistorySync>,
+  /// The mirror of the instance's kill ring.
+  kill_ring: KillRing,
+  /// The limit to apply to the kill ring.
+  kill_ring_limit: Limit,
+  /// The instance's own keymaps, if it is isolated.
+  keymaps: Option<Box<Keymaps>>,
+  /// Whether the instance handles sensitive data.
+  #[cfg(feature = "zeroize")]
+  sensitive: bool,
+  /// The hook to invoke whenever libreadline wants the line displayed.
+  redisplay: Option<Hook<RedisplayFn>>,
+  /// The function used for highlighting the line.
+  highlighter: Option<Hook<HighlightFn>>,
+  /// Whether to suggest completions of the line from the history.
+  autosuggest: bool,
+  /// The function used for checking whether an accepted line is
+  /// complete.
+  validator: Option<Hook<ValidateFn>>,
+  /// The transformations to apply to completed lines, in order.
+  transforms: Vec<Transform>,
+  /// The function used for suggesting corrections of accepted lines.
+  corrector: Option<Hook<CorrectFn>>,
+  /// The correction suggested for the line most recently accepted, if
+  /// any.
+  correction: Option<CString>,
+  /// The way continuation lines of multi-line input are indented, if
+  /// at all.
+  indent: Option<Indent>,
+  /// The mask to apply to the line for displaying purposes, if any.
+  mask: Option<Mask>,
+  /// The transient status message set by the application, if any.
+  status: Option<CString>,
+  /// The screen size (rows and columns) set for the instance, if any.
+  screen_size: Option<(c_int, c_int)>,
+  /// The editing mode as of the most recent feed.
+  mode: EditMode,
+  /// The hook to invoke whenever the editing mode changes.
+  mode_hook: Option<Hook<ModeFn>>,
+  /// The hook to invoke when libreadline starts reading a new line.
+  startup_hook: Option<Hook<LineHookFn>>,
+  /// The hook to invoke when libreadline starts reading a new line,
+  /// after the prompt got displayed.
+  pre_input_hook: Option<Hook<LineHookFn>>,
+  /// The hook to invoke for key sequences without a binding.
+  unbound_hook: Option<Hook<UnboundFn>>,
+  /// The filter to consult before inserting a key into the line.
+  input_filter: Option<Hook<InputFilterFn>>,
+  /// The observer to report editing events to, if any.
+  observer: Option<Hook<dyn LineObserver>>,
+  /// The hook to report output libreadline wrote to its output stream
+  /// to, if any.
+  stray_output_hook: Option<Hook<StrayOutputFn>>,
+  /// The change to the line caused by the most recent feed.
+  change: Change,
+  /// Whether the line got modified since it was last reset or
+  /// accepted.
+  dirty: bool,
+  /// Whether modifications of the line are rejected.
+  read_only: bool,
+  /// The composition text of an input method, if any.
+  preedit: Option<CString>,
+  /// The abbreviations to expand, keyed by the abbreviated word.
+  abbreviations: HashMap<Vec<u8>, CString>,
+  /// The hook deciding whether to expand an abbreviation.
+  abbreviation_hook: Option<Hook<AbbreviationFn>>,
+  /// Whether to automatically pair brackets and quotes.
+  auto_pair: bool,
+  /// Whether to move the cursor across entire grapheme clusters.
+  grapheme_movement: bool,
+  /// The named vi registers.
+  registers: Registers,
+  /// The vi operator (e.g., `vi-delete-to`) waiting for its motion to
+  /// complete, if any.
+  operator: Option<rl_command_func_t>,
+  /// The index of the history entry to make current once the line
+  /// most recently accepted via `operate-and-get-next` got added to the
+  /// history.
+  next_entry: Option<usize>,
+  /// The key sequence executed by the most recent feed.
+  keyseq: Vec<u8>,
+  /// Bytes fed but not yet processed by libreadline.
+  pending: Vec<u8>,
+  /// Usage counters of the instance.
+  metrics: Counters,
+  /// The recorder of input events, if recording.
+  recorder: Option<Recorder>,
+  /// The byte treated as interrupt character, if any.
+  interrupt: Option<u8>,
+  /// The byte treated as end-of-file character on an empty line, if
+  /// any.
+  eof: Option<u8>,
+  /// The number of bytes after which the line is accepted
+  /// automatically, or zero.
+  chars_to_read: c_int,
+  /// The text inserted by `insert-comment` set for the instance, if
+  /// any.
+  comment_begin: Option<CString>,
+  /// Whether the application displays the prompt itself.
+  already_prompted: bool,
+  /// The name of the terminal set for the instance, if any.
+  terminal: Option<CString>,
+  /// The maximum number of undo records to keep for the line, if
+  /// limited.
+  undo_limit: Option<usize>,
+  /// The label identifying the instance in `Debug` output, if any.
+  label: Option<String>,
+  /// Whether to include the line in `Debug` output.
+  debug_line: bool,
 }
 
-impl Drop for ReadlineGuard<'_> {
-  fn drop(&mut self) {
-    // Before unlocking (by virtue of dropping the embedded guard)
-    // always make sure to read back the most recent version of the
-    // state from the globals.
-    self.state.load()
+impl State {
+  /// Determine the highlighting spans for the given line, if a
+  /// highlighter is installed.
+  ///
+  /// Masked lines are never highlighted.
+  fn highlight(&mut self, line: &CStr) -> Vec<Span> {
+    match &mut self.highlighter {
+      Some(Hook(highlighter)) if self.mask.is_none() => highlighter(line),
+      _ => Vec::new(),
+    }
   }
-}
-
-
-/// A type representing a single key. A key is a sequence of bytes which
-/// can be anything from a single byte representing an ASCII character
-/// or a terminal escape sequence.
-type Key = [u8];
 
+  /// Check whether the given accepted line is complete.
+  fn validate(&mut self, line: &CStr) -> Validation {
+    match &mut self.validator {
+      Some(Hook(validator)) => validator(line),
+      None => Validation::Complete,
+    }
+  }
 
-/// A struct representing a context for reading a line using libreadline.
-#[derive(Debug)]
-pub struct Readline {
-  id: Id,
-  state: RefCell<Box<readline_state>>,
-}
+  /// Apply the configured transformations to the given completed line.
+  fn transform(&mut self, line: CString) -> CString {
+    #[cfg(feature = "zeroize")]
+    let sensitive = self.sensitive;
 
-impl Readline {
-  /// Stub used as a terminal preparation function.
-  extern "C" fn initialize_term(_: c_int) {}
+    self.transforms.iter_mut().fold(line, |line, transform| {
+      let transformed = transform.apply(&line);
+      #[cfg(feature = "zeroize")]
+      {
+        if sensitive {
+          line.into_bytes().zeroize();
+        }
+      }
+      transformed
+    })
+  }
 
-  /// Stub used as a terminal "unpreparation" function.
-  extern "C" fn uninitialize_term() {}
+  /// Suggest a correction for the given accepted line.
+  ///
+  /// Masked lines are never corrected.
+  fn correct(&mut self, line: &CStr) -> Option<CString> {
+    match &mut self.corrector {
+      Some(Hook(corrector)) if self.mask.is_none() => {
+        corrector(line).filter(|correction| correction.as_c_str() != line)
+      },
+      _ => None,
+    }
+  }
 
-  /// Stub used as a display function.
-  extern "C" fn display() {}
+  /// Compute the indentation of a continuation line inserted after
+  /// the given text.
+  fn indentation(&mut self, text: &[u8]) -> Vec<u8> {
+    let mut indent = match &mut self.indent {
+      Some(Indent::Copy) => {
+        let start = text.iter().rposition(|b| *b == b'\n').map(|i| i + 1).unwrap_or(0);
+        text[start..]
+          .iter()
+          .take_while(|b| matches!(b, b' ' | b'\t'))
+          .copied()
+          .collect()
+      },
+      Some(Indent::Custom(indent)) => {
+        // The text is a prefix of the line and, hence, free of NUL
+        // bytes.
+        let text = CString::new(text).unwrap();
+        indent(&text)
+      },
+      None => Vec::new(),
+    };
 
-  /// Stub used as a callback to check whether new input is available.
-  /// We explicitly feed input on demand, so we never want libreadline
-  /// to read from stdin.
-  extern "C" fn input_available() -> c_int {
-    // We feed input explicitly, so there is never ever something
-    // available on our input streams.
-    0
+    if let Some(nul) = indent.iter().position(|b| *b == b'\0') {
+      let () = indent.truncate(nul);
+    }
+    indent
   }
 
-  /// A callback invoked when libreadline has completed a line.
+  /// Determine the suggestion for completing the given line, if
+  /// autosuggestions are enabled.
   ///
-  /// This function can only be invoked indirectly through the `feed`
-  /// method. As such, we are guaranteed mutual exclusion with respect
-  /// to global libreadline state.
-  extern "C" fn handle_line(line: *mut c_char) {
-    debug_assert!(Self::mutex().is_locked());
-
-    // SAFETY: Our global mutex is locked (as per assertion above) and
-    //         we only call the function once.
-    let line_ref = unsafe { Self::line() };
-    if line.is_null() {
-      let _ = replace(line_ref, Some(CString::new("").unwrap()));
+  /// Suggestions are only made with the cursor at the end of the line
+  /// and never for masked lines. The instance has to be active for the
+  /// history to be consulted.
+  fn suggestion(&self, line: &CStr, cursor: usize) -> Option<&CStr> {
+    let line = line.to_bytes();
+    if self.autosuggest && self.mask.is_none() && cursor == line.len() {
+      suggest::suggest(line, self.history.lines())
     } else {
-      unsafe {
-        let _ = replace(line_ref, Some(CStr::from_ptr(line).into()));
-        free(line.cast());
-      }
+      None
     }
   }
 
-  /// Create a new `Readline` instance.
+  /// Retrieve the status message to report, if any.
   ///
-  /// # Panics
+  /// A message set by the application takes precedence over messages
+  /// describing libreadline's own state. The instance has to be active
+  /// for the latter to be meaningful.
+  fn status(&self) -> Option<CString> {
+    if let Some(status) = &self.status {
+      Some(status.clone())
+    } else if unsafe { state_flags() }.contains(StateFlags::NUMERICARG) {
+      let arg = unsafe { numeric_arg() };
+      // A formatted number never contains a NUL byte.
+      Some(CString::new(format!("(arg: {})", arg)).unwrap())
+    } else {
+      None
+    }
+  }
+
+  /// Retrieve the composition text to display, if any, in its masked
+  /// form if a mask is set.
+  fn preedit(&self) -> Option<CString> {
+    let preedit = self.preedit.as_ref()?;
+    match self.mask {
+      Some(mask) => Some(mask.apply(preedit.to_bytes(), 0).0),
+      None => Some(preedit.clone()),
+    }
+  }
+
+  /// Expand the abbreviation immediately before the cursor, if any,
+  /// provided that the given key is about to insert a word break
+  /// character or to accept the line.
   ///
-  /// Panics on failure to allocate internally used C objects.
-  pub fn new() -> Self {
-    let rl = Self {
-      id: Id::new(),
-      state: RefCell::new(Box::new(Self::initial().clone())),
+  /// # Safety
+  /// Callers must ensure that the instance is active.
+  unsafe fn expand_abbreviation(&mut self, key: &[u8]) {
+    if self.abbreviations.is_empty() || self.mask.is_some() || self.read_only {
+      return
+    }
+
+    let breaks = unsafe { word_breaks() };
+    let breaking = match key {
+      [c] if breaks.contains(c) => unsafe { is_bound_to(key, rl_insert) },
+      _ => false,
     };
+    if !breaking && !unsafe { is_bound_to(key, rl_newline) } {
+      return
+    }
 
-    {
-      // Make sure that the new state is activated.
-      // TODO: Strictly speaking we could omit the load operation
-      //       happening when the guard leaves the scope. We know that
-      //       the state is current, so it just wastes cycles.
-      let mut guard = rl.activate();
+    let (line, cursor) = unsafe { (Readline::buffer(), rl_point as usize) };
+    let range = match word::find(line, cursor, breaks) {
+      Some(range) if range.end == cursor => range,
+      _ => return,
+    };
+    let expansion = match self.abbreviations.get(&line[range.clone()]) {
+      Some(expansion) => expansion,
+      None => return,
+    };
 
-      unsafe {
-        debug_assert!(rl_line_buffer.is_null());
-        debug_assert!(rl_executing_keyseq.is_null());
+    if let Some(Hook(hook)) = &mut self.abbreviation_hook {
+      // The word is part of a C string and so cannot contain a NUL
+      // byte.
+      let word = CString::new(&line[range.clone()]).unwrap();
+      if !hook(&word, expansion) {
+        return
+      }
+    }
 
-        // Unfortunately `readline_state` contains some data that is
-        // allocated by libreadline itself, as part of its
-        // initialization. Because we create a new context we need to
-        // reinitialize this data.
-        rl_line_buffer = calloc(1, rl_line_buffer_len as _).cast();
-        rl_executing_keyseq = calloc(1, rl_key_sequence_length as _).cast();
+    unsafe {
+      let _result = rl_begin_undo_group();
+      let _count = rl_delete_text(range.start as _, range.end as _);
+      rl_point = range.start as _;
+      let _count = rl_insert_text(expansion.as_ptr());
+      let _result = rl_end_undo_group();
+    }
+  }
 
-        // We use similar behavior to default Rust and panic on
-        // allocation failure.
-        assert!(!rl_line_buffer.is_null(), "failed to allocate rl_line_buffer");
-        assert!(!rl_executing_keyseq.is_null(), "failed to allocate rl_executing_keyseq");
-      }
+  /// Handle the given key as part of selecting a vi register or of
+  /// putting the content of the selected one.
+  ///
+  /// Returns `true` if the key got handled and must not be fed to
+  /// libreadline.
+  ///
+  /// # Safety
+  /// Callers must ensure that the instance is active.
+  unsafe fn handle_register(&mut self, key: &[u8]) -> bool {
+    if self.registers.is_selecting() {
+      let () = self.registers.finish_selection(key);
+      return true
+    }
 
-      // We allocated some memory with the new addresses going directly
-      // into libreadline's globals. So make sure to read back that
-      // state to have an up-to-date snapshot.
-      guard.state.load();
-      // Believe it or not, but libreadline aliases the line buffer
-      // internally with a pointer, and only storing the state back into
-      // the global will update this pointer. So we need this additional
-      // save here. Yes, that one is a pearl.
-      guard.state.save();
+    #[cfg(feature = "zeroize")]
+    let sensitive = self.sensitive;
+    #[cfg(not(feature = "zeroize"))]
+    let sensitive = false;
+
+    if sensitive || self.mask.is_some() || unsafe { EditMode::current() } != EditMode::ViCommand {
+      return false
     }
 
-    rl
+    if key == [register::UNNAMED] && unsafe { is_unbound(key) } {
+      let () = self.registers.start_selection();
+      return true
+    }
+
+    match self.registers.selected() {
+      Some(name) if name != register::UNNAMED && unsafe { is_bound_to(key, rl_vi_put) } => {
+        match self.registers.get(name).cloned() {
+          Some(text) if !text.as_bytes().is_empty() => {
+            // `put` inserts the most recently killed text, so make the
+            // register's content just that.
+            let () = unsafe { push_kill(&text) };
+            let () = unsafe { self.record_kill(|ring| ring.add(text.as_bytes())) };
+            false
+          },
+          // There is nothing to put.
+          _ => {
+            let () = self.registers.clear_selection();
+            true
+          },
+        }
+      },
+      _ => false,
+    }
   }
 
-  /// Retrieve the pristine initial `readline_state` as it was set by libreadline.
-  fn initial() -> &'static readline_state {
-    // We effectively cache a version of `readline_state` as it was set
-    // by libreadline before anything could have changed. This state
-    // acts as the template for all the states we create later on.
-    static mut STATE: MaybeUninit<readline_state> = MaybeUninit::uninit();
-    static ONCE: Once = Once::new();
+  /// Update the selected vi register, if any, after a command got
+  /// executed, storing the text it killed.
+  ///
+  /// # Safety
+  /// Callers must ensure that the instance is active.
+  unsafe fn update_register(
+    &mut self,
+    func: Option<rl_command_func_t>,
+    before: &[u8],
+    after: &[u8],
+    cursor: usize,
+  ) {
+    if let Some(name) = self.registers.selected() {
+      if name != register::UNNAMED {
+        if let Some(text) = kill::killed(func, before, after, cursor) {
+          let () = self.registers.set(name, text);
+        }
+      }
 
-    // We should be safe *not* using our all-protecting mutex here
-    // because this functionality is invoked only as the very first
-    // interaction with libreadline, by virtue of being used only in
-    // the constructor of objects of the one struct that has exclusive
-    // access to libreadline's global state.
-    ONCE.call_once(|| unsafe {
-      // Disable a bunch of libreadline stuff that would mess up things
-      // we don't want messed up, most prominently signal handler state
-      // and terminal state.
-      // This is all state that is part of `readline_state`, so we make
-      // those changes once for the template and don't have to worry
-      // about them again.
-      rl_catch_signals = 0;
-      rl_catch_sigwinch = 0;
-      rl_input_available_hook = Self::input_available as *mut _;
-      rl_redisplay_function = Self::display as *mut _;
-      rl_prep_term_function = Self::initialize_term as *mut _;
-      rl_deprep_term_function = Self::uninitialize_term as *mut _;
+      // The selection lasts for a single command, which may require
+      // more keys to complete.
+      if !unsafe { is_busy() } {
+        let () = self.registers.clear_selection();
+      }
+    }
+  }
 
-      // Note that we do not ever invoke rl_callback_handler_remove.
-      // This crate's assumption is that it is the sole user of
-      // libreadline meaning nobody else will mess with global state. As
-      // such, and because we set the same handler for all contexts,
-      // there is no point in doing additional work to remove it. In
-      // addition, due to the retardedness of libreadline and it not
-      // capturing even all of its own global state, we could not even
-      // remove the handler if we wanted to, because activating a
-      // `readline_state` object would not set the handler. Sigh.
-      rl_callback_handler_install(null(), Self::handle_line as *mut _);
+  /// Handle the given key as part of auto-pairing, if enabled and
+  /// applicable.
+  ///
+  /// Returns `true` if the key got handled and must not be fed to
+  /// libreadline.
+  ///
+  /// # Safety
+  /// Callers must ensure that the instance is active.
+  unsafe fn auto_pair(&mut self, key: &[u8]) -> bool {
+    if !self.auto_pair || self.mask.is_some() || self.read_only {
+      return false
+    }
 
-      // libreadline already has buffers allocated but we won't be using
-      // them.
-      free(rl_line_buffer.cast());
-      free(rl_executing_keyseq.cast());
+    let flags = unsafe { state_flags() };
+    if flags.contains(StateFlags::OVERWRITE) {
+      return false
+    }
 
-      rl_line_buffer = null_mut();
-      rl_executing_keyseq = null_mut();
-      rl_undo_list = null_mut();
+    let (line, cursor) = unsafe { (Readline::buffer(), rl_point as usize) };
+    let next = line.get(cursor).copied();
+    let prev = cursor.checked_sub(1).and_then(|i| line.get(i)).copied();
 
-      load_state(STATE.as_mut_ptr());
-    });
+    match key {
+      [c] if unsafe { is_bound_to(key, rl_insert) } => {
+        let c = *c;
+        if next == Some(c) && matches!(c, b')' | b']' | b'}' | b'"' | b'\'') {
+          // Type over the existing closing character.
+          unsafe { rl_point += 1 };
+          true
+        } else if let Some(closer) = pair_closer(c) {
+          // Quotes following a word character, as in "don't", are not
+          // paired.
+          if c == closer && matches!(prev, Some(p) if p.is_ascii_alphanumeric()) {
+            return false
+          }
 
-    // `STATE` is guaranteed to be initialized after the above call to
-    // `load_state`, so it should be safe to create a reference to the
-    // data now.
-    // TODO: Until MaybeUninit::get_ref is stable we have to craft our
-    //       own version.
-    unsafe { &*STATE.as_ptr() }
+          let text = [c, closer, b'\0'];
+          unsafe {
+            let _count = rl_insert_text(text.as_ptr().cast());
+            rl_point -= 1;
+          }
+          true
+        } else {
+          false
+        }
+      },
+      _ if unsafe { is_bound_to(key, rl_rubout) } => match (prev, next) {
+        (Some(p), Some(n)) if pair_closer(p) == Some(n) => {
+          // Delete an empty pair entirely.
+          unsafe {
+            let _count = rl_delete_text((cursor - 1) as _, (cursor + 1) as _);
+            rl_point = (cursor - 1) as _;
+          }
+          true
+        },
+        _ => false,
+      },
+      _ => false,
+    }
   }
 
-  /// Retrieve a reference to the `Mutex` protecting all accesses to
-  /// libreadline's global state.
-  fn mutex() -> &'static Mutex<Id> {
-    static mut MUTEX: Option<Mutex<Id>> = None;
-    static ONCE: Once = Once::new();
+  /// Handle the given key as a cursor movement across a grapheme
+  /// cluster, if enabled and applicable.
+  ///
+  /// Returns `true` if the key got handled and must not be fed to
+  /// libreadline.
+  ///
+  /// # Safety
+  /// Callers must ensure that the instance is active.
+  unsafe fn move_grapheme(&mut self, key: &[u8]) -> bool {
+    if !self.grapheme_movement {
+      return false
+    }
 
-    ONCE.call_once(|| unsafe { MUTEX = Some(Mutex::new(Id::new())) });
+    if unsafe { is_bound_to(key, rl_forward_char) } {
+      unsafe { move_grapheme(true) }
+    } else if unsafe { is_bound_to(key, rl_backward_char) } {
+      unsafe { move_grapheme(false) }
+    } else {
+      false
+    }
+  }
 
-    // SAFETY: We never ever hand out mutable references to `MUTEX` or
-    //         use one beyond this point and so it's always safe to
-    //         create a shared one.
-    match unsafe { &*addr_of!(MUTEX) } {
-      Some(mutex) => mutex,
-      None => unreachable!(),
+  /// Discard the composition text, if any.
+  fn clear_preedit(&mut self) {
+    let _preedit = self.preedit.take();
+    #[cfg(feature = "zeroize")]
+    {
+      if let Some(preedit) = _preedit {
+        if self.sensitive {
+          preedit.into_bytes().zeroize();
+        }
+      }
     }
   }
 
-  /// A reference to the global line storage.
+  /// Record killed text in the instance's kill ring mirror and, unless
+  /// the instance is isolated, in the one shared by all instances.
   ///
   /// # Safety
-  /// Callers must ensure that the global mutex is held for the duration
+  /// Callers must ensure that the instance is active.
+  unsafe fn record_kill<F>(&mut self, record: F)
+  where
+    F: Fn(&mut KillRing),
+  {
+    let () = record(&mut self.kill_ring);
+    if self.keymaps.is_none() {
+      let () = record(unsafe { shared_kill_ring() });
+    }
+    let () = unsafe { self.enforce_kill_ring_limit() };
+  }
+
+  /// Apply the kill ring limit to the instance's kill ring mirror and,
+  /// unless the instance is isolated, to the one shared by all
+  /// instances, evicting entries from libreadline's kill ring as well.
+  ///
+  /// # Safety
+  /// Callers must ensure that the instance is active.
+  unsafe fn enforce_kill_ring_limit(&mut self) {
+    let limit = self.kill_ring_limit;
+    let mut evicted = self.kill_ring.enforce_limit(limit);
+    if self.keymaps.is_none() {
+      // libreadline's kill ring is the shared one in this case.
+      evicted |= unsafe { shared_kill_ring() }.enforce_limit(limit);
+    }
+
+    if evicted {
+      let () = unsafe { reload_kill_ring(self) };
+    }
+  }
+
+  /// Load the state from libreadline's globals.
+  fn load(&mut self) {
+    if let Some(limit) = self.undo_limit {
+      unsafe { undo::trim(&mut *addr_of_mut!(rl_undo_list), limit) }
+    }
+    self.rl.load();
+    self.callback.load();
+    #[cfg(readline_8_1)]
+    self.mark.load();
+    self.history.load();
+    // libreadline's input buffer is shared by all instances. Unprocessed
+    // input is restored on activation.
+    self.pending = unsafe { take_pending() };
+    if let Some(keymaps) = &mut self.keymaps {
+      unsafe { keymaps.load() }
+    }
+  }
+
+  /// Save the state into libreadline's globals.
+  fn save(&self) {
+    self.rl.save();
+    self.callback.save();
+    #[cfg(readline_8_1)]
+    self.mark.save();
+    self.history.save();
+  }
+}
+
+
+/// A wrapper around a boxed hook function, providing a `Debug`
+/// implementation.
+struct Hook<F: ?Sized>(Box<F>);
+
+impl<F: ?Sized> Debug for Hook<F> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+    f.debug_tuple("Hook").finish()
+  }
+}
+
+
+/// The data reported to a redisplay hook.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct Redisplay<'data> {
+  /// The line being edited.
+  pub line: &'data CStr,
+  /// The cursor position, as a byte index into `line`.
+  pub cursor: usize,
+  /// The prompt as it should be displayed. Note that libreadline may
+  /// temporarily change the prompt, e.g., while searching the history
+  /// incrementally.
+  pub prompt: &'data CStr,
+  /// The highlighting spans for `line`, as reported by the installed
+  /// highlighter. Empty if no highlighter is installed.
+  pub spans: &'data [Span],
+  /// The text suggested for appending to `line`, if any. See
+  /// [`Readline::set_autosuggestions`].
+  pub suggestion: Option<&'data CStr>,
+  /// The status message to display separately from the line, if any.
+  /// See [`Readline::status`].
+  pub status: Option<&'data CStr>,
+  /// The composition text of an input method to display at the cursor,
+  /// if any. See [`Readline::set_preedit`].
+  pub preedit: Option<&'data CStr>,
+  /// The state of keyboard macro handling, if a macro is being defined
+  /// or executed. See [`Readline::macro_state`].
+  pub macro_state: Option<MacroState>,
+  /// Whether the application displays the prompt itself, in which case
+  /// it is already on screen. See [`Readline::set_already_prompted`].
+  pub already_prompted: bool,
+}
+
+
+/// The context provided to startup and pre-input hooks (see
+/// [`Readline::set_startup_hook`]), granting access to the line about to
+/// be read.
+///
+/// The instance the hook belongs to is active for the lifetime of the
+/// context.
+#[derive(Debug)]
+pub struct LineHookContext<'rl> {
+  _guard: PhantomData<&'rl mut State>,
+}
+
+impl LineHookContext<'_> {
+  /// Retrieve the current line.
+  pub fn line(&self) -> &CStr {
+    // SAFETY: The context only exists while the instance is active.
+    unsafe {
+      if rl_line_buffer.is_null() {
+        <&CStr>::default()
+      } else {
+        CStr::from_ptr(rl_line_buffer)
+      }
+    }
+  }
+
+  /// Retrieve the cursor position, as a byte index into the line.
+  pub fn cursor(&self) -> usize {
+    unsafe { rl_point as usize }
+  }
+
+  /// Replace the line with the given one and place the cursor at the
+  /// given byte index, e.g., to seed the line with text for the user
+  /// to edit.
+  ///
+  /// # Panics
+  ///
+  /// Panics if the cursor is not less than or equal to the number of
+  /// bytes in the given line.
+  pub fn set_line(&mut self, line: &CStr, cursor: usize) {
+    assert!(cursor <= line.to_bytes().len(), "invalid cursor position");
+
+    unsafe {
+      rl_replace_line(line.as_ptr(), 0);
+      rl_point = cursor as _;
+    }
+  }
+
+  /// Set a libreadline variable, in the same way an `inputrc` file's
+  /// `set` directive does.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the variable is unknown or the value is invalid for it.
+  pub fn set_variable(&mut self, variable: &CStr, value: &CStr) -> io::Result<()> {
+    // libreadline merely complains about unknown variables, so check
+    // for their existence up front.
+    let known = unsafe { variable_value(variable.to_bytes_with_nul()) }.is_some();
+    if known && unsafe { rl_variable_bind(variable.as_ptr(), value.as_ptr()) } == 0 {
+      Ok(())
+    } else {
+      Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("failed to set variable {:?} to {:?}", variable, value),
+      ))
+    }
+  }
+}
+
+
+/// The type of a redisplay hook function.
+pub type RedisplayFn = dyn FnMut(&Redisplay<'_>) + Send;
+
+
+/// A styled range of a line, as produced by a highlighter.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Span {
+  /// The byte index at which the span starts.
+  pub start: usize,
+  /// The byte index just past the end of the span.
+  pub end: usize,
+  /// An application defined identifier of the style to apply.
+  pub style: u32,
+}
+
+
+/// The type of a highlighter function, mapping a line to the spans
+/// to style.
+pub type HighlightFn = dyn FnMut(&CStr) -> Vec<Span> + Send;
+
+
+/// The result of validating an accepted line.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Validation {
+  /// The line is complete and should be reported.
+  Complete,
+  /// The line is incomplete and editing should continue on a new
+  /// line.
+  Incomplete,
+  /// The line is invalid and editing of it should continue, with the
+  /// given message (e.g., `unterminated string`) being set as status.
+  Invalid(CString),
+}
+
+
+/// The outcome of feeding a key to a [`Readline`] instance.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Feed {
+  /// The key got processed and editing of the line continues.
+  Pending,
+  /// A line got completed.
+  Line(CString),
+  /// The interrupt character got fed, discarding the line.
+  Interrupted,
+  /// The end-of-file character got fed on an empty line.
+  Eof,
+}
+
+impl Feed {
+  /// Retrieve the completed line, if any.
+  pub fn into_line(self) -> Option<CString> {
+    match self {
+      Self::Line(line) => Some(line),
+      Self::Pending | Self::Interrupted | Self::Eof => None,
+    }
+  }
+
+  /// Retrieve the completed line, if any, as a `String`.
+  ///
+  /// An error is reported if the line is not valid UTF-8. The original
+  /// line can be retrieved from it.
+  pub fn into_string(self) -> Option<Result<String, IntoStringError>> {
+    self.into_line().map(CString::into_string)
+  }
+
+  /// Retrieve the completed line, if any, as a `String`, replacing
+  /// invalid UTF-8 sequences with `U+FFFD REPLACEMENT CHARACTER`.
+  pub fn into_string_lossy(self) -> Option<String> {
+    self
+      .into_line()
+      .map(|line| line.to_string_lossy().into_owned())
+  }
+
+  /// Retrieve the completed line, if any, as an `OsString`.
+  ///
+  /// The line's bytes are used as-is, irrespective of their encoding.
+  pub fn into_os_string(self) -> Option<OsString> {
+    self
+      .into_line()
+      .map(|line| OsString::from_vec(line.into_bytes()))
+  }
+
+  /// Retrieve the completed line, if any, as a `PathBuf`.
+  ///
+  /// The line's bytes are used as-is, irrespective of their encoding.
+  pub fn into_path_buf(self) -> Option<PathBuf> {
+    self.into_os_string().map(PathBuf::from)
+  }
+
+  /// Retrieve the completed line, if any, as a `String`, converting it
+  /// from the given encoding.
+  ///
+  /// This conversion is meant for lines edited under a locale using a
+  /// legacy encoding (e.g., ISO-8859-1), in which case libreadline
+  /// operates on text in that encoding. An error is reported if the
+  /// line is not valid in it.
+  #[cfg(feature = "encoding_rs")]
+  pub fn decode(
+    self,
+    encoding: &'static encoding_rs::Encoding,
+  ) -> Option<Result<String, transcode::TranscodeError>> {
+    self.into_line().map(|line| {
+      encoding
+        .decode_without_bom_handling_and_without_replacement(line.as_bytes())
+        .map(Cow::into_owned)
+        .ok_or(transcode::TranscodeError { encoding })
+    })
+  }
+}
+
+
+/// The type of a validator function, checking whether an accepted
+/// line is complete.
+pub type ValidateFn = dyn FnMut(&CStr) -> Validation + Send;
+
+
+/// The type of a corrector function, suggesting a correction for an
+/// accepted line.
+pub type CorrectFn = dyn FnMut(&CStr) -> Option<CString> + Send;
+
+
+/// The type of an indentation function, computing the indentation of a
+/// continuation line from the text preceding it.
+pub type IndentFn = dyn FnMut(&CStr) -> Vec<u8> + Send;
+
+
+/// The way continuation lines of multi-line input are indented.
+pub enum Indent {
+  /// Copy the leading whitespace (spaces and tabs) of the line
+  /// preceding the continuation line.
+  Copy,
+  /// Use the indentation computed by the given function, which is
+  /// provided with the text preceding the continuation line.
+  Custom(Box<IndentFn>),
+}
+
+impl Debug for Indent {
+  fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+    match self {
+      Self::Copy => f.write_str("Copy"),
+      Self::Custom(..) => f.debug_tuple("Custom").finish(),
+    }
+  }
+}
+
+
+/// An editing mode of libreadline.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum EditMode {
+  /// The emacs editing mode.
+  Emacs,
+  /// The insert mode of the vi editing mode.
+  ViInsert,
+  /// The command (or movement) mode of the vi editing mode.
+  ViCommand,
+}
+
+impl EditMode {
+  /// Determine the editing mode libreadline is currently in.
+  ///
+  /// # Safety
+  /// Callers must ensure that the global mutex is held.
+  unsafe fn current() -> Self {
+    if unsafe { rl_editing_mode } == 1 {
+      Self::Emacs
+    } else {
+      let name = b"vi-command\0";
+      let command = unsafe { rl_get_keymap_by_name(name.as_ptr().cast()) };
+      if unsafe { rl_get_keymap() } == command {
+        Self::ViCommand
+      } else {
+        Self::ViInsert
+      }
+    }
+  }
+}
+
+
+/// The state of libreadline's keyboard macro handling.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum MacroState {
+  /// A keyboard macro is being defined (recorded).
+  Defining,
+  /// A keyboard macro is being executed (played back).
+  Executing,
+}
+
+impl MacroState {
+  /// Determine the state of keyboard macro handling libreadline is
+  /// currently in.
+  ///
+  /// # Safety
+  /// Callers must ensure that the global mutex is held.
+  unsafe fn current() -> Option<Self> {
+    let flags = unsafe { state_flags() };
+    // Keys played back while a macro is being defined become part of
+    // the definition, but the playback is what is going on right now.
+    if flags.contains(StateFlags::MACROINPUT) {
+      Some(Self::Executing)
+    } else if flags.contains(StateFlags::MACRODEF) {
+      Some(Self::Defining)
+    } else {
+      None
+    }
+  }
+}
+
+
+/// Retrieve libreadline's current state flags.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held.
+unsafe fn state_flags() -> StateFlags {
+  StateFlags::from_bits_truncate(unsafe { rl_readline_state } as u32)
+}
+
+
+/// Whether 8-bit clean input handling has been forced.
+static EIGHT_BIT: AtomicBool = AtomicBool::new(false);
+/// The one-time initialization of libreadline, as performed on first
+/// use.
+static INITIALIZE: Once = Once::new();
+/// The number of `Readline` instances alive.
+static INSTANCES: AtomicUsize = AtomicUsize::new(0);
+/// Whether global resources got released via [`shutdown`].
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+/// The terminal name libreadline determined on initialization, used by
+/// instances for which none was set.
+static TERMINAL: AtomicPtr<c_char> = AtomicPtr::new(null_mut());
+/// The screen height, width, and number of characters libreadline
+/// determined on initialization, used by instances for which no screen
+/// size was set.
+static SCREEN: [AtomicI32; 3] = [AtomicI32::new(0), AtomicI32::new(0), AtomicI32::new(0)];
+/// The text inserted by `insert-comment` as configured on
+/// initialization, used by instances for which none was set.
+static COMMENT_BEGIN: AtomicPtr<c_char> = AtomicPtr::new(null_mut());
+
+
+/// Make the given terminal name, or the one determined on
+/// initialization, libreadline's current one.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held and that `name`
+/// stays valid for as long as it is in use.
+unsafe fn apply_terminal_name(name: Option<&CStr>) {
+  let name = match name {
+    Some(name) => name.as_ptr(),
+    None => TERMINAL.load(Ordering::Relaxed),
+  };
+  unsafe { rl_terminal_name = name }
+}
+
+
+/// Make the given screen size, in rows and columns, or the one
+/// determined on initialization libreadline's current one.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held.
+unsafe fn apply_screen_size(size: Option<(c_int, c_int)>) {
+  match size {
+    Some((rows, columns)) => unsafe { rl_set_screen_size(rows, columns) },
+    // libreadline may adjust the size it is given, so restore the
+    // values it ended up with directly.
+    None => unsafe {
+      _rl_screenheight = SCREEN[0].load(Ordering::Relaxed);
+      _rl_screenwidth = SCREEN[1].load(Ordering::Relaxed);
+      _rl_screenchars = SCREEN[2].load(Ordering::Relaxed);
+    },
+  }
+}
+
+
+/// Configure libreadline for 8-bit clean input handling, if it has
+/// been forced.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held or that no other
+/// thread can interact with libreadline.
+unsafe fn apply_eight_bit() {
+  if EIGHT_BIT.load(Ordering::Relaxed) {
+    let variables: [(&[u8], &[u8]); 3] = [
+      (b"input-meta\0", b"on\0"),
+      (b"convert-meta\0", b"off\0"),
+      (b"output-meta\0", b"on\0"),
+    ];
+
+    for (variable, value) in &variables {
+      let _result = unsafe { rl_variable_bind(variable.as_ptr().cast(), value.as_ptr().cast()) };
+    }
+  }
+}
+
+
+/// Remove all bytes stuffed into libreadline's input buffer that have
+/// not yet been processed, returning them.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held.
+unsafe fn take_pending() -> Vec<u8> {
+  // Reading a key counts towards the current key sequence, which we do
+  // not want to be affected.
+  let length = unsafe { rl_key_sequence_length };
+  let mut pending = Vec::new();
+  while unsafe { _rl_pushed_input_available() } != 0 {
+    // libreadline hands out the stuffed bytes one by one.
+    pending.push(unsafe { rl_read_key() } as u8);
+  }
+  unsafe { rl_key_sequence_length = length };
+  pending
+}
+
+
+/// Retrieve the numeric argument, including its sign, as currently
+/// known to libreadline.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held.
+unsafe fn numeric_arg() -> i32 {
+  unsafe { rl_arg_sign * rl_numeric_arg }
+}
+
+
+/// Retrieve the value of the libreadline variable with the given name,
+/// which has to be NUL terminated.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held.
+unsafe fn variable_value(name: &[u8]) -> Option<&'static CStr> {
+  debug_assert_eq!(name.last(), Some(&0));
+
+  let value = unsafe { rl_variable_value(name.as_ptr().cast()) };
+  if value.is_null() {
+    None
+  } else {
+    Some(unsafe { CStr::from_ptr(value) })
+  }
+}
+
+
+/// Retrieve the mode string libreadline is configured to display for
+/// the given editing mode, as per the `emacs-mode-string`,
+/// `vi-ins-mode-string`, and `vi-cmd-mode-string` variables.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held.
+unsafe fn mode_string(mode: EditMode) -> CString {
+  let name: &[u8] = match mode {
+    EditMode::Emacs => b"emacs-mode-string\0",
+    EditMode::ViInsert => b"vi-ins-mode-string\0",
+    EditMode::ViCommand => b"vi-cmd-mode-string\0",
+  };
+  unsafe { variable_value(name) }
+    .map(CStr::to_owned)
+    .unwrap_or_default()
+}
+
+
+/// Set libreadline's `comment-begin` variable to the given text, or to
+/// the one configured on initialization if `None`.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held.
+unsafe fn bind_comment_begin(comment: Option<&CStr>) {
+  let variable = b"comment-begin\0";
+  let comment = match comment {
+    Some(comment) => comment.as_ptr(),
+    None => COMMENT_BEGIN.load(Ordering::Relaxed),
+  };
+  let _result = unsafe { rl_variable_bind(variable.as_ptr().cast(), comment) };
+}
+
+
+/// The type of a hook function invoked on editing mode changes.
+pub type ModeFn = dyn FnMut(EditMode) + Send;
+
+/// The type of a hook function invoked when libreadline starts reading
+/// a new line. See [`Readline::set_startup_hook`] and
+/// [`Readline::set_pre_input_hook`].
+pub type LineHookFn = dyn FnMut(&mut LineHookContext<'_>) + Send;
+
+/// The type of a hook function invoked for fed key sequences that are
+/// not bound to anything.
+pub type UnboundFn = dyn FnMut(&[u8]) + Send;
+
+
+/// The type of a hook function deciding whether to expand an
+/// abbreviation, receiving the abbreviation and its expansion.
+pub type AbbreviationFn = dyn FnMut(&CStr, &CStr) -> bool + Send;
+
+
+/// The decision of an input filter about a key about to be inserted
+/// into the line.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum InputAction {
+  /// Insert the key as is.
+  Accept,
+  /// Insert the given text instead of the key.
+  Replace(Vec<u8>),
+  /// Do not insert anything.
+  Reject,
+}
+
+/// The type of a filter function consulted before a key gets inserted
+/// into the line.
+pub type InputFilterFn = dyn FnMut(&[u8]) -> InputAction + Send;
+
+
+/// The type of a hook function receiving output libreadline wrote to
+/// its output stream.
+pub type StrayOutputFn = dyn FnMut(&[u8]) + Send;
+
+
+/// libreadline's `ISFUNC` key type.
+const ISFUNC: c_int = 0;
+/// libreadline's `ISMACR` key type.
+const ISMACR: c_int = 2;
+
+
+/// Check whether libreadline is in the middle of processing a command
+/// or key sequence.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held.
+unsafe fn is_busy() -> bool {
+  let busy = StateFlags::MOREINPUT
+    | StateFlags::ISEARCH
+    | StateFlags::NSEARCH
+    | StateFlags::NUMERICARG
+    | StateFlags::MACROINPUT
+    | StateFlags::VIMOTION
+    | StateFlags::MULTIKEY
+    | StateFlags::CHARSEARCH;
+
+  unsafe { state_flags() }.intersects(busy)
+    || unsafe { !_rl_callback_func.is_null() || _rl_pushed_input_available() != 0 }
+}
+
+
+/// Look up the function the given key sequence is bound to in the
+/// current keymap, along with the type of the binding.
+///
+/// `None` is returned if libreadline is in the middle of processing a
+/// command or key sequence, in which case the key would not be looked
+/// up in the keymap directly.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held.
+unsafe fn lookup(key: &[u8]) -> Option<(Option<rl_command_func_t>, c_int)> {
+  if unsafe { is_busy() } {
+    return None
+  }
+
+  let mut type_ = ISFUNC;
+  let func = unsafe {
+    rl_function_of_keyseq_len(key.as_ptr().cast(), key.len(), rl_get_keymap(), &mut type_)
+  };
+  Some((func, type_))
+}
+
+
+/// Revert a modification of the line made by the most recently
+/// dispatched command, for instances in read-only mode.
+///
+/// `before`, `from`, `undo`, and `position` describe the line, cursor,
+/// undo list, and history position before the command got dispatched.
+/// Changes caused by history navigation or searches are not considered
+/// modifications. Returns `true` if a modification got reverted.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held.
+unsafe fn revert_modification(
+  before: &[u8],
+  from: usize,
+  undo: *mut UNDO_LIST,
+  position: c_int,
+  searching: bool,
+) -> bool {
+  let search = StateFlags::ISEARCH | StateFlags::NSEARCH;
+  unsafe {
+    if searching
+      || state_flags().intersects(search)
+      || where_history() != position
+      || Readline::buffer() == before
+    {
+      return false
+    }
+
+    if undo::contains(rl_undo_list, undo) {
+      while rl_undo_list != undo {
+        let head = rl_undo_list;
+        if rl_do_undo() == 0 || rl_undo_list == head {
+          break
+        }
+      }
+    }
+
+    // Undo records may have been consumed already, e.g., by the `undo`
+    // command itself, in which case we restore the text directly.
+    if Readline::buffer() != before {
+      let mut text = Vec::with_capacity(before.len() + 1);
+      text.extend_from_slice(before);
+      text.push(b'\0');
+      rl_replace_line(text.as_ptr().cast(), 0);
+    }
+    rl_point = from as _;
+  }
+  true
+}
+
+
+/// Retrieve libreadline's completer word break characters, falling
+/// back to the basic word break characters if none are set.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held.
+unsafe fn word_breaks() -> &'static [u8] {
+  unsafe {
+    if !rl_completer_word_break_characters.is_null() {
+      CStr::from_ptr(rl_completer_word_break_characters).to_bytes()
+    } else if !rl_basic_word_break_characters.is_null() {
+      CStr::from_ptr(rl_basic_word_break_characters).to_bytes()
+    } else {
+      &[]
+    }
+  }
+}
+
+
+/// Check whether the given key sequence is bound to nothing in the
+/// current keymap.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held.
+unsafe fn is_unbound(key: &[u8]) -> bool {
+  matches!(unsafe { lookup(key) }, Some((None, ISFUNC)))
+}
+
+
+/// Check whether libreadline can safely process the given key
+/// sequence in one go.
+///
+/// libreadline (as of 8.2) crashes when the processing of a multi-byte
+/// key sequence in one go ends up aborting the sequence, e.g., because
+/// (part of) it is not bound to anything (e.g., `M-"` in emacs mode) or
+/// is bound to a command that may abort (e.g., `yank-pop` when not
+/// preceded by a yank). Hence, only sequences known to be bound to a
+/// different command or to a macro are considered safe.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held.
+unsafe fn is_dispatchable(key: &[u8]) -> bool {
+  #[cfg(readline_7)]
+  let aborting: [rl_command_func_t; 4] =
+    [rl_abort, rl_do_lowercase_version, rl_yank_pop, rl_vi_yank_pop];
+  // `vi-yank-pop` is only part of libreadline as of version 7.0.
+  #[cfg(not(readline_7))]
+  let aborting: [rl_command_func_t; 3] = [rl_abort, rl_do_lowercase_version, rl_yank_pop];
+  match unsafe { lookup(key) } {
+    Some((Some(func), ISFUNC)) => !aborting
+      .iter()
+      .any(|aborting| *aborting as usize == func as usize),
+    Some((_, ISMACR)) => true,
+    // While libreadline is busy we cannot tell how the sequence is
+    // going to be processed, but escape sequences are the ones prone
+    // to ending up unbound.
+    None => key.first() != Some(&b'\x1b'),
+    _ => false,
+  }
+}
+
+
+/// Check whether the given key sequence is bound to the given command
+/// in the current keymap.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held.
+unsafe fn is_bound_to(key: &[u8], func: rl_command_func_t) -> bool {
+  matches!(
+    unsafe { lookup(key) },
+    Some((Some(bound), ISFUNC)) if bound as usize == func as usize
+  )
+}
+
+
+/// Move the cursor forward or backward across a single grapheme
+/// cluster.
+///
+/// Returns `false` if the line is not valid UTF-8, in which case the
+/// cursor is left untouched.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held.
+unsafe fn move_grapheme(forward: bool) -> bool {
+  let line = match str::from_utf8(unsafe { Readline::buffer() }) {
+    Ok(line) => line,
+    Err(_) => return false,
+  };
+  let cursor = unsafe { rl_point } as usize;
+
+  let pos = if forward {
+    let next = unicode::next_grapheme(line, cursor);
+    // In vi command mode the cursor rests on the last character at most.
+    if next == line.len() && unsafe { EditMode::current() } == EditMode::ViCommand {
+      cursor
+    } else {
+      next
+    }
+  } else {
+    unicode::previous_grapheme(line, cursor)
+  };
+  unsafe { rl_point = pos as _ };
+  true
+}
+
+
+/// Add the given text to libreadline's kill ring as a new entry,
+/// leaving the line untouched.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held.
+unsafe fn push_kill(text: &CStr) {
+  let length = text.to_bytes().len() as c_int;
+  if length == 0 {
+    return
+  }
+
+  // libreadline provides no means for adding text to the kill ring
+  // directly. Instead, we insert the text and kill it right away. None
+  // of that is supposed to be undoable, so we keep the undo records
+  // separate and throw them away.
+  unsafe {
+    let point = rl_point;
+    let undo = replace(&mut *addr_of_mut!(rl_undo_list), null_mut());
+
+    // Make sure that the text is not appended to the previous kill.
+    _rl_last_command_was_kill = 0;
+    let _count = rl_insert_text(text.as_ptr());
+    let _result = rl_kill_text(point, point + length);
+    _rl_last_command_was_kill = 0;
+
+    rl_free_undo_list();
+    rl_undo_list = undo;
+    rl_point = point;
+  }
+}
+
+
+/// Add an empty entry to libreadline's kill ring, leaving the line
+/// untouched.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held.
+unsafe fn push_empty_kill() {
+  // Just like for `push_kill`, we have to go through the line. The text
+  // killed is copied without regard for NUL bytes, so killing a NUL
+  // byte results in an empty entry.
+  unsafe {
+    let point = rl_point;
+    let undo = replace(&mut *addr_of_mut!(rl_undo_list), null_mut());
+
+    _rl_last_command_was_kill = 0;
+    let _count = rl_insert_text(b" \0".as_ptr().cast());
+    *rl_line_buffer.add(point as usize) = 0;
+    let _result = rl_kill_text(point, point + 1);
+    _rl_last_command_was_kill = 0;
+
+    rl_free_undo_list();
+    rl_undo_list = undo;
+    rl_point = point;
+  }
+}
+
+
+/// Refill libreadline's kill ring with the given entries, oldest first,
+/// with an empty text representing an empty entry.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held.
+unsafe fn load_kill_ring(entries: &[&CStr]) {
+  // There is no way to remove entries, so we evict the ones present by
+  // padding with empty entries where necessary.
+  for _ in entries.len()..kill::MAX_KILLS {
+    let () = unsafe { push_empty_kill() };
+  }
+
+  for entry in entries {
+    if entry.to_bytes().is_empty() {
+      let () = unsafe { push_empty_kill() };
+    } else {
+      let () = unsafe { push_kill(entry) };
+    }
+  }
+}
+
+
+/// Retrieve the mirror of the kill ring shared by all instances that are
+/// not isolated.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held for the duration
+/// of the usage of the returned reference and are not allowed to call
+/// this function while another such reference is active.
+unsafe fn shared_kill_ring() -> &'static mut KillRing {
+  static mut SHARED: Option<KillRing> = None;
+
+  // SAFETY: As per the function contract, callers need to hold the
+  //         global mutex.
+  unsafe { (*addr_of_mut!(SHARED)).get_or_insert_with(KillRing::default) }
+}
+
+
+/// Switch libreadline's kill ring to the one of the instance with the
+/// given state, if it is isolated, or to the one shared by all other
+/// instances.
+///
+/// libreadline's kill ring cannot be swapped out, so we refill it from
+/// the respective mirror whenever its owner changes. Refilling it
+/// requires a line buffer, so for an instance still being created the
+/// switch is deferred until one got allocated.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held.
+unsafe fn switch_kill_ring(id: Id, state: &State) {
+  /// The isolated instance the kill ring currently belongs to, if any.
+  static mut OWNER: Option<Id> = None;
+
+  let owner = state.keymaps.as_ref().map(|_keymaps| id);
+  // SAFETY: As per the function contract, callers need to hold the
+  //         global mutex.
+  let current = unsafe { &mut *addr_of_mut!(OWNER) };
+  if *current == owner || unsafe { rl_line_buffer.is_null() } {
+    return
+  }
+  *current = owner;
+
+  let ring = unsafe { kill_ring_mirror(state) };
+  let entries = ring.entries().map(CString::as_c_str).collect::<Vec<_>>();
+  let () = unsafe { load_kill_ring(&entries) };
+}
+
+
+/// Retrieve the mirror of libreadline's kill ring while the instance
+/// with the given state is active: its own, if it is isolated, or the
+/// one shared by all instances otherwise.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held and that no
+/// reference to the shared mirror is active.
+unsafe fn kill_ring_mirror(state: &State) -> &KillRing {
+  match state.keymaps {
+    Some(_) => &state.kill_ring,
+    None => unsafe { &*shared_kill_ring() },
+  }
+}
+
+
+/// Refill libreadline's kill ring from its mirror, after entries got
+/// evicted from the latter.
+///
+/// # Safety
+/// Callers must ensure that the instance with the given state is
+/// active.
+unsafe fn reload_kill_ring(state: &State) {
+  let ring = unsafe { kill_ring_mirror(state) };
+  let entries = ring.entries().map(CString::as_c_str).collect::<Vec<_>>();
+
+  let kills = unsafe { _rl_last_command_was_kill };
+  let () = unsafe { load_kill_ring(&entries) };
+  // Subsequent kills should still be combined with the last entry.
+  unsafe { _rl_last_command_was_kill = kills };
+}
+
+
+/// Evict the text just killed from the masked line of the active
+/// instance with the given state from libreadline's kill ring.
+///
+/// The ring gets refilled from the mirror of the instance's kill ring,
+/// if it is isolated, or the shared one, followed by an empty entry in
+/// place of the killed text.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held.
+unsafe fn scrub_kill_ring(state: &State) {
+  let ring = unsafe { kill_ring_mirror(state) };
+  let mut entries = ring.entries().map(CString::as_c_str).collect::<Vec<_>>();
+  let () = entries.push(CStr::from_bytes_with_nul(b"\0").unwrap());
+
+  let kills = unsafe { _rl_last_command_was_kill };
+  let () = unsafe { load_kill_ring(&entries[entries.len().saturating_sub(kill::MAX_KILLS)..]) };
+  // Subsequent kills should still be combined with the now empty entry.
+  unsafe { _rl_last_command_was_kill = kills };
+}
+
+
+/// Retrieve the closing counterpart of the given character, if it is
+/// subject to auto-pairing.
+fn pair_closer(c: u8) -> Option<u8> {
+  match c {
+    b'(' => Some(b')'),
+    b'[' => Some(b']'),
+    b'{' => Some(b'}'),
+    b'"' => Some(b'"'),
+    b'\'' => Some(b'\''),
+    _ => None,
+  }
+}
+
+
+/// A limit on the amount of data retained by a `Readline` instance.
+///
+/// Once a limit is exceeded, the oldest data are evicted first.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Limit {
+  /// The maximum number of entries to retain, if any.
+  pub entries: Option<usize>,
+  /// The maximum number of bytes to retain, if any.
+  pub bytes: Option<usize>,
+}
+
+impl Limit {
+  /// Check whether the given usage is within this limit.
+  fn admits(&self, usage: Usage) -> bool {
+    !matches!(self.entries, Some(entries) if usage.entries > entries)
+      && !matches!(self.bytes, Some(bytes) if usage.bytes > bytes)
+  }
+}
+
+
+/// The amount of data retained by a `Readline` instance.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Usage {
+  /// The number of entries stored.
+  pub entries: usize,
+  /// The total number of bytes occupied by all entries.
+  pub bytes: usize,
+}
+
+
+/// The approximate amount of heap memory consumed by the data retained
+/// by a `Readline` instance, in bytes.
+///
+/// Contrary to [`Usage`], which only accounts for the text stored, the
+/// bookkeeping overhead of libreadline and the crate is included, as is
+/// data associated with history entries. Allocator overhead is not.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct MemoryUsage {
+  /// The memory consumed by the history.
+  pub history: usize,
+  /// The memory consumed by the crate's record of the kill ring.
+  pub kill_ring: usize,
+}
+
+impl MemoryUsage {
+  /// Retrieve the total memory consumed.
+  pub fn total(&self) -> usize {
+    self.history + self.kill_ring
+  }
+}
+
+
+/// A wrapper for `MutexGuard` ensuring that our libreadline state is read back before dropping.
+struct ReadlineGuard<'data> {
+  guard: Option<MutexGuard<'data, Id>>,
+  state: RefMut<'data, Box<State>>,
+  /// The number of history entries at the time of activation.
+  history_entries: usize,
+}
+
+impl<'data> ReadlineGuard<'data> {
+  /// Read back the state from the globals, just as dropping the guard
+  /// does, but without releasing the global mutex.
+  fn into_lock(mut self) -> MutexGuard<'data, Id> {
+    let guard = self.guard.take().unwrap();
+    drop(self);
+    guard
+  }
+
+  /// Run a function with the guarded state registered as the one that
+  /// callbacks invoked by libreadline operate on.
+  fn dispatch<F, R>(&mut self, f: F) -> R
+  where
+    F: FnOnce() -> R,
+  {
+    // SAFETY: We hold the global mutex.
+    let capture = if self.state.stray_output_hook.is_some() {
+      unsafe { Capture::start() }
+    } else {
+      None
+    };
+
+    let state: *mut State = &mut **self.state;
+    // SAFETY: We hold the global mutex and `state` stays valid for
+    //         the duration of the call, because we keep it borrowed.
+    unsafe { *Readline::active_ptr() = state };
+    let result = f();
+    unsafe { *Readline::active_ptr() = null_mut() };
+
+    if let Some(capture) = capture {
+      // SAFETY: We hold the global mutex.
+      let output = unsafe { capture.finish() };
+      if !output.is_empty() {
+        if let Some(Hook(hook)) = &mut self.state.stray_output_hook {
+          hook(&output)
+        }
+      }
+    }
+    result
+  }
+}
+
+impl Drop for ReadlineGuard<'_> {
+  fn drop(&mut self) {
+    // Before unlocking (by virtue of dropping the embedded guard)
+    // always make sure to read back the most recent version of the
+    // state from the globals.
+    let history_entries = self.state.history.usage().entries;
+    let () = metrics::history_changed(self.history_entries, history_entries);
+    self.state.load()
+  }
+}
+
+
+/// A marker for an ongoing operation on an instance, during which the
+/// instance may get activated multiple times while only a single
+/// activation is counted.
+struct Operation<'data> {
+  state: &'data RefCell<Box<State>>,
+}
+
+impl Drop for Operation<'_> {
+  fn drop(&mut self) {
+    self.state.borrow_mut().metrics.end_operation()
+  }
+}
+
+
+/// The marker indicating the start of invisible characters in a
+/// prompt.
+const PROMPT_START_IGNORE: u8 = b'\x01';
+/// The marker indicating the end of invisible characters in a prompt.
+const PROMPT_END_IGNORE: u8 = b'\x02';
+
+
+/// A type representing a single key. A key is a sequence of bytes which
+/// can be anything from a single byte representing an ASCII character
+/// or a terminal escape sequence.
+type Key = [u8];
+
+
+/// A struct representing a context for reading a line using libreadline.
+///
+/// All methods take `&self`, as state is protected internally, so that
+/// instances can be shared through `Rc` or be stored in GUI callback
+/// structures directly. Note, however, that hooks installed on an
+/// instance must not call back into the same instance, as doing so
+/// results in a panic.
+pub struct Readline {
+  id: Id,
+  state: RefCell<Box<State>>,
+}
+
+impl Readline {
+  /// Stub used as a terminal preparation function.
+  extern "C" fn initialize_term(_: c_int) {}
+
+  /// Stub used as a terminal "unpreparation" function.
+  extern "C" fn uninitialize_term() {}
+
+  /// Stub used as a display function.
+  extern "C" fn display() {}
+
+  /// A callback invoked when libreadline wants the line displayed.
+  ///
+  /// We do not display anything ourselves, but forward the request to
+  /// the active instance's redisplay hook, if any.
+  extern "C" fn redisplay() {
+    // SAFETY: libreadline only invokes this function while input is
+    //         being dispatched, i.e., with our global mutex held.
+    let state = match unsafe { Self::active() } {
+      Some(state) => state,
+      None => return,
+    };
+
+    if state.redisplay.is_some() {
+      let (line, cursor, prompt) = unsafe {
+        let line = if rl_line_buffer.is_null() {
+          <&CStr>::default()
+        } else {
+          CStr::from_ptr(rl_line_buffer)
+        };
+        let prompt = if rl_display_prompt.is_null() {
+          <&CStr>::default()
+        } else {
+          CStr::from_ptr(rl_display_prompt)
+        };
+        (line, rl_point as usize, prompt)
+      };
+
+      let spans = state.highlight(line);
+      let suggestion = state.suggestion(line, cursor).map(CStr::to_owned);
+      let status = state.status();
+      let preedit = state.preedit();
+      let masked = state
+        .mask
+        .map(|mask| mask.apply(line.to_bytes(), cursor));
+      let (line, cursor) = match &masked {
+        Some((line, cursor)) => (line.as_c_str(), *cursor),
+        None => (line, cursor),
+      };
+
+      if let Some(Hook(hook)) = &mut state.redisplay {
+        hook(&Redisplay {
+          line,
+          cursor,
+          prompt,
+          spans: &spans,
+          suggestion: suggestion.as_deref(),
+          status: status.as_deref(),
+          preedit: preedit.as_deref(),
+          macro_state: unsafe { MacroState::current() },
+          already_prompted: state.already_prompted,
+        })
+      }
+    }
+  }
+
+  /// A callback invoked by libreadline when it starts reading a new
+  /// line, forwarding to the active instance's startup hook.
+  extern "C" fn startup() -> c_int {
+    // SAFETY: libreadline only invokes this function while input is
+    //         being dispatched, i.e., with our global mutex held.
+    if let Some(state) = unsafe { Self::active() } {
+      if let Some(Hook(hook)) = &mut state.startup_hook {
+        hook(&mut LineHookContext {
+          _guard: PhantomData,
+        })
+      }
+    }
+    0
+  }
+
+  /// A callback invoked by libreadline when it starts reading a new
+  /// line, after the prompt got displayed, forwarding to the active
+  /// instance's pre-input hook.
+  extern "C" fn pre_input() -> c_int {
+    // SAFETY: libreadline only invokes this function while input is
+    //         being dispatched, i.e., with our global mutex held.
+    if let Some(state) = unsafe { Self::active() } {
+      if let Some(Hook(hook)) = &mut state.pre_input_hook {
+        hook(&mut LineHookContext {
+          _guard: PhantomData,
+        })
+      }
+    }
+    0
+  }
+
+  /// Stub used as a callback to check whether new input is available.
+  /// We explicitly feed input on demand, so we never want libreadline
+  /// to read from stdin.
+  extern "C" fn input_available() -> c_int {
+    // We feed input explicitly, so there is never ever something
+    // available on our input streams.
+    0
+  }
+
+  /// A callback invoked when libreadline has completed a line.
+  ///
+  /// This function can only be invoked indirectly through the `feed`
+  /// method. As such, we are guaranteed mutual exclusion with respect
+  /// to global libreadline state.
+  extern "C" fn handle_line(line: *mut c_char) {
+    debug_assert!(Self::mutex().is_locked());
+
+    // SAFETY: Our global mutex is locked (as per assertion above) and
+    //         we only call the function once.
+    let line_ref = unsafe { Self::line() };
+    if line.is_null() {
+      let _ = replace(line_ref, Some(CString::new("").unwrap()));
+    } else {
+      unsafe {
+        let _ = replace(line_ref, Some(CStr::from_ptr(line).into()));
+        #[cfg(feature = "zeroize")]
+        {
+          if matches!(Self::active(), Some(state) if state.sensitive) {
+            wipe_str(line)
+          }
+        }
+        free(line.cast());
+      }
+    }
+  }
+
+  /// Create a new `Readline` instance.
+  ///
+  /// # Panics
+  ///
+  /// Panics on failure to allocate internally used C objects.
+  pub fn new() -> Self {
+    Self::create(None)
+  }
+
+  /// Create a new `Readline` instance with the given initial line and
+  /// cursor position.
+  ///
+  /// This is equivalent to creating an instance via
+  /// [`new`][Readline::new] and then calling [`reset`][Readline::reset]
+  /// on it, but without the need for a second activation.
+  ///
+  /// # Panics
+  ///
+  /// Panics if the cursor is not less than or equal to the number of
+  /// characters in the given line and on failure to allocate internally
+  /// used C objects.
+  pub fn with_line<S>(line: S, cursor: usize) -> Self
+  where
+    S: AsRef<CStr>,
+  {
+    fn with_line_impl(line: &CStr, cursor: usize) -> Readline {
+      assert!(cursor <= line.to_bytes().len(), "invalid cursor position");
+      Readline::create(Some((line, cursor)))
+    }
+
+    with_line_impl(line.as_ref(), cursor)
+  }
+
+  /// Create a new `Readline` instance, optionally with an initial line
+  /// and cursor position.
+  fn create(line: Option<(&CStr, usize)>) -> Self {
+    let _count = INSTANCES.fetch_add(1, Ordering::SeqCst);
+    if SHUTDOWN.load(Ordering::SeqCst) {
+      let _count = INSTANCES.fetch_sub(1, Ordering::SeqCst);
+      panic!("rline got shut down");
+    }
+
+    let rl = Self {
+      id: Id::new(),
+      state: RefCell::new(Box::new(State {
+        rl: Self::initial().clone(),
+        callback: Callback::default(),
+        #[cfg(readline_8_1)]
+        mark: ActiveMark::default(),
+        history: History::new(),
+        history_sync: None,
+        kill_ring: KillRing::default(),
+        kill_ring_limit: Limit::default(),
+        keymaps: None,
+        #[cfg(feature = "zeroize")]
+        sensitive: false,
+        redisplay: None,
+        highlighter: None,
+        autosuggest: false,
+        validator: None,
+        transforms: Vec::new(),
+        corrector: None,
+        correction: None,
+        indent: None,
+        mask: None,
+        status: None,
+        screen_size: None,
+        chars_to_read: 0,
+        comment_begin: None,
+        already_prompted: false,
+        terminal: None,
+        undo_limit: None,
+        label: None,
+        debug_line: false,
+        mode: EditMode::Emacs,
+        mode_hook: None,
+        startup_hook: None,
+        pre_input_hook: None,
+        unbound_hook: None,
+        input_filter: None,
+        observer: None,
+        stray_output_hook: None,
+        dirty: false,
+        read_only: false,
+        preedit: None,
+        abbreviations: HashMap::new(),
+        abbreviation_hook: None,
+        auto_pair: false,
+        grapheme_movement: false,
+        registers: Registers::default(),
+        operator: None,
+        next_entry: None,
+        change: Change::Unchanged,
+        keyseq: Vec::new(),
+        pending: Vec::new(),
+        metrics: Counters::default(),
+        recorder: None,
+        interrupt: None,
+        eof: Some(0x04),
+      })),
+    };
+
+    {
+      // Make sure that the new state is activated.
+      // TODO: Strictly speaking we could omit the load operation
+      //       happening when the guard leaves the scope. We know that
+      //       the state is current, so it just wastes cycles.
+      let mut guard = rl.activate();
+
+      unsafe {
+        debug_assert!(rl_line_buffer.is_null());
+        debug_assert!(rl_executing_keyseq.is_null());
+
+        // Unfortunately `readline_state` contains some data that is
+        // allocated by libreadline itself, as part of its
+        // initialization. Because we create a new context we need to
+        // reinitialize this data.
+        rl_line_buffer = calloc(1, rl_line_buffer_len as _).cast();
+        rl_executing_keyseq = calloc(1, rl_key_sequence_length as _).cast();
+
+        // We use similar behavior to default Rust and panic on
+        // allocation failure.
+        assert!(!rl_line_buffer.is_null(), "failed to allocate rl_line_buffer");
+        assert!(!rl_executing_keyseq.is_null(), "failed to allocate rl_executing_keyseq");
+      }
+
+      guard.state.mode = unsafe { EditMode::current() };
+
+      // We allocated some memory with the new addresses going directly
+      // into libreadline's globals. So make sure to read back that
+      // state to have an up-to-date snapshot.
+      guard.state.rl.load();
+      // Believe it or not, but libreadline aliases the line buffer
+      // internally with a pointer, and only storing the state back into
+      // the global will update this pointer. So we need this additional
+      // save here. Yes, that one is a pearl.
+      guard.state.rl.save();
+      // Now that there is a line buffer the kill ring can be switched
+      // over as well.
+      let () = unsafe { switch_kill_ring(rl.id, &guard.state) };
+
+      if let Some((line, cursor)) = line {
+        unsafe {
+          rl_replace_line(line.as_ptr(), 1);
+          rl_point = cursor as _;
+        }
+      }
+    }
+
+    rl
+  }
+
+  /// Release all resources associated with this instance.
+  // `io::Error::other` is not available on our minimum supported Rust
+  // version.
+  #[allow(clippy::io_other_error)]
+  fn teardown(&mut self) -> io::Result<()> {
+    // If a panic occurred while libreadline's globals were being
+    // manipulated, we cannot make any assumptions about their state and
+    // leak the instance's resources instead of risking a double free.
+    let guard = match Self::mutex().lock() {
+      Ok(guard) => guard,
+      Err(_err) => {
+        let _count = INSTANCES.fetch_sub(1, Ordering::SeqCst);
+        return Err(io::Error::new(
+          io::ErrorKind::Other,
+          "libreadline state is poisoned; resources were leaked",
+        ))
+      },
+    };
+    let mut guard = self.activate_locked(guard);
+    let result = match &mut guard.state.history_sync {
+      Some(sync) => sync.flush(),
+      None => Ok(()),
+    };
+
+    // Make sure to release the memory we or libreadline allocated.
+    guard.state.history.clear();
+
+    #[cfg(feature = "zeroize")]
+    {
+      if guard.state.sensitive {
+        unsafe {
+          undo::wipe(rl_undo_list);
+          wipe_line_buffer();
+        }
+      }
+    }
+
+    // Our keymaps must no longer be in use once they are released.
+    unsafe { keymap::switch(None) }
+    guard.state.keymaps = None;
+
+    unsafe {
+      // Release the state of any command waiting for more input.
+      #[cfg(readline_7)]
+      rl_callback_sigcleanup();
+      rl_free_undo_list();
+      free(rl_prompt.cast());
+      free(rl_executing_keyseq.cast());
+      free(rl_line_buffer.cast());
+      // Our terminal name is about to be freed.
+      apply_terminal_name(None);
+    }
+
+    let _count = INSTANCES.fetch_sub(1, Ordering::SeqCst);
+    result
+  }
+
+  /// Close this instance, releasing all resources associated with it.
+  ///
+  /// Dropping an instance releases its resources as well, but has to
+  /// ignore any errors encountered while doing so. Closing it
+  /// explicitly reports them instead.
+  ///
+  /// # Errors
+  ///
+  /// Fails if lines added to the history could not be appended to the
+  /// synchronized history file (see
+  /// [`set_history_sync`][Readline::set_history_sync]), in which case
+  /// all other resources are still released, or if libreadline's
+  /// global state got corrupted by a panic, in which case resources
+  /// are leaked.
+  pub fn close(self) -> io::Result<()> {
+    let mut this = ManuallyDrop::new(self);
+    let result = this.teardown();
+    // SAFETY: `this` is wrapped in `ManuallyDrop` and not used after
+    //         its fields got moved out.
+    let _state = unsafe { ptr::read(&this.state) };
+    let _id = unsafe { ptr::read(&this.id) };
+    result
+  }
+
+  /// Retrieve the pristine initial `readline_state` as it was set by libreadline.
+  fn initial() -> &'static readline_state {
+    // We effectively cache a version of `readline_state` as it was set
+    // by libreadline before anything could have changed. This state
+    // acts as the template for all the states we create later on.
+    static mut STATE: MaybeUninit<readline_state> = MaybeUninit::uninit();
+
+    // We should be safe *not* using our all-protecting mutex here
+    // because this functionality is invoked only as the very first
+    // interaction with libreadline, by virtue of being used only in
+    // the constructor of objects of the one struct that has exclusive
+    // access to libreadline's global state.
+    INITIALIZE.call_once(|| unsafe {
+      // Disable a bunch of libreadline stuff that would mess up things
+      // we don't want messed up, most prominently signal handler state
+      // and terminal state.
+      // This is all state that is part of `readline_state`, so we make
+      // those changes once for the template and don't have to worry
+      // about them again.
+      rl_catch_signals = 0;
+      rl_catch_sigwinch = 0;
+      rl_input_available_hook = Self::input_available as *mut _;
+      rl_redisplay_function = Self::display as *mut _;
+      rl_prep_term_function = Self::initialize_term as *mut _;
+      rl_deprep_term_function = Self::uninitialize_term as *mut _;
+
+      // Note that we do not invoke rl_callback_handler_remove, except
+      // as part of `shutdown`. This crate's assumption is that it is
+      // the sole user of libreadline meaning nobody else will mess with
+      // global state. As such, and because we set the same handler for all contexts,
+      // there is no point in doing additional work to remove it. In
+      // addition, due to the retardedness of libreadline and it not
+      // capturing even all of its own global state, we could not even
+      // remove the handler if we wanted to, because activating a
+      // `readline_state` object would not set the handler. Sigh.
+      rl_callback_handler_install(null(), Self::handle_line as *mut _);
+      // Initialization determined the terminal name, which instances
+      // not having their own use.
+      TERMINAL.store(rl_terminal_name as *mut _, Ordering::Relaxed);
+      // The same goes for the screen size.
+      SCREEN[0].store(_rl_screenheight, Ordering::Relaxed);
+      SCREEN[1].store(_rl_screenwidth, Ordering::Relaxed);
+      SCREEN[2].store(_rl_screenchars, Ordering::Relaxed);
+      // And the text inserted by `insert-comment`, of which we keep a
+      // copy, because libreadline frees its own once it gets changed.
+      let comment = variable_value(b"comment-begin\0")
+        .map(CStr::to_owned)
+        .unwrap_or_default();
+      COMMENT_BEGIN.store(comment.into_raw(), Ordering::Relaxed);
+      // Installing the handler causes a redisplay, at a point where we
+      // do not hold our mutex. Only now that this is over can we
+      // install the function forwarding redisplay requests.
+      rl_redisplay_function = Self::redisplay as *mut _;
+      // The same is true for the hooks invoked when starting to read a
+      // line, which we forward to the active instance.
+      rl_startup_hook = Self::startup as *mut _;
+      rl_pre_input_hook = Self::pre_input as *mut _;
+      // Installing the handler also initialized libreadline, which may
+      // have changed how 8-bit input is treated.
+      apply_eight_bit();
+
+      // libreadline already has buffers allocated but we won't be using
+      // them.
+      free(rl_line_buffer.cast());
+      free(rl_executing_keyseq.cast());
+
+      rl_line_buffer = null_mut();
+      rl_executing_keyseq = null_mut();
+      rl_undo_list = null_mut();
+
+      load_state(STATE.as_mut_ptr());
+    });
+
+    // `STATE` is guaranteed to be initialized after the above call to
+    // `load_state`, so it should be safe to create a reference to the
+    // data now.
+    // TODO: Until MaybeUninit::get_ref is stable we have to craft our
+    //       own version.
+    unsafe { &*STATE.as_ptr() }
+  }
+
+  /// Retrieve a reference to the `Mutex` protecting all accesses to
+  /// libreadline's global state.
+  fn mutex() -> &'static Mutex<Id> {
+    static mut MUTEX: Option<Mutex<Id>> = None;
+    static ONCE: Once = Once::new();
+
+    ONCE.call_once(|| unsafe { MUTEX = Some(Mutex::new(Id::new())) });
+
+    // SAFETY: We never ever hand out mutable references to `MUTEX` or
+    //         use one beyond this point and so it's always safe to
+    //         create a shared one.
+    match unsafe { &*addr_of!(MUTEX) } {
+      Some(mutex) => mutex,
+      None => unreachable!(),
+    }
+  }
+
+  /// A reference to the global line storage.
+  ///
+  /// # Safety
+  /// Callers must ensure that the global mutex is held for the duration
   /// of the usage of the returned reference and are not allowed to call
   /// this function while another such reference is active.
   unsafe fn line() -> &'static mut Option<CString> {
     static mut LINE: Option<CString> = None;
     debug_assert!(Self::mutex().is_locked());
 
-    // SAFETY: As per the function contract, callers need to hold the
-    //         global mutex and may only keep around a single mutable
-    //         reference being returned.
-    unsafe { &mut *addr_of_mut!(LINE) }
+    // SAFETY: As per the function contract, callers need to hold the
+    //         global mutex and may only keep around a single mutable
+    //         reference being returned.
+    unsafe { &mut *addr_of_mut!(LINE) }
+  }
+
+  /// A pointer to the state of the `Readline` instance currently
+  /// dispatching input, if any.
+  ///
+  /// # Safety
+  /// Callers must ensure that the global mutex is held for the duration
+  /// of the usage of the returned reference.
+  unsafe fn active_ptr() -> &'static mut *mut State {
+    static mut ACTIVE: *mut State = null_mut();
+    debug_assert!(Self::mutex().is_locked());
+
+    // SAFETY: As per the function contract, callers need to hold the
+    //         global mutex.
+    unsafe { &mut *addr_of_mut!(ACTIVE) }
+  }
+
+  /// Retrieve the state of the `Readline` instance currently
+  /// dispatching input, if any.
+  ///
+  /// # Safety
+  /// Callers must ensure that the global mutex is held for the duration
+  /// of the usage of the returned reference and are not allowed to call
+  /// this function while another such reference is active.
+  unsafe fn active() -> Option<&'static mut State> {
+    // SAFETY: The pointer is only ever set for the duration of a
+    //         `ReadlineGuard::dispatch` call, which keeps the state
+    //         alive and borrowed exclusively.
+    unsafe { (*Self::active_ptr()).as_mut() }
+  }
+
+  /// Start an operation that may activate this instance multiple
+  /// times, but should count as a single activation.
+  fn operation(&self) -> Operation<'_> {
+    let () = self.state.borrow_mut().metrics.begin_operation();
+    Operation { state: &self.state }
+  }
+
+  /// Activate this context.
+  fn activate(&self) -> ReadlineGuard<'_> {
+    let guard = Self::mutex().lock().unwrap();
+    self.activate_locked(guard)
+  }
+
+  /// Activate this instance, with the global mutex already being held.
+  fn activate_locked<'slf>(&'slf self, mut guard: MutexGuard<'slf, Id>) -> ReadlineGuard<'slf> {
+    let mut state = self.state.borrow_mut();
+
+    // Activate our state if necessary.
+    let swap = *guard != self.id;
+    if swap {
+      state.save();
+      *guard = self.id;
+
+      // libreadline caches an expanded version of the prompt outside
+      // of `readline_state`. Make sure that it refers to ours.
+      unsafe {
+        rl_display_prompt = if rl_prompt.is_null() {
+          b"\0".as_ptr() as *mut _
+        } else {
+          rl_prompt
+        };
+        let _width = rl_expand_prompt(rl_prompt);
+      }
+
+      // Neither are keymaps.
+      unsafe { keymap::switch(state.keymaps.as_deref()) }
+
+      // Nor is the kill ring.
+      unsafe { switch_kill_ring(self.id, &state) }
+
+      // The screen size is not part of `readline_state` either.
+      unsafe { apply_screen_size(state.screen_size) }
+
+      // Nor is the number of characters to read.
+      unsafe { rl_num_chars_to_read = state.chars_to_read };
+
+      // Nor is whether the prompt is displayed by the application.
+      unsafe { rl_already_prompted = c_int::from(state.already_prompted) };
+
+      // Nor is the terminal name.
+      unsafe { apply_terminal_name(state.terminal.as_deref()) }
+
+      // Nor is the comment text.
+      unsafe { bind_comment_begin(state.comment_begin.as_deref()) }
+    }
+
+    for b in state.pending.drain(..) {
+      // The bytes originate from libreadline's input buffer, so there
+      // is enough space to push them again.
+      let result = unsafe { rl_stuff_char(c_int::from(b)) };
+      debug_assert_ne!(result, 0);
+    }
+
+    state.metrics.activated(swap);
+    let history_entries = state.history.usage().entries;
+
+    ReadlineGuard {
+      guard: Some(guard),
+      state,
+      history_entries,
+    }
+  }
+
+  /// Retrieve the identifier of this instance.
+  pub fn id(&self) -> InstanceId {
+    InstanceId(self.id)
+  }
+
+  /// Set a label identifying this instance, e.g., by the purpose of
+  /// the line it reads, in its `Debug` output.
+  pub fn set_label<S>(&self, label: Option<S>)
+  where
+    S: Into<String>,
+  {
+    self.state.borrow_mut().label = label.map(S::into)
+  }
+
+  /// Retrieve the label of this instance, if any.
+  pub fn label(&self) -> Option<String> {
+    self.state.borrow().label.clone()
+  }
+
+  /// Set whether to include the line being edited in the `Debug`
+  /// output of this instance.
+  ///
+  /// The line is not included by default, as it may contain data that
+  /// should not end up in logs. If a [`Mask`] is set, the masked
+  /// representation of the line (and cursor) is included.
+  pub fn set_debug_line(&self, debug_line: bool) {
+    self.state.borrow_mut().debug_line = debug_line
+  }
+
+  /// Check whether this instance's state is the one currently loaded
+  /// into libreadline's globals.
+  ///
+  /// Operations on an instance that is not active cause its state to
+  /// be swapped in first. Note that the result may be outdated by the
+  /// time it is acted upon if instances are used from multiple
+  /// threads. This method must not be called from a hook, as it would
+  /// deadlock.
+  pub fn is_active(&self) -> bool {
+    Self::active_id() == self.id()
+  }
+
+  /// Retrieve the identifier of the instance whose state is currently
+  /// loaded into libreadline's globals.
+  ///
+  /// The identifier does not necessarily refer to an instance that is
+  /// still alive. This function must not be called from a hook, as it
+  /// would deadlock.
+  pub fn active_id() -> InstanceId {
+    InstanceId(*Self::mutex().lock().unwrap())
+  }
+
+  /// Force 8-bit clean input handling for all instances.
+  ///
+  /// By default, libreadline's treatment of bytes greater than or
+  /// equal to `0x80` depends on the locale and on the `input-meta`,
+  /// `convert-meta`, and `output-meta` variables. Depending on the
+  /// host's environment, fed UTF-8 may get converted into ESC prefixed
+  /// (meta) key sequences. Once this function got called, such bytes
+  /// are always treated as input, irrespective of the locale and of
+  /// init files read at any point.
+  ///
+  /// Note that the setting is process wide and cannot be reverted.
+  /// Whether libreadline treats multi-byte characters as a unit (e.g.,
+  /// when moving the cursor) still depends on the locale.
+  pub fn force_eight_bit() {
+    EIGHT_BIT.store(true, Ordering::Relaxed);
+    // Make sure that libreadline is initialized, so that our settings
+    // do not get overwritten later on.
+    let _state = Self::initial();
+
+    let _guard = Self::mutex().lock().unwrap();
+    unsafe { apply_eight_bit() }
+  }
+
+  /// Create a [`ReadlineBuilder`] for configuring a new instance.
+  pub fn builder() -> ReadlineBuilder {
+    ReadlineBuilder::new()
+  }
+
+  /// Feed a key to libreadline.
+  ///
+  /// The provided buffer should comprise not more than a single key,
+  /// which may be a single byte only or an escape sequence.
+  ///
+  /// # Panics
+  ///
+  /// Panics if too many bytes are supplied, i.e., more than reported by
+  /// [`input_capacity`][Readline::input_capacity]. libreadline's
+  /// internal buffer holds 511 bytes, some of which may be occupied by
+  /// [pending input][Readline::pending_input].
+  ///
+  /// Note that the end-of-file character (see
+  /// [`set_eof_char`][Readline::set_eof_char]) fed on an empty line does
+  /// not complete a line and `None` is returned. Use
+  /// [`feed_key`][Readline::feed_key] to detect the end of input.
+  pub fn feed(&self, key: impl AsRef<Key>) -> Option<CString> {
+    self.feed_key(key).into_line()
+  }
+
+  /// Feed a key to libreadline, reporting the outcome.
+  ///
+  /// Contrary to [`feed`][Readline::feed], special outcomes such as
+  /// the feeding of the interrupt character (see
+  /// [`set_interrupt_char`][Readline::set_interrupt_char]) or of the
+  /// end-of-file character (see [`set_eof_char`][Readline::set_eof_char])
+  /// are reported. Panics under the same conditions as `feed`.
+  pub fn feed_key(&self, key: impl AsRef<Key>) -> Feed {
+    self.feed_key_impl(key.as_ref(), false)
+  }
+
+  /// Feed a key to libreadline, reporting the outcome just like
+  /// [`feed_key`][Readline::feed_key], but treating it as the final key
+  /// of a key sequence.
+  ///
+  /// Because input is fed explicitly, libreadline never times out
+  /// waiting for the remainder of a key sequence. A key that is bound
+  /// to a command but also prefixes longer key sequences, such as a
+  /// lone ESC bound to a command (ESC prefixes cursor keys), hence
+  /// stays unresolved until more input arrives. A key fed via this
+  /// method is resolved to the command right away instead, e.g., once
+  /// a frontend determined that no more input is pending. Key sequences
+  /// not bound to a command on their own stay incomplete.
+  ///
+  /// Note that libreadline resolves ESC in vi insertion mode right away
+  /// in any case.
+  pub fn feed_final_key(&self, key: impl AsRef<Key>) -> Feed {
+    self.feed_key_impl(key.as_ref(), true)
+  }
+
+  /// Feed a stream of input bytes, e.g., as produced by a decompressor
+  /// or decoder, returning an iterator over the lines completed by it.
+  ///
+  /// Input is split into key presses (including escape sequences and
+  /// UTF-8 encoded characters), which are fed one by one, as per
+  /// [`feed_key`][Readline::feed_key]. It is consumed lazily, only as
+  /// far as necessary for completing the next line. Once the input is
+  /// exhausted, bytes not forming a complete key (such as a trailing
+  /// Escape byte) are fed via [`feed_final_key`][Readline::feed_final_key].
+  /// Iteration ends early if the user signaled the end of input (see
+  /// [`set_eof_char`][Readline::set_eof_char]).
+  pub fn feed_iter<I>(&self, input: I) -> FeedLines<'_, I::IntoIter>
+  where
+    I: IntoIterator<Item = u8>,
+  {
+    FeedLines::new(self, input.into_iter())
+  }
+
+  /// Read a line in a blocking fashion, pulling input bytes one by one
+  /// from `source`, e.g., for simple tools preferring libreadline's
+  /// classic synchronous model.
+  ///
+  /// The prompt is set as per [`set_prompt`][Readline::set_prompt].
+  /// Bytes are fed until a line got completed, which is returned.
+  /// Nothing gets displayed; install a redisplay hook (see
+  /// [`set_redisplay_hook`][Readline::set_redisplay_hook]) to render
+  /// the line as it is being edited. Interrupts (see
+  /// [`set_interrupt_char`][Readline::set_interrupt_char]) discard the
+  /// line and reading continues with an empty one.
+  ///
+  /// `None` is returned if the user signaled the end of input by
+  /// feeding the end-of-file character (see
+  /// [`set_eof_char`][Readline::set_eof_char]) on an empty line or if
+  /// `source` reported an error of kind
+  /// [`UnexpectedEof`][io::ErrorKind::UnexpectedEof].
+  ///
+  /// # Errors
+  ///
+  /// Any other error reported by `source` is passed through, with the
+  /// line being edited left as is.
+  pub fn read_line<P, F>(&self, prompt: P, mut source: F) -> io::Result<Option<CString>>
+  where
+    P: AsRef<CStr>,
+    F: FnMut() -> io::Result<u8>,
+  {
+    fn read_line_impl(
+      rl: &Readline,
+      prompt: &CStr,
+      source: &mut dyn FnMut() -> io::Result<u8>,
+    ) -> io::Result<Option<CString>> {
+      let () = rl.set_prompt(prompt);
+
+      loop {
+        let byte = match source() {
+          Ok(byte) => byte,
+          Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+          Err(err) => return Err(err),
+        };
+
+        match rl.feed_key([byte]) {
+          Feed::Line(line) => return Ok(Some(line)),
+          Feed::Eof => return Ok(None),
+          Feed::Pending | Feed::Interrupted => (),
+        }
+      }
+    }
+
+    let _operation = self.operation();
+    read_line_impl(self, prompt.as_ref(), &mut source)
+  }
+
+  /// Feed a key to libreadline, reporting the outcome, treating it as
+  /// the final key of a key sequence if `last` is set.
+  fn feed_key_impl(&self, key: &Key, last: bool) -> Feed {
+    let _operation = self.operation();
+    let () = self.record(|| RecordedEvent::Feed(key.to_vec()));
+
+    let (interrupt, eof) = {
+      let state = self.state.borrow();
+      (state.interrupt, state.eof)
+    };
+    match (interrupt, eof) {
+      (Some(interrupt), _) if key == [interrupt] && !self.is_quoted_insert() => {
+        let () = self.interrupt();
+        Feed::Interrupted
+      },
+      (_, Some(eof)) if key == [eof] && self.is_at_eof() => {
+        let () = self.state.borrow_mut().metrics.fed();
+        Feed::Eof
+      },
+      _ if self.feed_unbound(key) => Feed::Pending,
+      _ => {
+        let replacement;
+        let key = match self.filter_input(key) {
+          InputAction::Accept => key,
+          InputAction::Replace(text) => {
+            replacement = text;
+            &replacement
+          },
+          InputAction::Reject => return Feed::Pending,
+        };
+
+        match self.feed_line(key, last) {
+          Some(line) => Feed::Line(line),
+          None => Feed::Pending,
+        }
+      },
+    }
+  }
+
+  /// Check whether the end-of-file character signals the end of input,
+  /// i.e., whether the line is empty and libreadline is not waiting for
+  /// more input for a command in progress, such as an incremental
+  /// search or `quoted-insert`.
+  fn is_at_eof(&self) -> bool {
+    let _guard = self.activate();
+    unsafe { Readline::buffer().is_empty() && !is_busy() }
+  }
+
+  /// Feed a key to libreadline, returning the completed line, if any.
+  ///
+  /// If `last` is set, a key sequence left incomplete by the key is
+  /// resolved instead of waiting for more input.
+  fn feed_line(&self, key: &Key, last: bool) -> Option<CString> {
+    fn feed_impl(rl: &Readline, key: &Key, last: bool) -> Option<CString> {
+      if key.is_empty() {
+        return None
+      }
+
+      let mut guard = rl.activate();
+      guard.state.metrics.fed();
+      // Status messages are transient and only last until the next key.
+      guard.state.status = None;
+      // So are corrections.
+      guard.state.correction = None;
+
+      let before = unsafe { Readline::buffer() }.to_vec();
+      let from = unsafe { rl_point } as usize;
+      let position = unsafe { where_history() };
+      let undo = unsafe { rl_undo_list };
+      // libreadline forgets about the command executed once a line got
+      // accepted, so check up front.
+      #[cfg(readline_8_1)]
+      let operate = unsafe { is_bound_to(key, rl_operate_and_get_next) };
+      // The command is only part of libreadline as of version 8.1.
+      #[cfg(not(readline_8_1))]
+      let operate = false;
+      let searching =
+        unsafe { state_flags() }.intersects(StateFlags::ISEARCH | StateFlags::NSEARCH);
+
+      #[cfg(feature = "zeroize")]
+      let sensitive = guard.state.sensitive;
+      #[cfg(not(feature = "zeroize"))]
+      let sensitive = false;
+
+      // Make room for the key upfront, so that the line buffer grows
+      // geometrically when very long lines get typed or pasted.
+      let () = unsafe { reserve_line(before.len() + key.len(), sensitive) };
+
+      let () = unsafe { guard.state.expand_abbreviation(key) };
+      let intercepted = unsafe {
+        guard.state.handle_register(key)
+          || guard.state.auto_pair(key)
+          || guard.state.move_grapheme(key)
+      };
+
+      if !intercepted {
+        // Sequences that libreadline cannot process in one go are
+        // processed byte by byte instead, which gets them discarded just
+        // the same if need be, but safely. The same goes for masked
+        // lines, for each kill to be caught.
+        let masked = guard.state.mask.is_some();
+        let size = if key.len() > 1 && (masked || !unsafe { is_dispatchable(key) }) {
+          1
+        } else {
+          key.len()
+        };
+
+        for chunk in key.chunks(size) {
+          for &b in chunk {
+            // This call will only fail if there is not enough space
+            // available to push the given character (with libreadline
+            // specifying a buffer size large enough for 512 characters).
+            // As we feed one character at a time and process (i.e.,
+            // consume) it immediately afterwards, there is no risk of us
+            // ever hitting this limit.
+            //
+            // Note that despite `rl_stuff_char` accepting a `c_int`, it
+            // actually casts that value down to a single byte
+            // internally, which is why we provide a saner interface that
+            // directly just accepts bytes.
+            let result = unsafe { rl_stuff_char(c_int::from(b)) };
+            // There is nothing we can do about this error. Heck, not even
+            // the user can do anything about this problem *after* hitting
+            // it. We cannot safely call `rl_callback_read_char` without
+            // risking cutting off input in the middle of an escape
+            // sequence, resulting in what effectively is corrupted input.
+            // We also cannot revert the buffer back to its previous state
+            // because there is no API to do that. Holy crap what a mess.
+            assert_ne!(result, 0, "libreadline's input buffer overflowed");
+          }
+
+          // Input following an accepted line is left pending, just as
+          // libreadline does when processing it in one go.
+          if unsafe { Readline::line() }.is_none() {
+            let kills = unsafe { _rl_last_command_was_kill };
+            guard.dispatch(|| unsafe { rl_callback_read_char() });
+            // libreadline's kill ring is shared by all instances, so text
+            // killed from a masked line must not end up in it.
+            if masked && unsafe { _rl_last_command_was_kill } > kills {
+              unsafe { scrub_kill_ring(&guard.state) }
+            }
+          }
+        }
+
+        let waiting = unsafe { state_flags() }.contains(StateFlags::MULTIKEY);
+        if last && waiting && unsafe { Readline::line() }.is_none() {
+          // Resolve the incomplete key sequence just like libreadline
+          // does once it timed out waiting for the remainder: by
+          // dispatching its final key once more, with the command
+          // shadowed by the sequence bound to it.
+          let b = key[key.len() - 1];
+          let _resolved = unsafe {
+            keymap::with_shadowed(b, || {
+              let result = rl_stuff_char(c_int::from(b));
+              debug_assert_ne!(result, 0);
+              let kills = _rl_last_command_was_kill;
+              guard.dispatch(|| rl_callback_read_char());
+              if masked && _rl_last_command_was_kill > kills {
+                scrub_kill_ring(&guard.state)
+              }
+              // The key got read twice, but we want it to count once.
+              if !is_busy() && rl_key_sequence_length > 0 {
+                rl_key_sequence_length -= 1;
+              }
+            })
+          };
+        }
+      }
+
+      // SAFETY: `guard` will outlive the returned reference and we
+      //         only call the function once.
+      let line_ref = unsafe { Readline::line() };
+      if guard.state.read_only && line_ref.is_none() {
+        let reverted = unsafe { revert_modification(&before, from, undo, position, searching) };
+        if reverted {
+          if let Some(Hook(observer)) = &mut guard.state.observer {
+            observer.input_rejected(key)
+          }
+        }
+      }
+
+      let line = match line_ref.take() {
+        Some(line) => match guard.state.validate(&line) {
+          Validation::Complete => Some(guard.state.transform(line)),
+          Validation::Incomplete => {
+            // Continue editing the line, with a newline inserted at the
+            // cursor position at the time the line got accepted.
+            #[cfg_attr(not(feature = "zeroize"), allow(unused_mut))]
+            let mut line = line.into_bytes();
+            let cursor = if line == before { from } else { line.len() };
+            #[cfg_attr(not(feature = "zeroize"), allow(unused_mut))]
+            let mut indent = guard.state.indentation(&line[..cursor]);
+            let mut text = Vec::with_capacity(line.len() + indent.len() + 2);
+            text.extend_from_slice(&line[..cursor]);
+            text.push(b'\n');
+            text.extend_from_slice(&indent);
+            text.extend_from_slice(&line[cursor..]);
+            text.push(b'\0');
+
+            unsafe {
+              rl_replace_line(text.as_ptr().cast(), 0);
+              rl_point = (cursor + 1 + indent.len()) as _;
+            }
+
+            #[cfg(feature = "zeroize")]
+            {
+              if sensitive {
+                line.zeroize();
+                indent.zeroize();
+                text.zeroize();
+              }
+            }
+            None
+          },
+          Validation::Invalid(message) => {
+            // Continue editing the line as it was, with the cursor where
+            // it was at the time the line got accepted.
+            let cursor = if line.as_bytes() == before.as_slice() {
+              from
+            } else {
+              line.as_bytes().len()
+            };
+            unsafe {
+              rl_replace_line(line.as_ptr(), 0);
+              rl_point = cursor as _;
+            }
+
+            #[cfg(feature = "zeroize")]
+            {
+              if sensitive {
+                line.into_bytes().zeroize();
+              }
+            }
+            guard.state.status = Some(message);
+            guard.dispatch(|| Readline::redisplay());
+            None
+          },
+        },
+        None => None,
+      };
+      guard.state.correction = match &line {
+        Some(line) => guard.state.correct(line),
+        None => None,
+      };
+
+      let after = unsafe { Readline::buffer() };
+      let to = unsafe { rl_point } as usize;
+      let mask = guard.state.mask;
+      let (shown_before, shown_from, shown_after, shown_to) = match mask {
+        Some(mask) => {
+          let (before, from) = mask.apply(&before, from);
+          let (after, to) = mask.apply(after, to);
+          (Cow::Owned(before.into_bytes()), from, Cow::Owned(after.into_bytes()), to)
+        },
+        None => (Cow::Borrowed(before.as_slice()), from, Cow::Borrowed(after), to),
+      };
+      guard.state.change = Change::new(&shown_before, shown_from, &shown_after, shown_to);
+      guard.state.dirty = line.is_none() && (guard.state.dirty || before != after);
+      guard.state.next_entry = match line {
+        Some(..) if operate => {
+          // libreadline already loaded the entry following the accepted
+          // one, if any. Adding the line to the history will reset the
+          // history position, though, so remember the entry.
+          let entries = guard.state.history.entries().len();
+          match unsafe { where_history() } as usize {
+            entry if entry < entries => Some(entry),
+            _ => Some(position as usize + 1),
+          }
+        },
+        _ => None,
+      };
+
+      let mode = unsafe { EditMode::current() };
+      if mode != guard.state.mode {
+        guard.state.mode = mode;
+        if let Some(Hook(hook)) = &mut guard.state.mode_hook {
+          hook(mode)
+        }
+      }
+
+      guard.state.keyseq.clear();
+      if intercepted {
+        if !sensitive {
+          guard.state.keyseq.extend_from_slice(key);
+        }
+      } else if !sensitive && mask.is_none() {
+        // libreadline reports the motion as the last function once a vi
+        // operator completes, but we care about the operator itself.
+        let operator = guard.state.operator.take();
+        let func = if unsafe { state_flags() }.contains(StateFlags::VIMOTION) {
+          // The operator has not been executed yet.
+          guard.state.operator = operator.or(unsafe { rl_last_func });
+          None
+        } else {
+          operator.or(unsafe { rl_last_func })
+        };
+
+        if operator.is_some() && func.is_some() {
+          let record = |ring: &mut KillRing| ring.record_operator(func, &before, after, to);
+          let () = unsafe { guard.state.record_kill(record) };
+        } else {
+          // libreadline counts the kills performed since the last
+          // command that was not a kill, so a count above one means
+          // that the kill followed another one.
+          let consecutive = unsafe { _rl_last_command_was_kill } > 1;
+          let vi = unsafe { rl_editing_mode } != 1;
+          let record =
+            |ring: &mut KillRing| ring.record(func, &before, from, after, to, consecutive, vi);
+          let () = unsafe { guard.state.record_kill(record) };
+        }
+        let () = unsafe { guard.state.update_register(func, &before, after, to) };
+
+        let keyseq = unsafe {
+          if rl_executing_keyseq.is_null() || rl_key_sequence_length <= 0 {
+            &[]
+          } else {
+            slice::from_raw_parts(rl_executing_keyseq.cast::<u8>(), rl_key_sequence_length as _)
+          }
+        };
+        guard.state.keyseq.extend_from_slice(keyseq);
+      }
+
+      let state = &mut *guard.state;
+      if let Some(Hook(observer)) = &mut state.observer {
+        let position = match unsafe { where_history() } {
+          now if now != position && line.is_none() => Some(now as usize),
+          _ => None,
+        };
+        let edit = Edit {
+          keyseq: &state.keyseq,
+          before: &shown_before,
+          from: shown_from,
+          after: &shown_after,
+          to: shown_to,
+          history: position,
+          line: line.as_deref(),
+        };
+        let () = edit.notify(observer.as_mut());
+      }
+
+      if sensitive || mask.is_some() {
+        // We do not keep undo records around for sensitive or masked
+        // data, as they may contain deleted text.
+        unsafe {
+          #[cfg(feature = "zeroize")]
+          undo::wipe(rl_undo_list);
+          rl_free_undo_list();
+        }
+      }
+
+      #[cfg(feature = "zeroize")]
+      {
+        if sensitive && line.is_some() {
+          unsafe { wipe_line_buffer() }
+        }
+      }
+
+      if line.is_some() {
+        guard.state.metrics.completed();
+      }
+      line
+    }
+
+    feed_impl(self, key, last)
+  }
+
+  /// Hand the given key to the unbound key hook, if one is installed
+  /// and the key is not bound to anything.
+  ///
+  /// Returns `true` if the key got handed to the hook.
+  fn feed_unbound(&self, key: &Key) -> bool {
+    if key.is_empty() || self.state.borrow().unbound_hook.is_none() {
+      return false
+    }
+
+    let mut guard = self.activate();
+    if !unsafe { is_unbound(key) } {
+      return false
+    }
+
+    guard.state.metrics.fed();
+    guard.state.status = None;
+    guard.state.change = Change::Unchanged;
+    guard.state.keyseq.clear();
+
+    if let Some(Hook(hook)) = &mut guard.state.unbound_hook {
+      hook(key)
+    }
+    true
+  }
+
+  /// Consult the input filter, if one is installed, about the given key
+  /// if it is about to be inserted into the line.
+  ///
+  /// Rejections are reported to the observer, if any.
+  fn filter_input(&self, key: &Key) -> InputAction {
+    if key.is_empty() || self.state.borrow().input_filter.is_none() {
+      return InputAction::Accept
+    }
+
+    let mut guard = self.activate();
+    if !unsafe { is_bound_to(key, rl_insert) } {
+      return InputAction::Accept
+    }
+
+    let action = match &mut guard.state.input_filter {
+      Some(Hook(filter)) => filter(key),
+      None => InputAction::Accept,
+    };
+
+    match action {
+      InputAction::Replace(text) if !text.is_empty() => InputAction::Replace(text),
+      InputAction::Accept => InputAction::Accept,
+      InputAction::Replace(..) | InputAction::Reject => {
+        guard.state.metrics.fed();
+        guard.state.status = None;
+        guard.state.change = Change::Unchanged;
+        guard.state.keyseq.clear();
+
+        if let Some(Hook(observer)) = &mut guard.state.observer {
+          observer.input_rejected(key)
+        }
+        InputAction::Reject
+      },
+    }
+  }
+
+  /// Discard the line being edited along with any state of partially
+  /// executed commands, as libreadline does when receiving `SIGINT`.
+  fn interrupt(&self) {
+    let mut guard = self.activate();
+    guard.state.metrics.fed();
+    guard.state.status = None;
+    // Input not yet processed belongs to the line being discarded.
+    let _pending = unsafe { take_pending() };
+
+    let before = unsafe { Readline::buffer() }.to_vec();
+    let from = unsafe { rl_point } as usize;
+
+    guard.dispatch(|| unsafe {
+      #[cfg(readline_7)]
+      rl_callback_sigcleanup();
+      #[cfg(feature = "zeroize")]
+      undo::wipe(rl_undo_list);
+      rl_free_line_state();
+    });
+
+    #[cfg(feature = "zeroize")]
+    {
+      if guard.state.sensitive {
+        unsafe { wipe_line_buffer() }
+      }
+    }
+
+    unsafe {
+      rl_replace_line(b"\0".as_ptr().cast(), 1);
+      rl_point = 0;
+    }
+
+    let (before, from) = match guard.state.mask {
+      Some(mask) => {
+        let (before, from) = mask.apply(&before, from);
+        (before.into_bytes(), from)
+      },
+      None => (before, from),
+    };
+    guard.state.change = Change::new(&before, from, b"", 0);
+    guard.state.keyseq.clear();
+    guard.state.dirty = false;
+
+    if let Some(Hook(observer)) = &mut guard.state.observer {
+      let edit = Edit {
+        keyseq: &[],
+        before: &before,
+        from,
+        after: &[],
+        to: 0,
+        history: None,
+        line: None,
+      };
+      let () = edit.notify(observer.as_mut());
+    }
+  }
+
+  /// Record the event produced by the given function, if recording.
+  fn record<F>(&self, event: F)
+  where
+    F: FnOnce() -> RecordedEvent,
+  {
+    let mut state = self.state.borrow_mut();
+    let state = &mut **state;
+    #[cfg(feature = "zeroize")]
+    let sensitive = state.sensitive;
+    #[cfg(not(feature = "zeroize"))]
+    let sensitive = false;
+
+    if let Some(recorder) = &mut state.recorder {
+      // Input is not recorded if it may be of a sensitive nature.
+      if !sensitive && state.mask.is_none() {
+        let () = recorder.record(event());
+      }
+    }
+  }
+
+  /// Start recording the input events of this instance, discarding any
+  /// recording in progress.
+  ///
+  /// All keys fed (see [`feed_key`][Readline::feed_key]) and all resets
+  /// of the line (see [`reset`][Readline::reset]) are recorded, allowing
+  /// for reproducing the editing session later on via
+  /// [`Recording::replay`]. Input provided while the instance handles
+  /// sensitive data (see `set_sensitive`) or masks the line (see
+  /// [`set_mask`][Readline::set_mask]) is not recorded.
+  pub fn start_recording(&self) {
+    self.state.borrow_mut().recorder = Some(Recorder::new())
+  }
+
+  /// Stop recording the input events of this instance, returning the
+  /// recording made, if any.
+  pub fn stop_recording(&self) -> Option<Recording> {
+    self.state.borrow_mut().recorder.take().map(Recorder::finish)
+  }
+
+  /// Set the byte to treat as interrupt character, if any.
+  ///
+  /// Feeding the interrupt character on its own discards the line being
+  /// edited, along with partially entered commands (such as an
+  /// incremental search or a numeric argument). [`feed_key`][Readline::feed_key]
+  /// reports [`Feed::Interrupted`] in this case. Typically, the
+  /// interrupt character is Ctrl-C (`0x03`). By default, no byte is
+  /// treated specially and it is up to libreadline's key bindings to
+  /// act on it. A key to be inserted verbatim (see
+  /// [`is_quoted_insert`][Readline::is_quoted_insert]) is never treated
+  /// as interrupt character.
+  pub fn set_interrupt_char(&self, interrupt: Option<u8>) {
+    self.state.borrow_mut().interrupt = interrupt
+  }
+
+  /// Set the byte to treat as end-of-file character, if any.
+  ///
+  /// When fed on its own while the line is empty, the end-of-file
+  /// character is reported as [`Feed::Eof`] by
+  /// [`feed_key`][Readline::feed_key] and not passed on to libreadline.
+  /// On a non-empty line it is processed according to libreadline's key
+  /// bindings, which typically means deleting the character under the
+  /// cursor. A key meant for a command waiting for more input, e.g., an
+  /// incremental search or one to be inserted verbatim (see
+  /// [`is_quoted_insert`][Readline::is_quoted_insert]), is never treated
+  /// as end-of-file character. Defaults to Ctrl-D (`0x04`). With `None`,
+  /// libreadline's own handling applies, which completes an empty line.
+  pub fn set_eof_char(&self, eof: Option<u8>) {
+    self.state.borrow_mut().eof = eof
+  }
+
+  /// Set the length, in bytes, after which the line is accepted
+  /// automatically, if any.
+  ///
+  /// Once the line reached the given length, it is completed just as if
+  /// it had been accepted by the user, which is useful for prompts such
+  /// as "press y/n" or PIN entry. The line is reported through the
+  /// usual means, e.g., as [`Feed::Line`]. Note that the length is
+  /// checked against the line's byte length, after the command a key
+  /// is bound to got executed. `None` or `Some(0)` disables automatic
+  /// acceptance, which is the default.
+  pub fn set_chars_to_read(&self, count: Option<usize>) {
+    let count = c_int::try_from(count.unwrap_or(0)).unwrap_or(c_int::MAX);
+    let mut guard = self.activate();
+    guard.state.chars_to_read = count;
+    unsafe { rl_num_chars_to_read = count };
+  }
+
+  /// Retrieve the length, in bytes, after which the line is accepted
+  /// automatically, if any.
+  pub fn chars_to_read(&self) -> Option<usize> {
+    match self.state.borrow().chars_to_read {
+      0 => None,
+      count => Some(count as usize),
+    }
+  }
+
+  /// Set whether the application displays the prompt itself, e.g.,
+  /// because it emits it as part of other output, instead of leaving
+  /// that to whatever displays the line being edited.
+  ///
+  /// libreadline then assumes that the prompt is already on screen
+  /// whenever it starts out on a new line, accounting for the columns it
+  /// occupies without displaying it. The setting is reported to the
+  /// redisplay hook as [`Redisplay::already_prompted`]. Use
+  /// [`on_new_line_with_prompt`][Readline::on_new_line_with_prompt] to
+  /// inform libreadline about the prompt having been displayed in
+  /// between lines, too.
+  pub fn set_already_prompted(&self, prompted: bool) {
+    let mut guard = self.activate();
+    guard.state.already_prompted = prompted;
+    unsafe { rl_already_prompted = c_int::from(prompted) };
+  }
+
+  /// Check whether the application displays the prompt itself.
+  pub fn is_already_prompted(&self) -> bool {
+    self.state.borrow().already_prompted
+  }
+
+  /// Inform libreadline that the cursor moved to a new line on which
+  /// the prompt got displayed already, e.g., by the application after
+  /// having emitted output of its own, so that libreadline's column
+  /// accounting for the line being edited stays correct.
+  ///
+  /// If no prompt is set, libreadline is merely informed about the
+  /// cursor being at the start of a new line.
+  pub fn on_new_line_with_prompt(&self) {
+    let _guard = self.activate();
+    let _result = unsafe {
+      if rl_prompt.is_null() {
+        rl_on_new_line()
+      } else {
+        rl_on_new_line_with_prompt()
+      }
+    };
+  }
+
+  /// Feed a key to libreadline, converting a completed line into a
+  /// `String`.
+  ///
+  /// An error is reported if the line is not valid UTF-8. The original
+  /// line can be retrieved from it. Panics under the same conditions as
+  /// [`feed`][Readline::feed].
+  pub fn feed_string(&self, key: impl AsRef<Key>) -> Option<Result<String, IntoStringError>> {
+    self.feed_key(key).into_string()
+  }
+
+  /// Feed a key to libreadline, converting a completed line into a
+  /// `String` and replacing invalid UTF-8 sequences with
+  /// `U+FFFD REPLACEMENT CHARACTER`.
+  ///
+  /// Panics under the same conditions as [`feed`][Readline::feed].
+  pub fn feed_string_lossy(&self, key: impl AsRef<Key>) -> Option<String> {
+    self.feed_key(key).into_string_lossy()
+  }
+
+  /// Reset libreadline's line state to the given line with the given
+  /// cursor position. If `clear_undo` is set, the undo list associated
+  /// with the current line is cleared
+  ///
+  /// Note that this method does not deal with input related modes. For
+  /// example, libreadline always starts in input mode, but, depending
+  /// on user configuration, it can be transitioned to vi-movement-mode
+  /// in which key bindings behave differently (other similar modes
+  /// exist). When resetting the line using this method the input mode
+  /// is unaffected. If you truly need to manually force libreadline
+  /// into input mode, a new `Readline` will help:
+  /// ```rust
+  /// # use rline::Readline;
+  /// # let mut current = Readline::new();
+  /// current = Readline::new();
+  /// ```
+  ///
+  /// # Panics
+  ///
+  /// Panics if the cursor is not less than or equal to the number of
+  /// characters in the given line.
+  pub fn reset<S>(&self, line: S, cursor: usize, clear_undo: bool)
+  where
+    S: AsRef<CStr>,
+  {
+    fn reset_impl(rl: &Readline, s: &CStr, cursor: usize, clear_undo: bool) {
+      assert!(cursor <= s.to_bytes().len(), "invalid cursor position");
+
+      let () = rl.record(|| RecordedEvent::Reset {
+        line: s.to_owned(),
+        cursor,
+        clear_undo,
+      });
+      let mut guard = rl.activate();
+      guard.state.dirty = false;
+      #[cfg(feature = "zeroize")]
+      let sensitive = guard.state.sensitive;
+      #[cfg(not(feature = "zeroize"))]
+      let sensitive = false;
+
+      #[cfg(feature = "zeroize")]
+      {
+        if sensitive {
+          unsafe {
+            undo::wipe(rl_undo_list);
+            rl_free_undo_list();
+            wipe_line_buffer();
+          }
+        }
+      }
+
+      unsafe {
+        reserve_line(s.to_bytes().len(), sensitive);
+        rl_replace_line(s.as_ptr(), clear_undo.into());
+        rl_point = cursor as _;
+      }
+    }
+
+    reset_impl(self, line.as_ref(), cursor, clear_undo)
+  }
+
+  /// Reset libreadline's line state to the given line with the given
+  /// cursor position, as [`reset`][Readline::reset] does.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the line contains a NUL byte, in which case
+  /// the line state is left untouched.
+  ///
+  /// # Panics
+  ///
+  /// Panics if the cursor is not less than or equal to the number of
+  /// bytes in the given line.
+  pub fn reset_str<S>(&self, line: S, cursor: usize, clear_undo: bool) -> Result<(), NulError>
+  where
+    S: AsRef<str>,
+  {
+    let line = CString::new(line.as_ref())?;
+    self.reset(&line, cursor, clear_undo);
+
+    #[cfg(feature = "zeroize")]
+    {
+      line.into_bytes().zeroize();
+    }
+    Ok(())
+  }
+
+  /// Exchange the lines being edited, along with the cursor positions,
+  /// between this instance and `other`.
+  ///
+  /// This is useful for user interfaces moving in-progress text between
+  /// input fields. If `undo` is `true`, the undo lists travel along
+  /// with the lines, otherwise both are cleared. The exchange happens
+  /// atomically with respect to all other instances.
+  pub fn swap_line(&mut self, other: &mut Readline, undo: bool) {
+    let _operation = self.operation();
+    let _other_operation = other.operation();
+    let mut guard = self.activate();
+    let line = unsafe { LineState::take(&mut guard.state, undo) };
+
+    let mut guard = other.activate_locked(guard.into_lock());
+    let other_line = unsafe { LineState::take(&mut guard.state, undo) };
+    let () = unsafe { line.put(&mut guard.state) };
+
+    let mut guard = self.activate_locked(guard.into_lock());
+    let () = unsafe { other_line.put(&mut guard.state) };
+  }
+
+  /// Move the line being edited, along with the cursor position, from
+  /// this instance to `other`, replacing its line and leaving this
+  /// instance with an empty one.
+  ///
+  /// If `undo` is `true`, the undo list travels along with the line.
+  /// The undo lists of both instances are cleared otherwise. The move
+  /// happens atomically with respect to all other instances.
+  pub fn move_line_to(&mut self, other: &mut Readline, undo: bool) {
+    let _operation = self.operation();
+    let _other_operation = other.operation();
+    let mut guard = self.activate();
+    let line = unsafe { LineState::take(&mut guard.state, undo) };
+    let empty = LineState {
+      text: vec![b'\0'],
+      cursor: 0,
+      undo: null_mut(),
+      dirty: false,
+    };
+    let () = unsafe { empty.put(&mut guard.state) };
+
+    let mut guard = other.activate_locked(guard.into_lock());
+    let _other_line = unsafe { LineState::take(&mut guard.state, false) };
+    let () = unsafe { line.put(&mut guard.state) };
+  }
+
+  /// Inspect the records of the undo list associated with the current
+  /// line through a closure.
+  ///
+  /// The closure is provided with an iterator over the records, from
+  /// the one undone next to the oldest one. Note that libreadline
+  /// merges consecutive insertions into a single record and that a
+  /// single undo reverts an entire group, as delimited by records of
+  /// kind [`UndoKind::Begin`] and [`UndoKind::End`].
+  pub fn undo_records<F, R>(&self, f: F) -> R
+  where
+    F: FnOnce(UndoRecords<'_>) -> R,
+  {
+    let _guard = self.activate();
+    // SAFETY: The undo list is not modified while we hold the guard,
+    //         which outlives the iterator.
+    let records = unsafe { UndoRecords::new(rl_undo_list) };
+    f(records)
+  }
+
+  /// Limit the number of undo records kept for the line, or remove the
+  /// limit.
+  ///
+  /// Whenever the instance gets deactivated, e.g., at the end of a
+  /// [`feed`][Readline::feed], the oldest records in excess of the
+  /// limit are discarded. Groups of records that are undone together
+  /// are never split up, though, meaning that more records than
+  /// requested may be kept.
+  pub fn set_undo_limit(&self, limit: Option<usize>) {
+    let mut guard = self.activate();
+    guard.state.undo_limit = limit;
+  }
+
+  /// Retrieve the limit on the number of undo records kept for the
+  /// line, if any.
+  pub fn undo_limit(&self) -> Option<usize> {
+    self.state.borrow().undo_limit
+  }
+
+  /// Check whether the line got modified since it was last reset (see
+  /// [`reset`][Readline::reset]) or accepted.
+  ///
+  /// Any change to the text of the line counts as a modification,
+  /// including the recall of a history entry. Applications can use
+  /// this information to, e.g., warn before discarding input.
+  pub fn is_dirty(&self) -> bool {
+    self.state.borrow().dirty
+  }
+
+  /// Retrieve the contents of libreadline's line buffer.
+  ///
+  /// # Safety
+  /// Callers must ensure that the global mutex is held for the duration
+  /// of the usage of the returned reference.
+  unsafe fn buffer() -> &'static [u8] {
+    debug_assert!(Self::mutex().is_locked());
+
+    unsafe {
+      debug_assert!(rl_end >= 0);
+
+      let buf = rl_line_buffer;
+      if buf.is_null() {
+        &[]
+      } else {
+        slice::from_raw_parts(buf.cast(), rl_end as _)
+      }
+    }
+  }
+
+  /// Add a line to this instance's history.
+  ///
+  /// Each `Readline` instance has its own history, which is navigable
+  /// using libreadline's history commands. If a history limit is in
+  /// effect, the oldest entries are evicted as necessary.
+  ///
+  /// Adding a line positions the history past its most recent entry,
+  /// unless the line accepted last was accepted via
+  /// `operate-and-get-next` (bound to `C-o` in emacs mode by default and
+  /// available as of libreadline 8.1). In that case libreadline already
+  /// loaded the history entry following the accepted one into the line,
+  /// and adding the accepted line to the history keeps that entry
+  /// current, so that the history can be navigated from it, in the way
+  /// bash does. If the accepted line was the most recent history entry,
+  /// the newly added copy of it gets loaded instead. To benefit from this behavior, the accepted
+  /// line has to be added before any more input is fed and the line must
+  /// not be reset in between.
+  pub fn add_history<S>(&self, line: S)
+  where
+    S: AsRef<CStr>,
+  {
+    self.add_history_entry(line.as_ref(), None)
+  }
+
+  /// Add a line to this instance's history, associating the given data
+  /// with the new entry.
+  ///
+  /// The data can be arbitrary, e.g., the exit status of a command or
+  /// the time it took to run. It is retrievable along with the entry
+  /// (see [`history`][Readline::history]) until the entry gets evicted
+  /// and is not persisted in history files. Please refer to
+  /// [`add_history`][Readline::add_history] for more details.
+  pub fn add_history_with_data<S, T>(&self, line: S, data: T)
+  where
+    S: AsRef<CStr>,
+    T: Any + Send,
+  {
+    self.add_history_entry(line.as_ref(), Some(Data(Box::new(data))))
+  }
+
+  /// Add a line along with its data, if any, to this instance's
+  /// history.
+  fn add_history_entry(&self, line: &CStr, data: Option<Data>) {
+    let mut guard = self.activate();
+    let next_entry = guard.state.next_entry.take();
+    let entries = guard.state.history.entries().len();
+    let () = guard.state.history.add(line, data);
+    if let Some(sync) = &mut guard.state.history_sync {
+      let () = sync.append(line);
+    }
+
+    if let Some(entry) = next_entry {
+      let evicted = (entries + 1).saturating_sub(guard.state.history.entries().len());
+      if entry == entries {
+        // The accepted line was the most recent entry, so the one
+        // following it is its copy we just added.
+        let _result = unsafe { rl_get_previous_history(1, 0) };
+      } else if entry < entries && entry >= evicted {
+        let () = guard.state.history.set_position(entry - evicted);
+      }
+    }
+  }
+
+  /// Append the given lines to this instance's history, as part of a
+  /// bulk import.
+  fn extend_history(&self, lines: &[CString]) {
+    let mut guard = self.activate();
+    for line in lines {
+      let () = guard.state.history.add(line, None);
+    }
+  }
+
+  /// Inspect this instance's history through a closure.
+  ///
+  /// The closure is provided with the history entries, from oldest to
+  /// newest. Indices into the slice correspond to those reported for
+  /// history navigation and searches (see
+  /// [`LineObserver::history_navigated`]), allowing for the data
+  /// associated with the entry found (see
+  /// [`add_history_with_data`][Readline::add_history_with_data]) to be
+  /// looked up.
+  pub fn history<F, R>(&self, f: F) -> R
+  where
+    F: FnOnce(&[HistoryEntry<'_>]) -> R,
+  {
+    let guard = self.activate();
+    f(&guard.state.history.with_data())
+  }
+
+  /// Set the limit for this instance's history.
+  ///
+  /// Entries in excess of the limit are evicted immediately, oldest
+  /// first.
+  pub fn set_history_limit(&self, limit: Limit) {
+    let mut guard = self.activate();
+    guard.state.history.set_limit(limit)
+  }
+
+  /// Retrieve the memory usage of this instance's history.
+  pub fn history_usage(&self) -> Usage {
+    let guard = self.activate();
+    guard.state.history.usage()
+  }
+
+  /// Enable or disable synchronization of this instance's history with
+  /// the file at the given path, akin to bash's `history -a` and
+  /// `history -n` being run for every prompt.
+  ///
+  /// While enabled, every line added to the history (see
+  /// [`add_history`][Readline::add_history]) is appended to the file
+  /// immediately. Lines appended by other processes (or instances) are
+  /// merged into the history on demand, by means of
+  /// [`sync_history`][Readline::sync_history]. Accesses to the file are
+  /// protected by advisory locks (`flock(2)`). Lines already in the file
+  /// are read into the history when synchronization gets enabled. A
+  /// file that does not exist is treated as empty and created once the
+  /// first line is appended. Timestamps (`#<seconds>`) in the file are
+  /// skipped, and multi-line entries are kept out of it.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the file exists but could not be read. Synchronization
+  /// stays enabled regardless.
+  pub fn set_history_sync<P>(&self, path: Option<P>) -> io::Result<()>
+  where
+    P: AsRef<Path>,
+  {
+    fn set_history_sync_impl(rl: &Readline, path: Option<&Path>) -> io::Result<()> {
+      rl.state.borrow_mut().history_sync = path.map(HistorySync::new);
+      if path.is_some() {
+        let _count = rl.sync_history()?;
+      }
+      Ok(())
+    }
+
+    set_history_sync_impl(self, path.as_ref().map(AsRef::as_ref))
+  }
+
+  /// Synchronize this instance's history with the file configured via
+  /// [`set_history_sync`][Readline::set_history_sync], if any.
+  ///
+  /// Lines that previously could not be appended to the file are
+  /// appended, and lines appended to it by others since the last
+  /// synchronization are added to the history, in order. The number of
+  /// lines added to the history is returned.
+  ///
+  /// # Errors
+  ///
+  /// Fails if the file could not be written to or read from.
+  pub fn sync_history(&self) -> io::Result<usize> {
+    let mut guard = self.activate();
+    let state = &mut *guard.state;
+    let sync = match &mut state.history_sync {
+      Some(sync) => sync,
+      None => return Ok(0),
+    };
+
+    let () = sync.flush()?;
+    let lines = sync.read()?;
+    for line in &lines {
+      // Lines read by `HistorySync::read` do not contain NUL bytes.
+      let line = CString::new(line.as_slice()).unwrap();
+      let () = state.history.add(&line, None);
+    }
+    Ok(lines.len())
+  }
+
+  /// Set the limit for this instance's kill ring.
+  ///
+  /// libreadline keeps its kill ring private, so the crate keeps a
+  /// record of the text killed through this instance. Entries in excess
+  /// of the limit are evicted from this record immediately, oldest
+  /// first, and from libreadline's kill ring as well, by refilling it.
+  /// Unless this instance is isolated (see
+  /// [`set_isolated`][Readline::set_isolated]), libreadline's kill ring
+  /// is shared with other instances and the limit gets applied to it
+  /// whenever text is killed through this instance.
+  pub fn set_kill_ring_limit(&self, limit: Limit) {
+    let mut guard = self.activate();
+    guard.state.kill_ring_limit = limit;
+    let () = unsafe { guard.state.enforce_kill_ring_limit() };
+  }
+
+  /// Retrieve the memory usage of this instance's kill ring.
+  ///
+  /// What is reported (here and by
+  /// [`memory_usage`][Readline::memory_usage]) is the crate's record of
+  /// the text killed through this instance, which retains as many
+  /// entries as libreadline does, within the configured limit (see
+  /// [`set_kill_ring_limit`][Readline::set_kill_ring_limit]).
+  pub fn kill_ring_usage(&self) -> Usage {
+    self.state.borrow().kill_ring.usage()
+  }
+
+  /// Retrieve the approximate amount of heap memory consumed by this
+  /// instance's history and kill ring, e.g., for implementing custom
+  /// eviction policies or for diagnostic purposes.
+  pub fn memory_usage(&self) -> MemoryUsage {
+    let guard = self.activate();
+    MemoryUsage {
+      history: guard.state.history.memory(),
+      kill_ring: guard.state.kill_ring.memory(),
+    }
+  }
+
+  /// Retrieve the content of the vi register with the given name.
+  ///
+  /// libreadline has no notion of named registers, but the crate
+  /// provides the registers `a` through `z` for instances in the vi
+  /// editing mode. In command mode, typing `"` followed by a register
+  /// name selects the register for the next command, provided that `"`
+  /// is not bound to anything else: text deleted or changed by that
+  /// command gets stored in the register, with an uppercase name
+  /// appending to its content, and a put (`p` or `P`) inserts the
+  /// register's content. Yanking (`y`) into a named register is not
+  /// supported. The unnamed register, `"`, refers to the text most
+  /// recently killed through this instance.
+  ///
+  /// Note that puts from named registers go through libreadline's kill
+  /// ring, which is shared by all instances.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `name` is not a valid register name.
+  pub fn register(&self, name: char) -> Option<CString> {
+    assert!(register::is_valid(name), "invalid register name: {}", name);
+
+    let state = self.state.borrow();
+    if name == char::from(register::UNNAMED) {
+      state.kill_ring.last().cloned()
+    } else {
+      state.registers.get(name as u8).cloned()
+    }
+  }
+
+  /// Set the content of the vi register with the given name.
+  ///
+  /// An uppercase name appends `text` to the register's content. Setting
+  /// the unnamed register, `"`, adds `text` to the kill ring. Please
+  /// refer to [`register`][Readline::register] for details.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `name` is not a valid register name.
+  pub fn set_register<S>(&self, name: char, text: S)
+  where
+    S: AsRef<CStr>,
+  {
+    fn set_register_impl(rl: &Readline, name: char, text: &CStr) {
+      assert!(register::is_valid(name), "invalid register name: {}", name);
+
+      if name == char::from(register::UNNAMED) {
+        let mut guard = rl.activate();
+        let () = unsafe { push_kill(text) };
+        let () = unsafe { guard.state.record_kill(|ring| ring.add(text.to_bytes())) };
+      } else {
+        let () = rl
+          .state
+          .borrow_mut()
+          .registers
+          .set(name as u8, text.to_bytes());
+      }
+    }
+
+    set_register_impl(self, name, text.as_ref())
+  }
+
+  /// Capture the portable parts of this instance's state, for
+  /// persisting or transferring them.
+  ///
+  /// Note that the captured state may contain sensitive data, such as
+  /// the line being edited.
+  #[cfg(feature = "serde")]
+  pub fn session(&self) -> Session {
+    let guard = self.activate();
+    let line = unsafe { Readline::buffer() };
+    Session {
+      // The line buffer is NUL terminated and so cannot contain a NUL
+      // byte within its first `rl_end` bytes.
+      line: CString::new(line).unwrap(),
+      cursor: unsafe { rl_point } as _,
+      history: guard.state.history.lines().map(CStr::to_owned).collect(),
+      kill_ring: guard.state.kill_ring.entries().cloned().collect(),
+    }
+  }
+
+  /// Restore the state captured in a [`Session`], replacing the line,
+  /// history, and kill ring entries of this instance.
+  ///
+  /// Configured limits are applied to the restored history and kill
+  /// ring. Note that libreadline's own kill ring is not affected.
+  ///
+  /// # Panics
+  ///
+  /// Panics if the session's cursor position is not within its line.
+  #[cfg(feature = "serde")]
+  pub fn restore_session(&self, session: &Session) {
+    let _operation = self.operation();
+    self.reset(&session.line, session.cursor, true);
+
+    let mut guard = self.activate();
+    guard.state.history.clear();
+    session
+      .history
+      .iter()
+      .for_each(|line| guard.state.history.add(line, None));
+    guard
+      .state
+      .kill_ring
+      .set_entries(session.kill_ring.iter().cloned());
+    let limit = guard.state.kill_ring_limit;
+    let _evicted = guard.state.kill_ring.enforce_limit(limit);
+  }
+
+  /// Retrieve the number of bytes sitting in libreadline's input buffer
+  /// that have not yet been processed.
+  ///
+  /// Fed bytes are usually processed right away, but malformed input,
+  /// e.g., an incomplete escape sequence followed by more keys, may
+  /// leave some behind. Such bytes will be processed as part of the
+  /// next [`feed`][Readline::feed].
+  pub fn pending_input(&self) -> usize {
+    self.state.borrow().pending.len()
+  }
+
+  /// Retrieve the number of bytes that can currently be fed at once.
+  ///
+  /// libreadline's input buffer has a fixed size and is shared with
+  /// [pending input][Readline::pending_input]. Adapters feeding large
+  /// chunks of data, e.g., pasted text, can use this method for
+  /// slicing their writes, instead of running into the panic described
+  /// for [`feed`][Readline::feed]. Note that bytes produced by an input
+  /// filter (see [`set_input_filter`][Readline::set_input_filter]) are
+  /// subject to the same limit.
+  pub fn input_capacity(&self) -> usize {
+    INPUT_CAPACITY.saturating_sub(self.state.borrow().pending.len())
+  }
+
+  /// Discard all bytes sitting in libreadline's input buffer that have
+  /// not yet been processed, returning their number.
+  ///
+  /// Applications can use this method to recover from feeding
+  /// malformed input, preventing it from corrupting subsequent keys.
+  pub fn drain_pending(&self) -> usize {
+    let mut state = self.state.borrow_mut();
+    let count = state.pending.len();
+    state.pending.clear();
+    count
+  }
+
+  /// Retrieve the usage counters of this instance.
+  pub fn metrics(&self) -> Metrics {
+    let state = self.state.borrow();
+    state.metrics.metrics(state.history.len())
+  }
+
+  /// Retrieve the usage counters aggregated over all instances.
+  ///
+  /// The reported number of history entries covers only instances that
+  /// are still alive.
+  pub fn global_metrics() -> Metrics {
+    metrics::global()
+  }
+
+  /// Retrieve the key sequence executed as part of the most recent
+  /// [`feed`][Readline::feed].
+  ///
+  /// If the fed bytes only form the prefix of a multi-key sequence,
+  /// this prefix is reported. For sensitive or masked instances, key
+  /// sequences are not recorded.
+  pub fn last_key_sequence(&self) -> Vec<u8> {
+    self.state.borrow().keyseq.clone()
+  }
+
+  /// Retrieve the change to the line caused by the most recent
+  /// [`feed`][Readline::feed].
+  ///
+  /// Frontends can use this information to update their displayed text
+  /// incrementally instead of re-rendering the entire line. Note that
+  /// once a line got completed, the reported change reflects the
+  /// removal of the entire line. Changes made through other means, such
+  /// as [`reset`][Readline::reset], are not reported.
+  pub fn last_change(&self) -> Change {
+    self.state.borrow().change
+  }
+
+  /// Install a hook to invoke whenever libreadline considers the
+  /// displayed line outdated, replacing any previously installed one.
+  ///
+  /// The hook is invoked while input is being fed and receives the
+  /// current line, cursor, and prompt. It catches changes that do not
+  /// coincide with a completed [`feed`][Readline::feed] (e.g., during
+  /// incremental search) and frees frontends from having to
+  /// [`peek`][Readline::peek] after every key.
+  pub fn set_redisplay_hook(&self, hook: Option<Box<RedisplayFn>>) {
+    self.state.borrow_mut().redisplay = hook.map(Hook)
+  }
+
+  /// Install a highlighter, replacing any previously installed one.
+  ///
+  /// The highlighter maps a line to a list of styled [`Span`]s, which
+  /// are reported to the redisplay hook as well as by
+  /// [`peek_highlighted`][Readline::peek_highlighted]. The crate
+  /// attaches no meaning to style identifiers; it is up to the
+  /// application to map them to colors or other attributes. Spans are
+  /// passed on as they are, i.e., they are not checked for being in
+  /// bounds or non-overlapping.
+  pub fn set_highlighter(&self, highlighter: Option<Box<HighlightFn>>) {
+    self.state.borrow_mut().highlighter = highlighter.map(Hook)
+  }
+
+  /// Install a validator for accepted lines, replacing any previously
+  /// installed one.
+  ///
+  /// The validator is invoked whenever a line got accepted (e.g., by
+  /// pressing enter). If it reports the line as
+  /// [`Incomplete`][Validation::Incomplete], a newline is inserted at
+  /// the cursor position and editing continues, instead of the line
+  /// being returned from [`feed`][Readline::feed]. This allows for
+  /// multi-line input, e.g., in a REPL waiting for a closing brace.
+  /// If it rejects the line as [`Invalid`][Validation::Invalid], the
+  /// line stays in the line buffer for further editing and the
+  /// rejection message is set as status message (see
+  /// [`set_status`][Readline::set_status]), to be displayed until the
+  /// next key is fed.
+  /// [`unicode::location`] helps with mapping cursor positions in such
+  /// lines.
+  pub fn set_validator(&self, validator: Option<Box<ValidateFn>>) {
+    self.state.borrow_mut().validator = validator.map(Hook)
+  }
+
+  /// Set the transformations to apply to completed lines, replacing
+  /// any previously set ones.
+  ///
+  /// Transformations are applied in the given order to lines that got
+  /// accepted and validated (see [`set_validator`][Self::set_validator]),
+  /// before they are returned from [`feed`][Readline::feed] and reported
+  /// to the corrector and observer, if any. Adding the returned lines to
+  /// the history hence stores them in their canonical form.
+  pub fn set_transforms(&self, transforms: Vec<Transform>) {
+    self.state.borrow_mut().transforms = transforms
+  }
+
+  /// Install a corrector for accepted lines, replacing any previously
+  /// installed one.
+  ///
+  /// The corrector is invoked whenever a line got completed and may
+  /// suggest a correction for it, e.g., fixing a mistyped command. The
+  /// line is returned from [`feed`][Readline::feed] as usual, but the
+  /// suggestion is available via [`correction`][Readline::correction]
+  /// until more input is fed. Applications can present it (e.g., as
+  /// "did you mean ...?") and, instead of processing the line, load the
+  /// suggestion into the line buffer for the user to confirm via
+  /// [`apply_correction`][Readline::apply_correction]. A suggestion
+  /// equal to the line itself is ignored, and masked lines (see
+  /// [`set_mask`][Readline::set_mask]) are not subject to correction.
+  pub fn set_corrector(&self, corrector: Option<Box<CorrectFn>>) {
+    let mut state = self.state.borrow_mut();
+    state.corrector = corrector.map(Hook);
+    state.correction = None;
+  }
+
+  /// Retrieve the correction suggested by the corrector (see
+  /// [`set_corrector`][Readline::set_corrector]) for the line most
+  /// recently completed, if any.
+  pub fn correction(&self) -> Option<CString> {
+    self.state.borrow().correction.clone()
+  }
+
+  /// Load the correction suggested for the line most recently completed
+  /// into the line buffer, with the cursor at its end and an empty undo
+  /// list, so that the user can confirm it by accepting it.
+  ///
+  /// Returns `false` and leaves the line untouched if there is no
+  /// correction.
+  pub fn apply_correction(&self) -> bool {
+    let correction = self.state.borrow_mut().correction.take();
+    match correction {
+      Some(correction) => {
+        let cursor = correction.as_bytes().len();
+        let () = self.reset(correction, cursor, true);
+        true
+      },
+      None => false,
+    }
+  }
+
+  /// Set the way continuation lines of multi-line input are indented,
+  /// if at all.
+  ///
+  /// Whenever the validator (see [`set_validator`][Self::set_validator])
+  /// causes a newline to be inserted, the indentation determined as per
+  /// `indent` is inserted after it, with the cursor placed after the
+  /// indentation. Indentation containing a NUL byte is truncated at it.
+  pub fn set_auto_indent(&self, indent: Option<Indent>) {
+    self.state.borrow_mut().indent = indent
+  }
+
+  /// Inform libreadline about the size of the screen (or widget) the
+  /// line is displayed on, in rows and columns.
+  ///
+  /// libreadline uses this information for, among other things,
+  /// laying out completion candidates and horizontal scrolling
+  /// decisions. The size is kept per instance. Instances for which no
+  /// size was set, or for which it got cleared by passing `None`, use
+  /// the size libreadline determined on initialization.
+  pub fn set_screen_size(&self, size: Option<(usize, usize)>) {
+    let size = size.map(|(rows, columns)| {
+      (
+        c_int::try_from(rows).unwrap_or(c_int::MAX),
+        c_int::try_from(columns).unwrap_or(c_int::MAX),
+      )
+    });
+
+    let mut guard = self.activate();
+    guard.state.screen_size = size;
+    unsafe { apply_screen_size(size) }
+  }
+
+  /// Set the name of the terminal the line is displayed on for this
+  /// instance, or revert to the one libreadline determined from the
+  /// `TERM` environment variable.
+  ///
+  /// libreadline consults the name for `$if term=...` conditionals in
+  /// init files (see [`ReadlineBuilder::init_file`]) and imported
+  /// bindings (see [`import_bindings`][Readline::import_bindings]),
+  /// which allows for bindings specific to the type of terminal an
+  /// instance serves. Note that because the line is never displayed by
+  /// libreadline itself, it does not query the terminal's capabilities
+  /// and, hence, does not bind key sequences based on them.
+  pub fn set_terminal_name<S>(&self, name: Option<S>)
+  where
+    S: AsRef<CStr>,
+  {
+    fn set_terminal_name_impl(rl: &Readline, name: Option<&CStr>) {
+      let mut guard = rl.activate();
+      guard.state.terminal = name.map(CStr::to_owned);
+      unsafe { apply_terminal_name(guard.state.terminal.as_deref()) }
+    }
+
+    set_terminal_name_impl(self, name.as_ref().map(AsRef::as_ref))
+  }
+
+  /// Retrieve the name of the terminal the line is displayed on, as
+  /// used by libreadline.
+  pub fn terminal_name(&self) -> Option<CString> {
+    let _guard = self.activate();
+    let name = unsafe { rl_terminal_name };
+    if name.is_null() {
+      None
+    } else {
+      Some(unsafe { CStr::from_ptr(name) }.to_owned())
+    }
+  }
+
+  /// Retrieve the size of the screen, in rows and columns, as used by
+  /// libreadline.
+  ///
+  /// Note that libreadline reserves the last column for terminals not
+  /// supporting automatic margins, in which case the reported number
+  /// of columns is one less than what was set.
+  pub fn screen_size(&self) -> (usize, usize) {
+    let _guard = self.activate();
+    let mut rows = 0;
+    let mut columns = 0;
+    unsafe { rl_get_screen_size(&mut rows, &mut columns) };
+    (rows.max(0) as _, columns.max(0) as _)
+  }
+
+  /// Isolate this instance from all others as far as possible, or undo
+  /// a previous isolation.
+  ///
+  /// Every instance always has its own line, history, kill ring mirror,
+  /// prompt, and completion functions. On top of that, an isolated
+  /// instance gets its own copy of libreadline's keymaps, including the
+  /// macros bound in them, so that key bindings made while it is active
+  /// do not affect other instances and vice versa. The copy is made
+  /// from the keymaps shared by all non-isolated instances at the time
+  /// of the call. It also gets its own kill ring, starting out with the
+  /// entries of its kill ring mirror. When isolation is undone, the
+  /// instance's bindings are discarded and it uses the shared keymaps
+  /// and kill ring again.
+  ///
+  /// The keyboard macro recorded with `start-kbd-macro` is kept in a
+  /// private variable of libreadline and remains shared.
+  pub fn set_isolated(&self, isolated: bool) {
+    let mut guard = self.activate();
+    if isolated {
+      if guard.state.keymaps.is_none() {
+        // Our keymaps are not isolated yet and so the shared ones are
+        // currently active.
+        guard.state.keymaps = Some(unsafe { Keymaps::capture() });
+      }
+      unsafe { keymap::switch(guard.state.keymaps.as_deref()) }
+    } else {
+      // Our keymaps must no longer be in use once they are released.
+      unsafe { keymap::switch(None) }
+      guard.state.keymaps = None;
+    }
+    unsafe { switch_kill_ring(self.id, &guard.state) }
+  }
+
+  /// Read the given init file, with `name` being used as the
+  /// application name for conditionals.
+  fn read_init_file(&self, name: Option<&CStr>, path: &CStr) -> io::Result<()> {
+    let mut guard = self.activate();
+    let result = guard.dispatch(|| unsafe {
+      let previous = rl_readline_name;
+      if let Some(name) = name {
+        rl_readline_name = name.as_ptr();
+      }
+      let result = rl_read_init_file(path.as_ptr());
+      rl_readline_name = previous;
+      apply_eight_bit();
+      result
+    });
+    // The init file may have changed the editing mode.
+    guard.state.mode = unsafe { EditMode::current() };
+
+    if result != 0 {
+      return Err(io::Error::from_raw_os_error(result))
+    }
+    Ok(())
+  }
+
+  /// Read the history stored in the given file, appending it to this
+  /// instance's history.
+  fn read_history(&self, path: &CStr) -> io::Result<()> {
+    self.activate().state.history.read(path)
+  }
+
+  /// Switch to the given editing mode.
+  pub fn set_edit_mode(&self, mode: EditMode) {
+    let mut guard = self.activate();
+    let _result = guard.dispatch(|| unsafe {
+      match mode {
+        EditMode::Emacs => rl_emacs_editing_mode(1, 0),
+        EditMode::ViInsert => rl_vi_editing_mode(1, 0),
+        EditMode::ViCommand => {
+          let _result = rl_vi_editing_mode(1, 0);
+          rl_vi_movement_mode(1, 0)
+        },
+      }
+    });
+    guard.state.mode = unsafe { EditMode::current() };
+  }
+
+  /// Enumerate the named commands bound to key sequences in the keymap
+  /// of the current editing mode, ordered by their names.
+  ///
+  /// Only bindings to commands are reported, not those to macros.
+  pub fn bindings(&self) -> Vec<Binding> {
+    let _guard = self.activate();
+    unsafe { bindings::current() }
+  }
+
+  /// Export the key bindings of the keymap of the current editing mode,
+  /// to commands as well as to macros, in the format used by `inputrc`
+  /// files, e.g., for saving them in a user's profile.
+  ///
+  /// The text starts with a `set keymap` directive, so that importing
+  /// it (see [`import_bindings`][Readline::import_bindings]) restores
+  /// the bindings in the same keymap.
+  pub fn export_bindings(&self) -> String {
+    let _guard = self.activate();
+    unsafe { bindings::export() }
+  }
+
+  /// Import key bindings and variable settings from text in the format
+  /// used by `inputrc` files, e.g., as produced by
+  /// [`export_bindings`][Readline::export_bindings].
+  ///
+  /// Bindings are made in this instance's keymaps, which are shared
+  /// with all other instances unless it is isolated (see
+  /// [`set_isolated`][Readline::set_isolated]). The keymap in use is
+  /// unaffected by `set keymap` directives in the text. Lines that
+  /// cannot be parsed are reported, but do not stop the remaining ones
+  /// from being imported.
+  ///
+  /// # Errors
+  ///
+  /// Fails with [`InvalidInput`][io::ErrorKind::InvalidInput] if the
+  /// text contains a NUL byte and with
+  /// [`InvalidData`][io::ErrorKind::InvalidData] if libreadline failed
+  /// to parse a line.
+  pub fn import_bindings<S>(&self, text: S) -> io::Result<()>
+  where
+    S: AsRef<[u8]>,
+  {
+    fn import_bindings_impl(rl: &Readline, text: &[u8]) -> io::Result<()> {
+      let mut guard = rl.activate();
+      let result = guard.dispatch(|| unsafe {
+        let result = bindings::import(text);
+        apply_eight_bit();
+        result
+      });
+      // The text may have changed the editing mode.
+      guard.state.mode = unsafe { EditMode::current() };
+      result
+    }
+
+    import_bindings_impl(self, text.as_ref())
+  }
+
+  /// Set the text the `insert-comment` command inserts at the
+  /// beginning of the line, as per the `comment-begin` variable.
+  ///
+  /// The text is kept per instance. Instances for which no text was
+  /// set, or for which `None` got set, use the text libreadline was
+  /// configured with on initialization, defaulting to `#`. An empty
+  /// text is not supported by libreadline and treated as `None`.
+  pub fn set_comment_begin<S>(&self, comment: Option<S>)
+  where
+    S: AsRef<CStr>,
+  {
+    fn set_comment_begin_impl(rl: &Readline, comment: Option<&CStr>) {
+      let comment = comment.filter(|comment| !comment.to_bytes().is_empty());
+      let mut guard = rl.activate();
+      guard.state.comment_begin = comment.map(CStr::to_owned);
+      unsafe { bind_comment_begin(comment) }
+    }
+
+    set_comment_begin_impl(self, comment.as_ref().map(AsRef::as_ref))
+  }
+
+  /// Retrieve the text the `insert-comment` command inserts at the
+  /// beginning of the line.
+  pub fn comment_begin(&self) -> CString {
+    let _guard = self.activate();
+    unsafe { variable_value(b"comment-begin\0") }
+      .map(CStr::to_owned)
+      .unwrap_or_default()
+  }
+
+  /// Comment out the line being edited and accept it, just like
+  /// invoking the `insert-comment` command (bound to `M-#` in emacs
+  /// mode and `#` in vi command mode by default) does.
+  ///
+  /// This allows users to keep a line they decided not to execute in
+  /// the history, as done by bash. The outcome is reported as for
+  /// [`feed_key`][Readline::feed_key], with the line typically being
+  /// reported as [`Feed::Line`]. The key sequence the command is bound
+  /// to in the current keymap is fed, meaning that nothing happens if
+  /// it is not bound to any.
+  pub fn insert_comment(&self) -> Feed {
+    let _operation = self.operation();
+    let keys = {
+      let _guard = self.activate();
+      let command = CStr::from_bytes_with_nul(b"insert-comment\0").unwrap();
+      unsafe { bindings::invoking_keys(command) }
+    };
+
+    match keys {
+      Some(keys) => self.feed_key(keys),
+      None => Feed::Pending,
+    }
+  }
+
+  /// Create a grouped, human-readable summary of the key bindings in
+  /// the keymap of the current editing mode, e.g., for displaying it on
+  /// a help screen. See [`CheatSheet`] for details.
+  pub fn cheat_sheet(&self) -> CheatSheet {
+    CheatSheet::new(self.bindings())
+  }
+
+  /// Retrieve the flags describing what libreadline is currently doing
+  /// for this instance, e.g., whether it is searching the history
+  /// incrementally or a keyboard macro is being defined.
+  pub fn state_flags(&self) -> StateFlags {
+    let _guard = self.activate();
+    unsafe { state_flags() }
+  }
+
+  /// Retrieve the state of keyboard macro handling, i.e., whether a
+  /// keyboard macro is being defined (e.g., after `C-x (`) or executed
+  /// (e.g., via `C-x e`).
+  ///
+  /// Frontends can use this information for displaying a "recording"
+  /// indicator. Note that a macro gets executed in its entirety as part
+  /// of the feed causing its execution, during which no other operation
+  /// can be performed on the instance. Hence, outside of hooks, only
+  /// [`MacroState::Defining`] is ever reported. Redisplay hooks are
+  /// informed about macro playback in progress via
+  /// [`Redisplay::macro_state`].
+  pub fn macro_state(&self) -> Option<MacroState> {
+    let _guard = self.activate();
+    unsafe { MacroState::current() }
+  }
+
+  /// Retrieve the numeric argument the user is entering for the next
+  /// command, if any.
+  ///
+  /// This is, for example, `Some(12)` after the user typed `M-1 2` and
+  /// `Some(-1)` after `M--`. Once the command the argument applies to
+  /// got executed, `None` is reported again.
+  pub fn numeric_arg(&self) -> Option<i32> {
+    let _guard = self.activate();
+    if unsafe { state_flags() }.contains(StateFlags::NUMERICARG) {
+      Some(unsafe { numeric_arg() })
+    } else {
+      None
+    }
+  }
+
+  /// Check whether libreadline is waiting for a key to insert
+  /// verbatim, i.e., after `quoted-insert` (usually bound to `C-v` and
+  /// `C-q`) got executed.
+  ///
+  /// Adapters pre-processing input can use this information for passing
+  /// the next key through untouched, e.g., even if it is a control
+  /// character they would otherwise handle themselves. Renderers may
+  /// want to display an indicator such as `^` at the cursor position.
+  pub fn is_quoted_insert(&self) -> bool {
+    let _guard = self.activate();
+    let quoted_insert: rl_command_func_t = rl_quoted_insert;
+    // In callback mode libreadline remembers that it is waiting for the
+    // key to insert by means of a callback function private to it.
+    // With `quoted-insert` being the last command executed, it must
+    // have been the one installing it.
+    unsafe {
+      !_rl_callback_func.is_null()
+        && matches!(rl_last_func, Some(func) if func as usize == quoted_insert as usize)
+    }
+  }
+
+  /// Retrieve the editing mode libreadline is in.
+  pub fn edit_mode(&self) -> EditMode {
+    self.state.borrow().mode
+  }
+
+  /// Retrieve the string indicating the current editing mode, if
+  /// libreadline's `show-mode-in-prompt` setting is enabled.
+  ///
+  /// The string is configured by the `emacs-mode-string`,
+  /// `vi-ins-mode-string`, and `vi-cmd-mode-string` settings (e.g., in
+  /// the user's `inputrc`) and is meant to be displayed in front of
+  /// the prompt. It may contain invisible characters enclosed in `\1`
+  /// and `\2`, in the same way prompts can, which is why they are left
+  /// in place.
+  pub fn mode_string(&self) -> Option<CString> {
+    let guard = self.activate();
+    let show = unsafe { variable_value(b"show-mode-in-prompt\0") };
+    if matches!(show, Some(show) if show.to_bytes() == b"on") {
+      Some(unsafe { mode_string(guard.state.mode) })
+    } else {
+      None
+    }
+  }
+
+  /// Install a hook to invoke whenever the editing mode changes as a
+  /// result of a [`feed`][Readline::feed], replacing any previously
+  /// installed one.
+  ///
+  /// Frontends can use it to, e.g., switch the shape of the cursor
+  /// between a bar in insert mode and a block in command mode.
+  pub fn set_mode_hook(&self, hook: Option<Box<ModeFn>>) {
+    self.state.borrow_mut().mode_hook = hook.map(Hook)
+  }
+
+  /// Install a hook to invoke whenever libreadline starts reading a new
+  /// line, before the prompt is displayed, replacing any previously
+  /// installed one. This is libreadline's `rl_startup_hook`.
+  ///
+  /// With input being fed, libreadline starts reading a new line right
+  /// after one got accepted, as part of the [`feed`][Readline::feed]
+  /// completing it. The hook may seed the new line or set variables
+  /// through the provided [`LineHookContext`], with the instance being
+  /// active.
+  pub fn set_startup_hook(&self, hook: Option<Box<LineHookFn>>) {
+    self.state.borrow_mut().startup_hook = hook.map(Hook)
+  }
+
+  /// Install a hook to invoke whenever libreadline starts reading a new
+  /// line, after the prompt got displayed and right before the first
+  /// key is read, replacing any previously installed one. This is
+  /// libreadline's `rl_pre_input_hook`.
+  ///
+  /// The hook gets invoked at the same point in time as the one
+  /// installed via [`set_startup_hook`][Readline::set_startup_hook],
+  /// just later.
+  pub fn set_pre_input_hook(&self, hook: Option<Box<LineHookFn>>) {
+    self.state.borrow_mut().pre_input_hook = hook.map(Hook)
+  }
+
+  /// Install a hook to invoke for fed key sequences that are not bound
+  /// to anything, replacing any previously installed one.
+  ///
+  /// Without a hook, libreadline rings the bell for such keys or, for
+  /// unknown escape sequences, may even insert parts of them into the
+  /// line. With a hook installed, such keys are reported to it in raw
+  /// form instead and not processed any further, allowing applications
+  /// to implement their own shortcuts (e.g., for function keys).
+  pub fn set_unbound_hook(&self, hook: Option<Box<UnboundFn>>) {
+    self.state.borrow_mut().unbound_hook = hook.map(Hook)
+  }
+
+  /// Install a filter to consult before a fed key gets inserted into the
+  /// line, replacing any previously installed one.
+  ///
+  /// The filter is invoked for keys bound to `self-insert` only and may
+  /// accept, replace, or reject them. Replacement text is fed in place
+  /// of the key, while an empty replacement is treated as a rejection.
+  /// Rejected keys are reported to the observer, if any (see
+  /// [`LineObserver::input_rejected`]). That allows for, e.g.,
+  /// restricting input to digits.
+  pub fn set_input_filter(&self, filter: Option<Box<InputFilterFn>>) {
+    self.state.borrow_mut().input_filter = filter.map(Hook)
+  }
+
+  /// Install a hook receiving any output libreadline writes to its
+  /// output stream, replacing any previously installed one.
+  ///
+  /// This is a diagnostic aid: this crate attempts to keep libreadline
+  /// from writing to the terminal, but it is not certain that it
+  /// succeeds in all cases. While a hook is installed, libreadline's
+  /// output stream is redirected to an in-memory buffer whenever
+  /// libreadline processes input or otherwise acts on behalf of the
+  /// instance, and any bytes written are reported to the hook instead
+  /// of ending up on the terminal. Output libreadline writes to
+  /// `stderr` directly, such as the audible bell, is not captured. The
+  /// hook is invoked with the instance active and must not call back
+  /// into it.
+  pub fn set_stray_output_hook(&self, hook: Option<Box<StrayOutputFn>>) {
+    self.state.borrow_mut().stray_output_hook = hook.map(Hook)
+  }
+
+  /// Add an abbreviation, replacing any existing one for the same word.
+  ///
+  /// Whenever the abbreviated word is located immediately before the
+  /// cursor and a word break character gets inserted or the line gets
+  /// accepted, the word is replaced with its expansion. The replacement
+  /// forms a single undo group. Abbreviations are not expanded for
+  /// masked lines or in read-only mode.
+  pub fn add_abbreviation<A, E>(&self, abbreviation: A, expansion: E)
+  where
+    A: AsRef<CStr>,
+    E: AsRef<CStr>,
+  {
+    let abbreviation = abbreviation.as_ref().to_bytes().to_vec();
+    let expansion = expansion.as_ref().to_owned();
+    let _prev = self
+      .state
+      .borrow_mut()
+      .abbreviations
+      .insert(abbreviation, expansion);
+  }
+
+  /// Remove an abbreviation.
+  ///
+  /// Returns `true` if the abbreviation existed.
+  pub fn remove_abbreviation<A>(&self, abbreviation: A) -> bool
+  where
+    A: AsRef<CStr>,
+  {
+    let abbreviation = abbreviation.as_ref().to_bytes();
+    self
+      .state
+      .borrow_mut()
+      .abbreviations
+      .remove(abbreviation)
+      .is_some()
+  }
+
+  /// Install a hook deciding whether to expand an abbreviation,
+  /// replacing any previously installed one.
+  ///
+  /// The hook receives the abbreviation and its expansion and returns
+  /// `false` to veto the expansion.
+  pub fn set_abbreviation_hook(&self, hook: Option<Box<AbbreviationFn>>) {
+    self.state.borrow_mut().abbreviation_hook = hook.map(Hook)
+  }
+
+  /// Enable or disable automatic pairing of brackets and quotes.
+  ///
+  /// With auto-pairing enabled, inserting an opening bracket or a quote
+  /// (`(`, `[`, `{`, `"`, `'`) inserts the closing counterpart as well,
+  /// with the cursor placed in between. Inserting a closing character
+  /// right in front of the same one merely moves the cursor over it,
+  /// and deleting the opening character of an empty pair removes the
+  /// pair entirely. Quotes immediately following a letter or digit are
+  /// not paired. Keys are intercepted based on them being bound to
+  /// `self-insert` and `backward-delete-char`, respectively, so that
+  /// custom bindings are honored.
+  pub fn set_auto_pair(&self, enable: bool) {
+    self.state.borrow_mut().auto_pair = enable
+  }
+
+  /// Move the cursor to the start of the grapheme cluster preceding it.
+  ///
+  /// libreadline moves the cursor by characters, which may land it in
+  /// the middle of a grapheme cluster made up of multiple characters
+  /// (e.g., an emoji with a skin tone modifier or a letter followed by
+  /// a combining mark). Lines that are not valid UTF-8 are handled the
+  /// way `backward-char` would.
+  pub fn move_left_grapheme(&self) {
+    let _guard = self.activate();
+    if !unsafe { move_grapheme(false) } {
+      let _result = unsafe { rl_backward_char(1, 0) };
+    }
+  }
+
+  /// Move the cursor to the end of the grapheme cluster at its
+  /// position.
+  ///
+  /// This is the counterpart to
+  /// [`move_left_grapheme`][Readline::move_left_grapheme]. Lines that
+  /// are not valid UTF-8 are handled the way `forward-char` would.
+  pub fn move_right_grapheme(&self) {
+    let _guard = self.activate();
+    if !unsafe { move_grapheme(true) } {
+      let _result = unsafe { rl_forward_char(1, 0) };
+    }
+  }
+
+  /// Enable or disable cursor movement across entire grapheme clusters
+  /// for keys bound to `forward-char` and `backward-char` (e.g., the
+  /// arrow keys), as per
+  /// [`move_right_grapheme`][Readline::move_right_grapheme] and
+  /// [`move_left_grapheme`][Readline::move_left_grapheme]. Keys fed as
+  /// part of a numeric argument or a vi motion are left to
+  /// libreadline.
+  pub fn set_grapheme_movement(&self, enable: bool) {
+    self.state.borrow_mut().grapheme_movement = enable
+  }
+
+  /// Enable or disable read-only mode.
+  ///
+  /// In read-only mode, commands for moving the cursor, navigating and
+  /// searching the history, and copying text work as usual, but any
+  /// modification of the line (e.g., through insertion, deletion,
+  /// killing, or undo) gets reverted. Keys causing a modification are
+  /// reported to the observer, if any (see
+  /// [`LineObserver::input_rejected`]). Lines can still be accepted.
+  /// That is useful for, e.g., viewing history entries or displaying a
+  /// disabled prompt.
+  pub fn set_read_only(&self, read_only: bool) {
+    self.state.borrow_mut().read_only = read_only
+  }
+
+  /// Install an observer to report editing events caused by feeding
+  /// input to, replacing any previously installed one.
+  ///
+  /// Contrary to polling [`last_change`][Readline::last_change] after
+  /// every feed, an observer allows for integration with event driven
+  /// frontends. Text is reported in its masked form if a mask is set.
+  pub fn set_observer(&self, observer: Option<Box<dyn LineObserver>>) {
+    self.state.borrow_mut().observer = observer.map(Hook)
+  }
+
+  /// Set a transient status message, replacing any previously set one.
+  ///
+  /// The message is reported by [`status`][Readline::status] and to the
+  /// redisplay hook, separately from the line, until the next key is
+  /// fed or it is cleared explicitly. Frontends may display it, e.g.,
+  /// below the line being edited.
+  pub fn set_status<S>(&self, status: S)
+  where
+    S: AsRef<CStr>,
+  {
+    self.state.borrow_mut().status = Some(status.as_ref().to_owned())
+  }
+
+  /// Clear the status message set by the application, if any.
+  pub fn clear_status(&self) {
+    self.state.borrow_mut().status = None
+  }
+
+  /// Retrieve the status message to display, if any.
+  ///
+  /// Besides a message set via [`set_status`][Readline::set_status],
+  /// messages describing libreadline's state, such as a numeric
+  /// argument being entered (e.g., `(arg: 4)`), are reported.
+  pub fn status(&self) -> Option<CString> {
+    self.activate().state.status()
+  }
+
+  /// Set the composition (pre-edit) text of an input method, replacing
+  /// any previously set one.
+  ///
+  /// Input methods deliver text being composed separately from the
+  /// text eventually committed. The composition text is not part of
+  /// the line, but is reported by [`preedit`][Readline::preedit] and
+  /// to the redisplay hook, for frontends to display it at the cursor.
+  /// Use [`commit_preedit`][Readline::commit_preedit] to insert the
+  /// composed text into the line.
+  pub fn set_preedit<S>(&self, preedit: S)
+  where
+    S: AsRef<CStr>,
+  {
+    fn set_preedit_impl(rl: &Readline, preedit: &CStr) {
+      let mut guard = rl.activate();
+      guard.state.clear_preedit();
+      if !preedit.to_bytes().is_empty() {
+        guard.state.preedit = Some(preedit.to_owned());
+      }
+      guard.dispatch(|| Readline::redisplay());
+    }
+
+    set_preedit_impl(self, preedit.as_ref())
+  }
+
+  /// Clear the composition text, if any.
+  pub fn clear_preedit(&self) {
+    let mut guard = self.activate();
+    guard.state.clear_preedit();
+    guard.dispatch(|| Readline::redisplay());
+  }
+
+  /// Retrieve the composition text set via
+  /// [`set_preedit`][Readline::set_preedit], if any.
+  ///
+  /// If a [`Mask`] is set, the masked representation is reported.
+  pub fn preedit(&self) -> Option<CString> {
+    self.state.borrow().preedit()
+  }
+
+  /// Clear the composition text and insert the given composed text at
+  /// the cursor as a single, undoable unit.
+  ///
+  /// The text is subject to the input filter (see
+  /// [`set_input_filter`][Readline::set_input_filter]) and read-only
+  /// mode (see [`set_read_only`][Readline::set_read_only]). Returns
+  /// `true` if text got inserted.
+  pub fn commit_preedit<S>(&self, text: S) -> bool
+  where
+    S: AsRef<CStr>,
+  {
+    fn commit_preedit_impl(rl: &Readline, text: &CStr) -> bool {
+      let mut guard = rl.activate();
+      guard.state.clear_preedit();
+
+      let state = &mut *guard.state;
+      let action = match &mut state.input_filter {
+        _ if state.read_only => InputAction::Reject,
+        Some(Hook(filter)) => filter(text.to_bytes()),
+        None => InputAction::Accept,
+      };
+
+      let insert = match action {
+        InputAction::Accept => Some(text.to_owned()),
+        // We cannot insert text containing NUL bytes.
+        InputAction::Replace(text) => CString::new(text).ok(),
+        InputAction::Reject => None,
+      };
+
+      let inserted = match insert {
+        Some(insert) if !insert.as_bytes().is_empty() => {
+          let before = unsafe { Readline::buffer() }.to_vec();
+          let from = unsafe { rl_point } as usize;
+          #[cfg(feature = "zeroize")]
+          let sensitive = state.sensitive;
+          #[cfg(not(feature = "zeroize"))]
+          let sensitive = false;
+          let () = unsafe { reserve_line(before.len() + insert.as_bytes().len(), sensitive) };
+          let _count = unsafe { rl_insert_text(insert.as_ptr()) };
+          let after = unsafe { Readline::buffer() };
+          let to = unsafe { rl_point } as usize;
+
+          let state = &mut *guard.state;
+          let (before, from, after, to) = match state.mask {
+            Some(mask) => {
+              let (before, from) = mask.apply(&before, from);
+              let (after, to) = mask.apply(after, to);
+              (before.into_bytes(), from, after.into_bytes(), to)
+            },
+            None => (before, from, after.to_vec(), to),
+          };
+          state.change = Change::new(&before, from, &after, to);
+          state.dirty = true;
+
+          if let Some(Hook(observer)) = &mut state.observer {
+            let edit = Edit {
+              keyseq: &[],
+              before: &before,
+              from,
+              after: &after,
+              to,
+              history: None,
+              line: None,
+            };
+            let () = edit.notify(observer.as_mut());
+          }
+          true
+        },
+        _ => {
+          if let Some(Hook(observer)) = &mut guard.state.observer {
+            observer.input_rejected(text.to_bytes())
+          }
+          false
+        },
+      };
+
+      guard.dispatch(|| Readline::redisplay());
+      inserted
+    }
+
+    commit_preedit_impl(self, text.as_ref())
+  }
+
+  /// Set the mask to apply to the line, e.g., for password input.
+  ///
+  /// While a mask is set, [`peek`][Readline::peek], the redisplay hook,
+  /// and [`last_change`][Readline::last_change] report the masked
+  /// representation of the line, while the actual line is kept intact
+  /// internally and eventually returned by [`feed`][Readline::feed].
+  /// No highlighting spans or suggestions are reported, killed text is
+  /// neither recorded by the crate nor kept in libreadline's kill ring
+  /// (the entries killed to are emptied instead), and no undo records
+  /// are retained.
+  /// Consider combining a mask with
+  /// [`set_sensitive`][Readline::set_sensitive] (requires the `zeroize`
+  /// feature) for wiping the line from memory as well.
+  ///
+  /// # Panics
+  ///
+  /// Panics if the mask character is NUL.
+  pub fn set_mask(&self, mask: Option<Mask>) {
+    assert!(mask != Some(Mask::Char('\0')), "mask character must not be NUL");
+    self.state.borrow_mut().mask = mask
+  }
+
+  /// Enable or disable fish-style autosuggestions.
+  ///
+  /// When enabled and the cursor is at the end of the line, the most
+  /// recent history entry starting with the current line is used for
+  /// suggesting a completion of it. The suggestion is reported to the
+  /// redisplay hook and by [`suggestion`][Readline::suggestion], for
+  /// frontends to display, typically dimmed after the cursor. It can be
+  /// accepted using [`accept_suggestion`][Readline::accept_suggestion]
+  /// and [`accept_suggestion_word`][Readline::accept_suggestion_word].
+  pub fn set_autosuggestions(&self, enable: bool) {
+    self.state.borrow_mut().autosuggest = enable
+  }
+
+  /// Retrieve the text currently suggested for appending to the line,
+  /// if any.
+  pub fn suggestion(&self) -> Option<CString> {
+    let guard = self.activate();
+    let line = unsafe { CStr::from_ptr(rl_line_buffer) };
+    let cursor = unsafe { rl_point } as usize;
+    guard.state.suggestion(line, cursor).map(CStr::to_owned)
+  }
+
+  /// Append the current suggestion in its entirety to the line.
+  ///
+  /// The edit is reported via [`last_change`][Readline::last_change],
+  /// the observer, and the redisplay hook, just like one caused by a
+  /// feed. Returns `true` if a suggestion was accepted, which is never
+  /// the case in read-only mode (see
+  /// [`set_read_only`][Readline::set_read_only]).
+  pub fn accept_suggestion(&self) -> bool {
+    self.accept_suggestion_with(|suggestion| suggestion)
+  }
+
+  /// Append the first word of the current suggestion to the line.
+  ///
+  /// Please see [`accept_suggestion`][Readline::accept_suggestion] for
+  /// details.
+  pub fn accept_suggestion_word(&self) -> bool {
+    self.accept_suggestion_with(suggest::word)
+  }
+
+  /// Append the part of the current suggestion selected by `select`
+  /// to the line, moving the cursor to the end.
+  fn accept_suggestion_with(&self, select: fn(&[u8]) -> &[u8]) -> bool {
+    let mut guard = self.activate();
+    if guard.state.read_only {
+      return false
+    }
+
+    let line = unsafe { CStr::from_ptr(rl_line_buffer) };
+    let from = unsafe { rl_point } as usize;
+    let text = match guard.state.suggestion(line, from) {
+      // The selected text is a part of a C string and so cannot contain
+      // a NUL byte.
+      Some(suggestion) => CString::new(select(suggestion.to_bytes())).unwrap(),
+      None => return false,
+    };
+
+    let before = unsafe { Readline::buffer() }.to_vec();
+    #[cfg(feature = "zeroize")]
+    let sensitive = guard.state.sensitive;
+    #[cfg(not(feature = "zeroize"))]
+    let sensitive = false;
+    let () = unsafe { reserve_line(before.len() + text.as_bytes().len(), sensitive) };
+    unsafe {
+      let _count = rl_insert_text(text.as_ptr());
+      rl_point = rl_end;
+    }
+    let after = unsafe { Readline::buffer() };
+    let to = unsafe { rl_point } as usize;
+
+    // Suggestions are never made for masked lines, so there is no need
+    // for masking the change.
+    let state = &mut *guard.state;
+    state.change = Change::new(&before, from, after, to);
+    state.dirty = true;
+
+    if let Some(Hook(observer)) = &mut state.observer {
+      let edit = Edit {
+        keyseq: &[],
+        before: &before,
+        from,
+        after,
+        to,
+        history: None,
+        line: None,
+      };
+      let () = edit.notify(observer.as_mut());
+    }
+
+    guard.dispatch(|| Readline::redisplay());
+    true
+  }
+
+  /// Set the prompt to use.
+  ///
+  /// libreadline uses the prompt for some of its column arithmetic,
+  /// e.g., when moving the cursor across line boundaries. As such, it
+  /// should be kept in sync with the prompt actually displayed. Each
+  /// `Readline` instance has its own prompt.
+  ///
+  /// Similar to other libreadline based applications, the prompt may
+  /// contain sequences of invisible characters (e.g., terminal escape
+  /// sequences for colors), which should be enclosed in `\1` and `\2`
+  /// bytes, respectively, in order to not be accounted for as part of
+  /// the prompt's visible width.
+  pub fn set_prompt<S>(&self, prompt: S)
+  where
+    S: AsRef<CStr>,
+  {
+    fn set_prompt_impl(rl: &Readline, prompt: &CStr) {
+      let _guard = rl.activate();
+      let result = unsafe { rl_set_prompt(prompt.as_ptr()) };
+      debug_assert_eq!(result, 0);
+    }
+
+    set_prompt_impl(self, prompt.as_ref())
+  }
+
+  /// Retrieve the prompt for displaying purposes.
+  ///
+  /// The returned prompt has all invisible character markers (`\1` and
+  /// `\2`) removed, but the enclosed characters themselves are left
+  /// in place.
+  pub fn prompt(&self) -> CString {
+    let _guard = self.activate();
+    unsafe { visible_prompt(rl_prompt) }
+  }
+
+  /// Retrieve the prompt as libreadline currently wants it displayed.
+  ///
+  /// This is the prompt set via [`set_prompt`][Readline::set_prompt],
+  /// unless libreadline is in a special state in which it displays a
+  /// composite prompt instead, such as
+  /// `` (reverse-i-search)`foo': `` while searching the history
+  /// incrementally or `(arg: 4) ` while a numeric argument is entered.
+  /// Invisible character markers are removed in the same way as
+  /// [`prompt`][Readline::prompt] does.
+  pub fn display_prompt(&self) -> CString {
+    let _guard = self.activate();
+    unsafe { visible_prompt(rl_display_prompt) }
+  }
+
+  /// Retrieve the visible width of the prompt, in characters.
+  ///
+  /// Invisible characters, as enclosed in `\1` and `\2`, do not count
+  /// towards the width. If the prompt spans multiple lines, the width
+  /// of the last one is reported.
+  pub fn prompt_width(&self) -> usize {
+    let _guard = self.activate();
+    let width = unsafe { rl_expand_prompt(rl_prompt) };
+    debug_assert!(width >= 0);
+    width as _
+  }
+
+  /// Mark this instance as handling sensitive data, such as passwords.
+  ///
+  /// When enabled, the line buffer is wiped once a line got completed,
+  /// as well as on [`reset`][Readline::reset] and when the instance is
+  /// dropped. Furthermore, no undo records are retained, as these may
+  /// contain deleted text, and killed text is not recorded by the
+  /// crate. Note that libreadline's own kill ring is out of our reach
+  /// and kill commands should not be used for sensitive input.
+  ///
+  /// Lines returned by [`feed`][Readline::feed] are owned by the
+  /// caller and not wiped. Use
+  /// [`feed_sensitive`][Readline::feed_sensitive] to have the returned
+  /// copy wiped as well once it is dropped.
+  #[cfg(feature = "zeroize")]
+  pub fn set_sensitive(&self, sensitive: bool) {
+    self.state.borrow_mut().sensitive = sensitive
+  }
+
+  /// Feed a key to libreadline, just like [`feed`][Readline::feed],
+  /// but return the completed line wrapped in [`Zeroizing`], so that it
+  /// gets wiped once it is dropped.
+  #[cfg(feature = "zeroize")]
+  pub fn feed_sensitive(&self, key: impl AsRef<Key>) -> Option<Zeroizing<CString>> {
+    self.feed(key).map(Zeroizing::new)
+  }
+
+  /// Find the bracket or quote matching the one at the cursor.
+  ///
+  /// The character under the cursor is considered first, followed by
+  /// the one before it, similar to how libreadline's
+  /// `blink-matching-paren` reacts to a just inserted closing bracket.
+  /// Text enclosed in quote characters is skipped when looking for
+  /// brackets. On success, the byte positions of the bracket and its
+  /// match are returned, in this order.
+  ///
+  /// No match is reported for masked lines.
+  pub fn matching_bracket(&self) -> Option<(usize, usize)> {
+    let guard = self.activate();
+    if guard.state.mask.is_some() {
+      return None
+    }
+
+    let (line, cursor, quotes) = unsafe {
+      let quotes = if rl_basic_quote_characters.is_null() {
+        &[][..]
+      } else {
+        CStr::from_ptr(rl_basic_quote_characters).to_bytes()
+      };
+      (Readline::buffer(), rl_point as usize, quotes)
+    };
+
+    [Some(cursor), cursor.checked_sub(1)]
+      .iter()
+      .flatten()
+      .find_map(|&position| Some((position, bracket::find(line, position, quotes)?)))
+  }
+
+  /// Retrieve the position of the mark, as a byte index into the line.
+  ///
+  /// The mark is set by commands such as `set-mark` (Ctrl-@) and, along
+  /// with the cursor, delimits the region. `None` is returned for masked
+  /// lines.
+  pub fn mark(&self) -> Option<usize> {
+    let guard = self.activate();
+    if guard.state.mask.is_some() {
+      return None
+    }
+    let len = unsafe { Readline::buffer() }.len();
+    Some((unsafe { rl_mark } as usize).min(len))
+  }
+
+  /// Retrieve the text of the region, i.e., the text between the cursor
+  /// and the mark, in either order.
+  ///
+  /// `None` is returned for masked lines.
+  pub fn region_text(&self) -> Option<CString> {
+    let guard = self.activate();
+    if guard.state.mask.is_some() {
+      return None
+    }
+
+    let (line, point, mark) = unsafe { (Readline::buffer(), rl_point as usize, rl_mark as usize) };
+    let mark = mark.min(line.len());
+    let region = &line[point.min(mark)..point.max(mark)];
+    // The line is a C string and so the region cannot contain a NUL
+    // byte.
+    Some(CString::new(region).unwrap())
+  }
+
+  /// Check whether the region is active, i.e., should be highlighted.
+  ///
+  /// libreadline activates the region as part of commands such as
+  /// `exchange-point-and-mark` or when text got pasted, provided that
+  /// `enable-active-region` is set. With versions of libreadline
+  /// older than 8.1, which do not support an active region, `false` is
+  /// reported.
+  pub fn is_mark_active(&self) -> bool {
+    let _guard = self.activate();
+    #[cfg(readline_8_1)]
+    {
+      unsafe { rl_mark_active_p() != 0 }
+    }
+    #[cfg(not(readline_8_1))]
+    {
+      false
+    }
+  }
+
+  /// Activate or deactivate the region.
+  ///
+  /// An activated region stays active until the next command that does
+  /// not keep it active, i.e., typically until the next key press. With
+  /// versions of libreadline older than 8.1 this method has no effect.
+  pub fn set_mark_active(&self, active: bool) {
+    let _guard = self.activate();
+    #[cfg(readline_8_1)]
+    unsafe {
+      if active {
+        rl_activate_mark();
+        // Activating the mark asks for it to be kept active beyond the
+        // currently executing command, but there is none.
+        _rl_keep_mark_active = 0;
+      } else {
+        rl_deactivate_mark();
+      }
+    }
+    #[cfg(not(readline_8_1))]
+    {
+      let _active = active;
+    }
+  }
+
+  /// Clear the line the cursor is on from the terminal, along with any
+  /// additional screen lines libreadline displayed the line being
+  /// edited on, leaving the cursor at the start of the line.
+  ///
+  /// This is useful for emitting output of an application's own while
+  /// a line is being edited, with the line getting redisplayed
+  /// afterwards. Output is written to libreadline's output stream,
+  /// using the capabilities of the configured terminal. With versions
+  /// of libreadline older than 7.0 this method has no effect.
+  pub fn clear_visible_line(&self) {
+    let _guard = self.activate();
+    #[cfg(readline_7)]
+    unsafe {
+      let _result = rl_clear_visible_line();
+      let _result = fflush(_rl_out_stream);
+    }
+  }
+
+  /// Retrieve the word under or immediately before the cursor, along
+  /// with its byte range in the line.
+  ///
+  /// Words are delimited by libreadline's completer word break
+  /// characters, falling back to the basic word break characters if
+  /// none are set. `None` is returned if there is no word at the
+  /// cursor or if a mask is set.
+  pub fn word_at_cursor(&self) -> Option<(CString, Range<usize>)> {
+    let guard = self.activate();
+    if guard.state.mask.is_some() {
+      return None
+    }
+
+    let (line, cursor, breaks) =
+      unsafe { (Readline::buffer(), rl_point as usize, word_breaks()) };
+
+    let range = word::find(line, cursor, breaks)?;
+    // The line is a C string and so the word cannot contain a NUL byte.
+    let word = CString::new(&line[range.clone()]).unwrap();
+    Some((word, range))
+  }
+
+  /// Peek at the current line state through a closure.
+  ///
+  /// If a [`Mask`] is set, the masked representation of the line is
+  /// reported.
+  pub fn peek<F, R>(&self, peeker: F) -> R
+  where
+    F: FnOnce(&CStr, usize) -> R,
+  {
+    let guard = self.activate();
+    let (s, pos, len) = unsafe {
+      debug_assert!(rl_end >= 0);
+      debug_assert!(rl_point >= 0);
+
+      let buf = rl_line_buffer;
+      let len = rl_end as usize;
+      let pos = rl_point as _;
+
+      (CStr::from_ptr(buf), pos, len)
+    };
+
+    debug_assert_eq!(s.to_bytes().len(), len);
+    match guard.state.mask {
+      Some(mask) => {
+        let (s, pos) = mask.apply(s.to_bytes(), pos);
+        peeker(&s, pos)
+      },
+      None => peeker(s, pos),
+    }
+  }
+
+  /// Peek at the current line state through a closure, with the line
+  /// converted to a `&str`.
+  ///
+  /// The closure receives an error if the line is not valid UTF-8.
+  pub fn peek_str<F, R>(&self, peeker: F) -> R
+  where
+    F: FnOnce(Result<&str, Utf8Error>, usize) -> R,
+  {
+    self.peek(|s, pos| peeker(s.to_str(), pos))
+  }
+
+  /// Peek at the current line state, along with the highlighting
+  /// spans produced by the installed highlighter, through a closure.
+  pub fn peek_highlighted<F, R>(&self, peeker: F) -> R
+  where
+    F: FnOnce(&CStr, usize, &[Span]) -> R,
+  {
+    let mut guard = self.activate();
+    let (s, pos) = unsafe {
+      debug_assert!(rl_point >= 0);
+      (CStr::from_ptr(rl_line_buffer), rl_point as usize)
+    };
+
+    let spans = guard.state.highlight(s);
+    match guard.state.mask {
+      Some(mask) => {
+        let (s, pos) = mask.apply(s.to_bytes(), pos);
+        peeker(&s, pos, &spans)
+      },
+      None => peeker(s, pos, &spans),
+    }
+  }
+}
+
+impl Debug for Readline {
+  /// Format the instance, including its label, whether it is active,
+  /// and its (possibly masked) cursor position as well as, if enabled
+  /// via [`set_debug_line`][Readline::set_debug_line], the line.
+  ///
+  /// Formatting neither blocks nor activates the instance: the cursor
+  /// and the line are only included if the instance is active and the
+  /// global mutex is available, which is not the case from within a
+  /// hook, for example.
+  fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+    let mut debug = f.debug_struct("Readline");
+    let _debug = debug.field("id", &self.id);
+
+    let (label, debug_line, mask) = match self.state.try_borrow() {
+      Ok(state) => (state.label.clone(), state.debug_line, state.mask),
+      Err(..) => return debug.finish(),
+    };
+    let _debug = debug.field("label", &label);
+
+    let guard = match Self::mutex().try_lock() {
+      Ok(guard) => guard,
+      Err(..) => return debug.finish(),
+    };
+    let active = *guard == self.id;
+    let _debug = debug.field("active", &active);
+
+    // The line and cursor of an inactive instance are kept in its
+    // saved `readline_state`, which we treat as opaque.
+    if !active {
+      return debug.finish()
+    }
+
+    let (line, cursor) = unsafe {
+      debug_assert!(rl_point >= 0);
+      (CStr::from_ptr(rl_line_buffer), rl_point as usize)
+    };
+    let (line, cursor) = match mask {
+      Some(mask) => mask.apply(line.to_bytes(), cursor),
+      None => (line.to_owned(), cursor),
+    };
+    let _debug = debug.field("cursor", &cursor);
+
+    if debug_line {
+      let _debug = debug.field("line", &line);
+    }
+    debug.finish()
+  }
+}
+
+impl Default for Readline {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Drop for Readline {
+  fn drop(&mut self) {
+    // Errors can only be reported by closing the instance explicitly.
+    let _result = self.teardown();
+  }
+}
+
+
+/// Release the global resources held by libreadline and this crate, so
+/// that leak checkers such as Valgrind or AddressSanitizer do not
+/// report them on exit.
+///
+/// This function is meant to be called right before exiting, once all
+/// [`Readline`] instances got dropped. It removes the callback handler,
+/// frees the state cached as template for new instances as well as
+/// keymaps and macros allocated for key bindings. Afterwards, no new
+/// instances can be created. Calling it more than once has no effect.
+///
+/// Note that some of libreadline's allocations, e.g., for terminal
+/// capabilities looked up on initialization, cannot be released.
+///
+/// # Panics
+///
+/// Panics if a `Readline` instance is still alive.
+pub fn shutdown() {
+  if SHUTDOWN.swap(true, Ordering::SeqCst) {
+    return
+  }
+
+  if INSTANCES.load(Ordering::SeqCst) != 0 {
+    SHUTDOWN.store(false, Ordering::SeqCst);
+    panic!("Readline instances are still alive");
+  }
+
+  let _guard = Readline::mutex().lock().unwrap();
+  if INITIALIZE.is_completed() {
+    unsafe {
+      // Make sure that libreadline's globals no longer refer to the
+      // state of any of the dropped instances.
+      keymap::switch(None);
+      Readline::initial().save();
+
+      keymap::discard();
+      rl_callback_handler_remove();
+      // Clear the cached expansion of the prompt.
+      let _width = rl_expand_prompt(null_mut());
+      let _line = Readline::line().take();
+    }
+  }
+}
+
+
+/// Retrieve a copy of the given prompt, with all invisible character
+/// markers removed.
+///
+/// # Safety
+/// `prompt` has to be null or point to a valid NUL terminated string.
+unsafe fn visible_prompt(prompt: *const c_char) -> CString {
+  if prompt.is_null() {
+    CString::default()
+  } else {
+    let prompt = unsafe { CStr::from_ptr(prompt) }.to_bytes();
+    let visible = prompt
+      .iter()
+      .copied()
+      .filter(|b| *b != PROMPT_START_IGNORE && *b != PROMPT_END_IGNORE)
+      .collect::<Vec<_>>();
+    // The bytes originate from a C string and so cannot contain a NUL
+    // byte.
+    CString::new(visible).unwrap()
+  }
+}
+
+/// Overwrite the given NUL terminated string with zeroes.
+///
+/// # Safety
+/// `s` has to be null or point to a valid NUL terminated string.
+#[cfg(feature = "zeroize")]
+unsafe fn wipe_str(s: *mut c_char) {
+  if !s.is_null() {
+    let len = unsafe { CStr::from_ptr(s) }.to_bytes().len();
+    unsafe { slice::from_raw_parts_mut(s.cast::<u8>(), len) }.zeroize()
+  }
+}
+
+/// Make sure that libreadline's line buffer can hold a line of `len`
+/// bytes, plus the terminating NUL byte, without reallocation.
+///
+/// libreadline grows the buffer on its own as text gets inserted, but
+/// only in small increments, which is slow for very long lines, and
+/// without wiping the memory it releases. We grow it geometrically
+/// instead and overwrite the old buffer with zeroes before releasing it
+/// if `wipe` is set.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held.
+unsafe fn reserve_line(len: usize, wipe: bool) {
+  unsafe {
+    let capacity = rl_line_buffer_len as usize;
+    // Lines this long are beyond what libreadline can handle anyway.
+    if len < capacity || len >= c_int::MAX as usize {
+      return
+    }
+
+    let capacity = capacity.saturating_mul(2).max(len + 1).min(c_int::MAX as usize);
+    let buffer = calloc(1, capacity).cast::<c_char>();
+    assert!(!buffer.is_null(), "failed to allocate rl_line_buffer");
+
+    if !rl_line_buffer.is_null() {
+      let old = slice::from_raw_parts_mut(rl_line_buffer.cast::<u8>(), rl_line_buffer_len as _);
+      let () = ptr::copy_nonoverlapping(old.as_ptr(), buffer.cast::<u8>(), old.len());
+      #[cfg(feature = "zeroize")]
+      {
+        if wipe {
+          old.zeroize()
+        }
+      }
+      free(rl_line_buffer.cast());
+    }
+    #[cfg(not(feature = "zeroize"))]
+    let _wipe = wipe;
+
+    rl_line_buffer = buffer;
+    rl_line_buffer_len = capacity as c_int;
+    // libreadline aliases the line buffer internally with a pointer,
+    // which extending the buffer updates. The buffer is large enough
+    // already, so that is all this call does.
+    rl_extend_line_buffer(0);
+  }
+}
+
+/// Overwrite libreadline's entire line buffer with zeroes.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held.
+#[cfg(feature = "zeroize")]
+unsafe fn wipe_line_buffer() {
+  unsafe {
+    if !rl_line_buffer.is_null() {
+      slice::from_raw_parts_mut(rl_line_buffer.cast::<u8>(), rl_line_buffer_len as _).zeroize()
+    }
+  }
+}
+
+
+/// The line of an instance, as transferred to another one.
+struct LineState {
+  /// The text of the line, NUL terminated.
+  text: Vec<u8>,
+  /// The cursor position.
+  cursor: usize,
+  /// The undo list belonging to the line, if it is transferred.
+  undo: *mut UNDO_LIST,
+  /// Whether the line got modified since it was last reset.
+  dirty: bool,
+}
+
+impl LineState {
+  /// Take the line of the active instance, leaving its undo list
+  /// empty. The undo list is retained if `undo` is `true` and released
+  /// otherwise.
+  ///
+  /// # Safety
+  /// Callers must ensure that the global mutex is held.
+  unsafe fn take(state: &mut State, undo: bool) -> Self {
+    let mut text = unsafe { Readline::buffer() }.to_vec();
+    text.push(b'\0');
+
+    let undo = if undo {
+      let undo = unsafe { replace(&mut *addr_of_mut!(rl_undo_list), null_mut()) };
+      // The list may be referenced by the instance's history, which is
+      // not transferred along with it.
+      let () = unsafe { undo::detach(undo) };
+      undo
+    } else {
+      #[cfg(feature = "zeroize")]
+      {
+        if state.sensitive {
+          unsafe { undo::wipe(rl_undo_list) }
+        }
+      }
+      unsafe { rl_free_undo_list() };
+      null_mut()
+    };
+
+    Self {
+      text,
+      cursor: unsafe { rl_point } as usize,
+      undo,
+      dirty: state.dirty,
+    }
+  }
+
+  /// Make this line the one of the active instance, releasing the
+  /// instance's undo list.
+  ///
+  /// # Safety
+  /// Callers must ensure that the global mutex is held and that the
+  /// undo list was taken as part of [`take`][LineState::take].
+  unsafe fn put(self, state: &mut State) {
+    #[cfg(feature = "zeroize")]
+    {
+      if state.sensitive {
+        unsafe {
+          undo::wipe(rl_undo_list);
+          wipe_line_buffer();
+        }
+      }
+    }
+
+    unsafe {
+      rl_replace_line(self.text.as_ptr().cast(), 1);
+      rl_point = self.cursor as _;
+      rl_undo_list = self.undo;
+    }
+    state.dirty = self.dirty;
+
+    #[cfg(feature = "zeroize")]
+    {
+      let mut text = self.text;
+      text.zeroize();
+    }
+  }
+}
+
+
+// Note that libreadline is pretty much fully configurable. With
+// specific configurations it is possible that some tests fail (although
+// we mostly use functionality that is pretty basic and unlikely to have
+// been reconfigured by the user). While it would be possible to
+// override the user configuration for the purpose of testing, that is
+// not trivial and out of the scope of this crate.
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::env::temp_dir;
+  use std::fs::create_dir;
+  use std::fs::read_to_string;
+  use std::fs::remove_dir;
+  use std::fs::remove_file;
+  use std::fs::write;
+  use std::mem::align_of;
+  use std::process;
+  use std::sync::Arc;
+  use std::time::Duration;
+  use std::time::UNIX_EPOCH;
+
+
+  /// Exercise the `Debug` representation of various types.
+  #[test]
+  fn debug_repr() {
+    assert_ne!(format!("{:?}", T(())), "");
+
+    let rl = Readline::default();
+    assert_ne!(format!("{:?}", rl), "");
+  }
+
+  /// Check that the `Debug` representation of a `Readline` instance
+  /// contains the expected information.
+  #[test]
+  fn debug_instance() {
+    let rl = Readline::new();
+    rl.set_label(Some("search-bar"));
+    assert_eq!(rl.label().as_deref(), Some("search-bar"));
+    assert_eq!(rl.feed(b"secret\x02"), None);
+
+    // Other tests may activate their instances concurrently, in which
+    // case the cursor is not reported.
+    let debug = format!("{:?}", rl);
+    assert!(debug.contains("label: Some(\"search-bar\")"), "{}", debug);
+    if debug.contains("active: true") {
+      assert!(debug.contains("cursor: 5"), "{}", debug);
+    } else {
+      assert!(!debug.contains("cursor"), "{}", debug);
+    }
+    assert!(!debug.contains("secret"), "{}", debug);
+
+    let () = rl.set_debug_line(true);
+    let debug = format!("{:?}", rl);
+    if debug.contains("active: true") {
+      assert!(debug.contains("line: \"secret\""), "{}", debug);
+    }
+
+    rl.set_label(None::<String>);
+    let debug = format!("{:?}", rl);
+    assert!(debug.contains("label: None"), "{}", debug);
+
+    // Formatting an inactive instance does not activate it.
+    let rl2 = Readline::new();
+    let _line = rl.peek(|s, _| s.to_owned());
+    let debug = format!("{:?}", rl2);
+    assert!(debug.contains("active: false"), "{}", debug);
+    assert!(!debug.contains("cursor"), "{}", debug);
+    assert!(!rl2.is_active());
+  }
+
+  #[test]
+  fn is_locked() {
+    let mutex = Mutex::<u64>::new(42);
+    assert!(!mutex.is_locked());
+    {
+      let _guard = mutex.lock().unwrap();
+      assert!(mutex.is_locked());
+    }
+    assert!(!mutex.is_locked());
+  }
+
+  #[test]
+  fn alignment() {
+    assert_eq!(align_of::<readline_state>(), 8);
+  }
+
+  #[test]
+  fn empty_input() {
+    let rl = Readline::new();
+
+    assert!(rl.feed(b"").is_none())
+  }
+
+  #[test]
+  fn empty_line_input() {
+    let rl = Readline::new();
+
+    assert_eq!(rl.feed(b"\n").unwrap(), CString::new("").unwrap())
+  }
+
+  #[test]
+  fn multiple_inputs() {
+    let rl = Readline::new();
+
+    assert!(rl.feed(b"first").is_none());
+    assert_eq!(rl.feed(b"\n").unwrap(), CString::new("first").unwrap());
+
+    assert!(rl.feed(b"second").is_none());
+    assert_eq!(rl.feed(b"\n").unwrap(), CString::new("second").unwrap());
+  }
+
+  #[test]
+  fn cursor() {
+    let rl = Readline::new();
+
+    assert_eq!(rl.feed(b"a"), None);
+    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("a").unwrap(), 1));
+
+    assert_eq!(rl.feed(b"b"), None);
+    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("ab").unwrap(), 2));
+
+    assert_eq!(rl.feed(b"c"), None);
+    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("abc").unwrap(), 3));
+  }
+
+  #[test]
+  fn reset() {
+    let rl = Readline::new();
+
+    assert_eq!(rl.feed(b"xyz"), None);
+    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("xyz").unwrap(), 3));
+
+    rl.reset(CString::new("abc").unwrap(), 1, true);
+    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("abc").unwrap(), 1));
+
+    assert_eq!(rl.feed(b"x"), None);
+    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("axbc").unwrap(), 2));
+    assert_eq!(rl.feed(b"\n").unwrap(), CString::new("axbc").unwrap());
+
+    rl.reset(CString::new("123").unwrap(), 3, true);
+    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("123").unwrap(), 3));
+
+    assert_eq!(rl.feed(b"y"), None);
+    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("123y").unwrap(), 4));
+  }
+
+  /// Check that we can create an instance with an initial line.
+  #[test]
+  fn with_line() {
+    let rl = Readline::with_line(CString::new("abc").unwrap(), 1);
+    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("abc").unwrap(), 1));
+
+    assert_eq!(rl.feed(b"x"), None);
+    assert_eq!(rl.feed(b"\n").unwrap(), CString::new("axbc").unwrap());
+  }
+
+  /// Check that we panic when creating an instance with an invalid
+  /// cursor position.
+  #[test]
+  #[should_panic(expected = "invalid cursor position")]
+  fn with_line_panic() {
+    let _rl = Readline::with_line(CString::new("abc").unwrap(), 4);
+  }
+
+  /// Make sure that we can mix usage of different `Readline` instances.
+  #[test]
+  fn multi_instance() {
+    let rl1 = Readline::new();
+    assert_eq!(rl1.feed(b"abcdefg"), None);
+
+    let rl2 = Readline::new();
+    assert_eq!(rl2.feed(b"efghijl"), None);
+
+    rl1.reset(CString::new("abc").unwrap(), 1, false);
+
+    assert_eq!(rl1.feed(b"\n").unwrap(), CString::new("abc").unwrap());
+    assert_eq!(rl2.feed(b"\n").unwrap(), CString::new("efghijl").unwrap());
+  }
+
+  /// Check that data can be associated with history entries.
+  #[test]
+  fn history_data() {
+    let rl = Readline::new();
+    rl.add_history_with_data(CString::new("true").unwrap(), 0i32);
+    rl.add_history(CString::new("ls").unwrap());
+    rl.add_history_with_data(CString::new("false").unwrap(), 1i32);
+
+    let entries = rl.history(|entries| {
+      entries
+        .iter()
+        .map(|entry| (entry.line().to_owned(), entry.data::<i32>().copied()))
+        .collect::<Vec<_>>()
+    });
+    let expected = vec![
+      (CString::new("true").unwrap(), Some(0)),
+      (CString::new("ls").unwrap(), None),
+      (CString::new("false").unwrap(), Some(1)),
+    ];
+    assert_eq!(entries, expected);
+    // Data of a different type is not reported.
+    assert!(rl.history(|entries| entries[0].data::<u32>().is_none()));
+
+    // Data is evicted along with its entry.
+    rl.set_history_limit(Limit {
+      entries: Some(2),
+      bytes: None,
+    });
+    let data = rl.history(|entries| {
+      entries
+        .iter()
+        .map(|entry| entry.data::<i32>().copied())
+        .collect::<Vec<_>>()
+    });
+    assert_eq!(data, vec![None, Some(1)]);
+  }
+
+  /// Check that multiple history sources can be merged into an
+  /// instance's history.
+  #[test]
+  fn history_merge() {
+    let path = temp_dir().join(format!("rline-history-merge-{}", process::id()));
+    let () = write(&path, "#100\nmake\n#300\nmake test\n").unwrap();
+
+    let rl = Readline::new();
+    rl.add_history(CString::new("ls").unwrap());
+    let time = UNIX_EPOCH + Duration::from_secs(200);
+    let entries = vec![(CString::new("cargo build").unwrap(), Some(time))];
+    let count = HistoryMerge::new()
+      .source(HistorySource::File(path.clone()))
+      .source(HistorySource::Entries(entries))
+      .order(MergeOrder::Chronological)
+      .merge_into(&rl)
+      .unwrap();
+    let () = remove_file(&path).unwrap();
+    assert_eq!(count, 3);
+
+    let lines = rl.history(|entries| {
+      entries
+        .iter()
+        .map(|entry| entry.line().to_str().unwrap().to_string())
+        .collect::<Vec<_>>()
+    });
+    assert_eq!(lines, vec!["ls", "make", "cargo build", "make test"]);
+  }
+
+  /// Check that histories of instances sharing a history file are
+  /// synchronized.
+  #[test]
+  fn history_sync() {
+    let path = temp_dir().join(format!("rline-history-sync-{}", process::id()));
+    let () = write(&path, "first\n").unwrap();
+    let lines = |rl: &Readline| {
+      rl.history(|entries| {
+        entries
+          .iter()
+          .map(|entry| entry.line().to_str().unwrap().to_string())
+          .collect::<Vec<_>>()
+      })
+    };
+
+    let rl1 = Readline::new();
+    let rl2 = Readline::new();
+    let () = rl1.set_history_sync(Some(&path)).unwrap();
+    let () = rl2.set_history_sync(Some(&path)).unwrap();
+    assert_eq!(lines(&rl1), vec!["first"]);
+
+    rl1.add_history(CString::new("second").unwrap());
+    rl2.add_history(CString::new("third").unwrap());
+    assert_eq!(read_to_string(&path).unwrap(), "first\nsecond\nthird\n");
+
+    assert_eq!(rl1.sync_history().unwrap(), 1);
+    assert_eq!(rl1.sync_history().unwrap(), 0);
+    assert_eq!(lines(&rl1), vec!["first", "second", "third"]);
+    assert_eq!(rl2.sync_history().unwrap(), 1);
+    assert_eq!(lines(&rl2), vec!["first", "third", "second"]);
+
+    let () = rl1.set_history_sync(None::<&Path>).unwrap();
+    rl1.add_history(CString::new("fourth").unwrap());
+    assert_eq!(rl1.sync_history().unwrap(), 0);
+    assert_eq!(rl2.sync_history().unwrap(), 0);
+    let () = remove_file(&path).unwrap();
+  }
+
+  /// Check that closing an instance reports failure to append history
+  /// lines to the synchronized history file.
+  #[test]
+  fn close() {
+    let rl = Readline::new();
+    assert_eq!(rl.feed(b"abc"), None);
+    let () = rl.close().unwrap();
+
+    let dir = temp_dir().join(format!("rline-close-{}", process::id()));
+    let () = create_dir(&dir).unwrap();
+    let path = dir.join("history");
+
+    let rl = Readline::new();
+    let () = rl.set_history_sync(Some(&path)).unwrap();
+    let () = remove_dir(&dir).unwrap();
+    rl.add_history(CString::new("first").unwrap());
+    let err = rl.close().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+  }
+
+  /// Check that `operate-and-get-next` loads the history entry following
+  /// the accepted one and keeps it current.
+  #[cfg(readline_8_1)]
+  #[test]
+  fn operate_and_get_next() {
+    let rl = Readline::new();
+    let line = |rl: &Readline| rl.peek(|s, _| s.to_str().unwrap().to_string());
+    let accept = |rl: &Readline| {
+      let line = rl.feed(b"\x0f").unwrap();
+      rl.add_history(&line);
+      line
+    };
+
+    rl.add_history(CString::new("first").unwrap());
+    rl.add_history(CString::new("second").unwrap());
+    rl.add_history(CString::new("third").unwrap());
+
+    assert_eq!(rl.feed(b"\x10"), None);
+    assert_eq!(rl.feed(b"\x10"), None);
+    assert_eq!(rl.feed(b"\x10"), None);
+    assert_eq!(accept(&rl), CString::new("first").unwrap());
+    assert_eq!(line(&rl), "second");
+    assert_eq!(accept(&rl), CString::new("second").unwrap());
+    assert_eq!(line(&rl), "third");
+    // Navigation continues from the loaded entry.
+    assert_eq!(rl.feed(b"\x10"), None);
+    assert_eq!(line(&rl), "second");
+    assert_eq!(rl.feed(b"\x0e"), None);
+    assert_eq!(rl.feed(b"\x0e"), None);
+    assert_eq!(line(&rl), "first");
+
+    // Accepting the most recent entry loads the copy just added.
+    assert_eq!(rl.feed(b"\x0e"), None);
+    assert_eq!(line(&rl), "second");
+    assert_eq!(accept(&rl), CString::new("second").unwrap());
+    assert_eq!(line(&rl), "second");
+    assert_eq!(rl.feed(b"\x0e"), None);
+    assert_eq!(line(&rl), "");
+    assert_eq!(rl.history_usage().entries, 6);
+
+    // A new line is accepted as usual.
+    assert_eq!(rl.feed(b"new"), None);
+    assert_eq!(accept(&rl), CString::new("new").unwrap());
+    assert_eq!(line(&rl), "");
+    assert_eq!(rl.feed(b"\x10"), None);
+    assert_eq!(line(&rl), "new");
+
+    // Accepting without `operate-and-get-next` resets the history
+    // position.
+    assert_eq!(rl.feed(b"\x10"), None);
+    assert_eq!(rl.feed(b"\x10"), None);
+    assert_eq!(rl.feed(b"\r").unwrap(), CString::new("second").unwrap());
+    rl.add_history(CString::new("second").unwrap());
+    assert_eq!(line(&rl), "");
+    assert_eq!(rl.feed(b"\x10"), None);
+    assert_eq!(line(&rl), "second");
+  }
+
+  /// Check that history is kept per instance and that history limits
+  /// are honored.
+  #[test]
+  fn history_limit() {
+    let rl1 = Readline::new();
+    let rl2 = Readline::new();
+
+    rl1.add_history(CString::new("first").unwrap());
+    rl1.add_history(CString::new("second").unwrap());
+    rl1.add_history(CString::new("third").unwrap());
+    assert_eq!(rl1.history_usage(), Usage { entries: 3, bytes: 16 });
+    assert_eq!(rl2.history_usage(), Usage::default());
+
+    rl1.set_history_limit(Limit {
+      entries: Some(2),
+      bytes: None,
+    });
+    assert_eq!(rl1.history_usage(), Usage { entries: 2, bytes: 11 });
+
+    rl1.set_history_limit(Limit {
+      entries: None,
+      bytes: Some(5),
+    });
+    assert_eq!(rl1.history_usage(), Usage { entries: 1, bytes: 5 });
+
+    // "Up" should recall the most recent entry of the respective
+    // instance only.
+    assert_eq!(rl1.feed(b"\x1b[A"), None);
+    assert_eq!(rl1.feed(b"\n").unwrap(), CString::new("third").unwrap());
+    assert_eq!(rl2.feed(b"\x1b[A"), None);
+    assert_eq!(rl2.feed(b"\n").unwrap(), CString::new("").unwrap());
+  }
+
+  /// Check that killed text is accounted for.
+  #[test]
+  fn kill_ring_usage() {
+    let rl = Readline::new();
+
+    // Ctrl-U kills the text before the cursor.
+    assert_eq!(rl.feed(b"abc"), None);
+    assert_eq!(rl.feed(b"\x15"), None);
+    assert_eq!(rl.feed(b"defg"), None);
+    assert_eq!(rl.feed(b"\n").unwrap(), CString::new("defg").unwrap());
+    assert_eq!(rl.kill_ring_usage(), Usage { entries: 1, bytes: 3 });
+
+    assert_eq!(rl.feed(b"hi"), None);
+    assert_eq!(rl.feed(b"\x15"), None);
+    assert_eq!(rl.kill_ring_usage(), Usage { entries: 2, bytes: 5 });
+    assert_eq!(rl.feed(b"\n").unwrap(), CString::new("").unwrap());
+
+    // Only as many entries as libreadline retains are kept.
+    for _ in 0..12 {
+      assert_eq!(rl.feed(b"jk"), None);
+      assert_eq!(rl.feed(b"\x15"), None);
+      assert_eq!(rl.feed(b"\n").unwrap(), CString::new("").unwrap());
+    }
+    assert_eq!(rl.kill_ring_usage(), Usage { entries: 10, bytes: 20 });
+  }
+
+  /// Check that the kill ring can be limited.
+  #[test]
+  fn kill_ring_limit() {
+    // libreadline's kill ring is only ours if we are isolated.
+    let rl = Readline::new();
+    rl.set_isolated(true);
+
+    for text in [&b"ab"[..], b"cd", b"ef"].iter() {
+      assert_eq!(rl.feed(text), None);
+      assert_eq!(rl.feed(b"\x15"), None);
+    }
+    assert_eq!(rl.kill_ring_usage(), Usage { entries: 3, bytes: 6 });
+
+    rl.set_kill_ring_limit(Limit {
+      entries: Some(2),
+      bytes: None,
+    });
+    assert_eq!(rl.kill_ring_usage(), Usage { entries: 2, bytes: 4 });
+
+    // The evicted entry is gone from libreadline's kill ring as well.
+    assert_eq!(rl.feed(b"\x19"), None);
+    assert_eq!(rl.feed(b"\x1by"), None);
+    assert_eq!(rl.peek(|s, _| s.to_owned()), CString::new("cd").unwrap());
+    assert_eq!(rl.feed(b"\x1by"), None);
+    assert_eq!(rl.peek(|s, _| s.to_owned()), CString::new("").unwrap());
+    assert_eq!(rl.feed(b"\n").unwrap(), CString::new("").unwrap());
+
+    rl.set_kill_ring_limit(Limit {
+      entries: None,
+      bytes: Some(5),
+    });
+    assert_eq!(rl.feed(b"ghi"), None);
+    assert_eq!(rl.feed(b"\x15"), None);
+    assert_eq!(rl.kill_ring_usage(), Usage { entries: 2, bytes: 5 });
+
+    // Consecutive kills are still merged after eviction.
+    assert_eq!(rl.feed(b"jk lm"), None);
+    assert_eq!(rl.feed(b"\x17"), None);
+    assert_eq!(rl.feed(b"\x17"), None);
+    assert_eq!(rl.kill_ring_usage(), Usage { entries: 1, bytes: 5 });
+    assert_eq!(rl.feed(b"\x19"), None);
+    assert_eq!(rl.peek(|s, _| s.to_owned()), CString::new("jk lm").unwrap());
+    assert_eq!(rl.feed(b"\x1by"), None);
+    assert_eq!(rl.peek(|s, _| s.to_owned()), CString::new("").unwrap());
+  }
+
+  /// Check that consecutive kills are merged in the same way as
+  /// libreadline merges them.
+  #[test]
+  fn kill_merging() {
+    let rl = Readline::new();
+
+    // Ctrl-W kills the word before the cursor, with consecutive kills
+    // getting merged.
+    assert_eq!(rl.feed(b"foo bar baz"), None);
+    assert_eq!(rl.feed(b"\x17"), None);
+    assert_eq!(rl.feed(b"\x17"), None);
+    assert_eq!(rl.feed(b"\x17"), None);
+    assert_eq!(rl.register('"'), Some(CString::new("foo bar baz").unwrap()));
+    // Ctrl-Y yanks the text libreadline recorded.
+    assert_eq!(rl.feed(b"\x19"), None);
+    assert_eq!(rl.feed(b"\n").unwrap(), CString::new("foo bar baz").unwrap());
+
+    // M-d kills the word after the cursor. libreadline processes the
+    // Escape byte as a command of its own, though, so kills bound to
+    // meta keys do not get merged.
+    assert_eq!(rl.feed(b"foo bar"), None);
+    assert_eq!(rl.feed(b"\x01"), None);
+    assert_eq!(rl.feed(b"\x1bd"), None);
+    assert_eq!(rl.feed(b"\x1bd"), None);
+    assert_eq!(rl.register('"'), Some(CString::new(" bar").unwrap()));
+    assert_eq!(rl.feed(b"\x19"), None);
+    assert_eq!(rl.feed(b"\n").unwrap(), CString::new(" bar").unwrap());
+  }
+
+
+  /// Check that lines can be read from a byte source in a blocking
+  /// fashion.
+  #[test]
+  fn read_line() {
+    let rl = Readline::new();
+    rl.set_eof_char(Some(b'\x04'));
+    let mut input = b"ab\x1b[Dc\rdef".iter().copied();
+    let mut source = || {
+      input
+        .next()
+        .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))
+    };
+
+    let prompt = CString::new("> ").unwrap();
+    let line = rl.read_line(&prompt, &mut source).unwrap();
+    assert_eq!(line, Some(CString::new("acb").unwrap()));
+    assert_eq!(rl.prompt(), prompt);
+
+    let line = rl.read_line(&prompt, &mut source).unwrap();
+    assert_eq!(line, None);
+    assert_eq!(rl.peek(|text, _| text.to_owned()), CString::new("def").unwrap());
+
+    let err = rl
+      .read_line(&prompt, || Err(io::Error::from(io::ErrorKind::BrokenPipe)))
+      .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+
+    // The end-of-file character only signals the end of input on an
+    // empty line.
+    let mut input = b"\x01\x04\r".iter().copied();
+    let line = rl.read_line(&prompt, || Ok(input.next().unwrap())).unwrap();
+    assert_eq!(line, Some(CString::new("ef").unwrap()));
+  }
+
+  /// Check that the memory consumed by history and kill ring is
+  /// reported.
+  #[test]
+  fn memory_usage() {
+    let rl = Readline::new();
+    assert_eq!(rl.memory_usage(), MemoryUsage::default());
+
+    rl.add_history(CString::new("abc").unwrap());
+    let history = rl.memory_usage().history;
+    assert!(history > 4, "{}", history);
+    rl.add_history_with_data(CString::new("abc").unwrap(), [0u8; 64]);
+    assert!(rl.memory_usage().history > history + 64);
+
+    assert_eq!(rl.feed(b"abc"), None);
+    assert_eq!(rl.feed(b"\x15"), None);
+    let usage = rl.memory_usage();
+    assert!(usage.kill_ring > 4, "{:?}", usage);
+    assert_eq!(usage.total(), usage.history + usage.kill_ring);
+  }
+
+  /// Check that sensitive instances do not retain deleted text.
+  #[cfg(feature = "zeroize")]
+  #[test]
+  fn sensitive() {
+    let rl = Readline::new();
+    rl.set_sensitive(true);
+
+    assert_eq!(rl.feed(b"secret"), None);
+    assert_eq!(rl.feed(b"\x15"), None);
+    assert_eq!(rl.kill_ring_usage(), Usage::default());
+
+    // Without undo records, Ctrl-_ (undo) cannot bring the text back.
+    assert_eq!(rl.feed(b"\x1f"), None);
+    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("").unwrap(), 0));
+
+    let line = rl.feed_sensitive(b"pass\n").unwrap();
+    assert_eq!(*line, CString::new("pass").unwrap());
+    let _guard = rl.activate();
+    let buffer = unsafe {
+      slice::from_raw_parts(rl_line_buffer.cast::<u8>(), rl_line_buffer_len as _)
+    };
+    assert!(buffer.iter().all(|b| *b == 0));
+  }
+
+  /// Check that very long lines grow the line buffer as necessary.
+  #[test]
+  fn long_lines() {
+    fn capacity(rl: &Readline) -> usize {
+      let _guard = rl.activate();
+      unsafe { rl_line_buffer_len as usize }
+    }
+
+    let rl1 = Readline::new();
+    let rl2 = Readline::new();
+    let long = vec![b'a'; 4 << 20];
+
+    // Typing grows the buffer geometrically.
+    for _ in 0..1100 {
+      assert_eq!(rl1.feed(b"x"), None);
+    }
+    assert_eq!(capacity(&rl1), 2048);
+
+    let line = CString::new(long.clone()).unwrap();
+    let () = rl1.reset(&line, long.len(), true);
+    assert!(capacity(&rl1) > long.len());
+    assert_eq!(rl2.feed(b"y"), None);
+    assert_eq!(rl1.feed(b"b"), None);
+    assert_eq!(rl1.feed(b"\x01"), None);
+    assert_eq!(rl1.feed(b"c"), None);
+
+    let mut expected = b"c".to_vec();
+    expected.extend_from_slice(&long);
+    expected.push(b'b');
+    assert_eq!(rl1.feed(b"\r").unwrap().as_bytes(), expected.as_slice());
+    assert_eq!(rl2.peek(|s, p| (s.to_owned(), p)), (CString::new("y").unwrap(), 1));
+
+    // Text committed by an input method may be long as well.
+    assert!(rl2.commit_preedit(&line));
+    assert!(capacity(&rl2) > long.len());
+    assert_eq!(rl2.feed(b"z"), None);
+
+    let mut expected = b"y".to_vec();
+    expected.extend_from_slice(&long);
+    expected.push(b'z');
+    assert_eq!(rl2.feed(b"\r").unwrap().as_bytes(), expected.as_slice());
+
+    // The history entry can be recalled in its entirety.
+    let () = rl2.add_history(CString::new(expected.clone()).unwrap());
+    assert_eq!(rl2.feed(b"\x1b[A"), None);
+    assert_eq!(rl2.feed(b"\r").unwrap().as_bytes(), expected.as_slice());
+
+    #[cfg(feature = "zeroize")]
+    {
+      let rl = Readline::new();
+      rl.set_sensitive(true);
+      let () = rl.reset(&line, long.len(), true);
+      assert_eq!(rl.feed(b"\r").unwrap(), line);
+    }
+  }
+
+  /// Check that prompts are maintained per instance.
+  #[test]
+  fn prompt() {
+    let rl1 = Readline::new();
+    let rl2 = Readline::new();
+    assert_eq!(rl1.prompt(), CString::default());
+    assert_eq!(rl1.prompt_width(), 0);
+
+    rl1.set_prompt(CString::new("\x01\x1b[1m\x02> \x01\x1b[0m\x02").unwrap());
+    rl2.set_prompt(CString::new("prompt: ").unwrap());
+
+    assert_eq!(rl1.prompt(), CString::new("\x1b[1m> \x1b[0m").unwrap());
+    assert_eq!(rl1.prompt_width(), 2);
+    assert_eq!(rl2.prompt(), CString::new("prompt: ").unwrap());
+    assert_eq!(rl2.prompt_width(), 8);
+
+    assert_eq!(rl1.feed(b"a\n").unwrap(), CString::new("a").unwrap());
+    assert_eq!(rl1.prompt_width(), 2);
+  }
+
+  /// Check that changes caused by feeds are reported correctly.
+  #[test]
+  fn last_change() {
+    let rl = Readline::new();
+    assert_eq!(rl.last_change(), Change::Unchanged);
+
+    assert_eq!(rl.feed(b"ac"), None);
+    assert_eq!(
+      rl.last_change(),
+      Change::Text {
+        start: 0,
+        removed: 0,
+        inserted: 2,
+        cursor: 2,
+      }
+    );
+
+    assert_eq!(rl.feed(b"\x1b[D"), None);
+    assert_eq!(rl.last_change(), Change::Cursor { from: 2, to: 1 });
+
+    assert_eq!(rl.feed(b"b"), None);
+    assert_eq!(
+      rl.last_change(),
+      Change::Text {
+        start: 1,
+        removed: 0,
+        inserted: 1,
+        cursor: 2,
+      }
+    );
+  }
+
+  /// Check that highlighting spans are reported as expected.
+  #[test]
+  fn highlighting() {
+    let rl = Readline::new();
+    rl.peek_highlighted(|_, _, spans| assert_eq!(spans, &[]));
+
+    let highlighter = |line: &CStr| {
+      line
+        .to_bytes()
+        .iter()
+        .enumerate()
+        .filter(|(_, b)| b.is_ascii_digit())
+        .map(|(idx, _)| Span {
+          start: idx,
+          end: idx + 1,
+          style: 1,
+        })
+        .collect()
+    };
+    rl.set_highlighter(Some(Box::new(highlighter)));
+
+    let spans = Arc::new(Mutex::new(Vec::new()));
+    let spans_clone = spans.clone();
+    let hook = move |redisplay: &Redisplay<'_>| {
+      *spans_clone.lock().unwrap() = redisplay.spans.to_vec();
+    };
+    rl.set_redisplay_hook(Some(Box::new(hook)));
+
+    assert_eq!(rl.feed(b"a1b"), None);
+    let expected = [Span {
+      start: 1,
+      end: 2,
+      style: 1,
+    }];
+    assert_eq!(*spans.lock().unwrap(), expected);
+    rl.peek_highlighted(|line, cursor, spans| {
+      assert_eq!(line.to_bytes(), b"a1b");
+      assert_eq!(cursor, 3);
+      assert_eq!(spans, &expected);
+    });
+  }
+
+  /// Check that autosuggestions work as expected.
+  #[test]
+  fn autosuggestions() {
+    let rl = Readline::new();
+    rl.add_history(CString::new("git status").unwrap());
+    rl.add_history(CString::new("ls -l").unwrap());
+
+    assert_eq!(rl.feed(b"g"), None);
+    assert_eq!(rl.suggestion(), None);
+
+    rl.set_autosuggestions(true);
+    assert_eq!(rl.suggestion().unwrap().to_bytes(), b"it status");
+
+    // No suggestions are made with the cursor not at the end.
+    assert_eq!(rl.feed(b"\x1b[D"), None);
+    assert_eq!(rl.suggestion(), None);
+    assert!(!rl.accept_suggestion_word());
+    assert_eq!(rl.feed(b"\x1b[C"), None);
+
+    assert!(rl.accept_suggestion_word());
+    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("git").unwrap(), 3));
+    assert!(rl.accept_suggestion());
+    assert_eq!(
+      rl.peek(|s, p| (s.to_owned(), p)),
+      (CString::new("git status").unwrap(), 10)
+    );
+    assert_eq!(rl.suggestion(), None);
+    assert!(!rl.accept_suggestion());
+
+    // Accepting a suggestion is reported like any other edit.
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let calls_clone = calls.clone();
+    let hook = move |redisplay: &Redisplay<'_>| {
+      let mut calls = calls_clone.lock().unwrap();
+      calls.push((redisplay.line.to_owned(), redisplay.cursor));
+    };
+    rl.set_redisplay_hook(Some(Box::new(hook)));
+    assert_eq!(rl.feed(b"\r"), Some(CString::new("git status").unwrap()));
+    assert_eq!(rl.feed(b"l"), None);
+    assert!(rl.accept_suggestion());
+    assert_eq!(
+      rl.last_change(),
+      Change::Text {
+        start: 1,
+        removed: 0,
+        inserted: 4,
+        cursor: 5,
+      }
+    );
+    assert_eq!(
+      calls.lock().unwrap().last(),
+      Some(&(CString::new("ls -l").unwrap(), 5))
+    );
+
+    // No suggestions are accepted in read-only mode.
+    assert_eq!(rl.feed(b"\r"), Some(CString::new("ls -l").unwrap()));
+    assert_eq!(rl.feed(b"g"), None);
+    rl.set_read_only(true);
+    assert!(!rl.accept_suggestion());
+    assert_eq!(rl.peek(|s, _| s.to_owned()), CString::new("g").unwrap());
+  }
+
+  /// Check that incomplete lines are continued as multi-line input.
+  #[test]
+  fn validator() {
+    let rl = Readline::new();
+    let validator = |line: &CStr| {
+      let line = line.to_bytes();
+      let open = line.iter().filter(|b| **b == b'{').count();
+      let close = line.iter().filter(|b| **b == b'}').count();
+      if open > close {
+        Validation::Incomplete
+      } else {
+        Validation::Complete
+      }
+    };
+    rl.set_validator(Some(Box::new(validator)));
+
+    assert_eq!(rl.feed(b"{"), None);
+    assert_eq!(rl.feed(b"\r"), None);
+    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("{\n").unwrap(), 2));
+    assert_eq!(rl.feed(b"x"), None);
+    assert_eq!(rl.feed(b"\x1b[D"), None);
+    assert_eq!(rl.feed(b"\r"), None);
+    assert_eq!(
+      rl.peek(|s, p| (s.to_owned(), p)),
+      (CString::new("{\n\nx").unwrap(), 3)
+    );
+    assert_eq!(rl.feed(b"}"), None);
+    assert_eq!(rl.feed(b"\r").unwrap(), CString::new("{\n\n}x").unwrap());
+  }
+
+  /// Check that invalid lines are rejected with a message.
+  #[test]
+  fn validator_rejection() {
+    let rl = Readline::new();
+    let validator = |line: &CStr| {
+      let quotes = line.to_bytes().iter().filter(|b| **b == b'"').count();
+      if quotes % 2 == 1 {
+        Validation::Invalid(CString::new("unterminated string").unwrap())
+      } else {
+        Validation::Complete
+      }
+    };
+    rl.set_validator(Some(Box::new(validator)));
+
+    assert_eq!(rl.feed(b"\"abc"), None);
+    assert_eq!(rl.feed(b"\x1b[D"), None);
+    assert_eq!(rl.feed(b"\r"), None);
+    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("\"abc").unwrap(), 3));
+    assert_eq!(rl.status(), Some(CString::new("unterminated string").unwrap()));
+    assert_eq!(rl.metrics().lines, 0);
+
+    assert_eq!(rl.feed(b"\x05"), None);
+    assert_eq!(rl.status(), None);
+    assert_eq!(rl.feed(b"\""), None);
+    assert_eq!(rl.feed(b"\r").unwrap(), CString::new("\"abc\"").unwrap());
+    assert_eq!(rl.status(), None);
+  }
+
+  /// Check that completed lines are transformed before being reported.
+  #[test]
+  fn transforms() {
+    let rl = Readline::new();
+    rl.set_transforms(vec![
+      Transform::StripComments(CString::new("#").unwrap()),
+      Transform::TrimTrailing,
+      Transform::CollapseWhitespace,
+    ]);
+
+    assert_eq!(rl.feed(b"ls   -l  # list\r").unwrap(), CString::new("ls -l").unwrap());
+    assert_eq!(rl.feed(b"ls\r").unwrap(), CString::new("ls").unwrap());
+
+    rl.set_transforms(Vec::new());
+    assert_eq!(rl.feed(b"ls  # list\r").unwrap(), CString::new("ls  # list").unwrap());
+  }
+
+  /// Check that corrections are suggested for accepted lines and can be
+  /// applied.
+  #[test]
+  fn corrector() {
+    let rl = Readline::new();
+    let corrector = |line: &CStr| match line.to_bytes() {
+      b"gti status" => Some(CString::new("git status").unwrap()),
+      line => Some(CString::new(line).unwrap()),
+    };
+    rl.set_corrector(Some(Box::new(corrector)));
+
+    assert_eq!(rl.feed(b"ls\r").unwrap(), CString::new("ls").unwrap());
+    assert_eq!(rl.correction(), None);
+    assert!(!rl.apply_correction());
+
+    assert_eq!(rl.feed(b"gti status\r").unwrap(), CString::new("gti status").unwrap());
+    assert_eq!(rl.correction(), Some(CString::new("git status").unwrap()));
+    assert!(rl.apply_correction());
+    assert_eq!(rl.correction(), None);
+    assert_eq!(
+      rl.peek(|s, p| (s.to_owned(), p)),
+      (CString::new("git status").unwrap(), 10)
+    );
+    assert_eq!(rl.feed(b"\r").unwrap(), CString::new("git status").unwrap());
+
+    // The correction is discarded once more input is fed.
+    assert_eq!(rl.feed(b"gti status\r").unwrap(), CString::new("gti status").unwrap());
+    assert_eq!(rl.feed(b"x"), None);
+    assert_eq!(rl.correction(), None);
+  }
+
+  /// Check that continuation lines are indented as requested.
+  #[test]
+  fn auto_indent() {
+    let rl = Readline::new();
+    let validator = |line: &CStr| {
+      if line.to_bytes().ends_with(b":") {
+        Validation::Incomplete
+      } else {
+        Validation::Complete
+      }
+    };
+    rl.set_validator(Some(Box::new(validator)));
+    rl.set_auto_indent(Some(Indent::Copy));
+
+    // Tab is bound to completion, so quote it.
+    assert_eq!(rl.feed(b"\x16"), None);
+    assert_eq!(rl.feed(b"\t"), None);
+    assert_eq!(rl.feed(b" "), None);
+    assert_eq!(rl.feed(b"a"), None);
+    assert_eq!(rl.feed(b":"), None);
+    assert_eq!(rl.feed(b"\r"), None);
+    assert_eq!(
+      rl.peek(|s, p| (s.to_owned(), p)),
+      (CString::new("\t a:\n\t ").unwrap(), 7)
+    );
+    assert_eq!(rl.feed(b"b"), None);
+    assert_eq!(rl.feed(b"\r").unwrap(), CString::new("\t a:\n\t b").unwrap());
+
+    let indent = |text: &CStr| {
+      let line = text.to_bytes().rsplit(|b| *b == b'\n').next().unwrap();
+      let depth = line.iter().take_while(|b| **b == b' ').count();
+      vec![b' '; depth + 2]
+    };
+    rl.set_auto_indent(Some(Indent::Custom(Box::new(indent))));
+
+    assert_eq!(rl.feed(b"a"), None);
+    assert_eq!(rl.feed(b":"), None);
+    assert_eq!(rl.feed(b"\r"), None);
+    assert_eq!(rl.feed(b"b"), None);
+    assert_eq!(rl.feed(b":"), None);
+    assert_eq!(rl.feed(b"\r"), None);
+    assert_eq!(
+      rl.peek(|s, p| (s.to_owned(), p)),
+      (CString::new("a:\n  b:\n    ").unwrap(), 12)
+    );
+
+    rl.set_auto_indent(None);
+    assert_eq!(rl.feed(b"\x01"), None);
+    assert_eq!(rl.feed(b"\x0b"), None);
+    assert_eq!(rl.feed(b" "), None);
+    assert_eq!(rl.feed(b":"), None);
+    assert_eq!(rl.feed(b"\r"), None);
+    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new(" :\n").unwrap(), 3));
+  }
+
+  /// Check that masked lines are reported as such.
+  #[test]
+  fn mask() {
+    let rl = Readline::new();
+    rl.set_mask(Some(Mask::Char('*')));
+
+    assert_eq!(rl.feed(b"secret"), None);
+    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("******").unwrap(), 6));
+    assert_eq!(rl.feed(b"\x17"), None);
+    assert_eq!(rl.kill_ring_usage(), Usage::default());
+
+    // Killed text does not end up in libreadline's kill ring either.
+    // Other tests may kill text concurrently, so only check that the
+    // secret is not yanked.
+    let rl2 = Readline::new();
+    assert_eq!(rl2.feed(b"\x19"), None);
+    assert!(!rl2.peek(|s, _| s.to_owned()).to_bytes().ends_with(b"secret"));
+    assert_eq!(
+      rl.last_change(),
+      Change::Text {
+        start: 0,
+        removed: 6,
+        inserted: 0,
+        cursor: 0,
+      }
+    );
+    {
+      let _guard = rl.activate();
+      assert!(unsafe { rl_undo_list }.is_null());
+    }
+
+    rl.set_mask(Some(Mask::Hidden));
+    // Consecutive kills, including ones part of the same feed, are
+    // caught as well.
+    assert_eq!(rl.feed(b"pass word\x17\x17\x19"), None);
+    assert_eq!(rl.feed(b"pass"), None);
+    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::default(), 0));
+    assert_eq!(rl.last_change(), Change::Unchanged);
+    assert_eq!(rl.feed(b"\r").unwrap(), CString::new("pass").unwrap());
+
+    rl.set_mask(None);
+    assert_eq!(rl.feed(b"x"), None);
+    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("x").unwrap(), 1));
+  }
+
+  /// Check that status messages are reported as expected.
+  #[test]
+  fn status() {
+    let rl = Readline::new();
+    assert_eq!(rl.status(), None);
+
+    rl.set_status(CString::new("recording").unwrap());
+    assert_eq!(rl.status(), Some(CString::new("recording").unwrap()));
+    rl.clear_status();
+    assert_eq!(rl.status(), None);
+
+    rl.set_status(CString::new("recording").unwrap());
+    assert_eq!(rl.feed(b"a"), None);
+    assert_eq!(rl.status(), None);
+
+    assert_eq!(rl.feed(b"\x1b4"), None);
+    assert_eq!(rl.status(), Some(CString::new("(arg: 4)").unwrap()));
+    assert_eq!(rl.feed(b"x"), None);
+    assert_eq!(rl.status(), None);
+    assert_eq!(rl.peek(|s, _| s.to_owned()), CString::new("axxxx").unwrap());
+  }
+
+  /// Check that the screen size is kept per instance.
+  #[test]
+  fn screen_size() {
+    let rl1 = Readline::new();
+    let rl2 = Readline::new();
+    let rl3 = Readline::new();
+    let default = rl3.screen_size();
+
+    rl1.set_screen_size(Some((24, 100)));
+    rl2.set_screen_size(Some((10, 50)));
+
+    let (rows, columns) = rl1.screen_size();
+    assert_eq!(rows, 24);
+    assert!(columns == 100 || columns == 99, "{}", columns);
+
+    let (rows, columns) = rl2.screen_size();
+    assert_eq!(rows, 10);
+    assert!(columns == 50 || columns == 49, "{}", columns);
+
+    assert_eq!(rl3.screen_size(), default);
+    rl2.set_screen_size(None);
+    assert_eq!(rl2.screen_size(), default);
+    assert_eq!(rl1.screen_size().0, 24);
+  }
+
+  /// Check that composite prompts for special states are reported.
+  #[test]
+  fn display_prompt() {
+    let rl = Readline::new();
+    rl.set_prompt(CString::new("> ").unwrap());
+    rl.add_history(CString::new("foobar").unwrap());
+    assert_eq!(rl.display_prompt(), CString::new("> ").unwrap());
+
+    assert_eq!(rl.feed(b"\x1b4"), None);
+    assert_eq!(rl.display_prompt(), CString::new("(arg: 4) ").unwrap());
+    assert_eq!(rl.feed(b"x"), None);
+    assert_eq!(rl.display_prompt(), CString::new("> ").unwrap());
+
+    assert_eq!(rl.feed(b"\x12"), None);
+    assert_eq!(rl.feed(b"o"), None);
+    assert_eq!(
+      rl.display_prompt(),
+      CString::new("(reverse-i-search)`o': ").unwrap()
+    );
+    assert_eq!(rl.feed(b"\x07"), None);
+    assert_eq!(rl.display_prompt(), CString::new("> ").unwrap());
+  }
+
+  /// Check that we can find the bracket matching the one at the cursor.
+  #[test]
+  fn matching_bracket() {
+    let rl = Readline::new();
+    assert_eq!(rl.matching_bracket(), None);
+
+    assert_eq!(rl.feed(b"f(x)"), None);
+    assert_eq!(rl.matching_bracket(), Some((3, 1)));
+    assert_eq!(rl.feed(b"\x01"), None);
+    assert_eq!(rl.matching_bracket(), None);
+    assert_eq!(rl.feed(b"\x06"), None);
+    assert_eq!(rl.matching_bracket(), Some((1, 3)));
+
+    rl.set_mask(Some(Mask::Char('*')));
+    assert_eq!(rl.matching_bracket(), None);
+  }
+
+  /// Check that we can retrieve the word at the cursor.
+  #[test]
+  fn word_at_cursor() {
+    let rl = Readline::new();
+    assert_eq!(rl.word_at_cursor(), None);
+
+    assert_eq!(rl.feed(b"cat foo.txt "), None);
+    assert_eq!(rl.word_at_cursor(), None);
+    assert_eq!(rl.feed(b"\x02"), None);
+    assert_eq!(rl.word_at_cursor(), Some((CString::new("foo.txt").unwrap(), 4..11)));
+    assert_eq!(rl.feed(b"\x01"), None);
+    assert_eq!(rl.word_at_cursor(), Some((CString::new("cat").unwrap(), 0..3)));
+  }
+
+  /// Check that commands waiting for more input do not leak between
+  /// instances.
+  #[test]
+  fn pending_command_isolation() {
+    let rl1 = Readline::new();
+    let rl2 = Readline::new();
+
+    assert_eq!(rl1.feed(b"abc"), None);
+    assert_eq!(rl1.feed(b"\x01"), None);
+    // Ctrl-] waits for the character to search for.
+    assert_eq!(rl1.feed(b"\x1d"), None);
+    assert_eq!(rl2.feed(b"x"), None);
+    assert_eq!(rl2.peek(|s, p| (s.to_owned(), p)), (CString::new("x").unwrap(), 1));
+    assert_eq!(rl1.feed(b"c"), None);
+    assert_eq!(rl1.peek(|s, p| (s.to_owned(), p)), (CString::new("abc").unwrap(), 2));
+  }
+
+  /// Check that output libreadline writes to its output stream is
+  /// reported to the installed hook.
+  #[test]
+  fn stray_output_hook() {
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let output_ = output.clone();
+    let hook = move |bytes: &[u8]| output_.lock().unwrap().extend_from_slice(bytes);
+
+    let rl = Readline::new();
+    rl.set_stray_output_hook(Some(Box::new(hook)));
+    assert_eq!(rl.feed(b"C"), None);
+    assert_eq!(*output.lock().unwrap(), b"");
+
+    // Listing possible completions (file names in the current
+    // directory, by default) writes them out directly.
+    assert_eq!(rl.feed(b"\x1b?"), None);
+    let listing = String::from_utf8(output.lock().unwrap().split_off(0)).unwrap();
+    assert!(listing.contains("Cargo.toml"), "{}", listing);
+    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("C").unwrap(), 1));
+
+    rl.set_stray_output_hook(None);
+    assert_eq!(rl.feed(b"\r"), Some(CString::new("C").unwrap()));
+    assert_eq!(*output.lock().unwrap(), b"");
+  }
+
+  /// Check that unbound key sequences are reported to the installed
+  /// hook.
+  #[test]
+  fn unbound_hook() {
+    let rl = Readline::new();
+    let keys = Arc::new(Mutex::new(Vec::new()));
+    let keys_clone = keys.clone();
+    let hook = move |key: &[u8]| keys_clone.lock().unwrap().push(key.to_vec());
+    rl.set_unbound_hook(Some(Box::new(hook)));
+
+    assert_eq!(rl.feed_key(b"a"), Feed::Pending);
+    // F5 is not bound by default.
+    assert_eq!(rl.feed_key(b"\x1b[15~"), Feed::Pending);
+    assert_eq!(rl.feed_key(b"\x1b[D"), Feed::Pending);
+    assert_eq!(rl.feed_key(b"b"), Feed::Pending);
+    assert_eq!(rl.feed_key(b"\r"), Feed::Line(CString::new("ba").unwrap()));
+    assert_eq!(*keys.lock().unwrap(), [b"\x1b[15~".to_vec()]);
+
+    // Ctrl-] reads the character to search for, which is not looked
+    // up in the keymap.
+    assert_eq!(rl.feed_key(b"\x1d"), Feed::Pending);
+    assert_eq!(rl.feed_key(b"\x1e"), Feed::Pending);
+    assert_eq!(keys.lock().unwrap().len(), 1);
+    // Ctrl-^ is unbound, though.
+    assert_eq!(rl.feed_key(b"\x1e"), Feed::Pending);
+    assert_eq!(keys.lock().unwrap().len(), 2);
+  }
+
+  /// Check that the input filter can accept, replace, and reject
+  /// inserted keys.
+  #[test]
+  fn input_filter() {
+    struct Rejections(Arc<Mutex<Vec<Vec<u8>>>>);
+
+    impl LineObserver for Rejections {
+      fn input_rejected(&mut self, key: &[u8]) {
+        self.0.lock().unwrap().push(key.to_vec())
+      }
+    }
+
+    let rl = Readline::new();
+    let rejected = Arc::new(Mutex::new(Vec::new()));
+    rl.set_observer(Some(Box::new(Rejections(rejected.clone()))));
+
+    let filter = |key: &[u8]| match key {
+      [b'0'..=b'9'] => InputAction::Accept,
+      b"," => InputAction::Replace(b".".to_vec()),
+      _ => InputAction::Reject,
+    };
+    rl.set_input_filter(Some(Box::new(filter)));
+
+    for key in [&b"1"[..], b"a", b",", b"5", b"\x02", b"x"].iter() {
+      assert_eq!(rl.feed_key(key), Feed::Pending);
+    }
+    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("1.5").unwrap(), 2));
+    assert_eq!(*rejected.lock().unwrap(), [b"a".to_vec(), b"x".to_vec()]);
+    assert_eq!(rl.feed_key(b"\r"), Feed::Line(CString::new("1.5").unwrap()));
+  }
+
+  /// Check that modifications are rejected in read-only mode.
+  #[test]
+  fn read_only() {
+    struct Rejections(Arc<Mutex<Vec<Vec<u8>>>>);
+
+    impl LineObserver for Rejections {
+      fn input_rejected(&mut self, key: &[u8]) {
+        self.0.lock().unwrap().push(key.to_vec())
+      }
+    }
+
+    let rl = Readline::new();
+    let rejected = Arc::new(Mutex::new(Vec::new()));
+    rl.set_observer(Some(Box::new(Rejections(rejected.clone()))));
+    rl.add_history(CString::new("first").unwrap());
+    rl.add_history(CString::new("second").unwrap());
+
+    assert_eq!(rl.feed(b"ab"), None);
+    rl.set_read_only(true);
+
+    // Insertion, deletion, killing, and undo are all rejected.
+    for key in [&b"c"[..], b"\x7f", b"\x15", b"\x1f"].iter() {
+      assert_eq!(rl.feed(key), None);
+      assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("ab").unwrap(), 2));
+    }
+    assert_eq!(rejected.lock().unwrap().len(), 4);
+
+    // Movement works.
+    assert_eq!(rl.feed(b"\x01"), None);
+    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("ab").unwrap(), 0));
+    // So do history navigation and search.
+    assert_eq!(rl.feed(b"\x1b[A"), None);
+    assert_eq!(rl.peek(|s, _| s.to_owned()), CString::new("second").unwrap());
+    assert_eq!(rl.feed(b"\x12"), None);
+    assert_eq!(rl.feed(b"f"), None);
+    assert_eq!(rl.feed(b"\x1b"), None);
+    assert_eq!(rl.peek(|s, _| s.to_owned()), CString::new("first").unwrap());
+    assert_eq!(rl.feed(b"\r"), Some(CString::new("first").unwrap()));
+    assert_eq!(rejected.lock().unwrap().len(), 4);
+
+    rl.set_read_only(false);
+    assert_eq!(rl.feed(b"x"), None);
+    assert_eq!(rl.peek(|s, _| s.to_owned()), CString::new("x").unwrap());
+  }
+
+  /// Check that vi registers can be selected, stored into, and put.
+  #[test]
+  fn registers() {
+    let rl = Readline::new();
+    rl.set_edit_mode(EditMode::ViCommand);
+
+    let state = |rl: &Readline| rl.peek(|s, p| (s.to_str().unwrap().to_string(), p));
+    // Registers are selected key by key, so type one at a time.
+    let type_ = |rl: &Readline, keys: &[u8]| {
+      keys
+        .iter()
+        .for_each(|key| assert_eq!(rl.feed_key([*key]), Feed::Pending))
+    };
+
+    rl.reset(CString::new("foo bar baz").unwrap(), 0, true);
+    type_(&rl, b"\"adw");
+    assert_eq!(state(&rl), ("bar baz".to_string(), 0));
+    assert_eq!(rl.register('a'), Some(CString::new("foo ").unwrap()));
+    assert_eq!(rl.register('"'), Some(CString::new("foo ").unwrap()));
+
+    // An uppercase name appends to the register.
+    type_(&rl, b"\"Adw");
+    assert_eq!(state(&rl), ("baz".to_string(), 0));
+    assert_eq!(rl.register('a'), Some(CString::new("foo bar ").unwrap()));
+    assert_eq!(rl.register('"'), Some(CString::new("bar ").unwrap()));
+
+    // Without a register selected, the register is left alone.
+    type_(&rl, b"x");
+    assert_eq!(state(&rl), ("az".to_string(), 0));
+    assert_eq!(rl.register('a'), Some(CString::new("foo bar ").unwrap()));
+    assert_eq!(rl.register('"'), Some(CString::new("b").unwrap()));
+
+    type_(&rl, b"\"aP");
+    assert_eq!(state(&rl).0, "foo bar az");
+    // A put without register selected uses the most recent kill.
+    type_(&rl, b"0P");
+    assert_eq!(state(&rl).0, "foo bar foo bar az");
+
+    rl.set_register('q', CString::new("xy").unwrap());
+    assert_eq!(rl.register('Q'), Some(CString::new("xy").unwrap()));
+    type_(&rl, b"$\"qp");
+    assert_eq!(state(&rl).0, "foo bar foo bar azxy");
+    // Putting an empty register does nothing.
+    assert_eq!(rl.register('e'), None);
+    type_(&rl, b"\"ep");
+    assert_eq!(state(&rl).0, "foo bar foo bar azxy");
+
+    rl.set_register('"', CString::new("!").unwrap());
+    assert_eq!(rl.register('"'), Some(CString::new("!").unwrap()));
+    type_(&rl, b"0P");
+    assert_eq!(state(&rl).0, "!foo bar foo bar azxy");
+    // The insertion used to add the text to the kill ring is not
+    // undoable, but the put is.
+    type_(&rl, b"u");
+    assert_eq!(state(&rl).0, "foo bar foo bar azxy");
+  }
+
+  /// Check that the cursor can be moved across grapheme clusters.
+  #[test]
+  fn grapheme_movement() {
+    let rl = Readline::new();
+    let line = CString::new("ao\u{308}\u{1f44d}\u{1f3fd}").unwrap();
+    let () = rl.reset(&line, 12, false);
+    let cursor = |rl: &Readline| rl.peek(|_, p| p);
+
+    let () = rl.move_left_grapheme();
+    assert_eq!(cursor(&rl), 4);
+    let () = rl.move_left_grapheme();
+    assert_eq!(cursor(&rl), 1);
+    let () = rl.move_right_grapheme();
+    assert_eq!(cursor(&rl), 4);
+    let () = rl.move_right_grapheme();
+    assert_eq!(cursor(&rl), 12);
+    let () = rl.move_right_grapheme();
+    assert_eq!(cursor(&rl), 12);
+
+    let () = rl.set_grapheme_movement(true);
+    assert_eq!(rl.feed(b"\x1b[D"), None);
+    assert_eq!(cursor(&rl), 4);
+    assert_eq!(rl.feed(b"\x02"), None);
+    assert_eq!(cursor(&rl), 1);
+    assert_eq!(rl.feed(b"\x06"), None);
+    assert_eq!(cursor(&rl), 4);
+    assert_eq!(rl.feed(b"\x1b[C"), None);
+    assert_eq!(cursor(&rl), 12);
+
+    let () = rl.set_grapheme_movement(false);
+    assert_eq!(rl.feed(b"\x01\x1b[C"), None);
+    assert_eq!(cursor(&rl), 1);
+  }
+
+  /// Check that the terminal name is kept per instance.
+  #[test]
+  fn terminal_name() {
+    let rl1 = Readline::new();
+    let rl2 = Readline::new();
+    let default = rl2.terminal_name();
+
+    let () = rl1.set_terminal_name(Some(CString::new("rline-term").unwrap()));
+    assert_eq!(rl1.terminal_name(), Some(CString::new("rline-term").unwrap()));
+    assert_eq!(rl2.terminal_name(), default);
+    assert_eq!(rl1.terminal_name(), Some(CString::new("rline-term").unwrap()));
+
+    let () = rl1.set_terminal_name(None::<&CStr>);
+    assert_eq!(rl1.terminal_name(), default);
+  }
+
+  /// Check that key bindings can be exported and imported.
+  #[test]
+  fn export_import_bindings() {
+    let rl1 = Readline::new();
+    let () = rl1.set_isolated(true);
+    let text = "# A comment.\n\"\\C-xq\": \"hi \\\"there\\\"\"\r\n\n\"\\C-xw\": backward-word\n";
+    let () = rl1.import_bindings(text).unwrap();
+
+    let exported = rl1.export_bindings();
+    assert!(exported.starts_with("set keymap emacs\n"), "{}", exported);
+    assert!(exported.contains("\"\\C-xw\": backward-word\n"), "{}", exported);
+    assert!(exported.contains("\"\\C-xq\": \"hi \\\"there\\\"\"\n"), "{}", exported);
+
+    let rl2 = Readline::new();
+    let () = rl2.set_isolated(true);
+    assert_eq!(rl2.feed(b"\x18q"), None);
+    assert_eq!(rl2.peek(|s, _| s.to_owned()), CString::default());
+
+    let () = rl2.import_bindings(&exported).unwrap();
+    assert_eq!(rl2.feed(b"\x18q"), None);
+    assert_eq!(rl2.feed(b"\x18w"), None);
+    assert_eq!(rl2.peek(|s, p| (s.to_owned(), p)), (CString::new("hi \"there\"").unwrap(), 4));
+
+    let err = rl2.import_bindings("\"\\C-xe: end-of-line").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let err = rl2.import_bindings("\0").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+  }
+
+  /// Check that brackets and quotes are paired automatically.
+  #[test]
+  fn auto_pair() {
+    let rl = Readline::new();
+    rl.set_auto_pair(true);
+
+    let state = |rl: &Readline| rl.peek(|s, p| (s.to_str().unwrap().to_string(), p));
+
+    assert_eq!(rl.feed(b"f"), None);
+    assert_eq!(rl.feed(b"("), None);
+    assert_eq!(state(&rl), ("f()".to_string(), 2));
+    assert_eq!(rl.feed(b"["), None);
+    assert_eq!(rl.feed(b"\""), None);
+    assert_eq!(state(&rl), ("f([\"\"])".to_string(), 4));
+    assert_eq!(rl.feed(b"x"), None);
+    // Typing over the closing characters.
+    assert_eq!(rl.feed(b"\""), None);
+    assert_eq!(rl.feed(b"]"), None);
+    assert_eq!(state(&rl), ("f([\"x\"])".to_string(), 7));
+    assert_eq!(rl.feed(b" "), None);
+    assert_eq!(rl.feed(b"{"), None);
+    assert_eq!(state(&rl), ("f([\"x\"] {})".to_string(), 9));
+    // Deleting an empty pair.
+    assert_eq!(rl.feed(b"\x7f"), None);
+    assert_eq!(state(&rl), ("f([\"x\"] )".to_string(), 8));
+    assert_eq!(rl.feed(b"\x7f"), None);
+    assert_eq!(state(&rl), ("f([\"x\"])".to_string(), 7));
+
+    // Apostrophes are not paired.
+    assert_eq!(rl.feed(b"\x01"), None);
+    assert_eq!(rl.feed(b"\x0b"), None);
+    assert_eq!(rl.feed(b"n"), None);
+    assert_eq!(rl.feed(b"'"), None);
+    assert_eq!(state(&rl), ("n'".to_string(), 2));
+    // Undo works as well.
+    assert_eq!(rl.feed(b"\x15"), None);
+    assert_eq!(rl.feed(b"("), None);
+    assert_eq!(rl.feed(b"\x1f"), None);
+    assert_eq!(state(&rl), ("".to_string(), 0));
+
+    rl.set_auto_pair(false);
+    assert_eq!(rl.feed(b"("), None);
+    assert_eq!(state(&rl), ("(".to_string(), 1));
+  }
+
+  /// Check that abbreviations are expanded.
+  #[test]
+  fn abbreviations() {
+    let rl = Readline::new();
+    rl.add_abbreviation(CString::new("gco").unwrap(), CString::new("git checkout").unwrap());
+    rl.add_abbreviation(CString::new("gst").unwrap(), CString::new("git status").unwrap());
+
+    for key in [&b"g"[..], b"c", b"o", b" ", b"x", b"g", b"c", b"o", b" "].iter() {
+      assert_eq!(rl.feed(key), None);
+    }
+    assert_eq!(
+      rl.peek(|s, _| s.to_owned()),
+      CString::new("git checkout xgco ").unwrap()
+    );
+
+    // The expansion is undone separately from the break character.
+    assert_eq!(rl.feed(b"\x15"), None);
+    for key in [&b"g"[..], b"c", b"o", b" "].iter() {
+      assert_eq!(rl.feed(key), None);
+    }
+    assert_eq!(rl.feed(b"\x1f"), None);
+    assert_eq!(rl.peek(|s, _| s.to_owned()), CString::new("git checkout").unwrap());
+    assert_eq!(rl.feed(b"\x1f"), None);
+    assert_eq!(rl.peek(|s, _| s.to_owned()), CString::new("gco").unwrap());
+
+    // Accepting the line expands as well.
+    assert_eq!(rl.feed(b"\x15"), None);
+    for key in [&b"g"[..], b"s", b"t"].iter() {
+      assert_eq!(rl.feed(key), None);
+    }
+    assert_eq!(rl.feed(b"\r"), Some(CString::new("git status").unwrap()));
+
+    // The hook may veto expansion.
+    rl.set_abbreviation_hook(Some(Box::new(|abbr, _| abbr.to_bytes() != b"gst")));
+    for key in [&b"g"[..], b"s", b"t", b" ", b"g", b"c", b"o", b" "].iter() {
+      assert_eq!(rl.feed(key), None);
+    }
+    assert_eq!(
+      rl.peek(|s, _| s.to_owned()),
+      CString::new("gst git checkout ").unwrap()
+    );
+
+    assert!(rl.remove_abbreviation(CString::new("gco").unwrap()));
+    assert!(!rl.remove_abbreviation(CString::new("gco").unwrap()));
+  }
+
+  /// Check that modifications of the line are tracked.
+  #[test]
+  fn dirty() {
+    let rl = Readline::new();
+    assert!(!rl.is_dirty());
+    assert_eq!(rl.feed(b"\x02"), None);
+    assert!(!rl.is_dirty());
+    assert_eq!(rl.feed(b"a"), None);
+    assert!(rl.is_dirty());
+    assert_eq!(rl.feed(b"\x7f"), None);
+    assert!(rl.is_dirty());
+    assert_eq!(rl.feed(b"b\r"), Some(CString::new("b").unwrap()));
+    assert!(!rl.is_dirty());
+
+    assert_eq!(rl.feed(b"c"), None);
+    assert!(rl.is_dirty());
+    rl.reset(CString::new("xyz").unwrap(), 0, true);
+    assert!(!rl.is_dirty());
+  }
+
+  /// Check that we can inspect the undo list.
+  #[test]
+  fn undo_records() {
+    let rl = Readline::new();
+    assert_eq!(rl.undo_records(|records| records.count()), 0);
+
+    assert_eq!(rl.feed(b"abc"), None);
+    assert_eq!(rl.feed(b"\x01"), None);
+    assert_eq!(rl.feed(b"\x04"), None);
+
+    let records = rl.undo_records(|records| {
+      records
+        .map(|record| (record.kind(), record.range(), record.text().map(CStr::to_owned)))
+        .collect::<Vec<_>>()
+    });
+    let expected = vec![
+      (UndoKind::Delete, 0..1, Some(CString::new("a").unwrap())),
+      (UndoKind::Insert, 0..3, None),
+    ];
+    assert_eq!(records, expected);
+
+    assert_eq!(rl.feed(b"\x1f"), None);
+    assert_eq!(rl.undo_records(|records| records.count()), 1);
+    assert_eq!(rl.feed(b"\r"), Some(CString::new("abc").unwrap()));
+    assert_eq!(rl.undo_records(|records| records.count()), 0);
+  }
+
+  /// Check that undo records in excess of the configured limit get
+  /// discarded.
+  #[test]
+  fn undo_limit() {
+    let rl = Readline::new();
+    assert_eq!(rl.undo_limit(), None);
+    let () = rl.set_undo_limit(Some(3));
+    assert_eq!(rl.undo_limit(), Some(3));
+
+    assert_eq!(rl.feed(b"abcdef"), None);
+    assert_eq!(rl.feed(b"\x01"), None);
+    for _ in 0..4 {
+      assert_eq!(rl.feed(b"\x04"), None);
+    }
+    assert_eq!(rl.undo_records(|records| records.count()), 3);
+
+    for _ in 0..4 {
+      assert_eq!(rl.feed(b"\x1f"), None);
+    }
+    assert_eq!(rl.peek(|s, _| s.to_owned()), CString::new("bcdef").unwrap());
+
+    // Records forming a group are never split up.
+    let () = rl.set_undo_limit(Some(2));
+    let () = rl.reset(CString::default(), 0, true);
+    let () = rl.add_abbreviation(CString::new("ty").unwrap(), CString::new("thank you").unwrap());
+    assert_eq!(rl.feed(b"ty "), None);
+    assert_eq!(rl.undo_records(|records| records.count()), 1);
+
+    let () = rl.set_undo_limit(Some(0));
+    assert_eq!(rl.feed(b"\x02"), None);
+    assert_eq!(rl.undo_records(|records| records.count()), 0);
+
+    // History entries edited and navigated away from keep no reference
+    // to records discarded.
+    let () = rl.reset(CString::default(), 0, true);
+    let () = rl.add_history(CString::new("abc").unwrap());
+    assert_eq!(rl.feed(b"\x10"), None);
+    assert_eq!(rl.feed(b"x\x0e"), None);
+    assert_eq!(rl.feed(b"\x10"), None);
+    assert_eq!(rl.peek(|s, _| s.to_owned()), CString::new("abcx").unwrap());
+    {
+      let guard = rl.activate();
+      let entries = guard.state.history.entries();
+      assert!(entries.iter().all(|entry| unsafe { (**entry).data }.is_null()));
+    }
+    assert_eq!(rl.feed(b"\x1f"), None);
+    assert_eq!(rl.peek(|s, _| s.to_owned()), CString::new("abcx").unwrap());
+    assert_eq!(rl.feed(b"\r"), Some(CString::new("abcx").unwrap()));
+    let () = rl.set_undo_limit(None);
+  }
+
+  /// Check that lines can be exchanged and moved between instances.
+  #[test]
+  fn swap_line() {
+    let peek = |rl: &Readline| rl.peek(|s, pos| (s.to_str().unwrap().to_string(), pos));
+
+    let mut rl1 = Readline::new();
+    let mut rl2 = Readline::new();
+    assert_eq!(rl1.feed(b"abc\x02"), None);
+    assert_eq!(rl2.feed(b"xy"), None);
+
+    rl1.swap_line(&mut rl2, true);
+    assert_eq!(peek(&rl1), ("xy".to_string(), 2));
+    assert_eq!(peek(&rl2), ("abc".to_string(), 2));
+    assert!(rl1.is_dirty());
+
+    // The undo lists got transferred as well.
+    assert_eq!(rl2.feed(b"d\x1f"), None);
+    assert_eq!(peek(&rl2).0, "abc");
+    assert_eq!(rl1.feed(b"\x1f"), None);
+    assert_eq!(peek(&rl1).0, "");
+
+    assert_eq!(rl1.feed(b"p"), None);
+    rl1.swap_line(&mut rl2, false);
+    assert_eq!(peek(&rl1).0, "abc");
+    assert_eq!(rl1.feed(b"\x1f"), None);
+    assert_eq!(peek(&rl1).0, "abc");
+
+    rl1.move_line_to(&mut rl2, true);
+    assert_eq!(peek(&rl1), (String::new(), 0));
+    assert!(!rl1.is_dirty());
+    assert_eq!(rl2.feed(b"\r"), Some(CString::new("abc").unwrap()));
+
+    // The undo list of an edited history entry is no longer referenced
+    // by the history it got moved out of.
+    let () = rl1.add_history(CString::new("foo").unwrap());
+    assert_eq!(rl1.feed(b"\x10"), None);
+    assert_eq!(rl1.feed(b"x\x0e"), None);
+    assert_eq!(rl1.feed(b"\x10"), None);
+    rl1.move_line_to(&mut rl2, true);
+    assert_eq!(rl2.feed(b"\x1f"), None);
+    assert_eq!(peek(&rl2).0, "foo");
+    assert_eq!(rl2.feed(b"\r"), Some(CString::new("foo").unwrap()));
+    {
+      let guard = rl1.activate();
+      let entries = guard.state.history.entries();
+      assert!(entries.iter().all(|entry| unsafe { (**entry).data }.is_null()));
+    }
+  }
+
+  /// Check that we can retrieve the text of the region.
+  #[test]
+  fn region_text() {
+    let rl = Readline::new();
+    assert_eq!(rl.mark(), Some(0));
+    assert_eq!(rl.region_text(), Some(CString::default()));
+
+    assert_eq!(rl.feed(b"hello world"), None);
+    assert_eq!(rl.region_text(), Some(CString::new("hello world").unwrap()));
+    // Ctrl-@ sets the mark.
+    assert_eq!(rl.feed(b"\x00"), None);
+    assert_eq!(rl.mark(), Some(11));
+    assert_eq!(rl.feed(b"\x1bb"), None);
+    assert_eq!(rl.region_text(), Some(CString::new("world").unwrap()));
+    // Ctrl-X Ctrl-X exchanges cursor and mark.
+    assert_eq!(rl.feed(b"\x18\x18"), None);
+    assert_eq!(rl.mark(), Some(6));
+    assert_eq!(rl.region_text(), Some(CString::new("world").unwrap()));
+
+    rl.set_mask(Some(Mask::Hidden));
+    assert_eq!(rl.mark(), None);
+    assert_eq!(rl.region_text(), None);
+  }
+
+  /// Check that the region can be activated and that its state is
+  /// tracked per instance.
+  #[cfg(readline_8_1)]
+  #[test]
+  fn active_mark() {
+    let rl1 = Readline::new();
+    let rl2 = Readline::new();
+    assert!(!rl1.is_mark_active());
+
+    assert_eq!(rl1.feed(b"abc"), None);
+    let () = rl1.set_mark_active(true);
+    assert!(rl1.is_mark_active());
+    assert!(!rl2.is_mark_active());
+
+    // The region gets deactivated by the next command.
+    assert_eq!(rl2.feed(b"x"), None);
+    assert!(rl1.is_mark_active());
+    assert_eq!(rl1.feed(b"\x02"), None);
+    assert!(!rl1.is_mark_active());
+
+    let () = rl1.set_mark_active(true);
+    let () = rl1.set_mark_active(false);
+    assert!(!rl1.is_mark_active());
+  }
+
+  /// Check that clearing the visible line leaves the line being edited
+  /// alone.
+  #[test]
+  fn clear_visible_line() {
+    let rl = Readline::new();
+    assert_eq!(rl.feed(b"abc"), None);
+    let () = rl.clear_visible_line();
+    assert_eq!(rl.peek(|s, pos| (s.to_owned(), pos)), (CString::new("abc").unwrap(), 3));
+    assert_eq!(rl.feed(b"\r"), Some(CString::new("abc").unwrap()));
+  }
+
+  /// Check that editing events are reported to an observer.
+  #[test]
+  fn observer() {
+    #[derive(Debug, PartialEq)]
+    enum Event {
+      Key(Vec<u8>),
+      Deleted(usize, Vec<u8>),
+      Inserted(usize, Vec<u8>),
+      Cursor(usize, usize),
+      History(usize),
+      Accepted(CString),
+    }
+
+    struct Recorder(Arc<Mutex<Vec<Event>>>);
+
+    impl LineObserver for Recorder {
+      fn key_dispatched(&mut self, keyseq: &[u8]) {
+        self.0.lock().unwrap().push(Event::Key(keyseq.to_vec()))
+      }
+
+      fn text_deleted(&mut self, start: usize, text: &[u8]) {
+        self.0.lock().unwrap().push(Event::Deleted(start, text.to_vec()))
+      }
+
+      fn text_inserted(&mut self, start: usize, text: &[u8]) {
+        self.0.lock().unwrap().push(Event::Inserted(start, text.to_vec()))
+      }
+
+      fn cursor_moved(&mut self, from: usize, to: usize) {
+        self.0.lock().unwrap().push(Event::Cursor(from, to))
+      }
+
+      fn history_navigated(&mut self, index: usize) {
+        self.0.lock().unwrap().push(Event::History(index))
+      }
+
+      fn line_accepted(&mut self, line: &CStr) {
+        self.0.lock().unwrap().push(Event::Accepted(line.to_owned()))
+      }
+    }
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let rl = Readline::new();
+    rl.add_history(CString::new("xy").unwrap());
+    rl.set_observer(Some(Box::new(Recorder(events.clone()))));
+
+    assert_eq!(rl.feed(b"a"), None);
+    assert_eq!(rl.feed(b"\x02"), None);
+    assert_eq!(rl.feed(b"\x04"), None);
+    assert_eq!(rl.feed(b"\x1b[A"), None);
+    assert_eq!(rl.feed(b"\r"), Some(CString::new("xy").unwrap()));
+
+    assert_eq!(
+      *events.lock().unwrap(),
+      [
+        Event::Key(b"a".to_vec()),
+        Event::Inserted(0, b"a".to_vec()),
+        Event::Cursor(0, 1),
+        Event::Key(b"\x02".to_vec()),
+        Event::Cursor(1, 0),
+        Event::Key(b"\x04".to_vec()),
+        Event::Deleted(0, b"a".to_vec()),
+        Event::Key(b"\x1b[A".to_vec()),
+        Event::Inserted(0, b"xy".to_vec()),
+        Event::Cursor(0, 2),
+        Event::History(0),
+        Event::Key(b"\r".to_vec()),
+        // Accepting the line clears it.
+        Event::Deleted(0, b"xy".to_vec()),
+        Event::Cursor(2, 0),
+        Event::Accepted(CString::new("xy").unwrap()),
+      ]
+    );
+  }
+
+  /// Check that startup and pre-input hooks are invoked when a new
+  /// line gets read.
+  #[test]
+  fn line_hooks() {
+    let rl1 = Readline::new();
+    let rl2 = Readline::new();
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let events_clone = events.clone();
+    let hook = move |ctx: &mut LineHookContext<'_>| {
+      let () = events_clone
+        .lock()
+        .unwrap()
+        .push(("startup", ctx.line().to_owned()));
+      let () = ctx.set_line(&CString::new("git ").unwrap(), 4);
+    };
+    rl1.set_startup_hook(Some(Box::new(hook)));
+    let events_clone = events.clone();
+    let hook = move |ctx: &mut LineHookContext<'_>| {
+      let () = events_clone
+        .lock()
+        .unwrap()
+        .push(("pre-input", ctx.line().to_owned()));
+      assert_eq!(ctx.cursor(), 4);
+      assert!(ctx
+        .set_variable(&CString::new("no-such-variable").unwrap(), &CString::new("on").unwrap())
+        .is_err());
+    };
+    rl1.set_pre_input_hook(Some(Box::new(hook)));
+
+    assert_eq!(rl1.feed(b"a"), None);
+    assert!(events.lock().unwrap().is_empty());
+    assert_eq!(rl1.feed(b"\r"), Some(CString::new("a").unwrap()));
+    let expected = [
+      ("startup", CString::default()),
+      ("pre-input", CString::new("git ").unwrap()),
+    ];
+    assert_eq!(*events.lock().unwrap(), expected);
+    assert_eq!(rl1.peek(|s, p| (s.to_owned(), p)), (CString::new("git ").unwrap(), 4));
+
+    // Hooks are per instance.
+    assert_eq!(rl2.feed(b"\r"), Some(CString::default()));
+    assert_eq!(events.lock().unwrap().len(), 2);
+    assert_eq!(rl2.peek(|s, _| s.to_owned()), CString::default());
+
+    assert_eq!(rl1.feed(b"log\r"), Some(CString::new("git log").unwrap()));
+    assert_eq!(events.lock().unwrap().len(), 4);
   }
 
-  /// Activate this context.
-  fn activate(&self) -> ReadlineGuard<'_> {
-    let mut guard = Self::mutex().lock().unwrap();
-    let state = self.state.borrow_mut();
+  /// Check that editing mode changes are reported.
+  #[test]
+  fn edit_mode() {
+    let rl = Readline::new();
+    assert_eq!(rl.edit_mode(), EditMode::Emacs);
 
-    // Activate our state if necessary.
-    if *guard != self.id {
-      state.save();
-      *guard = self.id;
+    let modes = Arc::new(Mutex::new(Vec::new()));
+    let modes_clone = modes.clone();
+    let hook = move |mode| modes_clone.lock().unwrap().push(mode);
+    rl.set_mode_hook(Some(Box::new(hook)));
+
+    // M-C-j switches to vi mode.
+    assert_eq!(rl.feed(b"\x1b\n"), None);
+    assert_eq!(rl.edit_mode(), EditMode::ViInsert);
+    assert_eq!(rl.feed(b"\x1b"), None);
+    assert_eq!(rl.edit_mode(), EditMode::ViCommand);
+    assert_eq!(rl.feed(b"i"), None);
+    assert_eq!(rl.feed(b"\x1b"), None);
+    // C-e in command mode switches back to emacs mode.
+    assert_eq!(rl.feed(b"\x05"), None);
+    assert_eq!(rl.edit_mode(), EditMode::Emacs);
+
+    assert_eq!(
+      *modes.lock().unwrap(),
+      [
+        EditMode::ViInsert,
+        EditMode::ViCommand,
+        EditMode::ViInsert,
+        EditMode::ViCommand,
+        EditMode::Emacs
+      ]
+    );
+
+    // Other instances are unaffected.
+    assert_eq!(Readline::new().edit_mode(), EditMode::Emacs);
+  }
+
+  /// Check that we can retrieve mode strings.
+  #[test]
+  fn mode_strings() {
+    let rl = Readline::new();
+    // Mode strings are not shown by default.
+    assert_eq!(rl.mode_string(), None);
+
+    let _guard = rl.activate();
+    assert_eq!(
+      unsafe { mode_string(EditMode::ViInsert) },
+      CString::new("(ins)").unwrap()
+    );
+    assert_eq!(
+      unsafe { mode_string(EditMode::ViCommand) },
+      CString::new("(cmd)").unwrap()
+    );
+  }
+
+  /// Check that sessions can be captured, serialized, and restored.
+  #[cfg(feature = "serde")]
+  #[test]
+  fn session() {
+    let rl = Readline::new();
+    rl.add_history(CString::new("first").unwrap());
+    rl.add_history(CString::new("second").unwrap());
+    assert_eq!(rl.feed(b"foo bar"), None);
+    assert_eq!(rl.feed(b"\x17"), None);
+    assert_eq!(rl.feed(b"baz"), None);
+    assert_eq!(rl.feed(b"\x1b[D"), None);
+
+    let session = rl.session();
+    let expected = Session {
+      line: CString::new("foo baz").unwrap(),
+      cursor: 6,
+      history: vec![CString::new("first").unwrap(), CString::new("second").unwrap()],
+      kill_ring: vec![CString::new("bar").unwrap()],
+    };
+    assert_eq!(session, expected);
+
+    let json = serde_json::to_string(&session).unwrap();
+    let session = serde_json::from_str::<Session>(&json).unwrap();
+
+    let rl = Readline::new();
+    rl.add_history(CString::new("other").unwrap());
+    rl.restore_session(&session);
+    assert_eq!(rl.session(), expected);
+    assert_eq!(rl.history_usage(), Usage { entries: 2, bytes: 11 });
+  }
+
+  /// Check that key bindings of isolated instances do not leak.
+  #[test]
+  fn isolation() {
+    extern "C" {
+      fn rl_bind_key(key: c_int, function: rl_command_func_t) -> c_int;
+      fn rl_beg_of_line(count: c_int, key: c_int) -> c_int;
     }
 
-    ReadlineGuard {
-      _guard: guard,
-      state,
+    let rl1 = Readline::new();
+    let rl2 = Readline::new();
+    rl1.set_isolated(true);
+
+    {
+      let _guard = rl1.activate();
+      let result = unsafe { rl_bind_key(c_int::from(b'\x14'), rl_beg_of_line) };
+      assert_eq!(result, 0);
     }
+
+    assert_eq!(rl1.feed(b"ab"), None);
+    assert_eq!(rl1.feed(b"\x14"), None);
+    assert_eq!(rl1.peek(|s, p| (s.to_owned(), p)), (CString::new("ab").unwrap(), 0));
+    assert_eq!(rl2.feed(b"ab"), None);
+    assert_eq!(rl2.feed(b"\x14"), None);
+    assert_ne!(rl2.peek(|_, p| p), 0);
+    assert_eq!(rl1.feed(b"\x1b[F"), None);
+    assert_eq!(rl1.feed(b"\x14"), None);
+    assert_eq!(rl1.peek(|_, p| p), 0);
+
+    // Bindings of multi-key sequences are made in keymaps libreadline
+    // allocated for their prefix, which must not be shared either.
+    let bindings = "\"\\e[Z\": beginning-of-line\n\"\\e[Y\": \"mac\"";
+    let () = rl1.import_bindings(bindings).unwrap();
+    let () = rl1.reset(CString::new("ab").unwrap(), 2, true);
+    assert_eq!(rl1.feed(b"\x1b[Z"), None);
+    assert_eq!(rl1.peek(|_, p| p), 0);
+    assert_eq!(rl1.feed(b"\x1b[Y"), None);
+    assert_eq!(rl1.peek(|s, _| s.to_owned()), CString::new("macab").unwrap());
+    let () = rl2.reset(CString::new("ab").unwrap(), 2, true);
+    assert_eq!(rl2.feed(b"\x1b[Z"), None);
+    assert_eq!(rl2.feed(b"\x1b[Y"), None);
+    assert_eq!(rl2.peek(|s, p| (s.to_owned(), p)), (CString::new("ab").unwrap(), 2));
+
+    // Once isolation is undone, the bindings are gone.
+    rl1.set_isolated(false);
+    assert_eq!(rl1.feed(b"\x1b[F"), None);
+    assert_eq!(rl1.feed(b"\x14"), None);
+    assert_ne!(rl1.peek(|_, p| p), 0);
+    let () = rl1.reset(CString::new("ab").unwrap(), 2, true);
+    assert_eq!(rl1.feed(b"\x1b[Z"), None);
+    assert_eq!(rl1.peek(|_, p| p), 2);
   }
 
-  /// Feed a key to libreadline.
-  ///
-  /// The provided buffer should comprise not more than a single key,
-  /// which may be a single byte only or an escape sequence.
-  ///
-  /// # Panics
-  ///
-  /// Panics if too many bytes are supplied. libreadline's internal
-  /// buffer is said to hold 512 bytes, so any slice of equal or greater
-  /// size may cause a panic.
-  pub fn feed(&mut self, key: impl AsRef<Key>) -> Option<CString> {
-    fn feed_impl(rl: &Readline, key: &Key) -> Option<CString> {
-      if key.is_empty() {
-        return None
-      }
+  /// Check that isolated instances have a kill ring of their own.
+  #[test]
+  fn isolated_kill_ring() {
+    let rl1 = Readline::new();
+    let rl2 = Readline::new();
+    rl1.set_isolated(true);
 
-      let _guard = rl.activate();
+    assert_eq!(rl1.feed(b"first"), None);
+    assert_eq!(rl1.feed(b"\x17"), None);
+    assert_eq!(rl1.feed(b"second"), None);
+    assert_eq!(rl1.feed(b"\x17"), None);
+    assert_eq!(rl2.feed(b"other\x17\x19"), None);
+    assert_eq!(rl2.peek(|s, _| s.to_owned()), CString::new("other").unwrap());
 
-      for &b in key {
-        // This call will only fail if there is not enough space available
-        // to push the given character (with libreadline specifying a
-        // buffer size large enough for 512 characters). As we feed one
-        // character at a time and process (i.e., consume) it immediately
-        // afterwards, there is no risk of us ever hitting this limit.
-        //
-        // Note that despite `rl_stuff_char` accepting a `c_int`, it
-        // actually casts that value down to a single byte internally,
-        // which is why we provide a saner interface that directly just
-        // accepts bytes.
-        let result = unsafe { rl_stuff_char(c_int::from(b)) };
-        // There is nothing we can do about this error. Heck, not even the
-        // user can do anything about this problem *after* hitting it. We
-        // cannot safely call `rl_callback_read_char` without risking
-        // cutting off input in the middle of an escape sequence,
-        // resulting in what effectively is corrupted input. We also
-        // cannot revert the buffer back to its previous state because
-        // there is no API to do that. Holy crap what a mess.
-        assert_ne!(result, 0, "libreadline's input buffer overflowed");
-      }
-
-      unsafe { rl_callback_read_char(); }
-      // SAFETY: `_guard` will outlive the returned reference and we
-      //         only call the function once.
-      let line_ref = unsafe { Readline::line() };
-      line_ref.take()
-    }
+    // Neither the killed text nor the entries shared with the other
+    // instance are visible in ours.
+    assert_eq!(rl1.feed(b"\x19"), None);
+    assert_eq!(rl1.peek(|s, _| s.to_owned()), CString::new("second").unwrap());
+    assert_eq!(rl1.feed(b"\x1by"), None);
+    assert_eq!(rl1.peek(|s, _| s.to_owned()), CString::new("first").unwrap());
+    assert_eq!(rl1.feed(b"\x1by"), None);
+    assert_eq!(rl1.peek(|s, _| s.to_owned()), CString::new("").unwrap());
+
+    // Once isolation is undone, the shared kill ring is used again.
+    rl1.set_isolated(false);
+    assert_eq!(rl1.feed(b"\r"), Some(CString::new("").unwrap()));
+    assert_eq!(rl1.feed(b"\x19"), None);
+    assert_ne!(rl1.peek(|s, _| s.to_owned()), CString::new("second").unwrap());
 
-    feed_impl(self, key.as_ref())
+    // Creating an instance while an isolated one owns the kill ring
+    // hands it back to the shared one.
+    rl1.set_isolated(true);
+    assert_eq!(rl1.feed(b"third"), None);
+    assert_eq!(rl1.feed(b"\x17"), None);
+    let rl3 = Readline::new();
+    assert_eq!(rl3.feed(b"\x19"), None);
+    assert_ne!(rl3.peek(|s, _| s.to_owned()), CString::new("third").unwrap());
   }
 
-  /// Reset libreadline's line state to the given line with the given
-  /// cursor position. If `clear_undo` is set, the undo list associated
-  /// with the current line is cleared
-  ///
-  /// Note that this method does not deal with input related modes. For
-  /// example, libreadline always starts in input mode, but, depending
-  /// on user configuration, it can be transitioned to vi-movement-mode
-  /// in which key bindings behave differently (other similar modes
-  /// exist). When resetting the line using this method the input mode
-  /// is unaffected. If you truly need to manually force libreadline
-  /// into input mode, a new `Readline` will help:
-  /// ```rust
-  /// # use rline::Readline;
-  /// # let mut current = Readline::new();
-  /// current = Readline::new();
-  /// ```
-  ///
-  /// # Panics
+  /// Check that state flags are reported as expected.
+  #[test]
+  fn state_flags() {
+    let rl = Readline::new();
+    let flags = rl.state_flags();
+    assert!(flags.contains(StateFlags::CALLBACK));
+    assert!(!flags.contains(StateFlags::ISEARCH));
+
+    assert_eq!(rl.feed(b"\x12"), None);
+    assert!(rl.state_flags().contains(StateFlags::ISEARCH));
+    assert_eq!(rl.feed(b"\x07"), None);
+    assert!(!rl.state_flags().contains(StateFlags::ISEARCH));
+
+    assert_eq!(rl.feed(b"\x18("), None);
+    assert!(rl.state_flags().contains(StateFlags::MACRODEF));
+    assert_eq!(rl.feed(b"\x18)"), None);
+    assert!(!rl.state_flags().contains(StateFlags::MACRODEF));
+  }
+
+  /// Check that multi-byte key sequences without a binding or bound to
+  /// an aborting command are discarded.
+  #[test]
+  fn unbound_sequence() {
+    let rl = Readline::new();
+    assert_eq!(rl.feed(b"x"), None);
+    assert_eq!(rl.feed(b"\x1b\""), None);
+    assert_eq!(rl.feed(b"\x1ba"), None);
+    // `yank-pop` without a preceding yank.
+    assert_eq!(rl.feed(b"\x1by"), None);
+    assert_eq!(rl.feed(b"y"), None);
+    assert_eq!(rl.feed(b"\n"), Some(CString::new("xy").unwrap()));
+
+    // The same holds while in the middle of a key sequence.
+    assert_eq!(rl.feed(b"\x1b["), None);
+    assert_eq!(rl.feed(b"\x1b[Z"), None);
+    assert_eq!(rl.feed(b"\x1b\x0b"), None);
+    assert_eq!(rl.feed(b"z"), None);
+    assert_eq!(rl.feed(b"\n"), Some(CString::new("[Zz").unwrap()));
+  }
+
+  /// Create a strategy generating sequences of key presses that
+  /// libreadline copes with.
   ///
-  /// Panics if the cursor is not less than or equal to the number of
-  /// characters in the given line.
-  pub fn reset<S>(&mut self, line: S, cursor: usize, clear_undo: bool)
-  where
-    S: AsRef<CStr>,
-  {
-    fn reset_impl(rl: &Readline, s: &CStr, cursor: usize, clear_undo: bool) {
-      assert!(cursor <= s.to_bytes().len(), "invalid cursor position");
+  /// libreadline (as of 8.2) is prone to crashing in callback mode when
+  /// combining numeric arguments or incremental searches with
+  /// multi-byte characters, or on unbound sequences in vi command mode,
+  /// among others. Hence, we stick to printable ASCII characters and
+  /// keys without a meta prefix.
+  #[cfg(feature = "proptest")]
+  fn key_presses() -> impl proptest::strategy::Strategy<Value = Vec<Vec<u8>>> {
+    use proptest::char::range;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
 
-      let _guard = rl.activate();
-      unsafe {
-        rl_replace_line(s.as_ptr(), clear_undo.into());
-        rl_point = cursor as _;
+    let keys = prop_oneof![
+      4 => range(' ', '~').prop_map(key::Key::Char),
+      1 => key::strategy::key().prop_filter("key is a character or escape", |key| {
+        !matches!(key, key::Key::Char(..) | key::Key::Escape)
+      }),
+    ];
+    let modifiers = any::<bool>().prop_map(|shift| key::Modifiers {
+      shift,
+      ..Default::default()
+    });
+    vec(key::strategy::press(keys, modifiers), 0..32)
+  }
+
+  #[cfg(feature = "proptest")]
+  proptest::proptest! {
+    #![proptest_config(proptest::prelude::ProptestConfig::with_cases(64))]
+
+    /// Check that feeding arbitrary key presses keeps the line state
+    /// consistent.
+    #[test]
+    fn arbitrary_input(sequence in key_presses()) {
+      let rl = Readline::new();
+      for press in sequence {
+        let _feed = rl.feed_key(press);
+        let () = rl.peek(|line, cursor| assert!(cursor <= line.to_bytes().len()));
       }
     }
+  }
 
-    reset_impl(self, line.as_ref(), cursor, clear_undo)
+  /// Check that completed lines can be converted as desired.
+  #[test]
+  fn feed_conversions() {
+    let line = || Feed::Line(CString::new(b"a\xe4".to_vec()).unwrap());
+    assert!(line().into_string().unwrap().is_err());
+    assert_eq!(line().into_string_lossy().unwrap(), "a\u{fffd}");
+    assert_eq!(line().into_os_string().unwrap().into_vec(), b"a\xe4");
+    assert_eq!(
+      line().into_path_buf().unwrap(),
+      PathBuf::from(OsString::from_vec(b"a\xe4".to_vec()))
+    );
+    #[cfg(feature = "encoding_rs")]
+    {
+      assert_eq!(line().decode(encoding_rs::WINDOWS_1252).unwrap().unwrap(), "aä");
+      assert!(line().decode(encoding_rs::UTF_8).unwrap().is_err());
+    }
+
+    let ok = Feed::Line(CString::new("abc").unwrap());
+    assert_eq!(ok.into_string().unwrap().unwrap(), "abc");
+    assert_eq!(Feed::Pending.into_string(), None);
+    assert_eq!(Feed::Eof.into_string_lossy(), None);
+    assert_eq!(Feed::Interrupted.into_os_string(), None);
+    assert_eq!(Feed::Pending.into_path_buf(), None);
   }
 
-  /// Peek at the current line state through a closure.
-  pub fn peek<F, R>(&self, peeker: F) -> R
-  where
-    F: FnOnce(&CStr, usize) -> R,
-  {
-    let _guard = self.activate();
-    let (s, pos, len) = unsafe {
-      debug_assert!(rl_end >= 0);
-      debug_assert!(rl_point >= 0);
+  /// Check that key bindings are enumerated and summarized.
+  #[test]
+  fn bindings() {
+    let rl = Readline::new();
+    let bindings = rl.bindings();
+    let binding = bindings
+      .iter()
+      .find(|binding| binding.command().to_bytes() == b"beginning-of-line")
+      .unwrap();
+    assert!(binding.keys().contains(&CString::new("\\C-a").unwrap()));
+    assert!(binding.key_names().iter().any(|name| name == "C-a"));
+    assert_eq!(binding.group(), BindingGroup::Movement);
 
-      let buf = rl_line_buffer;
-      let len = rl_end as _;
-      let pos = rl_point as _;
+    let sheet = rl.cheat_sheet().to_string();
+    assert!(sheet.starts_with("Movement\n"), "{}", sheet);
+    assert!(sheet.contains("\nHistory\n"), "{}", sheet);
+    assert!(!sheet.contains("self-insert"), "{}", sheet);
 
-      (CStr::from_ptr(buf), pos, len)
+    // The bindings of the current editing mode are reported.
+    rl.set_edit_mode(EditMode::ViCommand);
+    let bindings = rl.bindings();
+    let binding = bindings
+      .iter()
+      .find(|binding| binding.command().to_bytes() == b"vi-delete-to")
+      .unwrap();
+    assert!(binding.keys().contains(&CString::new("d").unwrap()));
+  }
+
+  /// Check that lines can be commented out and accepted.
+  #[test]
+  fn insert_comment() {
+    let rl1 = Readline::new();
+    let rl2 = Readline::new();
+    assert_eq!(rl1.comment_begin(), CString::new("#").unwrap());
+
+    assert_eq!(rl1.feed(b"ls -l\x02"), None);
+    assert_eq!(rl1.insert_comment(), Feed::Line(CString::new("#ls -l").unwrap()));
+
+    let () = rl1.set_comment_begin(Some(CString::new("// ").unwrap()));
+    let () = rl2.set_comment_begin(Some(CString::new(";").unwrap()));
+    assert_eq!(rl1.comment_begin(), CString::new("// ").unwrap());
+    assert_eq!(rl2.comment_begin(), CString::new(";").unwrap());
+
+    // Instances without a text of their own use the default, no
+    // matter what other instances set.
+    let rl3 = Readline::new();
+    assert_eq!(rl3.comment_begin(), CString::new("#").unwrap());
+
+    assert_eq!(rl1.feed(b"x"), None);
+    assert_eq!(rl1.insert_comment(), Feed::Line(CString::new("// x").unwrap()));
+    // The command is available in vi command mode as well.
+    rl2.set_edit_mode(EditMode::ViCommand);
+    assert_eq!(rl2.insert_comment(), Feed::Line(CString::new(";").unwrap()));
+
+    let () = rl2.set_comment_begin(None::<CString>);
+    assert_eq!(rl2.comment_begin(), CString::new("#").unwrap());
+    let () = rl1.set_comment_begin(Some(CString::default()));
+    assert_eq!(rl1.comment_begin(), CString::new("#").unwrap());
+    assert_eq!(rl1.feed(b"y"), None);
+    assert_eq!(rl1.insert_comment(), Feed::Line(CString::new("#y").unwrap()));
+  }
+
+  /// Check that the state of keyboard macro handling is reported.
+  #[test]
+  fn macro_state() {
+    let rl = Readline::new();
+    let states = Arc::new(Mutex::new(Vec::new()));
+    let states_clone = states.clone();
+    let hook = move |redisplay: &Redisplay<'_>| {
+      states_clone.lock().unwrap().push(redisplay.macro_state)
     };
+    rl.set_redisplay_hook(Some(Box::new(hook)));
+    assert_eq!(rl.macro_state(), None);
 
-    debug_assert_eq!(s.to_bytes().len(), len);
-    peeker(s, pos)
+    assert_eq!(rl.feed(b"\x18("), None);
+    assert_eq!(rl.macro_state(), Some(MacroState::Defining));
+    assert_eq!(rl.feed(b"a"), None);
+    assert_eq!(rl.feed(b"\x18)"), None);
+    assert_eq!(rl.macro_state(), None);
+    assert!(!states.lock().unwrap().contains(&Some(MacroState::Executing)));
+
+    assert_eq!(rl.feed(b"\x18e"), None);
+    assert_eq!(rl.macro_state(), None);
+    assert_eq!(rl.peek(|s, _| s.to_owned()), CString::new("aa").unwrap());
+    assert!(states.lock().unwrap().contains(&Some(MacroState::Executing)));
+    assert_eq!(states.lock().unwrap().last(), Some(&None));
   }
-}
 
-impl Default for Readline {
-  fn default() -> Self {
-    Self::new()
+  /// Check that we can determine which instance is active.
+  #[test]
+  fn active() {
+    let rl1 = Readline::new();
+    let rl2 = Readline::new();
+    assert_ne!(rl1.id(), rl2.id());
+
+    // Other tests may activate their instances concurrently, so we can
+    // only check for consistency.
+    assert_eq!(rl2.feed(b"a"), None);
+    assert!(!(rl1.is_active() && rl2.is_active()));
+    assert!(rl1.is_active() || Readline::active_id() != rl1.id());
   }
-}
 
-impl Drop for Readline {
-  fn drop(&mut self) {
-    let _guard = self.activate();
+  /// Check that executed key sequences are reported.
+  #[test]
+  fn last_key_sequence() {
+    let rl = Readline::new();
+    assert_eq!(rl.last_key_sequence(), b"");
 
-    // Make sure to release the memory we or libreadline allocated.
-    unsafe {
-      rl_free_undo_list();
-      free(rl_executing_keyseq.cast());
-      free(rl_line_buffer.cast());
+    assert_eq!(rl.feed(b"a"), None);
+    assert_eq!(rl.last_key_sequence(), b"a");
+    assert_eq!(rl.feed(b"\x1b[D"), None);
+    assert_eq!(rl.last_key_sequence(), b"\x1b[D");
+    // The prefix of a multi-key sequence is reported as well.
+    assert_eq!(rl.feed(b"\x18"), None);
+    assert_eq!(rl.last_key_sequence(), b"\x18");
+    assert_eq!(rl.feed(b"\x15"), None);
+    assert_eq!(rl.last_key_sequence(), b"\x18\x15");
+  }
+
+  /// Check that the `&str` based variants of our API work as expected.
+  #[test]
+  fn str_variants() {
+    let rl = Readline::new();
+    assert!(rl.reset_str("a\0b", 0, true).is_err());
+    assert_eq!(rl.peek_str(|s, pos| (s.unwrap().to_string(), pos)), ("".to_string(), 0));
+
+    rl.reset_str("abc", 1, true).unwrap();
+    assert_eq!(rl.peek_str(|s, pos| (s.unwrap().to_string(), pos)), ("abc".to_string(), 1));
+    assert_eq!(rl.feed_string(b"x"), None);
+    assert_eq!(rl.feed_string(b"\r").unwrap().unwrap(), "axbc");
+
+    rl.reset(CString::new(b"\xffa".to_vec()).unwrap(), 2, true);
+    assert!(rl.peek_str(|s, _| s.is_err()));
+    let err = rl.feed_string(b"\r").unwrap().unwrap_err();
+    assert_eq!(err.into_cstring().as_bytes(), b"\xffa");
+
+    rl.reset(CString::new(b"\xffa".to_vec()).unwrap(), 2, true);
+    assert_eq!(rl.feed_string_lossy(b"\r").unwrap(), "\u{fffd}a");
+  }
+
+  /// Check that fed UTF-8 is inserted verbatim once 8-bit clean input
+  /// handling got forced.
+  #[test]
+  fn eight_bit() {
+    let () = Readline::force_eight_bit();
+
+    let rl = Readline::new();
+    for &byte in "\u{e4}\u{20ac}".as_bytes() {
+      assert_eq!(rl.feed([byte]), None);
     }
+    let line = rl.feed(b"\r").unwrap();
+    assert_eq!(line.to_str().unwrap(), "\u{e4}\u{20ac}");
   }
-}
 
+  /// Check that lines are accepted automatically once they reached the
+  /// configured length.
+  #[test]
+  fn chars_to_read() {
+    let rl1 = Readline::new();
+    let rl2 = Readline::new();
+    assert_eq!(rl1.chars_to_read(), None);
 
-// Note that libreadline is pretty much fully configurable. With
-// specific configurations it is possible that some tests fail (although
-// we mostly use functionality that is pretty basic and unlikely to have
-// been reconfigured by the user). While it would be possible to
-// override the user configuration for the purpose of testing, that is
-// not trivial and out of the scope of this crate.
-#[cfg(test)]
-mod tests {
-  use super::*;
+    let () = rl1.set_chars_to_read(Some(3));
+    assert_eq!(rl1.chars_to_read(), Some(3));
+    assert_eq!(rl1.feed_key(b"ab"), Feed::Pending);
+    assert_eq!(rl2.feed_key(b"abc"), Feed::Pending);
+    assert_eq!(rl1.feed_key(b"c"), Feed::Line(CString::new("abc").unwrap()));
 
-  use std::mem::align_of;
+    let () = rl1.set_chars_to_read(Some(1));
+    assert_eq!(rl1.feed_key(b"y"), Feed::Line(CString::new("y").unwrap()));
+    assert_eq!(rl2.feed_key(b"\n"), Feed::Line(CString::new("abc").unwrap()));
 
+    let () = rl1.set_chars_to_read(None);
+    assert_eq!(rl1.chars_to_read(), None);
+    assert_eq!(rl1.feed_key(b"y"), Feed::Pending);
+  }
 
-  /// Exercise the `Debug` representation of various types.
+  /// Check that whether the prompt is displayed by the application is
+  /// tracked per instance.
   #[test]
-  fn debug_repr() {
-    assert_ne!(format!("{:?}", T(())), "");
+  fn already_prompted() {
+    let rl1 = Readline::new();
+    let rl2 = Readline::new();
+    assert!(!rl1.is_already_prompted());
 
-    let rl = Readline::default();
-    assert_ne!(format!("{:?}", rl), "");
+    let prompted = Arc::new(Mutex::new(Vec::new()));
+    let prompted_clone = prompted.clone();
+    let hook = move |redisplay: &Redisplay<'_>| {
+      prompted_clone.lock().unwrap().push(redisplay.already_prompted)
+    };
+    rl1.set_redisplay_hook(Some(Box::new(hook)));
+
+    let () = rl1.set_already_prompted(true);
+    assert!(rl1.is_already_prompted());
+    let () = rl1.on_new_line_with_prompt();
+    assert_eq!(rl1.feed(b"a"), None);
+    assert_eq!(rl2.feed(b"a"), None);
+    {
+      let _guard = rl1.activate();
+      assert_eq!(unsafe { rl_already_prompted }, 1);
+    }
+    {
+      let _guard = rl2.activate();
+      assert_eq!(unsafe { rl_already_prompted }, 0);
+    }
+
+    rl1.set_prompt(CString::new("> ").unwrap());
+    let () = rl1.on_new_line_with_prompt();
+    assert_eq!(rl1.feed(b"b"), None);
+    assert_eq!(rl1.feed(b"\r"), Some(CString::new("ab").unwrap()));
+    assert!(prompted.lock().unwrap().iter().all(|prompted| *prompted));
+    assert!(!prompted.lock().unwrap().is_empty());
   }
 
+  /// Check that feeding the interrupt character discards the line.
   #[test]
-  fn is_locked() {
-    let mutex = Mutex::<u64>::new(42);
-    assert!(!mutex.is_locked());
+  fn interrupt() {
+    let rl = Readline::new();
+    assert_eq!(rl.feed_key(b"a"), Feed::Pending);
+    assert_eq!(rl.feed_key(b"\x03"), Feed::Pending);
+
+    rl.set_interrupt_char(Some(0x03));
+    assert_eq!(rl.feed_key(b"b"), Feed::Pending);
+    assert_eq!(rl.feed_key(b"\x03"), Feed::Interrupted);
+    assert_eq!(rl.peek(|s, pos| (s.to_owned(), pos)), (CString::default(), 0));
+    assert!(matches!(rl.last_change(), Change::Text { .. }));
+
+    // Partially entered commands are discarded as well, provided that
+    // libreadline supports cleaning them up.
+    #[cfg(readline_7)]
     {
-      let _guard = mutex.lock().unwrap();
-      assert!(mutex.is_locked());
+      assert_eq!(rl.feed(b"x"), None);
+      assert_eq!(rl.feed(b"\x12"), None);
+      assert!(rl.state_flags().contains(StateFlags::ISEARCH));
+      assert_eq!(rl.feed(b"\x03"), None);
+      assert!(!rl.state_flags().contains(StateFlags::ISEARCH));
+      assert_eq!(rl.feed(b"\x1b3"), None);
+      assert_eq!(rl.feed_key(b"\x03"), Feed::Interrupted);
+      assert_eq!(rl.numeric_arg(), None);
     }
-    assert!(!mutex.is_locked());
+
+    assert_eq!(rl.feed_key(b"c"), Feed::Pending);
+    assert_eq!(rl.feed_key(b"\r"), Feed::Line(CString::new("c").unwrap()));
   }
 
+  /// Check that the end-of-file character is reported on an empty line
+  /// only.
   #[test]
-  fn alignment() {
-    assert_eq!(align_of::<readline_state>(), 8);
+  fn eof() {
+    let rl = Readline::new();
+    assert_eq!(rl.feed_key(b"\x04"), Feed::Eof);
+    assert_eq!(rl.feed(b"\x04"), None);
+
+    assert_eq!(rl.feed_key(b"ab"), Feed::Pending);
+    assert_eq!(rl.feed_key(b"\x01"), Feed::Pending);
+    assert_eq!(rl.feed_key(b"\x04"), Feed::Pending);
+    assert_eq!(rl.peek(|s, _| s.to_owned()), CString::new("b").unwrap());
+    assert_eq!(rl.feed_key(b"\x04"), Feed::Pending);
+    assert_eq!(rl.feed_key(b"\x04"), Feed::Eof);
+
+    // A command waiting for more input gets the key instead. The
+    // incremental search ends and libreadline handles the key itself,
+    // completing the empty line.
+    assert_eq!(rl.feed_key(b"\x12"), Feed::Pending);
+    assert!(rl.state_flags().contains(StateFlags::ISEARCH));
+    assert_eq!(rl.feed_key(b"\x04"), Feed::Line(CString::default()));
+    assert!(!rl.state_flags().contains(StateFlags::ISEARCH));
+    assert_eq!(rl.feed_key(b"\x04"), Feed::Eof);
+
+    rl.set_eof_char(Some(b'q'));
+    assert_eq!(rl.feed_key(b"q"), Feed::Eof);
+    assert_eq!(rl.feed_key(b"x"), Feed::Pending);
+    assert_eq!(rl.feed_key(b"q"), Feed::Pending);
+    assert_eq!(rl.feed_key(b"\r"), Feed::Line(CString::new("xq").unwrap()));
+
+    rl.set_eof_char(None);
+    assert_eq!(rl.feed_key(b"q"), Feed::Pending);
+    // libreadline completes an empty line on Ctrl-D.
+    let () = rl.reset(CString::default(), 0, true);
+    assert_eq!(rl.feed(b"\x04"), Some(CString::default()));
   }
 
+  /// Check that usage counters are maintained.
   #[test]
-  fn empty_input() {
-    let mut rl = Readline::new();
+  fn metrics() {
+    let rl1 = Readline::new();
+    let rl2 = Readline::new();
+    assert_eq!(rl1.metrics().keys, 0);
+    assert_eq!(rl1.metrics().history_entries, 0);
 
-    assert!(rl.feed(b"").is_none())
+    assert_eq!(rl1.feed(b"a"), None);
+    assert_eq!(rl2.feed(b"b"), None);
+    assert_eq!(rl1.feed(b"\r").unwrap(), CString::new("a").unwrap());
+    rl1.add_history(CString::new("a").unwrap());
+
+    // Creation, the two feeds, and the addition to the history each
+    // count as a single activation.
+    let metrics = rl1.metrics();
+    assert_eq!(metrics.activations, 4);
+    assert_eq!(metrics.keys, 2);
+    assert_eq!(metrics.lines, 1);
+    assert_eq!(metrics.history_entries, 1);
+    assert!(metrics.swaps >= 2, "{:?}", metrics);
+    assert!(metrics.activations >= metrics.swaps, "{:?}", metrics);
+
+    // So do operations activating the instance repeatedly.
+    let mut rl1 = rl1;
+    let mut rl2 = rl2;
+    let () = rl1.swap_line(&mut rl2, false);
+    assert_eq!(rl1.insert_comment(), Feed::Line(CString::new("#b").unwrap()));
+    let metrics = rl1.metrics();
+    assert_eq!(metrics.activations, 6);
+    assert!(metrics.activations >= metrics.swaps, "{:?}", metrics);
+    let metrics = rl2.metrics();
+    assert_eq!(metrics.activations, 3);
+    assert!(metrics.activations >= metrics.swaps, "{:?}", metrics);
+
+    let global = Readline::global_metrics();
+    assert!(global.keys >= 3, "{:?}", global);
+    assert!(global.lines >= 1, "{:?}", global);
   }
 
+  /// Check that we can query and drain unprocessed input.
   #[test]
-  fn empty_line_input() {
-    let mut rl = Readline::new();
+  fn pending_input() {
+    let rl = Readline::new();
+    assert_eq!(rl.pending_input(), 0);
+    assert_eq!(rl.drain_pending(), 0);
 
-    assert_eq!(rl.feed(b"\n").unwrap(), CString::new("").unwrap())
+    assert_eq!(rl.feed(b"\x1b["), None);
+    assert_eq!(rl.feed(b"x\r\ryz"), None);
+    let pending = rl.pending_input();
+    assert_ne!(pending, 0);
+    // Pending input must not leak into other instances.
+    let rl2 = Readline::new();
+    assert_eq!(rl2.pending_input(), 0);
+    assert_eq!(rl2.feed(b"a\r").unwrap(), CString::new("a").unwrap());
+    assert_eq!(rl.pending_input(), pending);
+    assert_eq!(rl.drain_pending(), pending);
+    assert_eq!(rl.pending_input(), 0);
   }
 
+  /// Check that the remaining input buffer capacity is reported
+  /// correctly.
   #[test]
-  fn multiple_inputs() {
-    let mut rl = Readline::new();
+  fn input_capacity() {
+    let rl = Readline::new();
+    let capacity = rl.input_capacity();
+    assert_eq!(capacity, INPUT_CAPACITY);
 
-    assert!(rl.feed(b"first").is_none());
-    assert_eq!(rl.feed(b"\n").unwrap(), CString::new("first").unwrap());
+    assert_eq!(rl.feed(b"\x1b["), None);
+    assert_eq!(rl.feed(b"x\r\ryz"), None);
+    assert_ne!(rl.input_capacity(), capacity);
+    assert_eq!(rl.input_capacity(), capacity - rl.pending_input());
+    let _count = rl.drain_pending();
+    assert_eq!(rl.input_capacity(), capacity);
 
-    assert!(rl.feed(b"second").is_none());
-    assert_eq!(rl.feed(b"\n").unwrap(), CString::new("second").unwrap());
+    // Feeding as many bytes as reported must not overflow the buffer.
+    let key = vec![b'a'; capacity];
+    assert_eq!(rl.feed(key), None);
   }
 
+  /// Check that we can detect libreadline waiting for a key to insert
+  /// verbatim.
   #[test]
-  fn cursor() {
-    let mut rl = Readline::new();
-
+  fn quoted_insert() {
+    let rl = Readline::new();
+    assert!(!rl.is_quoted_insert());
     assert_eq!(rl.feed(b"a"), None);
-    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("a").unwrap(), 1));
+    assert!(!rl.is_quoted_insert());
+    assert_eq!(rl.feed(b"\x16"), None);
+    assert!(rl.is_quoted_insert());
+    // The state must not leak into other instances.
+    let rl2 = Readline::new();
+    assert!(!rl2.is_quoted_insert());
+    assert_eq!(rl.feed(b"\x01"), None);
+    assert!(!rl.is_quoted_insert());
+    // Neither the interrupt nor the end-of-file character are special
+    // when inserted verbatim.
+    rl.set_interrupt_char(Some(0x03));
+    assert_eq!(rl.feed(b"\x16"), None);
+    assert_eq!(rl.feed_key(b"\x03"), Feed::Pending);
+    assert_eq!(rl.feed(b"\x08"), None);
+    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("a\x01").unwrap(), 2));
 
-    assert_eq!(rl.feed(b"b"), None);
-    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("ab").unwrap(), 2));
+    // A pending numeric argument is not mistaken for it.
+    assert_eq!(rl.feed(b"\x1b2"), None);
+    assert!(!rl.is_quoted_insert());
+    assert_eq!(rl.feed(b"\x11"), None);
+    assert!(rl.is_quoted_insert());
+    assert_eq!(rl.feed(b"\r"), None);
+    assert!(!rl.is_quoted_insert());
+    assert_eq!(rl.feed(b"\r").unwrap(), CString::new("a\x01\r\r").unwrap());
+  }
 
+  /// Check that we can record input events and replay them.
+  #[test]
+  fn recording() {
+    let rl = Readline::new();
+    assert_eq!(rl.stop_recording(), None);
+    assert_eq!(rl.feed(b"x"), None);
+
+    rl.start_recording();
+    assert_eq!(rl.feed(b"a"), None);
+    assert_eq!(rl.feed(b"b"), None);
+    rl.reset(CString::new("xyz").unwrap(), 1, false);
+    assert_eq!(rl.feed(b"\x1b[C"), None);
     assert_eq!(rl.feed(b"c"), None);
-    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("abc").unwrap(), 3));
+    assert_eq!(rl.feed(b"\r").unwrap(), CString::new("xycz").unwrap());
+    rl.set_mask(Some(Mask::Hidden));
+    assert_eq!(rl.feed(b"d"), None);
+    rl.set_mask(None);
+    assert_eq!(rl.feed(b"e"), None);
+
+    let recording = rl.stop_recording().unwrap();
+    assert_eq!(rl.stop_recording(), None);
+    let events = recording
+      .events()
+      .iter()
+      .map(|(_, event)| event.clone())
+      .collect::<Vec<_>>();
+    assert_eq!(
+      events,
+      vec![
+        RecordedEvent::Feed(b"a".to_vec()),
+        RecordedEvent::Feed(b"b".to_vec()),
+        RecordedEvent::Reset {
+          line: CString::new("xyz").unwrap(),
+          cursor: 1,
+          clear_undo: false,
+        },
+        RecordedEvent::Feed(b"\x1b[C".to_vec()),
+        RecordedEvent::Feed(b"c".to_vec()),
+        RecordedEvent::Feed(b"\r".to_vec()),
+        RecordedEvent::Feed(b"e".to_vec()),
+      ]
+    );
+
+    let recording = recording.to_string().parse::<Recording>().unwrap();
+    let rl = Readline::new();
+    let feeds = recording.replay(&rl);
+    assert_eq!(
+      feeds,
+      vec![
+        Feed::Pending,
+        Feed::Pending,
+        Feed::Pending,
+        Feed::Pending,
+        Feed::Line(CString::new("xycz").unwrap()),
+        Feed::Pending,
+      ]
+    );
+    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("e").unwrap(), 1));
   }
 
+  /// Check that numeric arguments are reported.
   #[test]
-  fn reset() {
-    let mut rl = Readline::new();
+  fn numeric_arg() {
+    let rl = Readline::new();
+    assert_eq!(rl.numeric_arg(), None);
 
-    assert_eq!(rl.feed(b"xyz"), None);
-    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("xyz").unwrap(), 3));
+    assert_eq!(rl.feed(b"\x1b1"), None);
+    assert_eq!(rl.numeric_arg(), Some(1));
+    assert_eq!(rl.feed(b"2"), None);
+    assert_eq!(rl.numeric_arg(), Some(12));
+    assert_eq!(rl.feed(b"a"), None);
+    assert_eq!(rl.numeric_arg(), None);
+    assert_eq!(rl.peek(|s, _| s.to_bytes().len()), 12);
 
-    rl.reset(CString::new("abc").unwrap(), 1, true);
-    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("abc").unwrap(), 1));
+    assert_eq!(rl.feed(b"\x1b-"), None);
+    assert_eq!(rl.numeric_arg(), Some(-1));
+  }
 
-    assert_eq!(rl.feed(b"x"), None);
-    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("axbc").unwrap(), 2));
-    assert_eq!(rl.feed(b"\n").unwrap(), CString::new("axbc").unwrap());
+  /// Make sure that `Readline` objects can be sent between threads.
+  #[test]
+  fn send() {
+    fn check<T: Send>() {}
+    check::<Readline>();
+  }
 
-    rl.reset(CString::new("123").unwrap(), 3, true);
-    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("123").unwrap(), 3));
+  /// Check that redisplay hooks are invoked as expected.
+  #[test]
+  fn redisplay_hook() {
+    let rl = Readline::new();
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let calls_clone = calls.clone();
+    let hook = move |redisplay: &Redisplay<'_>| {
+      let mut calls = calls_clone.lock().unwrap();
+      calls.push((redisplay.line.to_owned(), redisplay.cursor));
+    };
+    rl.set_redisplay_hook(Some(Box::new(hook)));
 
-    assert_eq!(rl.feed(b"y"), None);
-    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("123y").unwrap(), 4));
+    assert_eq!(rl.feed(b"a"), None);
+    assert_eq!(rl.feed(b"b"), None);
+    assert_eq!(rl.feed(b"\x1b[D"), None);
+
+    // libreadline may redisplay more often than strictly necessary, so
+    // we only check for the presence of the expected calls.
+    let calls = calls.lock().unwrap();
+    assert_eq!(calls[0], (CString::new("a").unwrap(), 1));
+    assert_eq!(calls[1], (CString::new("ab").unwrap(), 2));
+    assert_eq!(calls.last().unwrap(), &(CString::new("ab").unwrap(), 1));
   }
 
-  /// Make sure that we can mix usage of different `Readline` instances.
+  /// Check that we can display and commit composition text of an input
+  /// method.
   #[test]
-  fn multi_instance() {
-    let mut rl1 = Readline::new();
-    assert_eq!(rl1.feed(b"abcdefg"), None);
+  fn preedit() {
+    let rl = Readline::new();
+    let preedits = Arc::new(Mutex::new(Vec::new()));
+    let preedits_clone = preedits.clone();
+    let hook = move |redisplay: &Redisplay<'_>| {
+      let preedit = redisplay.preedit.map(CStr::to_owned);
+      preedits_clone.lock().unwrap().push(preedit);
+    };
+    rl.set_redisplay_hook(Some(Box::new(hook)));
 
-    let mut rl2 = Readline::new();
-    assert_eq!(rl2.feed(b"efghijl"), None);
+    assert_eq!(rl.feed(b"ab"), None);
+    assert_eq!(rl.feed(b"\x02"), None);
+    rl.set_preedit(CString::new("ni").unwrap());
+    assert_eq!(rl.preedit(), Some(CString::new("ni").unwrap()));
+    assert_eq!(
+      preedits.lock().unwrap().last(),
+      Some(&Some(CString::new("ni").unwrap()))
+    );
+    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("ab").unwrap(), 1));
 
-    rl1.reset(CString::new("abc").unwrap(), 1, false);
+    assert!(rl.commit_preedit(CString::new("\u{4f60}").unwrap()));
+    assert_eq!(rl.preedit(), None);
+    assert_eq!(preedits.lock().unwrap().last(), Some(&None));
+    assert_eq!(
+      rl.peek(|s, p| (s.to_owned(), p)),
+      (CString::new("a\u{4f60}b").unwrap(), 4)
+    );
+    assert!(rl.is_dirty());
 
-    assert_eq!(rl1.feed(b"\n").unwrap(), CString::new("abc").unwrap());
-    assert_eq!(rl2.feed(b"\n").unwrap(), CString::new("efghijl").unwrap());
+    // The committed text is undone as a unit.
+    assert_eq!(rl.feed(b"\x1f"), None);
+    assert_eq!(rl.peek(|s, p| (s.to_owned(), p)), (CString::new("ab").unwrap(), 1));
+
+    rl.set_read_only(true);
+    rl.set_preedit(CString::new("x").unwrap());
+    assert!(!rl.commit_preedit(CString::new("x").unwrap()));
+    assert_eq!(rl.preedit(), None);
+    assert_eq!(rl.peek(|s, _| s.to_owned()), CString::new("ab").unwrap());
   }
 
   #[test]
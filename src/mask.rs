@@ -0,0 +1,61 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Masking of lines for displaying purposes, e.g., for password input.
+
+use std::ffi::CString;
+
+use unicode_segmentation::UnicodeSegmentation as _;
+
+
+/// The way a line is to be masked.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Mask {
+  /// Every grapheme cluster is represented by the given character.
+  Char(char),
+  /// The line is hidden entirely.
+  Hidden,
+}
+
+impl Mask {
+  /// Produce the masked representation of `line` along with the
+  /// cursor position within it.
+  pub(crate) fn apply(self, line: &[u8], cursor: usize) -> (CString, usize) {
+    match self {
+      Self::Char(c) => {
+        let count = |bytes: &[u8]| String::from_utf8_lossy(bytes).graphemes(true).count();
+        let mut buf = [0; 4];
+        let c = c.encode_utf8(&mut buf);
+        let text = c.repeat(count(line));
+        let cursor = count(&line[..cursor.min(line.len())]) * c.len();
+        // The character is checked not to be NUL when the mask is set.
+        (CString::new(text).unwrap(), cursor)
+      },
+      Self::Hidden => (CString::default(), 0),
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+
+  /// Check that lines are masked as expected.
+  #[test]
+  fn masking() {
+    let line = "a\u{e4}o\u{308}b".as_bytes();
+    let (text, cursor) = Mask::Char('*').apply(line, 3);
+    assert_eq!(text.to_bytes(), b"****");
+    assert_eq!(cursor, 2);
+
+    let (text, cursor) = Mask::Char('\u{2022}').apply(line, line.len());
+    assert_eq!(text.to_str().unwrap(), "\u{2022}".repeat(4));
+    assert_eq!(cursor, 12);
+
+    let (text, cursor) = Mask::Hidden.apply(line, 3);
+    assert_eq!(text.to_bytes(), b"");
+    assert_eq!(cursor, 0);
+  }
+}
@@ -0,0 +1,327 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Merging of history from multiple sources.
+
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::fs::read;
+use std::io;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::str;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use crate::Readline;
+
+
+/// Parse a timestamp in seconds since the epoch.
+pub(crate) fn parse_timestamp(text: &[u8]) -> Option<SystemTime> {
+  if text.is_empty() || !text.iter().all(u8::is_ascii_digit) {
+    return None
+  }
+
+  let secs = str::from_utf8(text).ok()?.parse().ok()?;
+  Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+
+/// Parse an entry in zsh's extended history format, i.e.,
+/// `: <start>:<duration>;<command>`.
+fn parse_zsh(line: &[u8]) -> Option<(SystemTime, &[u8])> {
+  let line = line.strip_prefix(b": ")?;
+  let colon = line.iter().position(|b| *b == b':')?;
+  let semicolon = line.iter().position(|b| *b == b';')?;
+  if semicolon < colon {
+    return None
+  }
+
+  let timestamp = parse_timestamp(&line[..colon])?;
+  let _duration = parse_timestamp(&line[colon + 1..semicolon])?;
+  Some((timestamp, &line[semicolon + 1..]))
+}
+
+
+/// Parse the content of a history file into entries.
+///
+/// Lines of the form `#<seconds>` are interpreted as the timestamp of
+/// the entry following them, as written by libreadline (and bash) when
+/// timestamps are enabled. Entries in zsh's extended history format are
+/// recognized as well, including multi-line ones.
+fn parse(data: &[u8]) -> Vec<(CString, Option<SystemTime>)> {
+  let mut entries = Vec::new();
+  let mut timestamp = None;
+  let mut lines = data.split(|b| *b == b'\n');
+
+  while let Some(line) = lines.next() {
+    if let Some(time) = line.strip_prefix(b"#").and_then(parse_timestamp) {
+      timestamp = Some(time);
+      continue
+    }
+
+    let (time, line) = if let Some((time, command)) = parse_zsh(line) {
+      let mut command = command.to_vec();
+      // zsh escapes line breaks within an entry with a backslash.
+      while command.ends_with(b"\\") {
+        match lines.next() {
+          Some(next) => {
+            let _backslash = command.pop();
+            command.push(b'\n');
+            command.extend_from_slice(next);
+          },
+          None => break,
+        }
+      }
+      (Some(time), command)
+    } else {
+      (timestamp.take(), line.to_vec())
+    };
+
+    if line.is_empty() {
+      continue
+    }
+
+    // Lines containing NUL bytes cannot be represented in the history.
+    if let Ok(line) = CString::new(line) {
+      entries.push((line, time))
+    }
+  }
+  entries
+}
+
+
+/// A source of history entries to merge.
+#[derive(Clone, Debug)]
+pub enum HistorySource {
+  /// A history file. A file that does not exist is treated as empty.
+  ///
+  /// Besides plain lines, timestamps as written by libreadline (and
+  /// bash) are understood, as is zsh's extended history format.
+  File(PathBuf),
+  /// Entries provided in memory, oldest first, along with their
+  /// timestamps, if known.
+  Entries(Vec<(CString, Option<SystemTime>)>),
+}
+
+impl HistorySource {
+  /// Retrieve the entries of the source, oldest first.
+  fn entries(&self) -> io::Result<Vec<(CString, Option<SystemTime>)>> {
+    match self {
+      Self::File(path) => match read(path) {
+        Ok(data) => Ok(parse(&data)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err),
+      },
+      Self::Entries(entries) => Ok(entries.clone()),
+    }
+  }
+}
+
+
+/// The order in which entries of multiple history sources are merged.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MergeOrder {
+  /// Add the entries source by source, in the order the sources were
+  /// provided.
+  Sequential,
+  /// Add the entries of all sources ordered by their timestamps.
+  ///
+  /// Entries without a timestamp are considered to be as old as the
+  /// closest preceding entry of the same source that has one, or older
+  /// than all others if there is none. Entries with equal timestamps
+  /// retain the order of the sources.
+  Chronological,
+}
+
+
+/// The deduplication to perform on merged history entries.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Dedup {
+  /// Keep all entries.
+  None,
+  /// Only keep the first of consecutive identical entries.
+  Consecutive,
+  /// Only keep the most recent of identical entries.
+  All,
+}
+
+
+/// A merge of multiple history sources, e.g., history files of
+/// different projects or of another line editor, into the history of a
+/// `Readline` instance.
+#[derive(Clone, Debug)]
+pub struct HistoryMerge {
+  /// The sources to merge.
+  sources: Vec<HistorySource>,
+  /// The order in which to merge entries.
+  order: MergeOrder,
+  /// The deduplication to perform.
+  dedup: Dedup,
+}
+
+impl HistoryMerge {
+  /// Create a new `HistoryMerge` without any sources, merging entries
+  /// sequentially and without deduplication.
+  pub fn new() -> Self {
+    Self {
+      sources: Vec::new(),
+      order: MergeOrder::Sequential,
+      dedup: Dedup::None,
+    }
+  }
+
+  /// Add a source to merge entries from.
+  pub fn source(mut self, source: HistorySource) -> Self {
+    self.sources.push(source);
+    self
+  }
+
+  /// Set the order in which entries are merged.
+  pub fn order(mut self, order: MergeOrder) -> Self {
+    self.order = order;
+    self
+  }
+
+  /// Set the deduplication to perform on the merged entries.
+  pub fn dedup(mut self, dedup: Dedup) -> Self {
+    self.dedup = dedup;
+    self
+  }
+
+  /// Collect the entries of all sources, ordered and deduplicated.
+  fn collect(&self) -> io::Result<Vec<CString>> {
+    let mut entries = Vec::new();
+    for source in &self.sources {
+      let mut timestamp = UNIX_EPOCH;
+      for (line, time) in source.entries()? {
+        timestamp = time.unwrap_or(timestamp);
+        entries.push((line, timestamp));
+      }
+    }
+
+    if self.order == MergeOrder::Chronological {
+      // The sort is stable and so retains the order of sources for
+      // equal timestamps.
+      let () = entries.sort_by_key(|(_, time)| *time);
+    }
+
+    let mut lines = entries.into_iter().map(|(line, _)| line).collect::<Vec<_>>();
+    match self.dedup {
+      Dedup::None => (),
+      Dedup::Consecutive => lines.dedup(),
+      Dedup::All => {
+        let mut seen = HashSet::new();
+        let () = lines.reverse();
+        let () = lines.retain(|line| seen.insert(line.clone()));
+        let () = lines.reverse();
+      },
+    }
+    Ok(lines)
+  }
+
+  /// Merge the entries of all sources into the history of the given
+  /// `Readline` instance, returning the number of entries added.
+  ///
+  /// The entries are appended to the existing history, subject to the
+  /// instance's history limit. Deduplication only considers merged
+  /// entries. The sources are read in their entirety before any entry
+  /// is added.
+  ///
+  /// # Errors
+  ///
+  /// Fails if a history file exists but could not be read, in which
+  /// case the history is left unchanged.
+  pub fn merge_into(&self, rl: &Readline) -> io::Result<usize> {
+    let lines = self.collect()?;
+    let () = rl.extend_history(&lines);
+    Ok(lines.len())
+  }
+}
+
+impl Default for HistoryMerge {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+
+  /// Create a `CString` from the given string.
+  fn cstr(s: &str) -> CString {
+    CString::new(s).unwrap()
+  }
+
+  /// Create `CString` objects from the given strings.
+  fn cstrs(strs: &[&str]) -> Vec<CString> {
+    strs.iter().copied().map(cstr).collect()
+  }
+
+  /// Create a `SystemTime` from the given seconds since the epoch.
+  fn time(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+  }
+
+  /// Check that history files in the supported formats are parsed
+  /// correctly.
+  #[test]
+  fn parse_files() {
+    let data = b"ls\n#10\ncd /\n#11\n#12\npwd\n\n#13x\nnul\0\n";
+    let expected = vec![
+      (cstr("ls"), None),
+      (cstr("cd /"), Some(time(10))),
+      (cstr("pwd"), Some(time(12))),
+      (cstr("#13x"), None),
+    ];
+    assert_eq!(parse(data), expected);
+
+    let data = b": 20:0;echo a\n: 21:3;for x in y; do\\\n  true\\\ndone\nplain\n: x:0;z\n";
+    let expected = vec![
+      (cstr("echo a"), Some(time(20))),
+      (cstr("for x in y; do\n  true\ndone"), Some(time(21))),
+      (cstr("plain"), None),
+      (cstr(": x:0;z"), None),
+    ];
+    assert_eq!(parse(data), expected);
+  }
+
+  /// Check that entries are ordered and deduplicated as requested.
+  #[test]
+  fn collect_entries() {
+    let source1 = HistorySource::Entries(vec![
+      (cstr("a"), Some(time(1))),
+      (cstr("b"), None),
+      (cstr("c"), Some(time(5))),
+    ]);
+    let source2 = HistorySource::Entries(vec![
+      (cstr("x"), None),
+      (cstr("b"), Some(time(2))),
+      (cstr("c"), Some(time(5))),
+    ]);
+    let source3 = HistorySource::File(PathBuf::from("/this/path/does/not/exist"));
+    let merge = HistoryMerge::new()
+      .source(source1)
+      .source(source2)
+      .source(source3);
+
+    let lines = merge.collect().unwrap();
+    assert_eq!(lines, cstrs(&["a", "b", "c", "x", "b", "c"]));
+
+    let merge = merge.order(MergeOrder::Chronological);
+    let lines = merge.collect().unwrap();
+    assert_eq!(lines, cstrs(&["x", "a", "b", "b", "c", "c"]));
+
+    let merge = merge.dedup(Dedup::Consecutive);
+    let lines = merge.collect().unwrap();
+    assert_eq!(lines, cstrs(&["x", "a", "b", "c"]));
+
+    let merge = merge.order(MergeOrder::Sequential).dedup(Dedup::All);
+    let lines = merge.collect().unwrap();
+    assert_eq!(lines, cstrs(&["a", "x", "b", "c"]));
+  }
+}
@@ -0,0 +1,137 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Counters for monitoring the usage of `Readline` instances.
+
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+
+/// The number of activations across all instances.
+static ACTIVATIONS: AtomicU64 = AtomicU64::new(0);
+/// The number of state swaps across all instances.
+static SWAPS: AtomicU64 = AtomicU64::new(0);
+/// The number of keys fed across all instances.
+static KEYS: AtomicU64 = AtomicU64::new(0);
+/// The number of lines completed across all instances.
+static LINES: AtomicU64 = AtomicU64::new(0);
+/// The number of history entries across all live instances.
+static HISTORY_ENTRIES: AtomicUsize = AtomicUsize::new(0);
+
+
+/// Usage counters of a single or all [`Readline`][crate::Readline]
+/// instances.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct Metrics {
+  /// The number of operations that acquired exclusive access to
+  /// libreadline. An operation, such as feeding a key, counts once,
+  /// regardless of how often access got acquired in its course.
+  pub activations: u64,
+  /// The number of activations that required swapping state into
+  /// libreadline's globals, i.e., context switches between instances.
+  pub swaps: u64,
+  /// The number of keys fed.
+  pub keys: u64,
+  /// The number of lines completed.
+  pub lines: u64,
+  /// The number of history entries.
+  pub history_entries: usize,
+}
+
+
+/// Usage counters of a single instance.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+  activations: u64,
+  swaps: u64,
+  keys: u64,
+  lines: u64,
+  /// The number of operations currently in progress.
+  operations: usize,
+  /// Whether a state swap got counted for the outermost operation in
+  /// progress.
+  operation_swapped: bool,
+}
+
+impl Counters {
+  /// Record the start of an operation, which counts as a single
+  /// activation, no matter how often the instance gets activated
+  /// until its end.
+  pub(crate) fn begin_operation(&mut self) {
+    if self.operations == 0 {
+      self.activations += 1;
+      let _count = ACTIVATIONS.fetch_add(1, Ordering::Relaxed);
+      self.operation_swapped = false;
+    }
+    self.operations += 1;
+  }
+
+  /// Record the end of an operation.
+  pub(crate) fn end_operation(&mut self) {
+    debug_assert!(self.operations > 0);
+    self.operations -= 1;
+  }
+
+  /// Record an activation, which may have involved a state swap.
+  pub(crate) fn activated(&mut self, swapped: bool) {
+    if self.operations == 0 {
+      self.activations += 1;
+      let _count = ACTIVATIONS.fetch_add(1, Ordering::Relaxed);
+    } else if self.operation_swapped {
+      return
+    }
+
+    if swapped {
+      self.swaps += 1;
+      let _count = SWAPS.fetch_add(1, Ordering::Relaxed);
+      self.operation_swapped = self.operations > 0;
+    }
+  }
+
+  /// Record a fed key.
+  pub(crate) fn fed(&mut self) {
+    self.keys += 1;
+    let _count = KEYS.fetch_add(1, Ordering::Relaxed);
+  }
+
+  /// Record a completed line.
+  pub(crate) fn completed(&mut self) {
+    self.lines += 1;
+    let _count = LINES.fetch_add(1, Ordering::Relaxed);
+  }
+
+  /// Retrieve the counters in the form of a `Metrics` object.
+  pub(crate) fn metrics(&self, history_entries: usize) -> Metrics {
+    Metrics {
+      activations: self.activations,
+      swaps: self.swaps,
+      keys: self.keys,
+      lines: self.lines,
+      history_entries,
+    }
+  }
+}
+
+
+/// Record a change in the number of history entries of an instance.
+pub(crate) fn history_changed(before: usize, after: usize) {
+  if after > before {
+    let _count = HISTORY_ENTRIES.fetch_add(after - before, Ordering::Relaxed);
+  } else if before > after {
+    let _count = HISTORY_ENTRIES.fetch_sub(before - after, Ordering::Relaxed);
+  }
+}
+
+
+/// Retrieve the counters aggregated over all instances.
+pub(crate) fn global() -> Metrics {
+  Metrics {
+    activations: ACTIVATIONS.load(Ordering::Relaxed),
+    swaps: SWAPS.load(Ordering::Relaxed),
+    keys: KEYS.load(Ordering::Relaxed),
+    lines: LINES.load(Ordering::Relaxed),
+    history_entries: HISTORY_ENTRIES.load(Ordering::Relaxed),
+  }
+}
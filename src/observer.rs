@@ -0,0 +1,99 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Observation of the events caused by feeding input.
+
+use std::ffi::CStr;
+
+
+/// A trait for observing the editing events caused by feeding input to
+/// a [`Readline`][crate::Readline] instance.
+///
+/// All methods have empty default implementations, allowing observers
+/// to pick the events they are interested in. Events caused by the
+/// same key are reported in the order their methods are listed here.
+/// Observers are invoked with the instance active and must not call
+/// back into it.
+pub trait LineObserver: Send {
+  /// A key sequence got dispatched to a command.
+  ///
+  /// Key sequences are not reported for masked lines or instances
+  /// handling sensitive data.
+  fn key_dispatched(&mut self, _keyseq: &[u8]) {}
+
+  /// Text got deleted from the line, starting at byte offset `start`.
+  fn text_deleted(&mut self, _start: usize, _text: &[u8]) {}
+
+  /// Text got inserted into the line at byte offset `start`.
+  fn text_inserted(&mut self, _start: usize, _text: &[u8]) {}
+
+  /// The cursor moved from byte offset `from` to `to`.
+  fn cursor_moved(&mut self, _from: usize, _to: usize) {}
+
+  /// The history got navigated to the entry at the given index. An
+  /// index equal to the number of history entries refers to the line
+  /// being newly entered.
+  fn history_navigated(&mut self, _index: usize) {}
+
+  /// A line got accepted.
+  fn line_accepted(&mut self, _line: &CStr) {}
+
+  /// A key got rejected, either by the input filter (see
+  /// [`Readline::set_input_filter`][crate::Readline::set_input_filter])
+  /// or because the instance is in read-only mode (see
+  /// [`Readline::set_read_only`][crate::Readline::set_read_only]).
+  fn input_rejected(&mut self, _key: &[u8]) {}
+}
+
+
+/// The effects of a single feed, as reported to an observer.
+#[derive(Debug)]
+pub(crate) struct Edit<'data> {
+  /// The key sequence dispatched.
+  pub keyseq: &'data [u8],
+  /// The line before the feed.
+  pub before: &'data [u8],
+  /// The cursor position before the feed.
+  pub from: usize,
+  /// The line after the feed.
+  pub after: &'data [u8],
+  /// The cursor position after the feed.
+  pub to: usize,
+  /// The history index navigated to, if any.
+  pub history: Option<usize>,
+  /// The line accepted, if any.
+  pub line: Option<&'data CStr>,
+}
+
+impl Edit<'_> {
+  /// Report the effects to the given observer.
+  pub(crate) fn notify(&self, observer: &mut dyn LineObserver) {
+    if !self.keyseq.is_empty() {
+      observer.key_dispatched(self.keyseq)
+    }
+
+    if self.before != self.after {
+      let (prefix, suffix) = crate::diff::common(self.before, self.after);
+      let removed = &self.before[prefix..self.before.len() - suffix];
+      let inserted = &self.after[prefix..self.after.len() - suffix];
+      if !removed.is_empty() {
+        observer.text_deleted(prefix, removed)
+      }
+      if !inserted.is_empty() {
+        observer.text_inserted(prefix, inserted)
+      }
+    }
+
+    if self.from != self.to {
+      observer.cursor_moved(self.from, self.to)
+    }
+
+    if let Some(index) = self.history {
+      observer.history_navigated(index)
+    }
+
+    if let Some(line) = self.line {
+      observer.line_accepted(line)
+    }
+  }
+}
@@ -0,0 +1,301 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Recording and replaying of the input fed to a `Readline` instance.
+
+use std::ffi::CString;
+use std::fmt::Display;
+use std::fmt::Error;
+use std::fmt::Formatter;
+use std::io;
+use std::io::ErrorKind;
+use std::str::FromStr;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::Feed;
+use crate::Readline;
+
+
+/// An input event recorded for a `Readline` instance.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum RecordedEvent {
+  /// A key got fed (see [`Readline::feed_key`]).
+  Feed(Vec<u8>),
+  /// The line got reset (see [`Readline::reset`]).
+  Reset {
+    /// The line reset to.
+    line: CString,
+    /// The cursor position reset to.
+    cursor: usize,
+    /// Whether the undo list got cleared.
+    clear_undo: bool,
+  },
+}
+
+
+/// A wrapper for displaying bytes escaped such that they contain
+/// neither whitespace nor non-printable characters.
+pub(crate) struct Escaped<'bytes>(pub &'bytes [u8]);
+
+impl Display for Escaped<'_> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+    for &b in self.0 {
+      match b {
+        b'\\' => f.write_str("\\\\")?,
+        0x21..=0x7e => write!(f, "{}", char::from(b))?,
+        _ => write!(f, "\\x{:02x}", b)?,
+      }
+    }
+    Ok(())
+  }
+}
+
+
+/// Reverse the escaping performed by `Escaped`.
+pub(crate) fn unescape(text: &str) -> Option<Vec<u8>> {
+  let mut bytes = Vec::with_capacity(text.len());
+  let mut iter = text.bytes();
+  while let Some(b) = iter.next() {
+    if b == b'\\' {
+      match iter.next()? {
+        b'\\' => bytes.push(b'\\'),
+        b'e' => bytes.push(b'\x1b'),
+        b'n' => bytes.push(b'\n'),
+        b'r' => bytes.push(b'\r'),
+        b't' => bytes.push(b'\t'),
+        b'x' => {
+          let digits = [iter.next()?, iter.next()?];
+          let digits = std::str::from_utf8(&digits).ok()?;
+          bytes.push(u8::from_str_radix(digits, 16).ok()?);
+        },
+        _ => return None,
+      }
+    } else {
+      bytes.push(b)
+    }
+  }
+  Some(bytes)
+}
+
+
+/// A log of the input events of a `Readline` instance, along with the
+/// time they occurred at relative to the start of the recording.
+///
+/// A recording can be converted into a compact line based text format
+/// via its [`Display`] implementation and parsed back via [`FromStr`],
+/// e.g., to have users attach it to bug reports. Each line contains
+/// the time of an event in milliseconds followed by the event itself:
+/// ```text
+/// 0 feed a
+/// 215 feed \x1b[D
+/// 1003 reset 2 1 ab\x20c
+/// ```
+/// Bytes outside of the printable ASCII range as well as spaces are
+/// hex encoded, with backslashes being escaped by another one. When
+/// parsing, the escape sequences `\e`, `\n`, `\r`, and `\t` are
+/// understood as well.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Recording {
+  /// The recorded events, oldest first.
+  events: Vec<(Duration, RecordedEvent)>,
+}
+
+impl Recording {
+  /// Retrieve the recorded events along with their time relative to
+  /// the start of the recording, oldest first.
+  pub fn events(&self) -> &[(Duration, RecordedEvent)] {
+    &self.events
+  }
+
+  /// Replay the recorded events on the given `Readline` instance,
+  /// reporting the outcome of each feed.
+  ///
+  /// Events are replayed at once, i.e., without regard for the time
+  /// they occurred at. Applications requiring the original timing can
+  /// drive the instance based on [`events`][Recording::events]
+  /// themselves. To reproduce the recorded editing session faithfully,
+  /// `rl` should be configured the same way as the recorded instance,
+  /// which typically means being freshly created.
+  pub fn replay(&self, rl: &Readline) -> Vec<Feed> {
+    self
+      .events
+      .iter()
+      .filter_map(|(_, event)| match event {
+        RecordedEvent::Feed(key) => Some(rl.feed_key(key)),
+        RecordedEvent::Reset {
+          line,
+          cursor,
+          clear_undo,
+        } => {
+          let () = rl.reset(line, *cursor, *clear_undo);
+          None
+        },
+      })
+      .collect()
+  }
+}
+
+impl Display for Recording {
+  fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+    for (time, event) in &self.events {
+      write!(f, "{} ", time.as_millis())?;
+      match event {
+        RecordedEvent::Feed(key) => {
+          write!(f, "feed {}", Escaped(key))?;
+        },
+        RecordedEvent::Reset {
+          line,
+          cursor,
+          clear_undo,
+        } => {
+          write!(
+            f,
+            "reset {} {} {}",
+            cursor,
+            u8::from(*clear_undo),
+            Escaped(line.to_bytes())
+          )?;
+        },
+      }
+      f.write_str("\n")?;
+    }
+    Ok(())
+  }
+}
+
+impl FromStr for Recording {
+  type Err = io::Error;
+
+  /// Parse a recording from its textual representation.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error of kind [`InvalidData`][ErrorKind::InvalidData]
+  /// if `s` is not a valid recording.
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    fn parse_line(line: &str) -> Option<(Duration, RecordedEvent)> {
+      let mut fields = line.split(' ');
+      let time = Duration::from_millis(fields.next()?.parse().ok()?);
+      let event = match (fields.next()?, fields.next()?, fields.next()) {
+        ("feed", key, None) => RecordedEvent::Feed(unescape(key)?),
+        ("reset", cursor, Some(clear_undo)) => {
+          let cursor = cursor.parse().ok()?;
+          let clear_undo = match clear_undo {
+            "0" => false,
+            "1" => true,
+            _ => return None,
+          };
+          let line = CString::new(unescape(fields.next()?)?).ok()?;
+          if cursor > line.as_bytes().len() || fields.next().is_some() {
+            return None
+          }
+          RecordedEvent::Reset {
+            line,
+            cursor,
+            clear_undo,
+          }
+        },
+        _ => return None,
+      };
+      Some((time, event))
+    }
+
+    let events = s
+      .lines()
+      .enumerate()
+      .filter(|(_, line)| !line.is_empty())
+      .map(|(idx, line)| {
+        parse_line(line).ok_or_else(|| {
+          io::Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid recording event in line {}: {}", idx + 1, line),
+          )
+        })
+      })
+      .collect::<Result<_, _>>()?;
+
+    Ok(Self { events })
+  }
+}
+
+
+/// A recorder of the input events of a `Readline` instance.
+#[derive(Debug)]
+pub(crate) struct Recorder {
+  /// The time the recording got started at.
+  start: Instant,
+  /// The recording being made.
+  recording: Recording,
+}
+
+impl Recorder {
+  /// Create a new `Recorder`, starting the recording now.
+  pub(crate) fn new() -> Self {
+    Self {
+      start: Instant::now(),
+      recording: Recording::default(),
+    }
+  }
+
+  /// Record the given event as having happened now.
+  pub(crate) fn record(&mut self, event: RecordedEvent) {
+    let () = self.recording.events.push((self.start.elapsed(), event));
+  }
+
+  /// Finish the recording.
+  pub(crate) fn finish(self) -> Recording {
+    self.recording
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+
+  /// Check that recordings can be converted to and from text.
+  #[test]
+  fn text_conversion() {
+    let recording = Recording {
+      events: vec![
+        (Duration::from_millis(0), RecordedEvent::Feed(b"a".to_vec())),
+        (Duration::from_millis(215), RecordedEvent::Feed(b"\x1b[D".to_vec())),
+        (Duration::from_millis(300), RecordedEvent::Feed(b"\\ ".to_vec())),
+        (
+          Duration::from_millis(1003),
+          RecordedEvent::Reset {
+            line: CString::new("ab c").unwrap(),
+            cursor: 2,
+            clear_undo: true,
+          },
+        ),
+      ],
+    };
+
+    let text = recording.to_string();
+    assert_eq!(
+      text,
+      "0 feed a\n215 feed \\x1b[D\n300 feed \\\\\\x20\n1003 reset 2 1 ab\\x20c\n"
+    );
+    assert_eq!(text.parse::<Recording>().unwrap(), recording);
+    assert_eq!("".parse::<Recording>().unwrap(), Recording::default());
+
+    let invalid = [
+      "feed a",
+      "0 feed",
+      "0 feed a b",
+      "0 feed \\x1",
+      "0 feed \\q",
+      "0 type a",
+      "0 reset 3 1 ab",
+      "0 reset 0 2 ab",
+      "0 reset 0 1 \\x00",
+    ];
+    for text in invalid.iter() {
+      let err = text.parse::<Recording>().unwrap_err();
+      assert_eq!(err.kind(), ErrorKind::InvalidData, "{}", text);
+    }
+  }
+}
@@ -0,0 +1,135 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Named registers for libreadline's vi editing mode.
+//!
+//! libreadline itself only knows about its kill ring, which acts as
+//! the unnamed register. We provide the named registers `a` through
+//! `z` on top of it.
+
+use std::collections::HashMap;
+use std::ffi::CString;
+
+
+/// The name of the unnamed register.
+pub(crate) const UNNAMED: u8 = b'"';
+
+
+/// Check whether the given character names a register.
+pub(crate) fn is_valid(name: char) -> bool {
+  name == char::from(UNNAMED) || name.is_ascii_alphabetic()
+}
+
+
+/// The state of selecting a register for the next command.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Selection {
+  /// The selection got started, but no register named yet.
+  Pending,
+  /// The register with the given name is selected.
+  Register(u8),
+}
+
+
+/// The named registers of a single `Readline` instance.
+#[derive(Debug, Default)]
+pub(crate) struct Registers {
+  /// The contents of the named registers, keyed by their lowercase
+  /// name.
+  named: HashMap<u8, CString>,
+  /// The state of selecting a register, if any.
+  selection: Option<Selection>,
+}
+
+impl Registers {
+  /// Retrieve the content of the named register `name`.
+  pub(crate) fn get(&self, name: u8) -> Option<&CString> {
+    self.named.get(&name.to_ascii_lowercase())
+  }
+
+  /// Store text in the named register `name`, appending to its current
+  /// content if the name is an uppercase letter.
+  pub(crate) fn set(&mut self, name: u8, text: &[u8]) {
+    let mut bytes = match self.named.remove(&name.to_ascii_lowercase()) {
+      Some(content) if name.is_ascii_uppercase() => content.into_bytes(),
+      _ => Vec::new(),
+    };
+    bytes.extend_from_slice(text);
+    // The text originates from a NUL terminated line buffer or a
+    // `CStr` and so cannot contain any NUL bytes itself.
+    let _prev = self
+      .named
+      .insert(name.to_ascii_lowercase(), CString::new(bytes).unwrap());
+  }
+
+  /// Check whether a register selection got started, but the register
+  /// has not been named yet.
+  pub(crate) fn is_selecting(&self) -> bool {
+    self.selection == Some(Selection::Pending)
+  }
+
+  /// Start the selection of a register.
+  pub(crate) fn start_selection(&mut self) {
+    self.selection = Some(Selection::Pending)
+  }
+
+  /// Complete the selection of a register with the given key, which
+  /// has to be a valid register name for the selection to take effect.
+  pub(crate) fn finish_selection(&mut self, key: &[u8]) {
+    self.selection = match key {
+      [name] if is_valid(char::from(*name)) => Some(Selection::Register(*name)),
+      _ => None,
+    }
+  }
+
+  /// Retrieve the name of the register selected, if any.
+  pub(crate) fn selected(&self) -> Option<u8> {
+    match self.selection {
+      Some(Selection::Register(name)) => Some(name),
+      Some(Selection::Pending) | None => None,
+    }
+  }
+
+  /// Clear the register selection.
+  pub(crate) fn clear_selection(&mut self) {
+    self.selection = None
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+
+  /// Check that registers can be selected, set, and appended to.
+  #[test]
+  fn registers() {
+    let mut registers = Registers::default();
+    assert_eq!(registers.get(b'a'), None);
+
+    registers.set(b'a', b"foo");
+    registers.set(b'A', b"bar");
+    assert_eq!(registers.get(b'a').unwrap().to_bytes(), b"foobar");
+    assert_eq!(registers.get(b'A').unwrap().to_bytes(), b"foobar");
+    registers.set(b'a', b"baz");
+    assert_eq!(registers.get(b'a').unwrap().to_bytes(), b"baz");
+    registers.set(b'B', b"x");
+    assert_eq!(registers.get(b'b').unwrap().to_bytes(), b"x");
+
+    assert!(!registers.is_selecting());
+    registers.start_selection();
+    assert!(registers.is_selecting());
+    assert_eq!(registers.selected(), None);
+    registers.finish_selection(b"q");
+    assert!(!registers.is_selecting());
+    assert_eq!(registers.selected(), Some(b'q'));
+    registers.clear_selection();
+    assert_eq!(registers.selected(), None);
+
+    registers.start_selection();
+    registers.finish_selection(b"\x1b");
+    assert_eq!(registers.selected(), None);
+    assert!(!registers.is_selecting());
+  }
+}
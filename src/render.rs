@@ -0,0 +1,462 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Rendering of the input line to a terminal.
+//!
+//! The functionality in this module produces VT100 compatible escape
+//! sequences for (re)drawing a prompt and the line being edited,
+//! wrapping it across multiple terminal rows as necessary. It is
+//! entirely optional: applications using other means for displaying
+//! the input line can just ignore it.
+//!
+//! For displaying the line in widgets confined to a single row,
+//! [`Viewport`] provides horizontal scrolling instead.
+
+use std::fmt::Write as _;
+
+use unicode_segmentation::UnicodeSegmentation as _;
+use unicode_width::UnicodeWidthStr as _;
+
+use crate::PROMPT_END_IGNORE;
+use crate::PROMPT_START_IGNORE;
+
+
+/// A position on the screen, relative to the first row occupied by
+/// the input line.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+struct Position {
+  row: usize,
+  col: usize,
+}
+
+impl Position {
+  /// Advance the position by a grapheme cluster of the given width,
+  /// wrapping to the next row as a terminal with automatic margins
+  /// would.
+  fn advance(self, width: usize, columns: usize) -> Self {
+    if self.col + width > columns {
+      Self {
+        row: self.row + 1,
+        col: width,
+      }
+    } else {
+      Self {
+        row: self.row,
+        col: self.col + width,
+      }
+    }
+  }
+
+  /// Normalize a position that is located just past the last column
+  /// of a row to the start of the next one.
+  fn normalize(self, columns: usize) -> Self {
+    if self.col >= columns {
+      Self {
+        row: self.row + 1,
+        col: 0,
+      }
+    } else {
+      self
+    }
+  }
+}
+
+
+/// Convert a prompt, potentially containing `\1` and `\2` invisible
+/// character markers, into the text to output and its visible width.
+pub(crate) fn expand_prompt(prompt: &str) -> (String, usize) {
+  let mut visible = true;
+  let mut output = String::with_capacity(prompt.len());
+  let mut width = 0;
+
+  for c in prompt.chars() {
+    match c {
+      c if c as u32 == u32::from(PROMPT_START_IGNORE) => visible = false,
+      c if c as u32 == u32::from(PROMPT_END_IGNORE) => visible = true,
+      c => {
+        output.push(c);
+        if visible {
+          let mut buf = [0; 4];
+          width += c.encode_utf8(&mut buf).width();
+        }
+      },
+    }
+  }
+  (output, width)
+}
+
+
+/// Retrieve the textual representation of a grapheme cluster, as it
+/// is to be displayed, along with its width.
+///
+/// Control characters are displayed in caret notation (e.g., `^A`), in
+/// the same way libreadline does it.
+fn display(grapheme: &str) -> (Option<String>, usize) {
+  let mut chars = grapheme.chars();
+  match (chars.next(), chars.next()) {
+    (Some(c), None) if c.is_ascii_control() => {
+      let caret = format!("^{}", ((c as u8) ^ 0x40) as char);
+      (Some(caret), 2)
+    },
+    _ => (None, grapheme.width()),
+  }
+}
+
+
+/// A renderer for an input line on a terminal.
+///
+/// The renderer keeps track of the number of rows occupied by the most
+/// recently rendered line as well as the position of the cursor within
+/// it. It assumes that the terminal cursor has not been moved by
+/// other parties in between calls.
+#[derive(Clone, Copy, Debug)]
+pub struct Renderer {
+  /// The width of the terminal, in columns.
+  columns: usize,
+  /// The row the cursor was left on by the most recent render,
+  /// relative to the first row of the input line.
+  cursor_row: usize,
+  /// The number of rows occupied by the most recent render.
+  rows: usize,
+}
+
+impl Renderer {
+  /// Create a new `Renderer` for a terminal of the given width.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `columns` is zero.
+  pub fn new(columns: usize) -> Self {
+    assert!(columns > 0, "terminal width must not be zero");
+
+    Self {
+      columns,
+      cursor_row: 0,
+      rows: 0,
+    }
+  }
+
+  /// Retrieve the terminal width the renderer works with.
+  pub fn columns(&self) -> usize {
+    self.columns
+  }
+
+  /// Adjust the width of the terminal, e.g., after it got resized.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `columns` is zero.
+  pub fn set_columns(&mut self, columns: usize) {
+    assert!(columns > 0, "terminal width must not be zero");
+    self.columns = columns
+  }
+
+  /// Produce the output for drawing the given line and prompt, with
+  /// the cursor at `cursor`.
+  ///
+  /// `cursor` is a byte position in `line`, as reported by
+  /// [`Readline::peek`][crate::Readline::peek]. The prompt may contain
+  /// invisible characters enclosed in `\1` and `\2` markers, in the
+  /// same way as supported by [`Readline::set_prompt`][crate::Readline::set_prompt].
+  /// Newline characters in `line` start a new row. The previously
+  /// rendered input line, if any, is replaced.
+  pub fn render(&mut self, prompt: &str, line: &str, cursor: usize) -> String {
+    self.render_with_right_prompt(prompt, "", line, cursor)
+  }
+
+  /// Produce the output for drawing the given line and prompt, with
+  /// the cursor at `cursor`, along with a right-aligned prompt.
+  ///
+  /// The right prompt is displayed at the end of the first row, akin
+  /// to zsh's `RPROMPT`. It may contain invisible characters in the
+  /// same way `prompt` can. It is hidden if the prompt and line do not
+  /// fit on a single row with at least one blank column left between
+  /// them and the right prompt. See [`render`][Renderer::render] for
+  /// details on the remaining arguments.
+  pub fn render_with_right_prompt(
+    &mut self,
+    prompt: &str,
+    right_prompt: &str,
+    line: &str,
+    cursor: usize,
+  ) -> String {
+    let (prompt, prompt_width) = expand_prompt(prompt);
+    let mut output = self.clear();
+    output.push_str(&prompt);
+
+    let mut end = Position::default().advance(prompt_width, self.columns);
+    let mut cursor_pos = None;
+
+    for (idx, grapheme) in line.grapheme_indices(true) {
+      if cursor_pos.is_none() && cursor < idx + grapheme.len() {
+        cursor_pos = Some(end);
+      }
+
+      if grapheme == "\n" {
+        // Lines containing newlines, e.g., as a result of multi-line
+        // input, continue at the start of the next row.
+        end = Position {
+          row: end.row + 1,
+          col: 0,
+        };
+        output.push_str("\r\n");
+        continue
+      }
+
+      let (text, width) = display(grapheme);
+      end = end.advance(width, self.columns);
+      output.push_str(text.as_deref().unwrap_or(grapheme));
+    }
+
+    let cursor = cursor_pos.unwrap_or(end).normalize(self.columns);
+
+    let (right_prompt, right_width) = expand_prompt(right_prompt);
+    if right_width > 0
+      && end.row == 0
+      && cursor.row == 0
+      && end.col + right_width < self.columns
+    {
+      let _ = write!(output, "\r\x1b[{}C", self.columns - right_width);
+      output.push_str(&right_prompt);
+    }
+
+    // After having emitted all text, the terminal cursor may be in a
+    // "pending wrap" state in the last column. A carriage return gets
+    // us to a well defined position at the start of the last row.
+    output.push('\r');
+
+    if cursor.row > end.row {
+      // The cursor is positioned at the very start of a row not yet
+      // occupied by any text.
+      for _ in end.row..cursor.row {
+        output.push('\n');
+      }
+    } else if cursor.row < end.row {
+      let _ = write!(output, "\x1b[{}A", end.row - cursor.row);
+    }
+
+    if cursor.col > 0 {
+      let _ = write!(output, "\x1b[{}C", cursor.col);
+    }
+
+    self.rows = end.row.max(cursor.row) + 1;
+    self.cursor_row = cursor.row;
+    output
+  }
+
+  /// Produce the output for erasing the previously rendered input line,
+  /// leaving the cursor at the start of its first row.
+  pub fn clear(&mut self) -> String {
+    let mut output = String::new();
+    if self.cursor_row > 0 {
+      let _ = write!(output, "\x1b[{}A", self.cursor_row);
+    }
+    output.push_str("\r\x1b[J");
+
+    self.cursor_row = 0;
+    self.rows = 0;
+    output
+  }
+
+  /// Produce the output for moving the cursor to the start of the row
+  /// following the previously rendered input line, e.g., once the line
+  /// got accepted.
+  ///
+  /// Subsequent renders will start on that row.
+  pub fn finish(&mut self) -> String {
+    let mut output = String::new();
+    let rows = self.rows.max(1);
+    if rows - 1 > self.cursor_row {
+      let _ = write!(output, "\x1b[{}B", rows - 1 - self.cursor_row);
+    }
+    output.push_str("\r\n");
+
+    self.cursor_row = 0;
+    self.rows = 0;
+    output
+  }
+}
+
+
+/// A horizontally scrolling view of an input line, confined to a
+/// single row of fixed width.
+///
+/// This is the equivalent of libreadline's `horizontal-scroll-mode`,
+/// meant for embedding the line in status bars, dialog boxes, and
+/// similar widgets. The viewport scrolls as little as possible to keep
+/// the cursor visible.
+#[derive(Clone, Copy, Debug)]
+pub struct Viewport {
+  /// The width of the viewport, in columns.
+  width: usize,
+  /// The byte index of the first visible grapheme cluster.
+  offset: usize,
+}
+
+impl Viewport {
+  /// Create a new `Viewport` of the given width.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `width` is zero.
+  pub fn new(width: usize) -> Self {
+    assert!(width > 0, "viewport width must not be zero");
+
+    Self { width, offset: 0 }
+  }
+
+  /// Retrieve the width of the viewport.
+  pub fn width(&self) -> usize {
+    self.width
+  }
+
+  /// Adjust the width of the viewport.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `width` is zero.
+  pub fn set_width(&mut self, width: usize) {
+    assert!(width > 0, "viewport width must not be zero");
+    self.width = width
+  }
+
+  /// Determine the part of `line` that is visible with the cursor at
+  /// byte position `cursor`, scrolling as necessary, along with the
+  /// column of the cursor relative to the start of this part.
+  pub fn view<'line>(&mut self, line: &'line str, cursor: usize) -> (&'line str, usize) {
+    let graphemes = line
+      .grapheme_indices(true)
+      .map(|(idx, grapheme)| (idx, grapheme.width()))
+      .collect::<Vec<_>>();
+    let cursor = graphemes
+      .iter()
+      .take_while(|(idx, _)| *idx < cursor)
+      .count();
+    // The cursor itself occupies a cell even past the end of the line.
+    let cursor_width = graphemes.get(cursor).map(|(_, width)| *width).unwrap_or(1);
+
+    let mut first = graphemes
+      .iter()
+      .take_while(|(idx, _)| *idx < self.offset)
+      .count()
+      .min(cursor);
+    let mut column = graphemes[first..cursor]
+      .iter()
+      .map(|(_, width)| width)
+      .sum::<usize>();
+    while first < cursor && column + cursor_width > self.width {
+      column -= graphemes[first].1;
+      first += 1;
+    }
+
+    let mut used = 0;
+    let last = first
+      + graphemes[first..]
+        .iter()
+        .take_while(|(_, width)| {
+          used += width;
+          used <= self.width
+        })
+        .count();
+
+    let start = graphemes.get(first).map(|(idx, _)| *idx).unwrap_or(line.len());
+    let end = graphemes.get(last).map(|(idx, _)| *idx).unwrap_or(line.len());
+    self.offset = start;
+    (&line[start..end], column)
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+
+  /// Check that we expand prompts properly.
+  #[test]
+  fn prompt_expansion() {
+    assert_eq!(expand_prompt("> "), ("> ".to_string(), 2));
+    assert_eq!(
+      expand_prompt("\x01\x1b[1m\x02>\x01\x1b[0m\x02 "),
+      ("\x1b[1m>\x1b[0m ".to_string(), 2)
+    );
+  }
+
+  /// Check rendering of a line fitting on a single row.
+  #[test]
+  fn render_single_row() {
+    let mut renderer = Renderer::new(80);
+    assert_eq!(renderer.render("> ", "abc", 3), "\r\x1b[J> abc\r\x1b[5C");
+    assert_eq!(renderer.render("> ", "abc", 0), "\r\x1b[J> abc\r\x1b[2C");
+    assert_eq!(renderer.render("", "a\x01", 2), "\r\x1b[Ja^A\r\x1b[3C");
+    assert_eq!(renderer.finish(), "\r\n");
+  }
+
+  /// Check rendering of a line wrapping across rows.
+  #[test]
+  fn render_wrapped() {
+    let mut renderer = Renderer::new(4);
+    assert_eq!(renderer.render("> ", "abcde", 1), "\r\x1b[J> abcde\r\x1b[1A\x1b[3C");
+    assert_eq!(renderer.rows, 2);
+    assert_eq!(renderer.cursor_row, 0);
+
+    // The cursor at the end of a completely filled row ends up on the
+    // next one.
+    assert_eq!(renderer.render("> ", "ab", 2), "\r\x1b[J> ab\r\n");
+    assert_eq!(renderer.cursor_row, 1);
+    assert_eq!(renderer.clear(), "\x1b[1A\r\x1b[J");
+
+    // A wide character not fitting into the last column wraps as a
+    // whole.
+    assert_eq!(renderer.render("> ", "a\u{4f60}", 4), "\r\x1b[J> a\u{4f60}\r\x1b[2C");
+    assert_eq!(renderer.cursor_row, 1);
+    assert_eq!(renderer.finish(), "\r\n");
+  }
+
+  /// Check rendering of a right prompt.
+  #[test]
+  fn render_right_prompt() {
+    let mut renderer = Renderer::new(10);
+    assert_eq!(
+      renderer.render_with_right_prompt("> ", "\x01\x1b[2m\x02[1]", "ab", 2),
+      "\r\x1b[J> ab\r\x1b[7C\x1b[2m[1]\r\x1b[4C"
+    );
+    // Without a blank column in between, the right prompt is hidden.
+    assert_eq!(
+      renderer.render_with_right_prompt("> ", "[1]", "abcde", 5),
+      "\r\x1b[J> abcde\r\x1b[7C"
+    );
+    assert_eq!(
+      renderer.render_with_right_prompt("> ", "[1]", "abcdefghi", 9),
+      "\r\x1b[J> abcdefghi\r\x1b[1C"
+    );
+  }
+
+  /// Check that the viewport scrolls as expected.
+  #[test]
+  fn viewport_scrolling() {
+    let mut viewport = Viewport::new(4);
+    assert_eq!(viewport.view("ab", 2), ("ab", 2));
+    assert_eq!(viewport.view("abcd", 4), ("bcd", 3));
+    assert_eq!(viewport.view("abcdef", 6), ("def", 3));
+    assert_eq!(viewport.view("abcdef", 4), ("def", 1));
+    assert_eq!(viewport.view("abcdef", 2), ("cdef", 0));
+    assert_eq!(viewport.view("abcdef", 0), ("abcd", 0));
+
+    // Wide characters are never cut in half.
+    let mut viewport = Viewport::new(3);
+    assert_eq!(viewport.view("a\u{4f60}\u{597d}", 7), ("\u{597d}", 2));
+    assert_eq!(viewport.view("a\u{4f60}\u{597d}", 1), ("\u{4f60}", 0));
+  }
+
+  /// Check rendering of a line containing newlines.
+  #[test]
+  fn render_multi_line() {
+    let mut renderer = Renderer::new(80);
+    assert_eq!(renderer.render("> ", "a\nbc", 3), "\r\x1b[J> a\r\nbc\r\x1b[1C");
+    assert_eq!(renderer.cursor_row, 1);
+    assert_eq!(renderer.render("> ", "a\nbc", 1), "\x1b[1A\r\x1b[J> a\r\nbc\r\x1b[1A\x1b[3C");
+    assert_eq!(renderer.cursor_row, 0);
+    assert_eq!(renderer.finish(), "\x1b[1B\r\n");
+  }
+}
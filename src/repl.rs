@@ -0,0 +1,335 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! A turnkey read-eval-print loop driving a [`Readline`] instance.
+
+use std::ffi::CString;
+#[cfg(feature = "termion")]
+use std::io::stdin;
+#[cfg(feature = "termion")]
+use std::io::stdout;
+use std::io::BufRead as _;
+use std::io::BufReader;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Result;
+#[cfg(feature = "termion")]
+use std::io::Stdin;
+#[cfg(feature = "termion")]
+use std::io::Stdout;
+use std::io::Write;
+
+#[cfg(feature = "termion")]
+use termion::raw::IntoRawMode as _;
+#[cfg(feature = "termion")]
+use termion::raw::RawTerminal;
+
+use crate::frontend::complete;
+use crate::frontend::redraw;
+use crate::frontend::DEFAULT_COLUMNS;
+use crate::key::decode;
+use crate::key::Decode;
+use crate::key::Key;
+use crate::key::Modifiers;
+use crate::render::Renderer;
+use crate::Feed;
+use crate::Readline;
+
+
+/// A read-eval-print loop reading input bytes from a [`Read`]er and
+/// rendering the line being edited to a [`Write`]r.
+///
+/// Input is expected in the form a terminal in raw mode would provide
+/// it, i.e., unprocessed bytes as produced by key presses. Completed
+/// lines that are not empty are added to the instance's history.
+#[derive(Debug)]
+pub struct Repl<R, W> {
+  /// The `Readline` instance used for editing lines.
+  rl: Readline,
+  /// The prompt to display.
+  prompt: String,
+  /// The source of input bytes.
+  reader: BufReader<R>,
+  /// The sink to render to.
+  writer: W,
+  /// The renderer used for drawing the line.
+  renderer: Renderer,
+}
+
+impl<R, W> Repl<R, W>
+where
+  R: Read,
+  W: Write,
+{
+  /// Create a new `Repl` object using the given instance, prompt, and
+  /// I/O endpoints.
+  ///
+  /// Output is rendered assuming a width of 80 columns. Use
+  /// [`set_columns`][Repl::set_columns] to adjust it.
+  pub fn new<P>(rl: Readline, prompt: P, reader: R, writer: W) -> Self
+  where
+    P: Into<String>,
+  {
+    Self {
+      rl,
+      prompt: prompt.into(),
+      reader: BufReader::new(reader),
+      writer,
+      renderer: Renderer::new(DEFAULT_COLUMNS),
+    }
+  }
+
+  /// Retrieve the `Readline` instance used for editing lines.
+  pub fn readline(&self) -> &Readline {
+    &self.rl
+  }
+
+  /// Set the prompt to display.
+  pub fn set_prompt<P>(&mut self, prompt: P)
+  where
+    P: Into<String>,
+  {
+    self.prompt = prompt.into()
+  }
+
+  /// Set the number of columns available for rendering.
+  pub fn set_columns(&mut self, columns: usize) {
+    self.renderer.set_columns(columns)
+  }
+
+  /// Retrieve the writer the line is rendered to, e.g., for emitting
+  /// output in between lines.
+  pub fn writer_mut(&mut self) -> &mut W {
+    &mut self.writer
+  }
+
+  /// Read a line.
+  ///
+  /// `None` is returned if the user signaled the end of input by
+  /// feeding the end-of-file character (see
+  /// [`Readline::set_eof_char`]) on an empty line or if the reader
+  /// reached end-of-file.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error of kind [`InvalidInput`][ErrorKind::InvalidInput]
+  /// if the prompt contains a NUL byte, as well as any error reported
+  /// by the reader or writer.
+  pub fn read_line(&mut self) -> Result<Option<CString>> {
+    let prompt =
+      CString::new(self.prompt.as_str()).map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+    self.rl.set_prompt(&prompt);
+
+    let () = redraw(&self.rl, &mut self.renderer, &self.prompt, &mut self.writer)?;
+
+    loop {
+      let buffer = self.reader.fill_buf()?;
+      if buffer.is_empty() {
+        self.writer.write_all(self.renderer.finish().as_bytes())?;
+        self.writer.flush()?;
+        return Ok(None)
+      }
+
+      let mut consumed = 0;
+      let mut result = None;
+
+      for &byte in buffer {
+        consumed += 1;
+
+        // We feed byte by byte so that we never process input past a
+        // completed line.
+        match self.rl.feed_key([byte]) {
+          Feed::Line(line) => result = Some(Some(line)),
+          Feed::Eof => result = Some(None),
+          Feed::Pending | Feed::Interrupted => continue,
+        }
+        break
+      }
+      self.reader.consume(consumed);
+
+      match result {
+        Some(line) => {
+          let () = complete(
+            &self.rl,
+            &mut self.renderer,
+            &self.prompt,
+            &line,
+            &mut self.writer,
+          )?;
+          return Ok(line)
+        },
+        None => redraw(&self.rl, &mut self.renderer, &self.prompt, &mut self.writer)?,
+      }
+    }
+  }
+
+  /// Read a single key press, bypassing line editing.
+  ///
+  /// The key press is read from the same source as lines are and
+  /// escape sequences are resolved into the corresponding [`Key`],
+  /// which makes this method suitable for single-key menus and
+  /// confirmations in between line prompts. Neither the prompt nor the
+  /// line being edited is displayed in the process. Sequences not
+  /// corresponding to a known key are skipped. `None` is returned if
+  /// the reader reached end-of-file.
+  ///
+  /// Note that an Escape byte not followed by further input available
+  /// right away is reported as [`Key::Escape`].
+  ///
+  /// # Errors
+  ///
+  /// Returns any error reported by the reader.
+  pub fn read_key(&mut self) -> Result<Option<(Key, Modifiers)>> {
+    // The bytes of an incomplete key press consumed already.
+    let mut bytes = Vec::new();
+
+    loop {
+      let buffer = self.reader.fill_buf()?;
+      if buffer.is_empty() {
+        return Ok(None)
+      }
+
+      let consumed = bytes.len();
+      let () = bytes.extend_from_slice(buffer);
+
+      match decode(&bytes) {
+        Decode::Key(key, modifiers, len) => {
+          self.reader.consume(len - consumed);
+          return Ok(Some((key, modifiers)))
+        },
+        Decode::Unknown(len) => {
+          self.reader.consume(len - consumed);
+          let () = bytes.clear();
+        },
+        Decode::Incomplete => {
+          let len = bytes.len();
+          self.reader.consume(len - consumed);
+        },
+      }
+    }
+  }
+
+  /// Read lines until the end of input, handing each to the provided
+  /// handler.
+  ///
+  /// The handler may write output to the provided writer. It returns
+  /// `true` to continue reading lines and `false` to stop.
+  pub fn run<F>(&mut self, mut handler: F) -> Result<()>
+  where
+    F: FnMut(CString, &mut W) -> Result<bool>,
+  {
+    while let Some(line) = self.read_line()? {
+      if !handler(line, &mut self.writer)? {
+        break
+      }
+      self.writer.flush()?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(feature = "termion")]
+impl Repl<Stdin, RawTerminal<Stdout>> {
+  /// Create a new `Repl` object reading from stdin and rendering to
+  /// stdout, with the terminal transitioned into raw mode for the
+  /// lifetime of the object.
+  pub fn terminal<P>(rl: Readline, prompt: P) -> Result<Self>
+  where
+    P: Into<String>,
+  {
+    let writer = stdout().into_raw_mode()?;
+    let mut repl = Self::new(rl, prompt, stdin(), writer);
+    repl.set_columns(crate::frontend::termion::columns());
+    Ok(repl)
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+
+  /// Check that we can run a `Repl` over in-memory I/O endpoints.
+  #[test]
+  fn run() {
+    let input = &b"ab\rcd\r\rxy\x15\x04ef\r"[..];
+    let mut repl = Repl::new(Readline::new(), "> ", input, Vec::new());
+    let mut lines = Vec::new();
+    let () = repl
+      .run(|line, w| {
+        writeln!(w, "got {:?}", line)?;
+        lines.push(line);
+        Ok(true)
+      })
+      .unwrap();
+
+    assert_eq!(
+      lines,
+      vec![
+        CString::new("ab").unwrap(),
+        CString::new("cd").unwrap(),
+        CString::new("").unwrap(),
+      ]
+    );
+    assert_eq!(repl.readline().history_usage().entries, 2);
+
+    let output = String::from_utf8(repl.writer_mut().clone()).unwrap();
+    assert!(output.contains("> ab"), "{}", output);
+    assert!(output.contains("got \"cd\""), "{}", output);
+
+    let mut repl = Repl::new(Readline::new(), "> ", &b"a\rb\r"[..], Vec::new());
+    let mut count = 0;
+    let () = repl
+      .run(|_line, _w| {
+        count += 1;
+        Ok(false)
+      })
+      .unwrap();
+    assert_eq!(count, 1);
+    assert_eq!(repl.read_line().unwrap(), Some(CString::new("b").unwrap()));
+    assert_eq!(repl.read_line().unwrap(), None);
+  }
+
+  /// Check that single key presses can be read in between lines.
+  #[test]
+  fn read_key() {
+    let input = &b"ab\ry\x1b[1;5Dcd\r"[..];
+    let mut repl = Repl::new(Readline::new(), "> ", input, Vec::new());
+    let none = Modifiers::default();
+    let ctrl = Modifiers {
+      ctrl: true,
+      ..Default::default()
+    };
+
+    assert_eq!(repl.read_line().unwrap(), Some(CString::new("ab").unwrap()));
+    assert_eq!(repl.read_key().unwrap(), Some((Key::Char('y'), none)));
+    assert_eq!(repl.read_key().unwrap(), Some((Key::Left, ctrl)));
+    assert_eq!(repl.read_line().unwrap(), Some(CString::new("cd").unwrap()));
+    assert_eq!(repl.read_key().unwrap(), None);
+
+    let input = &b"\x1b[99~\x1bOPa"[..];
+    let mut repl = Repl::new(Readline::new(), "> ", input, Vec::new());
+    assert_eq!(repl.read_key().unwrap(), Some((Key::F(1), none)));
+    assert_eq!(repl.read_key().unwrap(), Some((Key::Char('a'), none)));
+    assert_eq!(repl.read_key().unwrap(), None);
+  }
+
+  /// Check that key presses split across reads are decoded correctly.
+  #[test]
+  fn read_key_split() {
+    let input = (&b"\x1b[1;"[..]).chain(&b"5D\xe2\x82"[..]).chain(&b"\xacx\xc3"[..]);
+    let mut repl = Repl::new(Readline::new(), "> ", input, Vec::new());
+    let ctrl = Modifiers {
+      ctrl: true,
+      ..Default::default()
+    };
+
+    assert_eq!(repl.read_key().unwrap(), Some((Key::Left, ctrl)));
+    let none = Modifiers::default();
+    assert_eq!(repl.read_key().unwrap(), Some((Key::Char('\u{20ac}'), none)));
+    assert_eq!(repl.read_key().unwrap(), Some((Key::Char('x'), none)));
+    assert_eq!(repl.read_key().unwrap(), None);
+  }
+}
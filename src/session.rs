@@ -0,0 +1,29 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Serializable snapshots of the portable parts of an editing session.
+
+use std::ffi::CString;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+
+/// A snapshot of the portable state of a `Readline` instance.
+///
+/// A session captures the line being edited, the cursor position, the
+/// history, and the killed text recorded by the crate. It can be
+/// retrieved via [`Readline::session`][crate::Readline::session] and
+/// applied to an instance (possibly in a different process) via
+/// [`Readline::restore_session`][crate::Readline::restore_session].
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Session {
+  /// The line being edited.
+  pub line: CString,
+  /// The cursor position, as a byte index into `line`.
+  pub cursor: usize,
+  /// The history entries, oldest first.
+  pub history: Vec<CString>,
+  /// The kill ring entries, oldest first.
+  pub kill_ring: Vec<CString>,
+}
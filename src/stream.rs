@@ -0,0 +1,147 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Feeding of input from an iterator of bytes.
+
+use std::ffi::CString;
+use std::iter::FusedIterator;
+
+use crate::key::decode;
+use crate::key::Decode;
+use crate::Feed;
+use crate::Readline;
+
+
+/// The Escape byte.
+const ESC: u8 = 0x1b;
+
+
+/// An iterator over the lines completed by feeding a stream of bytes
+/// to a [`Readline`] instance.
+///
+/// Objects of this type are created by [`Readline::feed_iter`]. Input
+/// is consumed lazily, only as far as necessary to complete the next
+/// line.
+#[derive(Debug)]
+pub struct FeedLines<'rl, I> {
+  /// The instance the input is fed to.
+  rl: &'rl Readline,
+  /// The input bytes not yet consumed.
+  input: I,
+  /// Consumed bytes not yet fed, forming an incomplete key.
+  key: Vec<u8>,
+  /// Whether all input got processed.
+  done: bool,
+}
+
+impl<'rl, I> FeedLines<'rl, I> {
+  /// Create a new `FeedLines` object feeding the given input to the
+  /// provided instance.
+  pub(crate) fn new(rl: &'rl Readline, input: I) -> Self {
+    Self {
+      rl,
+      input,
+      key: Vec::new(),
+      done: false,
+    }
+  }
+
+  /// Determine the length of the first complete key in the buffered
+  /// bytes, if any.
+  fn complete(&self) -> Option<usize> {
+    // Input from an iterator is not interactive, so an Escape byte
+    // always starts a sequence as long as more input follows.
+    if self.key == [ESC] {
+      return None
+    }
+
+    match decode(&self.key) {
+      Decode::Key(_, _, len) | Decode::Unknown(len) => Some(len),
+      Decode::Incomplete => None,
+    }
+  }
+
+  /// Evaluate the outcome of feeding a key, returning the line to
+  /// report, if any.
+  fn evaluate(&mut self, feed: Feed) -> Option<Option<CString>> {
+    match feed {
+      Feed::Line(line) => Some(Some(line)),
+      Feed::Eof => {
+        self.done = true;
+        Some(None)
+      },
+      Feed::Pending | Feed::Interrupted => None,
+    }
+  }
+}
+
+impl<I> Iterator for FeedLines<'_, I>
+where
+  I: Iterator<Item = u8>,
+{
+  type Item = CString;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    while !self.done {
+      if let Some(len) = self.complete() {
+        let key = self.key.drain(..len).collect::<Vec<_>>();
+        let feed = self.rl.feed_key(key);
+        if let Some(line) = self.evaluate(feed) {
+          return line
+        }
+        continue
+      }
+
+      match self.input.next() {
+        Some(byte) => self.key.push(byte),
+        None => {
+          self.done = true;
+          // Whatever is left is an incomplete key or a lone Escape
+          // byte, either of which may still resolve to a command.
+          if !self.key.is_empty() {
+            let key = self.key.split_off(0);
+            let feed = self.rl.feed_final_key(key);
+            if let Some(line) = self.evaluate(feed) {
+              return line
+            }
+          }
+        },
+      }
+    }
+    None
+  }
+}
+
+impl<I> FusedIterator for FeedLines<'_, I> where I: Iterator<Item = u8> {}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+
+  /// Check that lines completed by a stream of bytes are reported.
+  #[test]
+  fn feed_lines() {
+    let rl = Readline::new();
+    let input = b"ab\x1b[Dc\rdx\x1bb\x04e\r\rfg".iter().copied();
+    let lines = rl.feed_iter(input).collect::<Vec<_>>();
+    let expected = [
+      CString::new("acb").unwrap(),
+      CString::new("ex").unwrap(),
+      CString::default(),
+    ];
+    assert_eq!(lines, expected);
+    assert_eq!(rl.peek(|s, _| s.to_owned()), CString::new("fg").unwrap());
+  }
+
+  /// Check that end-of-file on an empty line stops the iteration.
+  #[test]
+  fn feed_lines_eof() {
+    let rl = Readline::new();
+    let mut lines = rl.feed_iter(b"a\r\x04b\r".iter().copied());
+    assert_eq!(lines.next(), Some(CString::new("a").unwrap()));
+    assert_eq!(lines.next(), None);
+    assert_eq!(lines.next(), None);
+  }
+}
@@ -0,0 +1,85 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Computation of fish-style autosuggestions from the history.
+
+use std::ffi::CStr;
+
+
+/// Find the suggestion for completing `line`, given the history
+/// entries in `history`, ordered from oldest to newest.
+///
+/// The suggestion is the remainder of the most recent entry that
+/// starts with, but is longer than, `line`. Empty lines never produce
+/// a suggestion.
+pub(crate) fn suggest<'h, I>(line: &[u8], history: I) -> Option<&'h CStr>
+where
+  I: DoubleEndedIterator<Item = &'h CStr>,
+{
+  if line.is_empty() {
+    return None
+  }
+
+  history.rev().find_map(|entry| {
+    let bytes = entry.to_bytes_with_nul();
+    // Note that `bytes` includes the NUL terminator and so any entry
+    // strictly longer than `line` yields a non-empty suggestion.
+    if bytes.len() > line.len() + 1 && bytes.starts_with(line) {
+      CStr::from_bytes_with_nul(&bytes[line.len()..]).ok()
+    } else {
+      None
+    }
+  })
+}
+
+
+/// Retrieve the first word of a suggestion, including any leading
+/// non-word characters.
+pub(crate) fn word(suggestion: &[u8]) -> &[u8] {
+  let start = suggestion
+    .iter()
+    .position(u8::is_ascii_alphanumeric)
+    .unwrap_or(suggestion.len());
+  let end = suggestion[start..]
+    .iter()
+    .position(|b| !b.is_ascii_alphanumeric())
+    .map(|end| start + end)
+    .unwrap_or(suggestion.len());
+  &suggestion[..end]
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::ffi::CString;
+
+
+  /// Check that suggestions are derived from the most recent matching
+  /// history entry.
+  #[test]
+  fn suggestions() {
+    let history = ["git commit", "git status", "git", "ls"]
+      .iter()
+      .map(|s| CString::new(*s).unwrap())
+      .collect::<Vec<_>>();
+    let history = || history.iter().map(CString::as_c_str);
+
+    assert_eq!(suggest(b"", history()), None);
+    assert_eq!(suggest(b"g", history()).unwrap().to_bytes(), b"it");
+    assert_eq!(suggest(b"git ", history()).unwrap().to_bytes(), b"status");
+    assert_eq!(suggest(b"git c", history()).unwrap().to_bytes(), b"ommit");
+    assert_eq!(suggest(b"git status", history()), None);
+    assert_eq!(suggest(b"x", history()), None);
+  }
+
+  /// Check that we extract words from suggestions as expected.
+  #[test]
+  fn words() {
+    assert_eq!(word(b""), b"");
+    assert_eq!(word(b"it status"), b"it");
+    assert_eq!(word(b" status -s"), b" status");
+    assert_eq!(word(b" --"), b" --");
+  }
+}
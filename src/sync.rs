@@ -0,0 +1,235 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Synchronization of history between processes via a shared history
+//! file.
+
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::ErrorKind;
+use std::io::Read as _;
+use std::io::Seek as _;
+use std::io::SeekFrom;
+use std::io::Write as _;
+use std::ops::Range;
+use std::os::unix::io::AsRawFd as _;
+use std::path::Path;
+use std::path::PathBuf;
+
+use libc::c_int;
+use libc::flock;
+use libc::LOCK_EX;
+use libc::LOCK_SH;
+
+use crate::merge::parse_timestamp;
+
+
+/// Acquire an advisory lock of the given kind on the given file.
+///
+/// The lock is released when the file is closed.
+fn lock(file: &File, kind: c_int) -> io::Result<()> {
+  loop {
+    let result = unsafe { flock(file.as_raw_fd(), kind) };
+    if result == 0 {
+      break Ok(())
+    }
+
+    let err = io::Error::last_os_error();
+    if err.kind() != ErrorKind::Interrupted {
+      break Err(err)
+    }
+  }
+}
+
+
+/// The state of synchronizing a history with a shared history file.
+///
+/// Lines are appended to the file as they are added to the history.
+/// Lines appended by others are read on demand, starting at the end of
+/// the part of the file read previously. Lines we appended ourselves
+/// are skipped when doing so, as are timestamps (`#<seconds>`) as
+/// written by libreadline. Multi-line entries are not synchronized, as
+/// they cannot be told apart from multiple entries in the file.
+#[derive(Debug)]
+pub(crate) struct HistorySync {
+  /// The path to the history file.
+  path: PathBuf,
+  /// The offset in the file up to which its content was read.
+  offset: u64,
+  /// The ranges of the file, past `offset`, holding lines appended by
+  /// us.
+  own: Vec<Range<u64>>,
+  /// Lines added to the history but not yet appended to the file.
+  pending: Vec<CString>,
+}
+
+impl HistorySync {
+  /// Create a new `HistorySync` object for the file at the given path,
+  /// none of which was read so far.
+  pub(crate) fn new(path: &Path) -> Self {
+    Self {
+      path: path.to_path_buf(),
+      offset: 0,
+      own: Vec::new(),
+      pending: Vec::new(),
+    }
+  }
+
+  /// Append the given line to the history file, or remember it for
+  /// being appended later.
+  pub(crate) fn append(&mut self, line: &CStr) {
+    if line.to_bytes().contains(&b'\n') {
+      return
+    }
+
+    self.pending.push(line.to_owned());
+    // Failure to append is reported the next time the file gets
+    // synchronized explicitly, as part of which appending is retried.
+    let _result = self.flush();
+  }
+
+  /// Append all lines not yet appended to the history file.
+  pub(crate) fn flush(&mut self) -> io::Result<()> {
+    if self.pending.is_empty() {
+      return Ok(())
+    }
+
+    let mut file = OpenOptions::new()
+      .append(true)
+      .create(true)
+      .open(&self.path)?;
+    let () = lock(&file, LOCK_EX)?;
+
+    let start = file.seek(SeekFrom::End(0))?;
+    let mut data = Vec::new();
+    for line in &self.pending {
+      data.extend_from_slice(line.as_bytes());
+      data.push(b'\n');
+    }
+    if let Err(err) = file.write_all(&data) {
+      // Remove what got written, if anything, so that retrying does not
+      // append a partial line followed by the complete one.
+      let _result = file.set_len(start);
+      return Err(err)
+    }
+
+    self.own.push(start..start + data.len() as u64);
+    self.pending.clear();
+    Ok(())
+  }
+
+  /// Read the lines appended to the history file by others since it
+  /// was last read.
+  pub(crate) fn read(&mut self) -> io::Result<Vec<Vec<u8>>> {
+    let mut file = match File::open(&self.path) {
+      Ok(file) => file,
+      Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+      Err(err) => return Err(err),
+    };
+    let () = lock(&file, LOCK_SH)?;
+
+    if file.metadata()?.len() < self.offset {
+      // The file got truncated or replaced. Start over.
+      self.offset = 0;
+      self.own.clear();
+    }
+
+    let _offset = file.seek(SeekFrom::Start(self.offset))?;
+    let mut data = Vec::new();
+    let _count = file.read_to_end(&mut data)?;
+    // Only consider complete lines; a partial one may still be in the
+    // process of being written.
+    let end = match data.iter().rposition(|b| *b == b'\n') {
+      Some(end) => end + 1,
+      None => return Ok(Vec::new()),
+    };
+
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for line in data[..end].split_inclusive(|b| *b == b'\n') {
+      let offset = self.offset + start as u64;
+      start += line.len();
+
+      let own = self.own.iter().any(|range| range.contains(&offset));
+      let line = &line[..line.len() - 1];
+      let timestamp = line
+        .strip_prefix(b"#")
+        .and_then(parse_timestamp)
+        .is_some();
+      if !own && !timestamp && !line.is_empty() && !line.contains(&b'\0') {
+        lines.push(line.to_vec());
+      }
+    }
+
+    self.offset += end as u64;
+    let offset = self.offset;
+    self.own.retain(|range| range.end > offset);
+    Ok(lines)
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::env::temp_dir;
+  use std::fs::remove_file;
+  use std::fs::write;
+  use std::process;
+
+
+  /// Check that lines appended by others are read, but not our own.
+  #[test]
+  fn append_and_read() {
+    let path = temp_dir().join(format!("rline-sync-{}", process::id()));
+    let () = write(&path, "first\n\nsecond\n").unwrap();
+
+    let mut sync1 = HistorySync::new(&path);
+    let mut sync2 = HistorySync::new(&path);
+    assert_eq!(sync1.read().unwrap(), vec![b"first".to_vec(), b"second".to_vec()]);
+    assert_eq!(sync1.read().unwrap(), Vec::<Vec<u8>>::new());
+
+    sync1.append(&CString::new("third").unwrap());
+    sync2.append(&CString::new("fourth").unwrap());
+    sync1.append(&CString::new("fifth").unwrap());
+
+    assert_eq!(sync1.read().unwrap(), vec![b"fourth".to_vec()]);
+    assert_eq!(
+      sync2.read().unwrap(),
+      vec![
+        b"first".to_vec(),
+        b"second".to_vec(),
+        b"third".to_vec(),
+        b"fifth".to_vec()
+      ]
+    );
+
+    // Partial lines are left alone until they are complete.
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    let () = file.write_all(b"six").unwrap();
+    assert_eq!(sync1.read().unwrap(), Vec::<Vec<u8>>::new());
+    let () = file.write_all(b"th\n").unwrap();
+    assert_eq!(sync1.read().unwrap(), vec![b"sixth".to_vec()]);
+
+    // Timestamps are skipped.
+    let () = file.write_all(b"#1700000000\nseventh\n#foo\n").unwrap();
+    assert_eq!(sync1.read().unwrap(), vec![b"seventh".to_vec(), b"#foo".to_vec()]);
+
+    // Multi-line entries are not appended.
+    sync2.append(&CString::new("eighth\nninth").unwrap());
+    assert!(sync2.pending.is_empty());
+    assert_eq!(sync1.read().unwrap(), Vec::<Vec<u8>>::new());
+
+    // A truncated file is read from the start.
+    let () = write(&path, "new\n").unwrap();
+    assert_eq!(sync1.read().unwrap(), vec![b"new".to_vec()]);
+    let () = remove_file(&path).unwrap();
+
+    assert_eq!(sync1.read().unwrap(), Vec::<Vec<u8>>::new());
+    let () = sync1.flush().unwrap();
+  }
+}
@@ -0,0 +1,210 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! A harness for testing input handling based on `Readline`.
+
+use std::cell::RefCell;
+use std::env::remove_var;
+use std::env::set_var;
+use std::env::var_os;
+use std::ffi::CString;
+use std::ops::Deref;
+use std::sync::Once;
+
+use crate::Feed;
+use crate::Readline;
+
+
+/// Make sure that libreadline gets initialized without reading the
+/// user's or the system's init file and that input is handled
+/// independently of the locale.
+fn initialize() {
+  static ONCE: Once = Once::new();
+
+  ONCE.call_once(|| {
+    // libreadline reads its init file once, as part of the creation of
+    // the first instance. Pointing it to an empty file for that time
+    // keeps the host's configuration out of the picture.
+    let inputrc = var_os("INPUTRC");
+    let () = set_var("INPUTRC", "/dev/null");
+    let _rl = Readline::new();
+    match inputrc {
+      Some(inputrc) => set_var("INPUTRC", inputrc),
+      None => remove_var("INPUTRC"),
+    }
+    let () = Readline::force_eight_bit();
+  })
+}
+
+
+/// A wrapper around a [`Readline`] instance for conveniently testing
+/// input handling.
+///
+/// The tester allows for typing text and checking the resulting line
+/// state, e.g.:
+/// ```rust
+/// # use rline::ReadlineTester;
+/// let rl = ReadlineTester::new();
+/// rl.type_str("ab\x1b[D c")
+///   .assert_line("a cb")
+///   .assert_cursor(3)
+///   .type_str("\r")
+///   .assert_accepted(&["a cb"]);
+/// ```
+///
+/// Testers are created isolated (see [`Readline::set_isolated`]), so
+/// that key bindings made for one test do not affect others. If the
+/// first `Readline` instance in the process is created by a tester,
+/// libreadline is prevented from reading the host's init file
+/// (`inputrc`), making tests independent of the user's configuration.
+/// Furthermore, 8-bit clean input handling is forced for all instances
+/// (see [`Readline::force_eight_bit`]), so that UTF-8 can be typed
+/// regardless of the locale.
+/// The wrapped instance is accessible via [`Deref`], e.g., for
+/// installing hooks.
+#[derive(Debug)]
+pub struct ReadlineTester {
+  /// The instance being tested.
+  rl: Readline,
+  /// The outcomes of feeds other than `Feed::Pending`, oldest first.
+  outcomes: RefCell<Vec<Feed>>,
+}
+
+impl ReadlineTester {
+  /// Create a new `ReadlineTester` using a fresh, isolated instance.
+  pub fn new() -> Self {
+    let () = initialize();
+    let rl = Readline::new();
+    rl.set_isolated(true);
+    Self::with_readline(rl)
+  }
+
+  /// Create a new `ReadlineTester` wrapping the given instance, e.g.,
+  /// one created via a [`ReadlineBuilder`][crate::ReadlineBuilder].
+  pub fn with_readline(rl: Readline) -> Self {
+    Self {
+      rl,
+      outcomes: RefCell::new(Vec::new()),
+    }
+  }
+
+  /// Feed the given key to the instance, remembering the outcome.
+  fn feed(&self, key: &[u8]) {
+    match self.rl.feed_key(key) {
+      Feed::Pending => (),
+      feed => self.outcomes.borrow_mut().push(feed),
+    }
+  }
+
+  /// Type the given text, character by character.
+  ///
+  /// Control characters and escape sequences can be embedded, e.g.,
+  /// `"\x1b[D"` for the left arrow key or `"\r"` for enter. Each
+  /// character is fed as a key on its own, leaving it to libreadline
+  /// to assemble multi-byte key sequences.
+  pub fn type_str(&self, text: &str) -> &Self {
+    let mut buf = [0; 4];
+    for c in text.chars() {
+      let () = self.feed(c.encode_utf8(&mut buf).as_bytes());
+    }
+    self
+  }
+
+  /// Type the given bytes, byte by byte, e.g., for testing the handling
+  /// of non-UTF-8 input.
+  pub fn type_bytes(&self, bytes: &[u8]) -> &Self {
+    for &b in bytes {
+      let () = self.feed(&[b]);
+    }
+    self
+  }
+
+  /// Retrieve the outcomes of all feeds since the last call that were
+  /// not [`Feed::Pending`], oldest first.
+  pub fn take_outcomes(&self) -> Vec<Feed> {
+    self.outcomes.take()
+  }
+
+  /// Assert that the line being edited is `line`.
+  ///
+  /// # Panics
+  ///
+  /// Panics if the line differs.
+  #[track_caller]
+  pub fn assert_line(&self, line: &str) -> &Self {
+    let actual = self.rl.peek(|text, _| text.to_string_lossy().into_owned());
+    assert_eq!(actual, line, "unexpected line");
+    self
+  }
+
+  /// Assert that the cursor is at the given byte offset.
+  ///
+  /// # Panics
+  ///
+  /// Panics if the cursor is elsewhere.
+  #[track_caller]
+  pub fn assert_cursor(&self, cursor: usize) -> &Self {
+    let actual = self.rl.peek(|_, cursor| cursor);
+    assert_eq!(actual, cursor, "unexpected cursor position");
+    self
+  }
+
+  /// Assert that exactly the given lines got accepted since outcomes
+  /// were last retrieved (see [`take_outcomes`][Self::take_outcomes]),
+  /// consuming them.
+  ///
+  /// # Panics
+  ///
+  /// Panics if different lines got accepted or if any other outcome,
+  /// e.g., [`Feed::Eof`], got reported.
+  #[track_caller]
+  pub fn assert_accepted(&self, lines: &[&str]) -> &Self {
+    let expected = lines
+      .iter()
+      .map(|line| Feed::Line(CString::new(*line).unwrap()))
+      .collect::<Vec<_>>();
+    assert_eq!(self.take_outcomes(), expected, "unexpected outcomes");
+    self
+  }
+}
+
+impl Default for ReadlineTester {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Deref for ReadlineTester {
+  type Target = Readline;
+
+  fn deref(&self) -> &Self::Target {
+    &self.rl
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+
+  /// Check that we can type text and check the outcome.
+  #[test]
+  fn typing() {
+    let rl = ReadlineTester::new();
+    let _rl = rl
+      .type_str("ab\x1b[D c")
+      .assert_line("a cb")
+      .assert_cursor(3)
+      .type_str("\x01\x0bx\u{e4}\r")
+      .assert_accepted(&["x\u{e4}"])
+      .type_str("\x04")
+      .type_bytes(b"y\r");
+    assert_eq!(
+      rl.take_outcomes(),
+      vec![Feed::Eof, Feed::Line(CString::new("y").unwrap())]
+    );
+    assert_eq!(rl.take_outcomes(), Vec::new());
+    assert_eq!(rl.metrics().lines, 2);
+  }
+}
@@ -0,0 +1,136 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! An asynchronous interface to [`Readline`] based on `tokio`.
+
+use std::ffi::CString;
+use std::io::Result;
+
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt as _;
+use tokio::sync::oneshot;
+
+use crate::worker::Worker;
+use crate::Feed;
+use crate::Readline;
+
+
+/// An asynchronous wrapper around a [`Readline`] instance.
+///
+/// The wrapped instance lives on a dedicated worker thread, so that
+/// libreadline is never invoked from within the runtime itself.
+/// Operations are executed in the order in which they were issued.
+#[derive(Debug)]
+pub struct AsyncReadline {
+  /// The worker owning the wrapped instance.
+  worker: Worker,
+}
+
+impl AsyncReadline {
+  /// Create a new `AsyncReadline` object wrapping the given instance.
+  pub fn new(rl: Readline) -> Self {
+    Self {
+      worker: Worker::new(rl),
+    }
+  }
+
+  /// Run the provided function against the wrapped instance, returning
+  /// its result.
+  ///
+  /// # Panics
+  ///
+  /// Panics if this or a previously run function panicked.
+  pub async fn with<F, R>(&self, f: F) -> R
+  where
+    F: FnOnce(&Readline) -> R + Send + 'static,
+    R: Send + 'static,
+  {
+    let (sender, receiver) = oneshot::channel();
+    let () = self.worker.submit(move |rl| {
+      let _result = sender.send(f(rl));
+    });
+
+    receiver
+      .await
+      .expect("function run on readline worker thread panicked")
+  }
+
+  /// Feed a key to the wrapped instance.
+  ///
+  /// Please see [`Readline::feed`] for details.
+  pub async fn feed<K>(&self, key: K) -> Option<CString>
+  where
+    K: Into<Vec<u8>>,
+  {
+    let key = key.into();
+    self.with(move |rl| rl.feed(key)).await
+  }
+
+  /// Read a line, feeding the wrapped instance with input from the
+  /// provided reader.
+  ///
+  /// Input is read byte by byte, so that nothing beyond the completed
+  /// line is consumed. Consider wrapping unbuffered readers in a
+  /// [`BufReader`][tokio::io::BufReader]. `None` is returned if the
+  /// reader reached end-of-file before a line got completed or if the
+  /// end-of-file character (see [`Readline::set_eof_char`]) got fed on
+  /// an empty line.
+  pub async fn read_line<R>(&self, reader: &mut R) -> Result<Option<CString>>
+  where
+    R: AsyncRead + Unpin,
+  {
+    let mut byte = [0];
+    loop {
+      if reader.read(&mut byte).await? == 0 {
+        break Ok(None)
+      }
+
+      match self.with(move |rl| rl.feed_key(byte)).await {
+        Feed::Line(line) => break Ok(Some(line)),
+        Feed::Eof => break Ok(None),
+        Feed::Pending | Feed::Interrupted => (),
+      }
+    }
+  }
+}
+
+impl From<Readline> for AsyncReadline {
+  fn from(rl: Readline) -> Self {
+    Self::new(rl)
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use tokio::runtime::Builder;
+
+
+  /// Check that we can drive a `Readline` instance asynchronously.
+  #[test]
+  fn read_lines() {
+    let runtime = Builder::new_current_thread().build().unwrap();
+    let () = runtime.block_on(async {
+      let rl = AsyncReadline::new(Readline::new());
+      assert_eq!(rl.feed(&b"a"[..]).await, None);
+      assert_eq!(rl.feed(&b"\r"[..]).await, Some(CString::new("a").unwrap()));
+
+      let mut input = &b"xy\rz"[..];
+      let line = rl.read_line(&mut input).await.unwrap();
+      assert_eq!(line, Some(CString::new("xy").unwrap()));
+      assert_eq!(rl.read_line(&mut input).await.unwrap(), None);
+
+      let line = rl.with(|rl| rl.peek(|s, _| s.to_owned())).await;
+      assert_eq!(line, CString::new("z").unwrap());
+
+      // The end-of-file character ends input on an empty line only.
+      let mut input = &b"\r\x04\x04q"[..];
+      let line = rl.read_line(&mut input).await.unwrap();
+      assert_eq!(line, Some(CString::new("z").unwrap()));
+      assert_eq!(rl.read_line(&mut input).await.unwrap(), None);
+      assert_eq!(input, b"\x04q");
+    });
+  }
+}
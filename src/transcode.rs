@@ -0,0 +1,204 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Transcoding of input in legacy encodings.
+//!
+//! A [`Transcoder`] sits in front of a [`Readline`] instance and
+//! converts bytes read from a terminal using a legacy encoding (e.g.,
+//! ISO-8859-1 or Shift_JIS) into UTF-8 before they are fed, so that the
+//! line buffer is consistently UTF-8 encoded. Input that is not valid
+//! in the configured encoding is reported as an error rather than
+//! being fed, so that the line does not get corrupted.
+
+use std::fmt::Debug;
+use std::fmt::Display;
+use std::fmt::Error;
+use std::fmt::Formatter;
+
+use encoding_rs::Decoder;
+use encoding_rs::DecoderResult;
+use encoding_rs::Encoding;
+
+use crate::key::decode;
+use crate::key::Decode;
+use crate::Feed;
+use crate::Readline;
+
+
+/// An error indicating that input was not valid in the encoding it was
+/// expected to be in.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TranscodeError {
+  /// The encoding the input was expected to be in.
+  pub encoding: &'static Encoding,
+}
+
+impl Display for TranscodeError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+    write!(f, "input is not valid {}", self.encoding.name())
+  }
+}
+
+impl std::error::Error for TranscodeError {}
+
+
+/// A converter of input in a legacy encoding to UTF-8.
+///
+/// The conversion is stateful: a multi-byte character split across
+/// multiple chunks of input is converted once it is complete.
+pub struct Transcoder {
+  /// The decoder used for the conversion.
+  decoder: Decoder,
+}
+
+impl Transcoder {
+  /// Create a new `Transcoder` for input in the given encoding.
+  ///
+  /// A byte order mark at the start of the input is not interpreted.
+  pub fn new(encoding: &'static Encoding) -> Self {
+    Self {
+      decoder: encoding.new_decoder_without_bom_handling(),
+    }
+  }
+
+  /// Retrieve the encoding input is converted from.
+  pub fn encoding(&self) -> &'static Encoding {
+    self.decoder.encoding()
+  }
+
+  /// Convert a chunk of input to UTF-8.
+  ///
+  /// An empty result is reported if the chunk ends in the middle of a
+  /// multi-byte character. Should the chunk contain a sequence that is
+  /// not valid in the input encoding, it is discarded in its entirety
+  /// and an error is reported.
+  pub fn transcode(&mut self, input: &[u8]) -> Result<Vec<u8>, TranscodeError> {
+    let mut output = String::new();
+    let mut input = input;
+
+    loop {
+      let capacity = self
+        .decoder
+        .max_utf8_buffer_length_without_replacement(input.len())
+        .unwrap_or(input.len() * 4);
+      let () = output.reserve(capacity);
+
+      let (result, read) = self
+        .decoder
+        .decode_to_string_without_replacement(input, &mut output, false);
+      input = &input[read..];
+
+      match result {
+        DecoderResult::InputEmpty => break Ok(output.into_bytes()),
+        DecoderResult::OutputFull => continue,
+        DecoderResult::Malformed(..) => {
+          let encoding = self.encoding();
+          // Start over afresh with the next chunk, instead of
+          // interpreting it in the context of the malformed one.
+          self.decoder = encoding.new_decoder_without_bom_handling();
+          break Err(TranscodeError { encoding })
+        },
+      }
+    }
+  }
+
+  /// Convert a chunk of input as [`transcode`][Transcoder::transcode]
+  /// does and feed the result, if any, to the given `Readline`
+  /// instance.
+  ///
+  /// The converted input is split into key presses (including escape
+  /// sequences and UTF-8 encoded characters), which are fed one by one,
+  /// as per [`Readline::feed_key`]. The outcomes of doing so other than
+  /// [`Feed::Pending`] are reported, in order. Nothing is reported while
+  /// a multi-byte character is incomplete.
+  pub fn feed(&mut self, rl: &Readline, input: &[u8]) -> Result<Vec<Feed>, TranscodeError> {
+    let input = self.transcode(input)?;
+    let mut input = input.as_slice();
+    let mut feeds = Vec::new();
+
+    while !input.is_empty() {
+      // Bytes not forming a complete key are fed as they are, with
+      // libreadline waiting for the remainder of the sequence.
+      let len = match decode(input) {
+        Decode::Key(_, _, len) | Decode::Unknown(len) => len,
+        Decode::Incomplete => input.len(),
+      };
+      let (key, rest) = input.split_at(len);
+      input = rest;
+
+      match rl.feed_key(key) {
+        Feed::Pending => (),
+        feed => feeds.push(feed),
+      }
+    }
+    Ok(feeds)
+  }
+}
+
+impl Debug for Transcoder {
+  fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+    f.debug_struct("Transcoder")
+      .field("encoding", &self.encoding())
+      .finish()
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::ffi::CString;
+
+  use encoding_rs::ISO_8859_15;
+  use encoding_rs::SHIFT_JIS;
+
+
+  /// Check that input in legacy encodings is converted to UTF-8.
+  #[test]
+  fn transcoding() {
+    let mut transcoder = Transcoder::new(ISO_8859_15);
+    assert_eq!(transcoder.transcode(b"a\xe4\xa4").unwrap(), "aä€".as_bytes());
+
+    let mut transcoder = Transcoder::new(SHIFT_JIS);
+    assert_eq!(transcoder.transcode(b"\x82").unwrap(), b"");
+    assert_eq!(transcoder.transcode(b"\xa0").unwrap(), "あ".as_bytes());
+
+    let err = transcoder.transcode(b"a\x82\x20").unwrap_err();
+    assert_eq!(err.to_string(), "input is not valid Shift_JIS");
+    // The transcoder recovers from the error.
+    assert_eq!(transcoder.transcode(b"b").unwrap(), b"b");
+  }
+
+  /// Check that transcoded input is fed to a `Readline` instance.
+  #[test]
+  fn feeding() {
+    let () = Readline::force_eight_bit();
+
+    let rl = Readline::new();
+    let mut transcoder = Transcoder::new(SHIFT_JIS);
+    assert_eq!(transcoder.feed(&rl, b"\x82").unwrap(), Vec::new());
+    assert_eq!(transcoder.feed(&rl, b"\xa0").unwrap(), Vec::new());
+    assert!(transcoder.feed(&rl, b"\x82\x20").is_err());
+
+    let line = CString::new("あ").unwrap();
+    assert_eq!(transcoder.feed(&rl, b"\n").unwrap(), vec![Feed::Line(line)]);
+
+    // A chunk completing multiple lines reports all of them, with
+    // escape sequences being fed as a whole, even when split.
+    let feeds = transcoder.feed(&rl, b"ab\x1b[Dc\rd\x1b[").unwrap();
+    assert_eq!(feeds, vec![Feed::Line(CString::new("acb").unwrap())]);
+    let feeds = transcoder.feed(&rl, b"De\r\x82\xa0\r").unwrap();
+    let expected = vec![
+      Feed::Line(CString::new("ed").unwrap()),
+      Feed::Line(CString::new("あ").unwrap()),
+    ];
+    assert_eq!(feeds, expected);
+
+    // Chunks exceeding libreadline's input buffer are fed fine.
+    let input = [b'x'; 4096];
+    assert_eq!(transcoder.feed(&rl, &input).unwrap(), Vec::new());
+    let line = transcoder.feed(&rl, b"\r").unwrap();
+    assert_eq!(line, vec![Feed::Line(CString::new(input.to_vec()).unwrap())]);
+  }
+}
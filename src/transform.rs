@@ -0,0 +1,147 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Transformations of completed lines.
+
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::fmt::Debug;
+use std::fmt::Error;
+use std::fmt::Formatter;
+
+
+/// The type of a custom transformation function, mapping a completed
+/// line to its transformed form.
+pub type TransformFn = dyn FnMut(&CStr) -> CString + Send;
+
+
+/// Check whether the given byte is whitespace within a line.
+fn is_blank(b: u8) -> bool {
+  b == b' ' || b == b'\t'
+}
+
+
+/// Apply the given function to each line of multi-line input.
+fn per_line<F>(text: &[u8], mut f: F) -> Vec<u8>
+where
+  F: FnMut(&[u8], &mut Vec<u8>),
+{
+  let mut result = Vec::with_capacity(text.len());
+  for (idx, line) in text.split(|b| *b == b'\n').enumerate() {
+    if idx > 0 {
+      result.push(b'\n');
+    }
+    let () = f(line, &mut result);
+  }
+  result
+}
+
+
+/// A transformation applied to completed lines before they are
+/// reported.
+///
+/// The built-in transformations operate on each line of multi-line
+/// input individually and consider spaces and tabs as whitespace.
+pub enum Transform {
+  /// Remove trailing whitespace.
+  TrimTrailing,
+  /// Collapse runs of whitespace between words into a single space,
+  /// leaving leading and trailing whitespace untouched.
+  CollapseWhitespace,
+  /// Strip comments, i.e., text starting with the given marker (e.g.,
+  /// `#`) at the beginning of a word, up to the end of the line.
+  StripComments(CString),
+  /// Transform lines using the given function.
+  Custom(Box<TransformFn>),
+}
+
+impl Transform {
+  /// Apply the transformation to the given line.
+  pub(crate) fn apply(&mut self, line: &CStr) -> CString {
+    let text = line.to_bytes();
+    let result = match self {
+      Self::TrimTrailing => per_line(text, |line, result| {
+        let end = line.iter().rposition(|b| !is_blank(*b)).map_or(0, |idx| idx + 1);
+        result.extend_from_slice(&line[..end]);
+      }),
+      Self::CollapseWhitespace => per_line(text, |line, result| {
+        let start = line.iter().position(|b| !is_blank(*b)).unwrap_or(line.len());
+        let end = line.iter().rposition(|b| !is_blank(*b)).map_or(start, |idx| idx + 1);
+        result.extend_from_slice(&line[..start]);
+        for (idx, b) in line[start..end].iter().enumerate() {
+          if !is_blank(*b) {
+            result.push(*b)
+          } else if !is_blank(line[start + idx - 1]) {
+            result.push(b' ')
+          }
+        }
+        result.extend_from_slice(&line[end..]);
+      }),
+      Self::StripComments(marker) => {
+        let marker = marker.to_bytes();
+        per_line(text, |line, result| {
+          let is_comment = |idx: usize| {
+            line[idx..].starts_with(marker) && (idx == 0 || is_blank(line[idx - 1]))
+          };
+          let start = if marker.is_empty() {
+            None
+          } else {
+            (0..line.len()).find(|idx| is_comment(*idx))
+          };
+          result.extend_from_slice(&line[..start.unwrap_or(line.len())]);
+        })
+      },
+      Self::Custom(f) => return f(line),
+    };
+    // The result is made up of parts of a C string and so cannot
+    // contain NUL bytes.
+    CString::new(result).unwrap()
+  }
+}
+
+impl Debug for Transform {
+  fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+    match self {
+      Self::TrimTrailing => f.write_str("TrimTrailing"),
+      Self::CollapseWhitespace => f.write_str("CollapseWhitespace"),
+      Self::StripComments(marker) => f.debug_tuple("StripComments").field(marker).finish(),
+      Self::Custom(..) => f.debug_tuple("Custom").finish(),
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+
+  /// Apply the given transformation to the given text.
+  fn apply(mut transform: Transform, text: &str) -> String {
+    let line = CString::new(text).unwrap();
+    transform.apply(&line).into_string().unwrap()
+  }
+
+  /// Check that the built-in transformations work as expected.
+  #[test]
+  fn transformations() {
+    assert_eq!(apply(Transform::TrimTrailing, " a b \t"), " a b");
+    assert_eq!(apply(Transform::TrimTrailing, "a \n b  \n "), "a\n b\n");
+    assert_eq!(apply(Transform::CollapseWhitespace, "  a  \tb c  "), "  a b c  ");
+    assert_eq!(apply(Transform::CollapseWhitespace, "a  b\n  c  d"), "a b\n  c d");
+    assert_eq!(apply(Transform::CollapseWhitespace, " \t "), " \t ");
+
+    let strip = || Transform::StripComments(CString::new("#").unwrap());
+    assert_eq!(apply(strip(), "ls -l # list"), "ls -l ");
+    assert_eq!(apply(strip(), "# comment"), "");
+    assert_eq!(apply(strip(), "echo a#b"), "echo a#b");
+    assert_eq!(apply(strip(), "a #1\nb\n\t#2"), "a \nb\n\t");
+    let strip = Transform::StripComments(CString::default());
+    assert_eq!(apply(strip, "a # b"), "a # b");
+
+    let custom = Transform::Custom(Box::new(|line| {
+      CString::new(line.to_bytes().to_ascii_uppercase()).unwrap()
+    }));
+    assert_eq!(apply(custom, "abc"), "ABC");
+  }
+}
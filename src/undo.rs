@@ -0,0 +1,288 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Helpers for dealing with libreadline's undo list.
+
+use std::ffi::CStr;
+use std::iter::FusedIterator;
+use std::marker::PhantomData;
+use std::ops::Range;
+use std::ptr::null_mut;
+
+use libc::c_char;
+use libc::c_int;
+use libc::c_void;
+use libc::free;
+
+
+/// libreadline's representation of a single undo record.
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub(crate) struct UNDO_LIST {
+  pub(crate) next: *mut UNDO_LIST,
+  pub(crate) start: c_int,
+  pub(crate) end: c_int,
+  pub(crate) text: *mut c_char,
+  pub(crate) what: c_int,
+}
+
+
+// Declarations as provided by libhistory.
+extern "C" {
+  // Not part of the public header, but exported nonetheless.
+  fn _hs_replace_history_data(which: c_int, old: *mut c_void, new: *mut c_void);
+}
+
+
+/// libreadline's `UNDO_DELETE`.
+const UNDO_DELETE: c_int = 0;
+/// libreadline's `UNDO_INSERT`.
+const UNDO_INSERT: c_int = 1;
+/// libreadline's `UNDO_BEGIN`.
+const UNDO_BEGIN: c_int = 2;
+/// libreadline's `UNDO_END`.
+const UNDO_END: c_int = 3;
+
+
+/// The kind of an undo record.
+///
+/// Note that the kind describes the modification that got recorded,
+/// not what undoing it does. Undoing an insertion removes text, while
+/// undoing a deletion inserts it again.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum UndoKind {
+  /// Text got inserted.
+  Insert,
+  /// Text got deleted.
+  Delete,
+  /// The start of a group of records that are undone together.
+  Begin,
+  /// The end of a group of records that are undone together.
+  End,
+}
+
+
+/// A single record of a `Readline` instance's undo list.
+#[derive(Clone, Debug)]
+pub struct UndoRecord<'list> {
+  /// The kind of modification recorded.
+  kind: UndoKind,
+  /// The byte range of the line buffer affected.
+  range: Range<usize>,
+  /// The deleted text, if any.
+  text: Option<&'list CStr>,
+}
+
+impl<'list> UndoRecord<'list> {
+  /// Retrieve the kind of modification recorded.
+  pub fn kind(&self) -> UndoKind {
+    self.kind
+  }
+
+  /// Retrieve the byte range of the line buffer affected by the
+  /// modification.
+  ///
+  /// For insertions this is the range the inserted text occupies, for
+  /// deletions the one the deleted text occupied. Group markers report
+  /// an empty range.
+  pub fn range(&self) -> Range<usize> {
+    self.range.clone()
+  }
+
+  /// Retrieve the text deleted, for records of kind
+  /// [`UndoKind::Delete`].
+  pub fn text(&self) -> Option<&'list CStr> {
+    self.text
+  }
+}
+
+
+/// An iterator over the records of a `Readline` instance's undo list,
+/// from the most recent one (i.e., the one undone next) to the oldest
+/// one.
+#[derive(Debug)]
+pub struct UndoRecords<'list> {
+  record: *mut UNDO_LIST,
+  _list: PhantomData<&'list UNDO_LIST>,
+}
+
+impl UndoRecords<'_> {
+  /// Create an iterator over the given undo list.
+  ///
+  /// # Safety
+  /// `list` has to be null or point to a valid undo list that outlives
+  /// the iterator.
+  pub(crate) unsafe fn new(list: *mut UNDO_LIST) -> Self {
+    Self {
+      record: list,
+      _list: PhantomData,
+    }
+  }
+}
+
+impl<'list> Iterator for UndoRecords<'list> {
+  type Item = UndoRecord<'list>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.record.is_null() {
+      return None
+    }
+
+    // SAFETY: The pointer is valid by virtue of our construction
+    //         contract.
+    let record = unsafe { &*self.record };
+    self.record = record.next;
+
+    let kind = match record.what {
+      UNDO_DELETE => UndoKind::Delete,
+      UNDO_INSERT => UndoKind::Insert,
+      UNDO_BEGIN => UndoKind::Begin,
+      UNDO_END => UndoKind::End,
+      what => unreachable!("encountered unexpected undo record kind {}", what),
+    };
+    let range = match kind {
+      UndoKind::Insert | UndoKind::Delete => {
+        let start = record.start.max(0) as usize;
+        let end = record.end.max(record.start).max(0) as usize;
+        start..end
+      },
+      UndoKind::Begin | UndoKind::End => 0..0,
+    };
+    let text = if record.text.is_null() {
+      None
+    } else {
+      // SAFETY: libreadline stores NUL terminated strings.
+      Some(unsafe { CStr::from_ptr(record.text) })
+    };
+
+    Some(UndoRecord { kind, range, text })
+  }
+}
+
+impl FusedIterator for UndoRecords<'_> {}
+
+
+/// Check whether the given undo list contains the given record. A null
+/// record is considered part of every list.
+///
+/// # Safety
+/// `list` has to be null or point to a valid undo list.
+pub(crate) unsafe fn contains(list: *mut UNDO_LIST, record: *mut UNDO_LIST) -> bool {
+  let mut current = list;
+  loop {
+    if current == record {
+      break true
+    } else if current.is_null() {
+      break false
+    }
+    current = unsafe { (*current).next };
+  }
+}
+
+
+/// Overwrite the text stored in all records of the given undo list
+/// with zeroes.
+///
+/// # Safety
+/// `list` has to be null or point to a valid undo list.
+#[cfg(feature = "zeroize")]
+pub(crate) unsafe fn wipe(list: *mut UNDO_LIST) {
+  let mut record = list;
+  while !record.is_null() {
+    unsafe {
+      crate::wipe_str((*record).text);
+      record = (*record).next;
+    }
+  }
+}
+
+
+/// Detach the records of the given undo list from all history entries
+/// referencing them.
+///
+/// libreadline stores the undo list of a line recalled from the
+/// history in the entry's data when navigating away from it, restoring
+/// it when navigating back. Records about to be freed or handed to
+/// another instance must no longer be referenced that way.
+///
+/// # Safety
+/// Callers must ensure that the global mutex is held and that the
+/// history the records may be referenced from is active. `list` has to
+/// be null or point to a valid undo list.
+pub(crate) unsafe fn detach(list: *mut UNDO_LIST) {
+  let mut record = list;
+  while !record.is_null() {
+    unsafe {
+      _hs_replace_history_data(-1, record.cast(), null_mut());
+      record = (*record).next;
+    }
+  }
+}
+
+
+/// Trim the given undo list to at most `limit` records, freeing the
+/// oldest ones.
+///
+/// Groups are never split up: the list is only cut where the records
+/// removed form complete groups, meaning that more than `limit`
+/// records may be kept. That includes a group still being recorded
+/// (e.g., the one of a vi insertion) which got started before the
+/// `limit` most recent records.
+///
+/// History entries referencing any of the records freed are detached
+/// from them.
+///
+/// # Safety
+/// `list` has to be null or point to a valid undo list. The
+/// requirements of [`detach`] apply as well.
+pub(crate) unsafe fn trim(list: &mut *mut UNDO_LIST, limit: usize) {
+  let mut records = Vec::new();
+  let mut record = *list;
+  while !record.is_null() {
+    let () = records.push(record);
+    record = unsafe { (*record).next };
+  }
+
+  if records.len() <= limit {
+    return
+  }
+
+  // Walking from the oldest record, find the most recent cut that
+  // removes only complete groups and keeps no more than `limit`
+  // records.
+  let mut depth = 0usize;
+  let mut cut = None;
+  for (idx, record) in records.iter().enumerate().rev() {
+    match unsafe { (**record).what } {
+      UNDO_BEGIN => depth += 1,
+      UNDO_END => depth = depth.saturating_sub(1),
+      _ => (),
+    }
+
+    if idx <= limit && depth == 0 {
+      cut = Some(idx);
+      break
+    }
+  }
+
+  let cut = match cut {
+    Some(cut) => cut,
+    None => return,
+  };
+
+  if cut > 0 {
+    unsafe { (*records[cut - 1]).next = null_mut() };
+  } else {
+    *list = null_mut();
+  }
+  let () = unsafe { detach(records[cut]) };
+
+  for record in &records[cut..] {
+    unsafe {
+      #[cfg(feature = "zeroize")]
+      crate::wipe_str((**record).text);
+      free((**record).text.cast());
+      free(record.cast());
+    }
+  }
+}
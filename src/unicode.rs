@@ -0,0 +1,200 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Utilities for mapping between byte positions as reported by
+//! libreadline and grapheme clusters or terminal columns.
+//!
+//! libreadline works with byte offsets into the line buffer. For
+//! displaying purposes, what matters is the number of terminal cells a
+//! piece of text occupies, though. Each grapheme cluster may span
+//! multiple bytes and east asian wide characters (as well as most
+//! emojis) occupy two cells.
+
+use unicode_segmentation::UnicodeSegmentation as _;
+use unicode_width::UnicodeWidthStr as _;
+
+
+/// Retrieve the number of terminal cells occupied by the given
+/// grapheme cluster.
+fn grapheme_width(grapheme: &str) -> usize {
+  grapheme.width()
+}
+
+
+/// Find the grapheme cluster index that maps to the given byte
+/// position reported by libreadline.
+///
+/// If `pos` points into the middle of a grapheme cluster, the index of
+/// this cluster is reported.
+pub fn grapheme_index(line: &str, pos: usize) -> usize {
+  line
+    .grapheme_indices(true)
+    .take_while(|(idx, grapheme)| pos >= idx + grapheme.len())
+    .count()
+}
+
+
+/// Find the byte position of the start of the grapheme cluster
+/// preceding the given byte position.
+///
+/// If `pos` points into the middle of a grapheme cluster, the start of
+/// this cluster is reported. A position at the start of the line is
+/// reported as is.
+pub fn previous_grapheme(line: &str, pos: usize) -> usize {
+  line
+    .grapheme_indices(true)
+    .map(|(idx, _)| idx)
+    .take_while(|idx| *idx < pos)
+    .last()
+    .unwrap_or(0)
+}
+
+
+/// Find the byte position of the end of the grapheme cluster at the
+/// given byte position.
+///
+/// If `pos` points into the middle of a grapheme cluster, the end of
+/// this cluster is reported. A position at the end of the line is
+/// reported as is.
+pub fn next_grapheme(line: &str, pos: usize) -> usize {
+  line
+    .grapheme_indices(true)
+    .map(|(idx, grapheme)| idx + grapheme.len())
+    .find(|end| *end > pos)
+    .unwrap_or(line.len())
+}
+
+
+/// Calculate the display width of the given text, in terminal cells.
+pub fn width(text: &str) -> usize {
+  text.graphemes(true).map(grapheme_width).sum()
+}
+
+
+/// Find the terminal column, relative to the start of the line, that
+/// maps to the given byte position reported by libreadline.
+///
+/// If `pos` points into the middle of a grapheme cluster, the column of
+/// the start of this cluster is reported.
+pub fn column(line: &str, pos: usize) -> usize {
+  line
+    .grapheme_indices(true)
+    .take_while(|(idx, grapheme)| pos >= idx + grapheme.len())
+    .map(|(_, grapheme)| grapheme_width(grapheme))
+    .sum()
+}
+
+
+/// Find the byte position in a line that maps to the given terminal
+/// column, relative to the start of the line.
+///
+/// This is the reverse operation of [`column`]. If the column is
+/// covered by a wide character, the position of this character is
+/// reported. Columns past the end of the line map to the line's
+/// length.
+pub fn position(line: &str, column: usize) -> usize {
+  let mut start = 0;
+  for (idx, grapheme) in line.grapheme_indices(true) {
+    let end = start + grapheme_width(grapheme);
+    if column < end {
+      return idx
+    }
+    start = end;
+  }
+  line.len()
+}
+
+
+/// Find the row and terminal column that map to the given byte
+/// position in a line spanning multiple rows by virtue of containing
+/// newline characters.
+///
+/// Rows are separated by `\n` and columns are relative to the start of
+/// the respective row, as determined by [`column`]. Wrapping of rows
+/// due to the terminal width is not taken into account.
+pub fn location(line: &str, pos: usize) -> (usize, usize) {
+  let pos = pos.min(line.len());
+  let (row, start) = line
+    .bytes()
+    .take(pos)
+    .enumerate()
+    .filter(|(_, b)| *b == b'\n')
+    .fold((0, 0), |(row, _), (idx, _)| (row + 1, idx + 1));
+  (row, column(&line[start..], pos - start))
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+
+  /// Check that we map byte positions to grapheme clusters correctly.
+  #[test]
+  fn grapheme_indices() {
+    let line = "a\u{e4}o\u{308}b";
+    assert_eq!(grapheme_index(line, 0), 0);
+    assert_eq!(grapheme_index(line, 1), 1);
+    assert_eq!(grapheme_index(line, 2), 1);
+    assert_eq!(grapheme_index(line, 3), 2);
+    assert_eq!(grapheme_index(line, 5), 2);
+    assert_eq!(grapheme_index(line, 6), 3);
+    assert_eq!(grapheme_index(line, 7), 4);
+  }
+
+  /// Check that we find grapheme cluster boundaries correctly.
+  #[test]
+  fn grapheme_boundaries() {
+    let line = "a\u{e4}o\u{308}\u{1f44d}\u{1f3fd}";
+    assert_eq!(previous_grapheme(line, 0), 0);
+    assert_eq!(previous_grapheme(line, 1), 0);
+    assert_eq!(previous_grapheme(line, 3), 1);
+    assert_eq!(previous_grapheme(line, 5), 3);
+    assert_eq!(previous_grapheme(line, 6), 3);
+    assert_eq!(previous_grapheme(line, 14), 6);
+
+    assert_eq!(next_grapheme(line, 0), 1);
+    assert_eq!(next_grapheme(line, 1), 3);
+    assert_eq!(next_grapheme(line, 3), 6);
+    assert_eq!(next_grapheme(line, 4), 6);
+    assert_eq!(next_grapheme(line, 6), 14);
+    assert_eq!(next_grapheme(line, 14), 14);
+  }
+
+  /// Check that we map between byte positions and columns correctly in
+  /// the presence of wide characters.
+  #[test]
+  fn columns() {
+    let line = "a\u{4f60}\u{597d}b";
+    assert_eq!(width(line), 6);
+
+    assert_eq!(column(line, 0), 0);
+    assert_eq!(column(line, 1), 1);
+    assert_eq!(column(line, 2), 1);
+    assert_eq!(column(line, 4), 3);
+    assert_eq!(column(line, 7), 5);
+    assert_eq!(column(line, 8), 6);
+
+    assert_eq!(position(line, 0), 0);
+    assert_eq!(position(line, 1), 1);
+    assert_eq!(position(line, 2), 1);
+    assert_eq!(position(line, 3), 4);
+    assert_eq!(position(line, 5), 7);
+    assert_eq!(position(line, 6), 8);
+    assert_eq!(position(line, 42), 8);
+  }
+
+  /// Check that we map byte positions in multi-line input to rows and
+  /// columns correctly.
+  #[test]
+  fn locations() {
+    let line = "ab\n\u{4f60}c\n";
+    assert_eq!(location(line, 0), (0, 0));
+    assert_eq!(location(line, 2), (0, 2));
+    assert_eq!(location(line, 3), (1, 0));
+    assert_eq!(location(line, 6), (1, 2));
+    assert_eq!(location(line, 7), (1, 3));
+    assert_eq!(location(line, 8), (2, 0));
+    assert_eq!(location("", 0), (0, 0));
+  }
+}
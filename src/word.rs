@@ -0,0 +1,51 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Determination of words in a line.
+
+use std::ops::Range;
+
+
+/// Find the byte range of the word under or immediately before
+/// `cursor` in `line`, with words being delimited by any of the given
+/// break characters.
+pub(crate) fn find(line: &[u8], cursor: usize, breaks: &[u8]) -> Option<Range<usize>> {
+  let cursor = cursor.min(line.len());
+  let start = line[..cursor]
+    .iter()
+    .rposition(|c| breaks.contains(c))
+    .map_or(0, |i| i + 1);
+  let end = line[cursor..]
+    .iter()
+    .position(|c| breaks.contains(c))
+    .map_or(line.len(), |i| cursor + i);
+
+  if start < end {
+    Some(start..end)
+  } else {
+    None
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+
+  /// Check that we find the word at a cursor position.
+  #[test]
+  fn finding() {
+    let breaks = b" \t=";
+    let line = b"ls  --color=auto";
+    assert_eq!(find(line, 0, breaks), Some(0..2));
+    assert_eq!(find(line, 1, breaks), Some(0..2));
+    assert_eq!(find(line, 2, breaks), Some(0..2));
+    assert_eq!(find(line, 3, breaks), None);
+    assert_eq!(find(line, 4, breaks), Some(4..11));
+    assert_eq!(find(line, 12, breaks), Some(12..16));
+    assert_eq!(find(line, 16, breaks), Some(12..16));
+    assert_eq!(find(b"", 0, breaks), None);
+    assert_eq!(find(line, 5, b""), Some(0..16));
+  }
+}
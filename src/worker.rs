@@ -0,0 +1,62 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! A thread owning a [`Readline`] instance, as used by the asynchronous
+//! wrappers.
+
+use std::sync::mpsc::channel;
+use std::sync::mpsc::Sender;
+use std::sync::Mutex;
+use std::thread::spawn;
+
+use crate::Readline;
+
+
+/// A job to run against the worker's `Readline` instance.
+type Job = Box<dyn FnOnce(&Readline) + Send>;
+
+
+/// A thread owning a `Readline` instance and running submitted jobs
+/// against it, in order.
+///
+/// libreadline operations may block for a short while (e.g., when
+/// waiting for another instance to finish its work). Moving them onto a
+/// dedicated thread prevents them from stalling asynchronous runtimes.
+#[derive(Debug)]
+pub(crate) struct Worker {
+  /// The sender for submitting jobs.
+  ///
+  /// The `Mutex` is only used for making the worker `Sync`.
+  sender: Mutex<Sender<Job>>,
+}
+
+impl Worker {
+  /// Create a new worker taking ownership of the given instance.
+  pub(crate) fn new(rl: Readline) -> Self {
+    let (sender, receiver) = channel::<Job>();
+    // The thread exits once all senders are gone, i.e., once the
+    // worker got dropped.
+    let _handle = spawn(move || {
+      for job in receiver {
+        job(&rl)
+      }
+    });
+
+    Self {
+      sender: Mutex::new(sender),
+    }
+  }
+
+  /// Submit a job for execution on the worker thread.
+  ///
+  /// The job is dropped without being run if a previously submitted
+  /// job panicked.
+  pub(crate) fn submit<F>(&self, job: F)
+  where
+    F: FnOnce(&Readline) + Send + 'static,
+  {
+    // Sending only fails if the thread exited because a job panicked,
+    // in which case there is nothing left to run the job on.
+    let _result = self.sender.lock().unwrap().send(Box::new(job));
+  }
+}
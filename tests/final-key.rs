@@ -0,0 +1,55 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::ffi::CString;
+
+use libc::c_char;
+use libc::c_int;
+
+use rline::Feed;
+use rline::Readline;
+
+
+extern "C" {
+  fn rl_parse_and_bind(line: *mut c_char) -> c_int;
+}
+
+
+/// Parse and apply the given line in `inputrc` format.
+fn parse_and_bind(line: &str) {
+  let mut line = CString::new(line).unwrap().into_bytes_with_nul();
+  assert_eq!(unsafe { rl_parse_and_bind(line.as_mut_ptr().cast()) }, 0);
+}
+
+
+/// Check that a lone ESC is resolved to its binding when fed as the
+/// final key, instead of libreadline waiting for more input.
+#[test]
+fn final_key() {
+  let rl = Readline::new();
+  let () = parse_and_bind("\"\\e\": beginning-of-line");
+  let () = parse_and_bind("\"\\C-xa\": end-of-line");
+
+  assert_eq!(rl.feed_key(b"ab"), Feed::Pending);
+  // libreadline waits for the remainder of a potential cursor key.
+  assert_eq!(rl.feed_key(b"\x1b"), Feed::Pending);
+  assert_eq!(rl.peek(|_, cursor| cursor), 2);
+  assert_eq!(rl.feed_key(b"[D"), Feed::Pending);
+  assert_eq!(rl.peek(|_, cursor| cursor), 1);
+
+  // Cursor keys fed as a whole are not affected.
+  assert_eq!(rl.feed_final_key(b"\x1b[C"), Feed::Pending);
+  assert_eq!(rl.peek(|_, cursor| cursor), 2);
+
+  assert_eq!(rl.feed_final_key(b"\x1b"), Feed::Pending);
+  assert_eq!(rl.peek(|_, cursor| cursor), 0);
+  assert_eq!(rl.last_key_sequence(), b"\x1b");
+  assert_eq!(rl.feed_key(b"x"), Feed::Pending);
+  assert_eq!(rl.peek(|text, _| text.to_owned()), CString::new("xab").unwrap());
+
+  // Key sequences not shadowing a command stay incomplete.
+  assert_eq!(rl.feed_final_key(b"\x18"), Feed::Pending);
+  assert_eq!(rl.feed_final_key(b"a"), Feed::Pending);
+  assert_eq!(rl.peek(|_, cursor| cursor), 3);
+  assert_eq!(rl.feed_key(b"\r"), Feed::Line(CString::new("xab").unwrap()));
+}
@@ -0,0 +1,32 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fs::read_dir;
+use std::path::Path;
+
+use rline::Golden;
+use rline::ReadlineTester;
+
+
+/// Run all golden sessions in `tests/golden/`.
+#[test]
+fn golden_sessions() {
+  let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden");
+  let mut count = 0;
+
+  for entry in read_dir(dir).unwrap() {
+    let path = entry.unwrap().path();
+    if path.extension().map(|ext| ext != "session").unwrap_or(true) {
+      continue
+    }
+
+    let golden = Golden::read(&path).unwrap();
+    let rl = ReadlineTester::new();
+    if let Err(err) = golden.run(&rl) {
+      panic!("{}: {}", path.display(), err)
+    }
+    count += 1;
+  }
+
+  assert_ne!(count, 0);
+}
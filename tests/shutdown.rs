@@ -0,0 +1,46 @@
+// Copyright (C) 2024 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::ffi::CString;
+use std::panic::catch_unwind;
+
+use libc::c_char;
+use libc::c_int;
+
+use rline::shutdown;
+use rline::Feed;
+use rline::Readline;
+
+
+extern "C" {
+  fn rl_parse_and_bind(line: *mut c_char) -> c_int;
+}
+
+
+/// Check that global resources can be released once all instances are
+/// gone.
+#[test]
+fn shutdown_after_drop() {
+  let rl = Readline::new();
+  // Bind a macro to a multi-key sequence, for which libreadline
+  // allocates both, a new keymap and the macro text.
+  let mut line = CString::new("\"\\C-xqr\": \"abc\"")
+    .unwrap()
+    .into_bytes_with_nul();
+  assert_eq!(unsafe { rl_parse_and_bind(line.as_mut_ptr().cast()) }, 0);
+  assert_eq!(rl.feed_key(b"\x18qr"), Feed::Pending);
+
+  let result = catch_unwind(shutdown);
+  assert!(result.is_err());
+  // The instance remains usable.
+  let line = CString::new("abc").unwrap();
+  assert_eq!(rl.feed_key(b"\n"), Feed::Line(line));
+  drop(rl);
+
+  let () = shutdown();
+  // Shutting down repeatedly is fine.
+  let () = shutdown();
+
+  let result = catch_unwind(Readline::new);
+  assert!(result.is_err());
+}